@@ -0,0 +1,15 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generate the gRPC service, without needing protoc
+#[cfg(feature = "grpc")]
+fn grpc() {
+    println!("cargo:rerun-if-changed=proto/kondis.proto");
+    let descriptors =
+        protox::compile(["proto/kondis.proto"], ["proto"]).expect("proto/kondis.proto is invalid");
+    tonic_prost_build::configure()
+        .compile_fds(descriptors)
+        .expect("Could not generate the gRPC service");
+}
@@ -0,0 +1,129 @@
+//! ANT, the protocol ANT+ sensors and trainers speak, spoken by USB sticks and tunnelled over BLE by
+//! some trainers
+//!
+//! A message starts with a sync byte, followed by the length of its data, its id and the data, and ends
+//! in the XOR of every byte before it.
+
+use crate::{KondisError, Result};
+
+/// Every message starts with this byte
+pub(crate) const SYNC: u8 = 0xA4;
+/// A data page a channel broadcasts, or received on a channel
+pub(crate) const BROADCAST_DATA: u8 = 0x4E;
+/// A data page the other end acknowledges receiving
+pub(crate) const ACKNOWLEDGED_DATA: u8 = 0x4F;
+
+/// An ANT message, of a USB stick or a trainer tunnelling ANT over BLE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Message {
+    pub id: u8,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(id: u8, data: impl Into<Vec<u8>>) -> Self {
+        Message {
+            id,
+            data: data.into(),
+        }
+    }
+
+    /// A data page, sent on `channel` as `id`, like `BROADCAST_DATA`
+    pub fn page(id: u8, channel: u8, page: &[u8; 8]) -> Self {
+        let mut data = Vec::with_capacity(9);
+        data.push(channel);
+        data.extend_from_slice(page);
+        Message { id, data }
+    }
+
+    /// The message as sent, framed and checksummed
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = vec![SYNC, self.data.len() as u8, self.id];
+        message.extend_from_slice(&self.data);
+        message.push(checksum(&message));
+        message
+    }
+
+    /// A whole message, as received
+    pub fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = || KondisError::InvalidData(format!("Invalid ANT message: {message:02x?}"));
+        let [SYNC, length, id, ref rest @ ..] = *message else {
+            return Err(invalid());
+        };
+        let [ref data @ .., last] = *rest else {
+            return Err(invalid());
+        };
+        if data.len() != usize::from(length) || last != checksum(&message[..message.len() - 1]) {
+            return Err(invalid());
+        }
+        Ok(Message::new(id, data))
+    }
+
+    /// The channel and data page of a broadcast or acknowledged data message, `None` for any other
+    pub fn data_page(&self) -> Option<(u8, &[u8])> {
+        match (self.id, &self.data[..]) {
+            (BROADCAST_DATA | ACKNOWLEDGED_DATA, [channel, page @ ..]) if page.len() == 8 => {
+                Some((*channel, page))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// Pull the first whole message out of the bytes received so far, dropping anything before its sync
+/// byte
+#[cfg(feature = "ant")]
+pub(crate) fn take_message(received: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = received.iter().position(|&byte| byte == SYNC)?;
+    received.drain(..start);
+    let length = usize::from(*received.get(1)?) + 4;
+    if received.len() < length {
+        return None;
+    }
+    Some(received.drain(..length).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() -> Result<()> {
+        let page = [0x31, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE8, 0x03];
+        let message = Message::page(ACKNOWLEDGED_DATA, 1, &page);
+        let encoded = message.encode();
+        assert_eq!(encoded[..4], [0xA4, 0x09, 0x4F, 0x01]);
+        assert_eq!(encoded.len(), 13);
+        let decoded = Message::decode(&encoded)?;
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.data_page(), Some((1, &page[..])));
+
+        let mut corrupt = encoded.clone();
+        corrupt[5] ^= 0x01;
+        assert!(Message::decode(&corrupt).is_err());
+        assert!(Message::decode(&encoded[..8]).is_err());
+        // a channel event, holding no page
+        let event = Message::new(0x40, [0x01, 0x01, 0x08]);
+        assert_eq!(Message::decode(&event.encode())?.data_page(), None);
+        Ok(())
+    }
+
+    #[cfg(feature = "ant")]
+    #[test]
+    fn test_take_message() {
+        let first = Message::new(0x6F, [0x20]).encode();
+        let second = Message::page(BROADCAST_DATA, 0, &[0x10; 8]).encode();
+        let mut received = vec![0x00, 0x00];
+        received.extend(&first);
+        received.extend(&second[..6]);
+        assert_eq!(take_message(&mut received), Some(first));
+        assert_eq!(take_message(&mut received), None);
+        received.extend(&second[6..]);
+        assert_eq!(take_message(&mut received), Some(second));
+        assert!(received.is_empty());
+    }
+}
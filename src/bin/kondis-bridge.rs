@@ -0,0 +1,117 @@
+//! Republish an ANT+ FE-C trainer as a Bluetooth FTMS indoor bike, for apps that only speak Bluetooth
+//!
+//! ```sh
+//! kondis-bridge [--name NAME] [--max-power WATTS] [STICK]
+//! ```
+//!
+//! `STICK` is the ANT USB stick to pair with the trainer through, `/dev/ttyUSB0` unless told otherwise.
+//! The bridge is advertised as "kondis bridge" unless named otherwise, and serves until interrupted or
+//! until the trainer is lost.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use futures::StreamExt;
+use kondis::bridge::Bridge;
+use kondis::devices::AntFecBike;
+use kondis::{CancellationToken, Equipment, FTMSData, KondisError, Result, ScanConfig};
+
+const DEFAULT_STICK: &str = "/dev/ttyUSB0";
+const DEFAULT_NAME: &str = "kondis bridge";
+/// The highest target power apps may set, as trainers tend to top out around it
+const DEFAULT_MAX_POWER: i16 = 2000;
+
+struct Options {
+    stick: PathBuf,
+    name: String,
+    max_power: i16,
+}
+
+fn options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options {
+        stick: PathBuf::from(DEFAULT_STICK),
+        name: DEFAULT_NAME.to_string(),
+        max_power: DEFAULT_MAX_POWER,
+    };
+    let missing = |option: &str| KondisError::InvalidArgument(format!("{option} needs a value"));
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--name" => options.name = args.next().ok_or_else(|| missing("--name"))?,
+            "--max-power" => {
+                let watts = args.next().ok_or_else(|| missing("--max-power"))?;
+                options.max_power = watts.parse().map_err(|_| {
+                    KondisError::InvalidArgument(format!("Invalid maximum power: {watts}"))
+                })?;
+            }
+            option if option.starts_with("--") => {
+                return Err(KondisError::InvalidArgument(format!(
+                    "Unknown option {option}"
+                )));
+            }
+            _ => options.stick = PathBuf::from(arg),
+        }
+    }
+    Ok(options)
+}
+
+async fn bridge(options: Options, shutdown: &CancellationToken) -> Result<()> {
+    let config = ScanConfig::new().port(&options.stick);
+    let mut trainer = AntFecBike::with_config(options.max_power, config, shutdown).await?;
+    trainer.connect().await?;
+    let data = trainer
+        .data_stream()
+        .await?
+        .map(|reading| FTMSData::from(reading.data));
+    let served = Bridge::new(shutdown)
+        .name(options.name)
+        .serve(&trainer, data)
+        .await;
+    let closed = trainer.close().await;
+    served.and(closed)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("usage: kondis-bridge [--name NAME] [--max-power WATTS] [STICK]");
+            return ExitCode::FAILURE;
+        }
+    };
+    let shutdown = CancellationToken::new();
+    let interrupted = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupted.cancel();
+        }
+    });
+    match bridge(options, &shutdown).await {
+        Ok(()) | Err(KondisError::Shutdown) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() -> Result<()> {
+        let args = ["--name", "garage", "/dev/ttyACM0", "--max-power", "1200"];
+        let parsed = options(args.into_iter().map(String::from))?;
+        assert_eq!(parsed.stick, PathBuf::from("/dev/ttyACM0"));
+        assert_eq!(parsed.name, "garage");
+        assert_eq!(parsed.max_power, 1200);
+
+        let defaults = options(std::iter::empty())?;
+        assert_eq!(defaults.stick, PathBuf::from(DEFAULT_STICK));
+        assert!(options(["--name"].into_iter().map(String::from)).is_err());
+        assert!(options(["--verbose"].into_iter().map(String::from)).is_err());
+        Ok(())
+    }
+}
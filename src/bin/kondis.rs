@@ -0,0 +1,388 @@
+//! Use equipment from the command line, without writing a program
+//!
+//! ```sh
+//! kondis scan
+//! kondis monitor [DEVICE]
+//! kondis erg --watts WATTS
+//! kondis workout FILE [--ftp WATTS]
+//! kondis record --out ride.fit
+//! ```
+//!
+//! `DEVICE` is one of the names of `Registry::names`, a generic FTMS bike unless told otherwise, and
+//! can be given to every command with `--device`. `--address` connects to the device with that
+//! address, as listed by `scan`, `--name` to a device whose name matches the pattern, see
+//! `ScanConfig::name_pattern`, and `--service` to a device advertising the service, like
+//! `--device debug-bike --service 0000fff0-0000-1000-8000-00805f9b34fb` to explore a new trainer.
+//! Every command but `scan` prints the data of the device until interrupted, or until the workout
+//! is over.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use futures::StreamExt;
+use kondis::workout::{self, Workout, WorkoutEvent, WorkoutPlayer};
+use kondis::{
+    CancellationToken, Equipment, ErgController, FTMSData, FitRecorder, KondisError, Registry,
+    Result, ScanConfig,
+};
+use uuid::Uuid;
+
+const USAGE: &str =
+    "usage: kondis [--device DEVICE] [--address ADDRESS] [--name PATTERN] [--service UUID]
+              [--max-level LEVEL] <command>
+
+commands:
+    scan                      list nearby devices
+    monitor [DEVICE]          print the data of a device
+    erg --watts WATTS         hold a target power
+    workout FILE [--ftp FTP]  ride a .erg, .mrc or .zwo workout
+    record --out FILE         record a ride to a FIT file";
+const DEFAULT_DEVICE: &str = "generic-ftms-bike";
+/// The highest level equipment gets set to, unless told otherwise
+const DEFAULT_MAX_LEVEL: i16 = 400;
+/// W/s, gentle enough for any trainer
+const ERG_RAMP: f64 = 25.;
+/// How long `scan` listens for advertisements
+const SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Scan,
+    Monitor,
+    Erg { watts: i16 },
+    Workout { file: PathBuf, ftp: Option<f64> },
+    Record { out: PathBuf },
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    device: String,
+    address: Option<String>,
+    name: Option<String>,
+    service: Option<Uuid>,
+    max_level: i16,
+}
+
+fn options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let invalid = |message: String| KondisError::InvalidArgument(message);
+    let mut command = None;
+    let mut positional = Vec::new();
+    let mut device = None;
+    let mut address = None;
+    let mut name = None;
+    let mut service = None;
+    let mut max_level = DEFAULT_MAX_LEVEL;
+    let mut watts = None;
+    let mut ftp = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            match command {
+                None => command = Some(arg),
+                Some(_) => positional.push(arg),
+            }
+            continue;
+        }
+        let Some(value) = args.next() else {
+            return Err(invalid(format!("{arg} needs a value")));
+        };
+        match &arg[..] {
+            "--device" => device = Some(value),
+            "--address" => address = Some(value),
+            "--name" => name = Some(value),
+            "--service" => {
+                let uuid = Uuid::parse_str(&value);
+                service = Some(uuid.map_err(|_| invalid(format!("Invalid {arg} {value}")))?);
+            }
+            "--max-level" => max_level = number(&arg, &value)?,
+            "--watts" => watts = Some(number(&arg, &value)?),
+            "--ftp" => ftp = Some(number(&arg, &value)?),
+            "--out" => out = Some(PathBuf::from(value)),
+            _ => return Err(invalid(format!("Unknown option {arg}"))),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let command = match command.as_deref() {
+        Some("scan") => Command::Scan,
+        Some("monitor") => {
+            device = positional.next().or(device);
+            Command::Monitor
+        }
+        Some("erg") => Command::Erg {
+            watts: watts.ok_or_else(|| invalid("erg needs --watts".to_string()))?,
+        },
+        Some("workout") => Command::Workout {
+            file: positional
+                .next()
+                .map(PathBuf::from)
+                .ok_or_else(|| invalid("workout needs a file".to_string()))?,
+            ftp,
+        },
+        Some("record") => Command::Record {
+            out: out.ok_or_else(|| invalid("record needs --out".to_string()))?,
+        },
+        Some(command) => return Err(invalid(format!("Unknown command {command}"))),
+        None => return Err(invalid("No command given".to_string())),
+    };
+    if let Some(arg) = positional.next() {
+        return Err(invalid(format!("Unexpected argument {arg}")));
+    }
+    Ok(Options {
+        command,
+        device: device.unwrap_or_else(|| DEFAULT_DEVICE.to_string()),
+        address,
+        name,
+        service,
+        max_level,
+    })
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| KondisError::InvalidArgument(format!("Invalid {option}: {value}")))
+}
+
+fn load_workout(file: &PathBuf) -> Result<Workout> {
+    let text = std::fs::read_to_string(file)?;
+    match file.extension().and_then(|extension| extension.to_str()) {
+        #[cfg(feature = "zwo")]
+        Some("zwo") => workout::parse_zwo(&text),
+        #[cfg(not(feature = "zwo"))]
+        Some("zwo") => Err(KondisError::Unsupported(
+            "Zwift workouts need the zwo feature".to_string(),
+        )),
+        _ => workout::parse_erg(&text),
+    }
+}
+
+/// The fields of `data` the device reported, on one line
+fn line(data: &FTMSData) -> String {
+    let fields = [
+        data.power.map(|watts| format!("{watts} W")),
+        data.cadence.map(|rpm| format!("{rpm:.0} rpm")),
+        data.speed.map(|speed| format!("{speed:.1} km/h")),
+        data.heart_rate.map(|bpm| format!("{bpm:.0} bpm")),
+        data.distance.map(|km| format!("{km:.2} km")),
+    ];
+    fields.into_iter().flatten().collect::<Vec<_>>().join("  ")
+}
+
+/// Print the data of `equipment` until `done` gets cancelled, recording it to `recorder` if given
+async fn monitor<E: Equipment + ?Sized>(
+    equipment: &E,
+    mut recorder: Option<&mut FitRecorder>,
+    done: &CancellationToken,
+) -> Result<()> {
+    let mut readings = equipment
+        .data_stream()
+        .await?
+        .take_until(done.cancelled())
+        .boxed();
+    while let Some(reading) = readings.next().await {
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.record(&reading);
+        }
+        println!("{}", line(&FTMSData::from(reading.data)));
+    }
+    Ok(())
+}
+
+async fn ride<E: Equipment + ?Sized>(
+    player: &WorkoutPlayer,
+    equipment: &E,
+    done: &CancellationToken,
+) -> Result<()> {
+    let mut events = player.events();
+    let announce = async {
+        while let Ok(event) = events.recv().await {
+            match event {
+                WorkoutEvent::BlockStarted { index } => println!("Block {}", index + 1),
+                WorkoutEvent::Message(text) => println!("{text}"),
+                WorkoutEvent::Paused => println!("Paused"),
+                WorkoutEvent::Resumed => println!("Resumed"),
+                WorkoutEvent::Finished => println!("Finished"),
+                WorkoutEvent::Progress { .. } => {}
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = player.run(equipment, done) => result,
+        _ = announce => Ok(()),
+    };
+    done.cancel();
+    result
+}
+
+async fn run(options: Options, shutdown: &CancellationToken) -> Result<()> {
+    if options.command == Command::Scan {
+        for device in kondis::scan(ScanConfig::new().timeout(SCAN_TIMEOUT)).await? {
+            let name = device.name.as_deref().unwrap_or("unnamed");
+            let rssi = device
+                .rssi
+                .map(|rssi| format!("{rssi} dBm"))
+                .unwrap_or_default();
+            match device.probable_type {
+                Some(probable_type) => {
+                    println!("{}  {name}  {rssi}  {probable_type:?}", device.address)
+                }
+                None => println!("{}  {name}  {rssi}", device.address),
+            }
+        }
+        return Ok(());
+    }
+
+    let mut config = ScanConfig::new();
+    if let Some(address) = &options.address {
+        config = config.address(address);
+    }
+    if let Some(name) = &options.name {
+        config = config.name_pattern(name);
+    }
+    if let Some(service) = options.service {
+        config = config.service_uuid(service);
+    }
+    let mut equipment = Registry::default()
+        .create(&options.device, options.max_level, config, shutdown)
+        .await?;
+    equipment.connect().await?;
+    let equipment = &*equipment;
+    let done = shutdown.child_token();
+    let result = match options.command {
+        Command::Scan | Command::Monitor => monitor(equipment, None, &done).await,
+        Command::Erg { watts } => {
+            let erg = ErgController::new(ERG_RAMP);
+            erg.set_target_power(watts);
+            let (held, monitored) =
+                tokio::join!(erg.run(equipment, &done), monitor(equipment, None, &done));
+            held.and(monitored)
+        }
+        Command::Workout { file, ftp } => {
+            let mut player = WorkoutPlayer::new(load_workout(&file)?);
+            if let Some(ftp) = ftp {
+                player = player.ftp(ftp);
+            }
+            let (ridden, monitored) = tokio::join!(
+                ride(&player, equipment, &done),
+                monitor(equipment, None, &done)
+            );
+            ridden.and(monitored)
+        }
+        Command::Record { out } => {
+            let mut recorder = FitRecorder::new();
+            let monitored = monitor(equipment, Some(&mut recorder), &done).await;
+            std::fs::write(&out, recorder.to_bytes())?;
+            println!("Recorded to {}", out.display());
+            monitored
+        }
+    };
+    let closed = equipment.close().await;
+    result.and(closed)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let shutdown = CancellationToken::new();
+    let interrupted = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupted.cancel();
+        }
+    });
+    match run(options, &shutdown).await {
+        Ok(()) | Err(KondisError::Shutdown) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_options() -> Result<()> {
+        let monitor = options(args(&[
+            "monitor",
+            "kickr-bike",
+            "--address",
+            "C0:FF:EE:00:00:01",
+        ]))?;
+        assert_eq!(monitor.command, Command::Monitor);
+        assert_eq!(monitor.device, "kickr-bike");
+        assert_eq!(monitor.address.as_deref(), Some("C0:FF:EE:00:00:01"));
+
+        let erg = options(args(&[
+            "--device",
+            "simulator-bike",
+            "erg",
+            "--watts",
+            "200",
+        ]))?;
+        assert_eq!(erg.command, Command::Erg { watts: 200 });
+        assert_eq!(erg.device, "simulator-bike");
+        assert_eq!(erg.max_level, DEFAULT_MAX_LEVEL);
+
+        let workout = options(args(&["workout", "sweet spot.erg", "--ftp", "250"]))?;
+        assert_eq!(
+            workout.command,
+            Command::Workout {
+                file: PathBuf::from("sweet spot.erg"),
+                ftp: Some(250.)
+            }
+        );
+        assert_eq!(workout.device, DEFAULT_DEVICE);
+
+        let debug = options(args(&[
+            "--device",
+            "debug-bike",
+            "--name",
+            "KICKR*",
+            "--service",
+            "00001826-0000-1000-8000-00805f9b34fb",
+            "monitor",
+        ]))?;
+        assert_eq!(debug.name.as_deref(), Some("KICKR*"));
+        assert!(debug.service.is_some());
+        assert!(options(args(&["monitor", "--service", "1826"])).is_err());
+
+        assert!(options(args(&["erg"])).is_err());
+        assert!(options(args(&["record"])).is_err());
+        assert!(options(args(&["scan", "--watts"])).is_err());
+        assert!(options(args(&["scan", "extra"])).is_err());
+        assert!(options(args(&["spin"])).is_err());
+        assert!(options(args(&[])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_line() {
+        let data = FTMSData {
+            power: Some(210),
+            cadence: Some(88.4),
+            heart_rate: Some(142.),
+            ..Default::default()
+        };
+        assert_eq!(line(&data), "210 W  88 rpm  142 bpm");
+        assert_eq!(line(&FTMSData::default()), "");
+    }
+}
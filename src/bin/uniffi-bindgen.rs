@@ -0,0 +1,5 @@
+//! Generates the Kotlin and Swift bindings of `kondis::ffi` from the library built with the `uniffi`
+//! feature, like `uniffi-bindgen generate --library target/release/libkondis.so --language kotlin`
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
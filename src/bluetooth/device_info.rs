@@ -0,0 +1,74 @@
+#[cfg(not(target_arch = "wasm32"))]
+use btleplug::platform::Peripheral;
+use uuid::Uuid;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::bluetooth::Transport;
+use crate::ftms::uuid_from_u16;
+
+/// Manufacturer Name String characteristic of the Device Information Service
+const MANUFACTURER_NAME_UUID: Uuid = uuid_from_u16(0x2A29);
+/// Model Number String characteristic of the Device Information Service
+const MODEL_NUMBER_UUID: Uuid = uuid_from_u16(0x2A24);
+/// Serial Number String characteristic of the Device Information Service
+const SERIAL_NUMBER_UUID: Uuid = uuid_from_u16(0x2A25);
+/// Firmware Revision String characteristic of the Device Information Service
+const FIRMWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A26);
+
+/// What a device tells about itself through the Device Information Service, see
+/// `Equipment::device_info`
+///
+/// Every field is `None` when the device doesn't have its characteristic, or leaves it empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub manufacturer: Option<String>,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub firmware_revision: Option<String>,
+}
+
+/// Read the Device Information Service of a connected peripheral
+///
+/// Resolves to `None` when the peripheral doesn't have any of its characteristics. Characteristics
+/// that can't be read are left out rather than failing, as plenty of devices fill in only some of them.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn read_device_info(peripheral: &Peripheral) -> Option<DeviceInfo> {
+    read_info(peripheral).await
+}
+
+/// Like `read_device_info`, through any transport
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn read_info(peripheral: &impl Transport) -> Option<DeviceInfo> {
+    let read = async |uuid| {
+        let characteristic = peripheral.characteristic(uuid)?;
+        text(&peripheral.read(&characteristic).await.ok()?)
+    };
+    let info = DeviceInfo {
+        manufacturer: read(MANUFACTURER_NAME_UUID).await,
+        model_number: read(MODEL_NUMBER_UUID).await,
+        serial_number: read(SERIAL_NUMBER_UUID).await,
+        firmware_revision: read(FIRMWARE_REVISION_UUID).await,
+    };
+    (info != DeviceInfo::default()).then_some(info)
+}
+
+/// The string value of a characteristic, which some devices pad with nul characters
+fn text(value: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(value);
+    let text = text.trim_end_matches('\0').trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        assert_eq!(text(b"Wahoo Fitness"), Some("Wahoo Fitness".to_string()));
+        assert_eq!(text(b"4.2.1 \0\0\0"), Some("4.2.1".to_string()));
+        assert_eq!(text(b"\0\0"), None);
+        assert_eq!(text(b""), None);
+    }
+}
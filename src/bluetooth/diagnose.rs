@@ -0,0 +1,96 @@
+use btleplug::api::{Central as _, CentralState};
+use btleplug::platform::Adapter;
+
+use crate::bluetooth::{ScanConfig, adapters};
+use crate::{KondisError, Result};
+
+/// Check that Bluetooth is usable, before scanning for equipment
+///
+/// Resolves to the description of every adapter `config` would scan with, see `list_adapters`. Fails
+/// with an error telling what to fix when there is no adapter, an adapter is powered off, or the
+/// process isn't allowed to use Bluetooth. Scanning runs the same checks, so this is for reporting
+/// problems up front, like when an app starts.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{KondisError, ScanConfig, bluetooth};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     match bluetooth::diagnose(&ScanConfig::new()).await {
+///         Ok(adapters) => println!("ready to scan with {}", adapters.join(", ")),
+///         Err(KondisError::AdapterPoweredOff(_)) => println!("turn Bluetooth on to find your bike"),
+///         Err(e) => println!("{e}"),
+///     }
+/// }
+/// ```
+pub async fn diagnose(config: &ScanConfig) -> Result<Vec<String>> {
+    let mut infos = Vec::new();
+    for adapter in adapters(config).await? {
+        infos.push(adapter.adapter_info().await.map_err(classify)?);
+    }
+    Ok(infos)
+}
+
+/// Fail when the adapter is known to be powered off
+///
+/// Platforms that can't tell the state of an adapter are given the benefit of the doubt.
+pub(crate) async fn check_powered(adapter: &Adapter) -> Result<()> {
+    match adapter.adapter_state().await {
+        Ok(CentralState::PoweredOff) => {
+            let info = adapter.adapter_info().await.unwrap_or_default();
+            Err(KondisError::AdapterPoweredOff(format!(
+                "{info} is powered off, {POWER_ON}"
+            )))
+        }
+        Ok(_) | Err(btleplug::Error::NotSupported(_)) => Ok(()),
+        Err(e) => Err(classify(e)),
+    }
+}
+
+/// Turn an error of the Bluetooth stack about the adapters into one telling what to do about it
+pub(crate) fn classify(e: btleplug::Error) -> KondisError {
+    match e {
+        btleplug::Error::PermissionDenied => KondisError::PermissionDenied(PERMISSION.to_string()),
+        e => KondisError::BluetoothUnavailable(format!("{e}, {UNAVAILABLE}")),
+    }
+}
+
+#[cfg(target_os = "linux")]
+const POWER_ON: &str =
+    "turn it on with `bluetoothctl power on`, after `rfkill unblock bluetooth` if it is blocked";
+#[cfg(not(target_os = "linux"))]
+const POWER_ON: &str = "turn Bluetooth on in the settings of the system";
+
+#[cfg(target_os = "linux")]
+const PERMISSION: &str = "BlueZ refused access, run as a user in the `bluetooth` group or allowed by the D-Bus policy of bluetoothd";
+#[cfg(target_os = "macos")]
+const PERMISSION: &str = "allow the app, or the terminal running it, to use Bluetooth under System Settings > Privacy & Security > Bluetooth";
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const PERMISSION: &str = "allow the app to use Bluetooth in the privacy settings of the system";
+
+#[cfg(target_os = "linux")]
+const UNAVAILABLE: &str =
+    "check that bluetoothd is running, like with `systemctl status bluetooth`";
+#[cfg(not(target_os = "linux"))]
+const UNAVAILABLE: &str = "check that the system has a Bluetooth adapter and that it is enabled";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert!(matches!(
+            classify(btleplug::Error::PermissionDenied),
+            KondisError::PermissionDenied(_)
+        ));
+        let KondisError::BluetoothUnavailable(message) =
+            classify(btleplug::Error::RuntimeError("no D-Bus".to_string()))
+        else {
+            panic!("expected the Bluetooth to be unavailable");
+        };
+        assert!(message.starts_with("Runtime Error: no D-Bus, "));
+    }
+}
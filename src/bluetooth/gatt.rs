@@ -0,0 +1,171 @@
+use btleplug::{
+    api::{CharPropFlags, Characteristic, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::bluetooth::{Timeouts, Transport, within};
+use crate::{KondisError, RawStream, Result};
+
+/// Connect to the peripheral unless already connected, and discover its services
+///
+/// The characteristics of the peripheral can only be looked up once this is done. Connecting and
+/// discovering fail with `KondisError::ConnectTimeout` and `KondisError::DiscoveryTimeout` once they
+/// take longer than the defaults of `ScanConfig`.
+pub async fn connect(peripheral: &Peripheral) -> Result<()> {
+    connect_within(peripheral, Timeouts::default()).await
+}
+
+/// Like `connect`, with the timeouts picked through `ScanConfig`
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "warn"), fields(address = %peripheral.address()))
+)]
+pub(crate) async fn connect_within(peripheral: &impl Transport, timeouts: Timeouts) -> Result<()> {
+    debug!(address = %peripheral.address(), "Connecting");
+    within(timeouts.connect, KondisError::ConnectTimeout, async {
+        if !peripheral.is_connected().await? {
+            peripheral.connect().await?;
+        }
+        Ok(())
+    })
+    .await?;
+    within(timeouts.discovery, KondisError::DiscoveryTimeout, async {
+        peripheral.discover_services().await
+    })
+    .await?;
+    debug!(
+        address = %peripheral.address(),
+        "Connected with {} characteristics",
+        peripheral.characteristics().len()
+    );
+    Ok(())
+}
+
+/// Look up a characteristic of a connected peripheral by its UUID
+pub fn find_characteristic(peripheral: &Peripheral, uuid: Uuid) -> Option<Characteristic> {
+    peripheral.characteristic(uuid)
+}
+
+/// Subscribe to the notifications or indications of a characteristic of a connected peripheral
+///
+/// Fails if the peripheral doesn't have the characteristic.
+pub async fn subscribe(peripheral: &Peripheral, uuid: Uuid) -> Result<Characteristic> {
+    subscribe_to(peripheral, uuid).await
+}
+
+/// Like `subscribe`, through any transport
+pub(crate) async fn subscribe_to(
+    peripheral: &impl Transport,
+    uuid: Uuid,
+) -> Result<Characteristic> {
+    let Some(characteristic) = peripheral.characteristic(uuid) else {
+        return Err(KondisError::CharacteristicMissing(uuid.to_string()));
+    };
+    peripheral.subscribe(&characteristic).await?;
+    debug!(address = %peripheral.address(), uuid = %uuid, "Subscribed");
+    Ok(characteristic)
+}
+
+/// Every notification of one characteristic received from now on
+///
+/// The characteristic has to be subscribed to for any notifications to arrive, see `subscribe`.
+pub async fn notifications(peripheral: &Peripheral, uuid: Uuid) -> Result<RawStream> {
+    notifications_of(peripheral, uuid).await
+}
+
+/// Like `notifications`, through any transport
+pub(crate) async fn notifications_of(peripheral: &impl Transport, uuid: Uuid) -> Result<RawStream> {
+    let notifications = peripheral.notifications().await?;
+    Ok(Box::pin(notifications.filter_map(move |data| async move {
+        (data.uuid == uuid).then_some(data.value)
+    })))
+}
+
+/// How a raw write goes to the characteristic, with a response unless it can only be written without
+pub(crate) fn write_type(characteristic: &Characteristic) -> WriteType {
+    if characteristic.properties.contains(CharPropFlags::WRITE) {
+        WriteType::WithResponse
+    } else {
+        WriteType::WithoutResponse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{Operation, RecordingTransport};
+    use crate::ftms::{CONTROL_POINT_UUID, INDOOR_BIKE_DATA_UUID, SUPPORTED_POWER_RANGE_UUID};
+
+    #[tokio::test]
+    async fn test_connect_and_subscribe() -> Result<()> {
+        let transport = RecordingTransport::new();
+        transport.fail_connects(1);
+        assert!(
+            connect_within(&transport, Timeouts::default())
+                .await
+                .is_err()
+        );
+        connect_within(&transport, Timeouts::default()).await?;
+        // already connected
+        connect_within(&transport, Timeouts::default()).await?;
+
+        let data = subscribe_to(&transport, INDOOR_BIKE_DATA_UUID).await?;
+        assert_eq!(data.uuid, INDOOR_BIKE_DATA_UUID);
+        assert!(matches!(
+            subscribe_to(&transport, SUPPORTED_POWER_RANGE_UUID).await,
+            Err(KondisError::CharacteristicMissing(_))
+        ));
+        assert_eq!(
+            transport.operations(),
+            [
+                Operation::Connect,
+                Operation::Connect,
+                Operation::Subscribe(INDOOR_BIKE_DATA_UUID),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_notifications() -> Result<()> {
+        let transport = RecordingTransport::new();
+        transport.connect().await?;
+        let mut values = notifications_of(&transport, INDOOR_BIKE_DATA_UUID).await?;
+        subscribe_to(&transport, INDOOR_BIKE_DATA_UUID).await?;
+        transport.notify(INDOOR_BIKE_DATA_UUID, &[0x01]);
+        transport.notify(CONTROL_POINT_UUID, &[0x80, 0x00, 0x01]);
+        transport.notify(INDOOR_BIKE_DATA_UUID, &[0x02, 0x03]);
+        // only the values of the characteristic, as they are, until disconnecting
+        let received: Vec<Vec<u8>> = values.by_ref().take(2).collect().await;
+        assert_eq!(received, [vec![0x01], vec![0x02, 0x03]]);
+        transport.disconnect().await?;
+        assert_eq!(values.next().await, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_type() {
+        let characteristic = |properties| Characteristic {
+            uuid: CONTROL_POINT_UUID,
+            service_uuid: Uuid::nil(),
+            properties,
+            descriptors: Default::default(),
+        };
+        assert_eq!(
+            write_type(&characteristic(CharPropFlags::WRITE)),
+            WriteType::WithResponse
+        );
+        assert_eq!(
+            write_type(&characteristic(
+                CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE
+            )),
+            WriteType::WithResponse
+        );
+        assert_eq!(
+            write_type(&characteristic(CharPropFlags::WRITE_WITHOUT_RESPONSE)),
+            WriteType::WithoutResponse
+        );
+    }
+}
@@ -1,54 +1,192 @@
-use std::sync::mpsc::Receiver;
+mod device_info;
+mod diagnose;
+mod gatt;
+mod scan;
+mod scan_config;
+mod timeouts;
+mod transport;
 
+use crate::runtime::Instant;
 use btleplug::{
-    api::{Central as _, CentralEvent, Manager as _, Peripheral as _, ScanFilter},
-    platform::{Manager, Peripheral},
+    api::{
+        Central as _, CentralEvent, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter,
+    },
+    platform::{Adapter, Manager},
 };
 use futures::StreamExt as _;
+use tokio_util::sync::CancellationToken;
 
-use crate::EquipmentType;
+use crate::ftms::{FITNESS_MACHINE_SERVICE_UUID, machine_type};
+use crate::{EquipmentType, KondisError, Result};
+pub use btleplug::{api::Characteristic, platform::Peripheral};
+pub(crate) use device_info::read_info;
+pub use device_info::{DeviceInfo, read_device_info};
+pub use diagnose::diagnose;
+pub use gatt::{connect, find_characteristic, notifications, subscribe};
+pub(crate) use gatt::{connect_within, subscribe_to, write_type};
+pub use scan::{DiscoveredDevice, scan};
+pub use scan_config::ScanConfig;
+pub(crate) use timeouts::{Timeouts, within};
+pub(crate) use transport::{NotificationStream, Transport};
 
-/// Get a Bluetooth peripheral for the specified equipment type
+/// Scan for a peripheral of the specified equipment type, narrowed down by `config`
+///
+/// Resolves to the peripheral and its name, or its address if it doesn't advertise a name, and to
+/// `None` when `shutdown` gets cancelled before one is found.
 pub async fn get_peripheral(
     equipment_type: EquipmentType,
-    shutdown_rx: &mut Receiver<()>,
-) -> anyhow::Result<Option<(Peripheral, String)>> {
-    let manager = Manager::new().await.unwrap();
-    let adapters = manager.adapters().await?;
-    let mut events = Vec::new();
-    let mut peripheral_meta: Option<(Peripheral, String)> = None;
+    config: &ScanConfig,
+    shutdown: &CancellationToken,
+) -> Result<Option<(Peripheral, String)>> {
+    let contains_predicate = match (&config.name_pattern, &equipment_type) {
+        (Some(pattern), _) => pattern.as_str(),
+        (None, EquipmentType::Iconsole0028Bike) => "iConsole+0028",
+        (None, EquipmentType::Iconsole0010Bike) => "iConsole+0010",
+        (None, EquipmentType::Iconsole0051Bike) => "iConsole+0051",
+        // pointed at a service, the debug bike takes any device advertising it
+        (None, EquipmentType::DebugBike) if config.service_uuid.is_some() => "*",
+        (None, EquipmentType::DebugBike) => "Console",
+        (None, EquipmentType::KeiserM3iBike) => "M3",
+        (None, EquipmentType::EchelonBike) => "ECH",
+        (None, EquipmentType::KickrBike) => "KICKR",
+        (None, EquipmentType::TacxFecBike) => "Tacx",
+        (None, EquipmentType::Concept2Pm5Rower) => "PM5",
+        (None, EquipmentType::ZwiftController) => "Zwift",
+        _ => "bike",
+    };
+    // generic devices are matched on the services they advertise rather than their name
+    let service_predicate = match equipment_type {
+        EquipmentType::GenericFtmsBike => Some(machine_type::INDOOR_BIKE),
+        EquipmentType::GenericFtmsTreadmill => Some(machine_type::TREADMILL),
+        EquipmentType::GenericFtmsRower => Some(machine_type::ROWER),
+        EquipmentType::GenericFtmsCrossTrainer => Some(machine_type::CROSS_TRAINER),
+        _ => None,
+    };
 
-    for adapter in &adapters {
-        events.push(adapter.events().await?);
-        adapter.start_scan(ScanFilter::default()).await?;
+    find_matching(
+        config,
+        scan_filter(config, equipment_type),
+        shutdown,
+        |properties| is_match(properties, config, service_predicate, contains_predicate),
+    )
+    .await
+}
+
+/// The services the platform only reports advertisements of while scanning for the equipment type
+///
+/// Fitness machines are scanned for by the Fitness Machine Service, so other devices are filtered out
+/// before their names are even looked at, unless the config asks for another service. A known address
+/// needs no filtering, and may belong to a machine that leaves the service out of its advertisement.
+fn scan_filter(config: &ScanConfig, equipment_type: EquipmentType) -> ScanFilter {
+    let ftms = matches!(
+        equipment_type,
+        EquipmentType::GenericFtmsBike
+            | EquipmentType::GenericFtmsTreadmill
+            | EquipmentType::GenericFtmsRower
+            | EquipmentType::GenericFtmsCrossTrainer
+            | EquipmentType::Iconsole0028Bike
+            | EquipmentType::Iconsole0010Bike
+            | EquipmentType::Iconsole0051Bike
+    );
+    let service = match config.service_uuid {
+        Some(uuid) => Some(uuid),
+        None if ftms && config.address.is_none() => Some(FITNESS_MACHINE_SERVICE_UUID),
+        None => None,
+    };
+    ScanFilter {
+        services: service.into_iter().collect(),
     }
+}
 
-    let contains_predicate = match equipment_type {
-        EquipmentType::Iconsole0028Bike => "iConsole+0028",
-        EquipmentType::DebugBike => "Console",
-        _ => "bike",
+/// Scan for the first peripheral matching `config`, for equipment implemented outside of this crate
+///
+/// Unlike `get_peripheral`, nothing but the options of `config` is looked at, so a name pattern, an
+/// address or a service is usually wanted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{bluetooth, CancellationToken, ScanConfig};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let config = ScanConfig::new().name_pattern("MyBike");
+///     if let Some((peripheral, name)) = bluetooth::find_peripheral(&config, &shutdown).await? {
+///         bluetooth::connect(&peripheral).await?;
+///         println!("connected to {name}");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn find_peripheral(
+    config: &ScanConfig,
+    shutdown: &CancellationToken,
+) -> Result<Option<(Peripheral, String)>> {
+    let filter = ScanFilter {
+        services: config.service_uuid.into_iter().collect(),
     };
+    find_matching(config, filter, shutdown, |properties| {
+        matches_config(properties, config)
+    })
+    .await
+}
 
-    if shutdown_rx.try_recv().is_ok() {
-        return Ok(peripheral_meta);
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "warn"), fields(name = ?config.name_pattern))
+)]
+async fn find_matching(
+    config: &ScanConfig,
+    filter: ScanFilter,
+    shutdown: &CancellationToken,
+    is_match: impl Fn(&PeripheralProperties) -> bool,
+) -> Result<Option<(Peripheral, String)>> {
+    let adapters = adapters(config).await?;
+    if let Some(address) = &config.address
+        && let Some(known) = find_known_peripheral(&adapters, address).await?
+    {
+        return Ok(Some(known));
+    }
+    let mut events = Vec::new();
+    let mut peripheral_meta: Option<(Peripheral, String)> = None;
+
+    for (index, adapter) in adapters.iter().enumerate() {
+        // remember which adapter saw each event, so the peripheral gets looked up on the right one
+        events.push(adapter.events().await?.map(move |event| (index, event)));
+        adapter
+            .start_scan(filter.clone())
+            .await
+            .map_err(diagnose::classify)?;
     }
+    debug!(services = ?filter.services, "Scanning on {} adapters", adapters.len());
 
-    while let Some(event) = futures::stream::iter(events.iter_mut())
-        .flatten()
-        .next()
-        .await
-    {
-        if shutdown_rx.try_recv().is_ok() {
+    let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+    let mut events = futures::stream::select_all(events);
+    let mut timed_out = false;
+    loop {
+        let event = tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            _ = sleep_until_deadline(deadline) => {
+                timed_out = true;
+                break;
+            }
+            event = events.next() => event,
+        };
+        let Some((index, event)) = event else {
             break;
-        }
+        };
         if let CentralEvent::DeviceDiscovered(id) = event {
-            let central = adapters.get(1).unwrap();
-            let peripheral = central.peripheral(&id).await?;
-            if let Some(name) = peripheral.properties().await.unwrap().unwrap().local_name
-                // todo: make this configurable ()
-                && name.contains(contains_predicate)
-            {
-                peripheral_meta = Some((peripheral, name.to_string()));
+            let peripheral = adapters[index].peripheral(&id).await?;
+            let properties = peripheral.properties().await?.unwrap_or_default();
+            trace!(address = %properties.address, name = ?properties.local_name, "Discovered");
+            if is_match(&properties) {
+                let name = properties
+                    .local_name
+                    .unwrap_or_else(|| properties.address.to_string());
+                info!(address = %properties.address, device = %name, "Found");
+                peripheral_meta = Some((peripheral, name));
                 break;
             }
         }
@@ -57,5 +195,337 @@ pub async fn get_peripheral(
         adapter.stop_scan().await?;
     }
 
+    if timed_out && let Some(timeout) = config.timeout {
+        debug!("Scanning timed out after {timeout:?}");
+        return Err(KondisError::ScanTimeout(timeout));
+    }
     Ok(peripheral_meta)
 }
+
+/// List the Bluetooth adapters of the system, in the order `ScanConfig::adapter_index` picks from
+///
+/// Every adapter is described the way the platform names it, like `"hci0 (usb:v1D6Bp0246d0537)"` on
+/// Linux, for `ScanConfig::adapter_name` to match.
+///
+/// # Examples
+///
+/// ```no_run
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     for (index, adapter) in kondis::bluetooth::list_adapters().await?.iter().enumerate() {
+///         println!("{index}: {adapter}");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn list_adapters() -> Result<Vec<String>> {
+    let mut infos = Vec::new();
+    for adapter in all_adapters().await? {
+        infos.push(adapter.adapter_info().await.map_err(diagnose::classify)?);
+    }
+    Ok(infos)
+}
+
+async fn all_adapters() -> Result<Vec<Adapter>> {
+    let manager = Manager::new().await.map_err(diagnose::classify)?;
+    manager.adapters().await.map_err(diagnose::classify)
+}
+
+/// The adapters to scan with, all of them unless the config picks one
+///
+/// Fails when any of them is powered off, rather than when scanning with it.
+pub(crate) async fn adapters(config: &ScanConfig) -> Result<Vec<Adapter>> {
+    let adapters = all_adapters().await?;
+    let mut infos = Vec::with_capacity(adapters.len());
+    if config.adapter_name.is_some() {
+        for adapter in &adapters {
+            infos.push(adapter.adapter_info().await?);
+        }
+    }
+    let picked = pick_adapters(adapters.len(), &infos, config)?;
+    let adapters: Vec<_> = adapters
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| picked.contains(index))
+        .map(|(_, adapter)| adapter)
+        .collect();
+    for adapter in &adapters {
+        diagnose::check_powered(adapter).await?;
+    }
+    Ok(adapters)
+}
+
+/// The indices of the adapters the config picks out of `count`, described by `infos` when it picks one
+/// by name
+///
+/// An adapter picked by both name and index has to match both.
+pub(crate) fn pick_adapters(
+    count: usize,
+    infos: &[String],
+    config: &ScanConfig,
+) -> Result<Vec<usize>> {
+    if count == 0 {
+        return Err(KondisError::BluetoothUnavailable(
+            "No adapter found".to_string(),
+        ));
+    }
+    let mut picked: Vec<usize> = (0..count).collect();
+    if let Some(name) = &config.adapter_name {
+        let lowercase = name.to_lowercase();
+        picked.retain(|index| {
+            infos
+                .get(*index)
+                .is_some_and(|info| info.to_lowercase().contains(&lowercase))
+        });
+        if picked.is_empty() {
+            return Err(KondisError::BluetoothUnavailable(format!(
+                "No adapter named {} (found {})",
+                name,
+                infos.join(", ")
+            )));
+        }
+    }
+    if let Some(index) = config.adapter_index {
+        if index >= count {
+            return Err(KondisError::BluetoothUnavailable(format!(
+                "No adapter at index {index} ({count} found)"
+            )));
+        }
+        if !picked.contains(&index) {
+            return Err(KondisError::BluetoothUnavailable(format!(
+                "The adapter at index {index} isn't named {}",
+                config.adapter_name.as_deref().unwrap_or_default()
+            )));
+        }
+        picked = vec![index];
+    }
+    Ok(picked)
+}
+
+/// Look for a peripheral the adapters already know about, like one that is bonded or was seen
+/// recently, so it can be connected to without scanning
+async fn find_known_peripheral(
+    adapters: &[Adapter],
+    address: &str,
+) -> Result<Option<(Peripheral, String)>> {
+    for adapter in adapters {
+        if let Some(peripheral) = with_address(adapter.peripherals().await?, address) {
+            let name = peripheral
+                .properties()
+                .await?
+                .and_then(|properties| properties.local_name)
+                .unwrap_or_else(|| address.to_string());
+            return Ok(Some((peripheral, name)));
+        }
+    }
+    Ok(None)
+}
+
+/// The peripheral at `address`, in whichever case it's written
+fn with_address<T: Transport>(
+    peripherals: impl IntoIterator<Item = T>,
+    address: &str,
+) -> Option<T> {
+    peripherals
+        .into_iter()
+        .find(|peripheral| peripheral.address().eq_ignore_ascii_case(address))
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => crate::runtime::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Check whether the advertised properties describe the device looked for
+pub(crate) fn is_match(
+    properties: &PeripheralProperties,
+    config: &ScanConfig,
+    service_predicate: Option<u16>,
+    contains_predicate: &str,
+) -> bool {
+    // a known address is all that's needed to tell devices apart
+    if let Some(address) = &config.address {
+        return properties.address.to_string().eq_ignore_ascii_case(address);
+    }
+    if let Some(uuid) = &config.service_uuid
+        && !properties.services.contains(uuid)
+    {
+        return false;
+    }
+    let name_matches = properties
+        .local_name
+        .as_ref()
+        .is_some_and(|name| name_matches(name, contains_predicate));
+    match service_predicate {
+        // generic devices only need to match the name when asked to
+        Some(machine_type) => {
+            is_fitness_machine(properties, machine_type)
+                && (config.name_pattern.is_none() || name_matches)
+        }
+        None => name_matches,
+    }
+}
+
+/// Check whether the advertised properties match every option of the config
+fn matches_config(properties: &PeripheralProperties, config: &ScanConfig) -> bool {
+    if let Some(address) = &config.address
+        && !properties.address.to_string().eq_ignore_ascii_case(address)
+    {
+        return false;
+    }
+    if let Some(uuid) = &config.service_uuid
+        && !properties.services.contains(uuid)
+    {
+        return false;
+    }
+    if let Some(pattern) = &config.name_pattern {
+        return properties
+            .local_name
+            .as_ref()
+            .is_some_and(|name| name_matches(name, pattern));
+    }
+    true
+}
+
+/// Check whether an advertised name matches a name pattern, see `ScanConfig::name_pattern`
+///
+/// Patterns with wildcards match the whole name, `*` standing for any run of characters and `?` for
+/// any one character. Patterns without them match any name containing them.
+fn name_matches(name: &str, pattern: &str) -> bool {
+    fn glob(name: &[char], pattern: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| glob(&name[skip..], rest)),
+            Some(('?', rest)) => !name.is_empty() && glob(&name[1..], rest),
+            Some((c, rest)) => name.first() == Some(c) && glob(&name[1..], rest),
+        }
+    }
+    if !pattern.contains(['*', '?']) {
+        return name.contains(pattern);
+    }
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    glob(&name, &pattern)
+}
+
+/// Check whether the advertised properties describe a fitness machine of the given type
+///
+/// Machines may advertise their type as service data of the Fitness Machine Service. Those that don't
+/// are assumed to be of the requested type as long as they advertise the service itself.
+fn is_fitness_machine(properties: &PeripheralProperties, machine_type: u16) -> bool {
+    if !properties.services.contains(&FITNESS_MACHINE_SERVICE_UUID) {
+        return false;
+    }
+    match properties.service_data.get(&FITNESS_MACHINE_SERVICE_UUID) {
+        // flags (uint8) followed by the fitness machine type (uint16)
+        Some(data) if data.len() >= 3 => u16::from_le_bytes([data[1], data[2]]) & machine_type != 0,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(name: &str, services: Vec<uuid::Uuid>) -> PeripheralProperties {
+        PeripheralProperties {
+            address: [0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x01].into(),
+            local_name: Some(name.to_string()),
+            services,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_match() {
+        let console = properties("iConsole+0028", Vec::new());
+        let trainer = properties("KICKR CORE", vec![FITNESS_MACHINE_SERVICE_UUID]);
+        let default = ScanConfig::new();
+        assert!(is_match(&console, &default, None, "iConsole+0028"));
+        assert!(!is_match(&trainer, &default, None, "iConsole+0028"));
+        assert!(is_match(
+            &trainer,
+            &default,
+            Some(machine_type::INDOOR_BIKE),
+            "bike"
+        ));
+
+        let by_name = ScanConfig::new().name_pattern("KICKR");
+        assert!(is_match(
+            &trainer,
+            &by_name,
+            Some(machine_type::INDOOR_BIKE),
+            "KICKR"
+        ));
+        assert!(!is_match(
+            &console,
+            &by_name,
+            Some(machine_type::INDOOR_BIKE),
+            "KICKR"
+        ));
+
+        let by_address = ScanConfig::new().address("c0:ff:ee:00:00:01");
+        assert!(is_match(&console, &by_address, None, "nothing"));
+
+        let by_service = ScanConfig::new().service_uuid(FITNESS_MACHINE_SERVICE_UUID);
+        assert!(!is_match(&console, &by_service, None, "iConsole+0028"));
+        assert!(is_match(&trainer, &by_service, None, "*"));
+    }
+
+    #[test]
+    fn test_with_address() {
+        let known = || vec![crate::devices::RecordingTransport::new()];
+        assert!(with_address(known(), "C0:FF:EE:00:00:01").is_some());
+        assert!(with_address(known(), "c0:ff:ee:00:00:01").is_some());
+        assert!(with_address(known(), "c0:ff:ee:00:00:02").is_none());
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("iConsole+0028", "Console"));
+        assert!(!name_matches("iConsole+0028", "console"));
+        assert!(name_matches("KICKR CORE 1A2B", "KICKR*"));
+        assert!(name_matches("KICKR CORE 1A2B", "*CORE ????"));
+        assert!(!name_matches("KICKR CORE 1A2B", "CORE*"));
+        assert!(!name_matches("KICKR CORE 1A2B", "*CORE ???"));
+        assert!(name_matches("", "*"));
+    }
+
+    #[test]
+    fn test_scan_filter() {
+        let default = ScanConfig::new();
+        let filter = scan_filter(&default, EquipmentType::GenericFtmsRower);
+        assert_eq!(filter.services, [FITNESS_MACHINE_SERVICE_UUID]);
+        let filter = scan_filter(&default, EquipmentType::DebugBike);
+        assert!(filter.services.is_empty());
+
+        let by_address = ScanConfig::new().address("c0:ff:ee:00:00:01");
+        let filter = scan_filter(&by_address, EquipmentType::GenericFtmsBike);
+        assert!(filter.services.is_empty());
+        let by_service = ScanConfig::new().service_uuid(crate::sensors::HEART_RATE_SERVICE_UUID);
+        let filter = scan_filter(&by_service, EquipmentType::Iconsole0028Bike);
+        assert_eq!(filter.services, [crate::sensors::HEART_RATE_SERVICE_UUID]);
+    }
+
+    #[test]
+    fn test_pick_adapters() {
+        let infos = [
+            "hci0 (usb:v1D6Bp0246d0537)".to_string(),
+            "hci1 (usb:v0A12p0001d8891)".to_string(),
+        ];
+        let default = ScanConfig::new();
+        assert_eq!(pick_adapters(2, &[], &default).unwrap(), [0, 1]);
+        assert!(pick_adapters(0, &[], &default).is_err());
+
+        let by_index = ScanConfig::new().adapter_index(1);
+        assert_eq!(pick_adapters(2, &[], &by_index).unwrap(), [1]);
+        assert!(pick_adapters(1, &[], &by_index).is_err());
+
+        let by_name = ScanConfig::new().adapter_name("HCI1");
+        assert_eq!(pick_adapters(2, &infos, &by_name).unwrap(), [1]);
+        assert!(pick_adapters(2, &infos, &ScanConfig::new().adapter_name("hci2")).is_err());
+        assert!(pick_adapters(2, &infos, &by_name.adapter_index(0)).is_err());
+    }
+}
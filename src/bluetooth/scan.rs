@@ -0,0 +1,194 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use btleplug::api::{Central as _, Peripheral as _, PeripheralProperties, ScanFilter};
+use uuid::Uuid;
+
+use crate::bluetooth::{ScanConfig, adapters, diagnose, is_fitness_machine, matches_config};
+use crate::devices::{KEISER_COMPANY_ID, iconsole_model_named};
+use crate::ftms::machine_type;
+use crate::sensors::{CYCLING_SPEED_AND_CADENCE_SERVICE_UUID, HEART_RATE_SERVICE_UUID};
+use crate::{EquipmentType, Result};
+
+/// How long `scan` listens for advertisements when the config doesn't say
+const DEFAULT_SCAN_DURATION: Duration = Duration::from_secs(5);
+
+/// A device found by `scan`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiscoveredDevice {
+    /// The advertised name of the device, if it has one
+    pub name: Option<String>,
+    /// The address of the device, to pass on to `ScanConfig::address` or `Equipment::connect_by_address`
+    pub address: String,
+    /// The signal strength of the last advertisement received, in dBm
+    pub rssi: Option<i16>,
+    /// The services the device advertises
+    pub services: Vec<Uuid>,
+    /// The equipment type the device most likely is, going by its advertisement
+    pub probable_type: Option<EquipmentType>,
+}
+
+/// Scan for nearby devices, to let the user pick one before creating an `Equipment`
+///
+/// Listens for advertisements for the configured timeout, 5 seconds by default. Every other option of
+/// `config` narrows down which devices get listed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use kondis::{ScanConfig, scan};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let devices = scan(ScanConfig::new().timeout(Duration::from_secs(10))).await?;
+///     for device in devices {
+///         println!("{:?} ({}): {:?}", device.name, device.address, device.probable_type);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn scan(config: ScanConfig) -> Result<Vec<DiscoveredDevice>> {
+    let adapters = adapters(&config).await?;
+    let filter = ScanFilter {
+        services: config.service_uuid.into_iter().collect(),
+    };
+    for adapter in &adapters {
+        adapter
+            .start_scan(filter.clone())
+            .await
+            .map_err(diagnose::classify)?;
+    }
+    crate::runtime::sleep(config.timeout.unwrap_or(DEFAULT_SCAN_DURATION)).await;
+
+    let mut devices = Vec::new();
+    for adapter in &adapters {
+        adapter.stop_scan().await?;
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            if !matches_config(&properties, &config) {
+                continue;
+            }
+            devices.push(DiscoveredDevice {
+                name: properties.local_name.clone(),
+                address: properties.address.to_string(),
+                rssi: properties.rssi,
+                probable_type: probable_type(&properties),
+                services: properties.services,
+            });
+        }
+    }
+    // strongest signal first, as the closest device is the likeliest pick
+    devices.sort_by_key(|device| Reverse(device.rssi));
+    Ok(devices)
+}
+
+/// Guess the equipment type from an advertisement
+///
+/// Fitness machines not advertising their type are assumed to be bikes, the most common kind.
+fn probable_type(properties: &PeripheralProperties) -> Option<EquipmentType> {
+    if let Some(quirks) = properties
+        .local_name
+        .as_deref()
+        .and_then(iconsole_model_named)
+    {
+        return Some(quirks.equipment_type);
+    }
+    if properties
+        .manufacturer_data
+        .contains_key(&KEISER_COMPANY_ID)
+    {
+        return Some(EquipmentType::KeiserM3iBike);
+    }
+    [
+        (machine_type::INDOOR_BIKE, EquipmentType::GenericFtmsBike),
+        (machine_type::TREADMILL, EquipmentType::GenericFtmsTreadmill),
+        (machine_type::ROWER, EquipmentType::GenericFtmsRower),
+        (
+            machine_type::CROSS_TRAINER,
+            EquipmentType::GenericFtmsCrossTrainer,
+        ),
+    ]
+    .into_iter()
+    .find(|(machine_type, _)| is_fitness_machine(properties, *machine_type))
+    .map(|(_, equipment_type)| equipment_type)
+    .or_else(|| {
+        // machines measuring heart rate themselves advertise both services
+        properties
+            .services
+            .contains(&HEART_RATE_SERVICE_UUID)
+            .then_some(EquipmentType::HeartRateMonitor)
+    })
+    .or_else(|| {
+        properties
+            .services
+            .contains(&CYCLING_SPEED_AND_CADENCE_SERVICE_UUID)
+            .then_some(EquipmentType::SpeedCadenceSensor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ftms::FITNESS_MACHINE_SERVICE_UUID;
+
+    #[test]
+    fn test_probable_type() {
+        let mut properties = PeripheralProperties {
+            local_name: Some("iConsole+0028".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::Iconsole0028Bike)
+        );
+        properties.local_name = Some("iConsole+0010".to_string());
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::Iconsole0010Bike)
+        );
+
+        properties.local_name = Some("Some Rower".to_string());
+        assert_eq!(probable_type(&properties), None);
+
+        properties
+            .manufacturer_data
+            .insert(KEISER_COMPANY_ID, vec![0x06, 0x40]);
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::KeiserM3iBike)
+        );
+        properties.manufacturer_data.clear();
+
+        properties.services = vec![HEART_RATE_SERVICE_UUID];
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::HeartRateMonitor)
+        );
+
+        properties.services = vec![CYCLING_SPEED_AND_CADENCE_SERVICE_UUID];
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::SpeedCadenceSensor)
+        );
+
+        properties.services = vec![HEART_RATE_SERVICE_UUID, FITNESS_MACHINE_SERVICE_UUID];
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::GenericFtmsBike)
+        );
+
+        // flags, then the rower bit of the fitness machine type
+        properties
+            .service_data
+            .insert(FITNESS_MACHINE_SERVICE_UUID, vec![0x01, 0x10, 0x00]);
+        assert_eq!(
+            probable_type(&properties),
+            Some(EquipmentType::GenericFtmsRower)
+        );
+    }
+}
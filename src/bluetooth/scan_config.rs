@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// Options for discovering equipment, see `Equipment::with_config`
+///
+/// Every option narrows down which device gets connected to, on top of what the equipment type itself
+/// looks for. The default scans every adapter until a matching device shows up or the scan is shut down.
+/// Once found, the steps of talking to the device do give up by default, see `connect_timeout`,
+/// `discovery_timeout` and `notification_timeout`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use kondis::ScanConfig;
+///
+/// let config = ScanConfig::new()
+///     .timeout(Duration::from_secs(30))
+///     .name_pattern("KICKR");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanConfig {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) discovery_timeout: Option<Duration>,
+    pub(crate) notification_timeout: Option<Duration>,
+    pub(crate) name_pattern: Option<String>,
+    pub(crate) address: Option<String>,
+    pub(crate) adapter_index: Option<usize>,
+    pub(crate) adapter_name: Option<String>,
+    pub(crate) service_uuid: Option<Uuid>,
+    pub(crate) capture: Option<PathBuf>,
+    pub(crate) port: Option<PathBuf>,
+    pub(crate) low_battery: Option<u8>,
+    pub(crate) stale_after: Option<Duration>,
+    pub(crate) reconnect_when_stale: bool,
+}
+
+impl ScanConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up scanning after `timeout`, failing instead of waiting for a device forever
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Give up connecting to the device after `timeout`, 15 seconds by default
+    ///
+    /// Some adapters never finish connecting to a device that went out of range mid-way.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up discovering the services of the device after `timeout`, 15 seconds by default
+    pub fn discovery_timeout(mut self, timeout: Duration) -> Self {
+        self.discovery_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up waiting for a notification of the device after `timeout`, 10 seconds by default, like
+    /// when reading its data with `Equipment::read`
+    pub fn notification_timeout(mut self, timeout: Duration) -> Self {
+        self.notification_timeout = Some(timeout);
+        self
+    }
+
+    /// Only connect to a device whose advertised name contains `pattern`
+    ///
+    /// A pattern with wildcards has to match the whole name instead, `*` standing for any run of
+    /// characters and `?` for any one character, like `"KICKR ????"`. Replaces the name equipment
+    /// types like `Iconsole0028Bike` look for by default.
+    pub fn name_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.name_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Only connect to the device with this address, like `"C0:FF:EE:00:00:01"`
+    ///
+    /// The device is connected to regardless of its name and the services it advertises, and without
+    /// scanning at all when an adapter already knows about it.
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Only scan with the adapter at `index`, in the order the system lists them, see
+    /// `bluetooth::list_adapters`
+    pub fn adapter_index(mut self, index: usize) -> Self {
+        self.adapter_index = Some(index);
+        self
+    }
+
+    /// Only scan with the adapters whose description contains `name`, ignoring case, like `"hci1"` for
+    /// a USB dongle next to a built-in adapter on Linux, see `bluetooth::list_adapters`
+    ///
+    /// Unlike an index, a name keeps picking the same adapter when another one is plugged in.
+    pub fn adapter_name(mut self, name: impl Into<String>) -> Self {
+        self.adapter_name = Some(name.into());
+        self
+    }
+
+    /// Only connect to a device advertising this service
+    pub fn service_uuid(mut self, uuid: Uuid) -> Self {
+        self.service_uuid = Some(uuid);
+        self
+    }
+
+    /// Capture every notification of the device to the file at `path` once connected, for
+    /// `ReplayDevice` to play back
+    ///
+    /// The file gets replaced with every connection. Captures are written as notifications arrive, so
+    /// they survive the process crashing.
+    pub fn capture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture = Some(path.into());
+        self
+    }
+
+    /// Talk to equipment wired to the serial port or USB HID device at `path`, like `/dev/ttyUSB0` or
+    /// `/dev/hidraw0` for a Concept2 monitor, see `devices::CsafeRower` and `devices::KettlerBike`, or
+    /// through the ANT stick at `path` with the `ant` feature, see `devices::AntFecBike`,
+    /// `devices::AntHeartRateMonitor` and `devices::AntPowerMeter`
+    ///
+    /// Bluetooth devices ignore the port.
+    pub fn port(mut self, path: impl Into<PathBuf>) -> Self {
+        self.port = Some(path.into());
+        self
+    }
+
+    /// Report `DeviceEvent::LowBattery` once the battery of the device drops below `percent`, 20% by
+    /// default
+    pub fn low_battery(mut self, percent: u8) -> Self {
+        self.low_battery = Some(percent);
+        self
+    }
+
+    /// Report `DeviceEvent::DataStale` once no data arrives for `interval` while connected, 10 seconds
+    /// by default
+    ///
+    /// A subscription can die without the connection dropping, leaving `read` with the same old data
+    /// forever.
+    pub fn stale_after(mut self, interval: Duration) -> Self {
+        self.stale_after = Some(interval);
+        self
+    }
+
+    /// Reconnect to the device once its data goes stale, see `stale_after`, rather than only reporting
+    /// it
+    ///
+    /// The connection is in `ConnectionState::Reconnecting` until the device is connected to again.
+    /// After three reconnects in a row fail, the device gets disconnected from, sending
+    /// `DeviceEvent::Disconnected`.
+    pub fn reconnect_when_stale(mut self) -> Self {
+        self.reconnect_when_stale = true;
+        self
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use crate::bluetooth::ScanConfig;
+use crate::{KondisError, Result};
+
+/// How long connecting takes at most unless configured otherwise, see `ScanConfig::connect_timeout`
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// See `ScanConfig::discovery_timeout`
+const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(15);
+/// See `ScanConfig::notification_timeout`
+const DEFAULT_NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long each step of talking to a peripheral may take, from the options of `ScanConfig`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Timeouts {
+    pub connect: Duration,
+    pub discovery: Duration,
+    pub notification: Duration,
+}
+
+impl Timeouts {
+    pub fn new(config: &ScanConfig) -> Self {
+        Timeouts {
+            connect: config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            discovery: config
+                .discovery_timeout
+                .unwrap_or(DEFAULT_DISCOVERY_TIMEOUT),
+            notification: config
+                .notification_timeout
+                .unwrap_or(DEFAULT_NOTIFICATION_TIMEOUT),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::new(&ScanConfig::default())
+    }
+}
+
+/// Run `future`, failing with the error `timeout` makes of `duration` once it takes any longer
+pub(crate) async fn within<T>(
+    duration: Duration,
+    timeout: fn(Duration) -> KondisError,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    crate::runtime::timeout(duration, future)
+        .await
+        .unwrap_or(Err(timeout(duration)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_within() {
+        let timeouts = Timeouts::new(&ScanConfig::new().connect_timeout(Duration::from_millis(10)));
+        assert_eq!(timeouts.connect, Duration::from_millis(10));
+        assert_eq!(timeouts.discovery, DEFAULT_DISCOVERY_TIMEOUT);
+
+        let done = within(timeouts.connect, KondisError::ConnectTimeout, async {
+            Ok(1)
+        });
+        assert_eq!(done.await.unwrap(), 1);
+        let pending = futures::future::pending::<Result<()>>();
+        assert!(matches!(
+            within(timeouts.connect, KondisError::ConnectTimeout, pending).await,
+            Err(KondisError::ConnectTimeout(timeout)) if timeout == Duration::from_millis(10)
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+use std::pin::Pin;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, ValueNotification, WriteType},
+    platform::Peripheral,
+};
+use futures::Stream;
+use uuid::Uuid;
+
+use crate::Result;
+
+/// Every notification of a peripheral, of whichever characteristic notified it
+pub(crate) type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// The GATT operations a peripheral gets talked to through, whichever Bluetooth stack carries them
+///
+/// `FtmsPeripheral`, `CommandQueue` and the notification forwarding only go through these, so equipment
+/// on another stack, like BlueZ through `bluer`, swaps the transport rather than the device.
+pub(crate) trait Transport: std::fmt::Debug + Clone + Send + Sync + 'static {
+    /// The address of the peripheral, for logging
+    fn address(&self) -> String;
+
+    fn is_connected(&self) -> impl Future<Output = Result<bool>> + Send;
+
+    fn connect(&self) -> impl Future<Output = Result<()>> + Send;
+
+    fn disconnect(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Look up the services of the connected peripheral, after which `characteristics` knows them
+    fn discover_services(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Every characteristic found by `discover_services`
+    fn characteristics(&self) -> BTreeSet<Characteristic>;
+
+    fn read(&self, characteristic: &Characteristic)
+    -> impl Future<Output = Result<Vec<u8>>> + Send;
+
+    /// Write to the characteristic and wait for the peripheral to acknowledge it
+    fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn subscribe(&self, characteristic: &Characteristic)
+    -> impl Future<Output = Result<()>> + Send;
+
+    fn unsubscribe(
+        &self,
+        characteristic: &Characteristic,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Every notification received from now on, of every subscribed characteristic, ending once the
+    /// peripheral disconnects
+    fn notifications(&self) -> impl Future<Output = Result<NotificationStream>> + Send;
+
+    /// The latest signal strength of the peripheral in dBm, unless the stack doesn't know it
+    fn rssi(&self) -> impl Future<Output = Result<Option<i16>>> + Send;
+
+    /// Look up a characteristic of the connected peripheral by its UUID
+    fn characteristic(&self, uuid: Uuid) -> Option<Characteristic> {
+        self.characteristics()
+            .into_iter()
+            .find(|characteristic| characteristic.uuid == uuid)
+    }
+}
+
+impl Transport for Peripheral {
+    fn address(&self) -> String {
+        btleplug::api::Peripheral::address(self).to_string()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(btleplug::api::Peripheral::is_connected(self).await?)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        Ok(btleplug::api::Peripheral::connect(self).await?)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        Ok(btleplug::api::Peripheral::disconnect(self).await?)
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        Ok(btleplug::api::Peripheral::discover_services(self).await?)
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        btleplug::api::Peripheral::characteristics(self)
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        Ok(btleplug::api::Peripheral::read(self, characteristic).await?)
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        Ok(
+            btleplug::api::Peripheral::write(self, characteristic, data, WriteType::WithResponse)
+                .await?,
+        )
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        Ok(btleplug::api::Peripheral::subscribe(self, characteristic).await?)
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        Ok(btleplug::api::Peripheral::unsubscribe(self, characteristic).await?)
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        Ok(btleplug::api::Peripheral::notifications(self).await?)
+    }
+
+    async fn rssi(&self) -> Result<Option<i16>> {
+        Ok(self
+            .properties()
+            .await?
+            .and_then(|properties| properties.rssi))
+    }
+}
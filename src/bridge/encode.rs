@@ -0,0 +1,341 @@
+use crate::FTMSData;
+use crate::ftms::indoor_bike_data::flags;
+use crate::ftms::reader::Reader;
+use crate::ftms::{Capabilities, FTMSControlOpCode, ResultCode, StopCode, machine_type};
+use crate::sensors::{HeartRateData, heart_rate_flags};
+
+/// A control point command an app wrote to the bridge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Command {
+    RequestControl,
+    Reset,
+    TargetResistanceLevel(f32),
+    TargetPower(i16),
+    TargetHeartRate(u8),
+    /// Start or resume
+    Start,
+    Stop,
+    Pause,
+    SimulationParameters {
+        /// m/s
+        wind_speed: f32,
+        /// %
+        grade: f32,
+        crr: f32,
+        /// kg/m
+        cw: f32,
+    },
+    /// rpm
+    TargetCadence(f32),
+}
+
+/// Parse a control point write, or the result code to refuse it with
+pub(crate) fn parse_command(data: &[u8]) -> Result<Command, ResultCode> {
+    let mut reader = Reader::new(data);
+    let invalid = |_| ResultCode::InvalidParameter;
+    let op_code = reader.u8().map_err(invalid)?;
+    let command = match op_code {
+        op if op == FTMSControlOpCode::RequestControl as u8 => Command::RequestControl,
+        op if op == FTMSControlOpCode::Reset as u8 => Command::Reset,
+        op if op == FTMSControlOpCode::TargetResistanceLevel as u8 => {
+            // a single byte in older revisions of the spec
+            let level = match reader.remaining() {
+                1 => f32::from(reader.u8().map_err(invalid)?),
+                _ => f32::from(reader.i16().map_err(invalid)?),
+            };
+            Command::TargetResistanceLevel(level / 10.)
+        }
+        op if op == FTMSControlOpCode::TargetPower as u8 => {
+            Command::TargetPower(reader.i16().map_err(invalid)?)
+        }
+        op if op == FTMSControlOpCode::TargetHeartRate as u8 => {
+            Command::TargetHeartRate(reader.u8().map_err(invalid)?)
+        }
+        op if op == FTMSControlOpCode::Start as u8 => Command::Start,
+        op if op == FTMSControlOpCode::Stop as u8 => match reader.u8().map_err(invalid)? {
+            code if code == StopCode::Stop as u8 => Command::Stop,
+            code if code == StopCode::Pause as u8 => Command::Pause,
+            _ => return Err(ResultCode::InvalidParameter),
+        },
+        op if op == FTMSControlOpCode::SimulationParameters as u8 => {
+            Command::SimulationParameters {
+                wind_speed: f32::from(reader.i16().map_err(invalid)?) / 1000.,
+                grade: f32::from(reader.i16().map_err(invalid)?) / 100.,
+                crr: f32::from(reader.u8().map_err(invalid)?) / 10000.,
+                cw: f32::from(reader.u8().map_err(invalid)?) / 100.,
+            }
+        }
+        op if op == FTMSControlOpCode::TargetCadence as u8 => {
+            Command::TargetCadence(f32::from(reader.u16().map_err(invalid)?) / 2.)
+        }
+        _ => return Err(ResultCode::NotSupported),
+    };
+    Ok(command)
+}
+
+/// The control point indication answering the command with `op_code`
+pub(crate) fn control_point_response(op_code: u8, result: ResultCode) -> Vec<u8> {
+    vec![FTMSControlOpCode::Success as u8, op_code, result.into()]
+}
+
+/// Encode an Indoor Bike Data notification, the fields of `data` that are `None` left out
+///
+/// Energy per hour and per minute are sent as not available.
+pub(crate) fn indoor_bike_data(data: &FTMSData) -> Vec<u8> {
+    let mut flags = 0;
+    let mut fields = Vec::new();
+    match data.speed {
+        Some(speed) => fields.extend(((speed * 100.).round() as u16).to_le_bytes()),
+        None => flags |= flags::MORE_DATA,
+    }
+    if let Some(cadence) = data.cadence {
+        flags |= flags::INSTANTANEOUS_CADENCE;
+        fields.extend(((cadence * 2.).round() as u16).to_le_bytes());
+    }
+    if let Some(distance) = data.distance {
+        flags |= flags::TOTAL_DISTANCE;
+        fields.extend(&((distance * 1000.).round() as u32).to_le_bytes()[..3]);
+    }
+    if let Some(resistance) = data.resistance {
+        flags |= flags::RESISTANCE_LEVEL;
+        fields.extend((resistance.round() as i16).to_le_bytes());
+    }
+    if let Some(power) = data.power {
+        flags |= flags::INSTANTANEOUS_POWER;
+        fields.extend(power.to_le_bytes());
+    }
+    if let Some(calories) = data.calories {
+        flags |= flags::EXPENDED_ENERGY;
+        fields.extend((calories.round() as u16).to_le_bytes());
+        fields.extend([0xFF, 0xFF, 0xFF]);
+    }
+    if let Some(heart_rate) = data.heart_rate {
+        flags |= flags::HEART_RATE;
+        fields.push(heart_rate.round() as u8);
+    }
+    if let Some(time) = data.time {
+        flags |= flags::ELAPSED_TIME;
+        fields.extend((time.min(u16::MAX.into()) as u16).to_le_bytes());
+    }
+    let mut encoded = flags.to_le_bytes().to_vec();
+    encoded.extend(fields);
+    encoded
+}
+
+/// Encode a Heart Rate Measurement notification, the inverse of `parse_heart_rate_measurement`
+///
+/// The heart rate takes a byte unless it doesn't fit, and the RR intervals that don't fit into a
+/// notification of the default size are left out.
+pub(crate) fn heart_rate_measurement(data: &HeartRateData) -> Vec<u8> {
+    let heart_rate = data.heart_rate.round().clamp(0., f64::from(u16::MAX)) as u16;
+    let mut flags = 0;
+    let mut fields = Vec::new();
+    match u8::try_from(heart_rate) {
+        Ok(heart_rate) => fields.push(heart_rate),
+        Err(_) => {
+            flags |= heart_rate_flags::HEART_RATE_U16;
+            fields.extend(heart_rate.to_le_bytes());
+        }
+    }
+    if let Some(contact) = data.sensor_contact {
+        flags |= heart_rate_flags::SENSOR_CONTACT_SUPPORTED;
+        if contact {
+            flags |= heart_rate_flags::SENSOR_CONTACT_DETECTED;
+        }
+    }
+    if let Some(energy) = data.energy_expended {
+        flags |= heart_rate_flags::ENERGY_EXPENDED;
+        fields.extend(energy.to_le_bytes());
+    }
+    if !data.rr_intervals.is_empty() {
+        flags |= heart_rate_flags::RR_INTERVALS;
+        // 20 bytes fit into a notification of the default ATT MTU, the flags taking one
+        let room = (19 - fields.len()) / 2;
+        for interval in data.rr_intervals.iter().take(room) {
+            let interval = (interval.as_secs_f64() * 1024.)
+                .round()
+                .min(f64::from(u16::MAX));
+            fields.extend((interval as u16).to_le_bytes());
+        }
+    }
+    let mut encoded = vec![flags];
+    encoded.extend(fields);
+    encoded
+}
+
+/// Encode the Fitness Machine Feature characteristic, the inverse of `parse_fitness_machine_feature`
+pub(crate) fn fitness_machine_feature(capabilities: &Capabilities) -> Vec<u8> {
+    let bits = |fields: &[(bool, u32)]| {
+        fields
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0u32, |bits, (_, bit)| bits | 1 << bit)
+    };
+    let (data, targets) = (&capabilities.data, &capabilities.targets);
+    let machine = bits(&[
+        (data.average_speed, 0),
+        (data.cadence, 1),
+        (data.distance, 2),
+        (data.inclination, 3),
+        (data.elevation_gain, 4),
+        (data.pace, 5),
+        (data.step_count, 6),
+        (data.resistance, 7),
+        (data.stride_count, 8),
+        (data.expended_energy, 9),
+        (data.heart_rate, 10),
+        (data.elapsed_time, 12),
+        (data.remaining_time, 13),
+        (data.power, 14),
+    ]);
+    let target = bits(&[
+        (targets.speed, 0),
+        (targets.inclination, 1),
+        (targets.resistance, 2),
+        (targets.power, 3),
+        (targets.heart_rate, 4),
+        (targets.expended_energy, 5),
+        (targets.distance, 8),
+        (targets.training_time, 9),
+        (targets.simulation, 13),
+        (targets.spin_down, 15),
+        (targets.cadence, 16),
+    ]);
+    let mut encoded = machine.to_le_bytes().to_vec();
+    encoded.extend(target.to_le_bytes());
+    encoded
+}
+
+/// The service data of the Fitness Machine Service, advertising an available indoor bike
+pub(crate) fn indoor_bike_service_data() -> Vec<u8> {
+    let [lsb, msb] = machine_type::INDOOR_BIKE.to_le_bytes();
+    // flags: fitness machine available
+    vec![0x01, lsb, msb]
+}
+
+/// Encode a supported range characteristic, in the unit and resolution of the characteristic
+pub(crate) fn supported_range(min: i16, max: i16, increment: u16) -> Vec<u8> {
+    let mut encoded = min.to_le_bytes().to_vec();
+    encoded.extend(max.to_le_bytes());
+    encoded.extend(increment.to_le_bytes());
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Result;
+    use crate::ftms::{
+        DataCapabilities, TargetCapabilities, parse_fitness_machine_feature,
+        parse_indoor_bike_data, simulation_parameters,
+    };
+    use crate::sensors::parse_heart_rate_measurement;
+
+    #[test]
+    fn test_commands() -> Result<()> {
+        assert_eq!(parse_command(&[0x00]), Ok(Command::RequestControl));
+        assert_eq!(
+            parse_command(&[0x05, 0xC8, 0x00]),
+            Ok(Command::TargetPower(200))
+        );
+        assert_eq!(
+            parse_command(&[0x04, 0x32, 0x00]),
+            Ok(Command::TargetResistanceLevel(5.))
+        );
+        assert_eq!(
+            parse_command(&[0x04, 0x32]),
+            Ok(Command::TargetResistanceLevel(5.))
+        );
+        assert_eq!(parse_command(&[0x08, 0x02]), Ok(Command::Pause));
+        assert_eq!(
+            parse_command(&simulation_parameters(2.5, -1., 0.004, 0.51)?),
+            Ok(Command::SimulationParameters {
+                wind_speed: -1.,
+                grade: 2.5,
+                crr: 0.004,
+                cw: 0.51
+            })
+        );
+        assert_eq!(
+            parse_command(&[0x05, 0xC8]),
+            Err(ResultCode::InvalidParameter)
+        );
+        assert_eq!(parse_command(&[0x13, 0x01]), Err(ResultCode::NotSupported));
+        assert_eq!(
+            control_point_response(0x05, ResultCode::Success),
+            [0x80, 0x05, 0x01]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_indoor_bike_data() -> Result<()> {
+        let data = FTMSData {
+            speed: Some(32.5),
+            cadence: Some(90.),
+            distance: Some(12.345),
+            power: Some(250),
+            calories: Some(300.),
+            heart_rate: Some(150.),
+            time: Some(1800),
+            ..Default::default()
+        };
+        let parsed: FTMSData = parse_indoor_bike_data(&indoor_bike_data(&data))?.into();
+        assert_eq!(parsed, data);
+
+        let power_only = FTMSData {
+            power: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(indoor_bike_data(&power_only), [0x41, 0x00, 0x64, 0x00]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_heart_rate_measurement() -> Result<()> {
+        let data = HeartRateData {
+            heart_rate: 142.,
+            sensor_contact: Some(true),
+            energy_expended: Some(120),
+            rr_intervals: vec![Duration::from_millis(500), Duration::from_secs(1)],
+        };
+        let encoded = heart_rate_measurement(&data);
+        assert_eq!(encoded[..2], [0x1E, 0x8E]);
+        assert_eq!(parse_heart_rate_measurement(&encoded)?, data);
+
+        let fast = HeartRateData {
+            heart_rate: 300.,
+            rr_intervals: vec![Duration::from_millis(200); 12],
+            ..Default::default()
+        };
+        let encoded = heart_rate_measurement(&fast);
+        assert_eq!(encoded.len(), 19);
+        let parsed = parse_heart_rate_measurement(&encoded)?;
+        assert_eq!(parsed.heart_rate, 300.);
+        assert_eq!(parsed.rr_intervals.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fitness_machine_feature() -> Result<()> {
+        let capabilities = Capabilities {
+            targets: TargetCapabilities {
+                power: true,
+                resistance: true,
+                simulation: true,
+                ..Default::default()
+            },
+            data: DataCapabilities {
+                cadence: true,
+                power: true,
+                heart_rate: true,
+                ..Default::default()
+            },
+        };
+        let encoded = fitness_machine_feature(&capabilities);
+        assert_eq!(parse_fitness_machine_feature(&encoded)?, capabilities);
+        Ok(())
+    }
+}
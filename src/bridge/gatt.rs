@@ -0,0 +1,468 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{Message, MethodErr, Path};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{KondisError, Result};
+
+const BLUEZ: &str = "org.bluez";
+const GATT_MANAGER: &str = "org.bluez.GattManager1";
+const ADVERTISING_MANAGER: &str = "org.bluez.LEAdvertisingManager1";
+const SERVICE: &str = "org.bluez.GattService1";
+const CHARACTERISTIC: &str = "org.bluez.GattCharacteristic1";
+const ADVERTISEMENT: &str = "org.bluez.LEAdvertisement1";
+const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// Where the services and advertisement of a server live, unique to the connection to D-Bus
+const ROOT: &str = "/org/kondis/bridge";
+/// How long BlueZ gets to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A service to serve, see `GattServer::register`
+#[derive(Debug, Clone)]
+pub(crate) struct Service {
+    pub uuid: Uuid,
+    pub characteristics: Vec<Characteristic>,
+}
+
+/// A characteristic of a service, holding `value` until notified with another, see `GattServer::notify`
+#[derive(Debug, Clone)]
+pub(crate) struct Characteristic {
+    pub uuid: Uuid,
+    /// How apps may use the characteristic, like "read", "write", "notify" and "indicate"
+    pub flags: &'static [&'static str],
+    pub value: Vec<u8>,
+}
+
+/// What the server advertises to apps looking for a peripheral
+#[derive(Debug, Clone)]
+pub(crate) struct Advertisement {
+    pub name: String,
+    pub services: Vec<Uuid>,
+    pub service_data: Vec<(Uuid, Vec<u8>)>,
+    /// What kind of device apps show the server as, like a heart rate belt
+    pub appearance: Option<u16>,
+}
+
+/// A value an app wrote to a characteristic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Write {
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// What the server serves, answering BlueZ asking about it
+#[derive(Debug)]
+struct Objects {
+    services: Vec<Service>,
+    advertisement: Advertisement,
+    /// The paths of the characteristics apps subscribed to
+    notifying: HashSet<String>,
+}
+
+/// A local GATT server, serving services and advertising them through BlueZ as a peripheral
+///
+/// Written values arrive on the receiver `register` resolves to. The services stop being served once
+/// the server is closed or dropped.
+pub(crate) struct GattServer {
+    connection: Arc<SyncConnection>,
+    adapter: Path<'static>,
+    objects: Arc<Mutex<Objects>>,
+    serving: JoinHandle<()>,
+}
+
+impl GattServer {
+    /// Serve `services` on the first adapter able to, advertising them as `advertisement`
+    pub async fn register(
+        services: Vec<Service>,
+        advertisement: Advertisement,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<Write>)> {
+        let (resource, connection) = dbus_tokio::connection::new_system_sync()
+            .map_err(|e| KondisError::BluetoothUnavailable(e.to_string()))?;
+        let serving = tokio::spawn(async move {
+            // only ever resolves once the connection to D-Bus is lost
+            let _ = resource.await;
+        });
+        let objects = Arc::new(Mutex::new(Objects {
+            services,
+            advertisement,
+            notifying: HashSet::new(),
+        }));
+        let (writes_tx, writes) = mpsc::unbounded_channel();
+        let answering = objects.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                if let Some(reply) = answer(&answering, &writes_tx, &message) {
+                    let _ = connection.send(reply);
+                }
+                true
+            }),
+        );
+        let server = GattServer {
+            adapter: adapter(&connection).await?,
+            connection,
+            objects,
+            serving,
+        };
+        server
+            .adapter_call(
+                GATT_MANAGER,
+                "RegisterApplication",
+                (Path::from(ROOT), PropMap::new()),
+            )
+            .await?;
+        server
+            .adapter_call(
+                ADVERTISING_MANAGER,
+                "RegisterAdvertisement",
+                (advertisement_path(), PropMap::new()),
+            )
+            .await?;
+        Ok((server, writes))
+    }
+
+    /// Change the value of the characteristic `uuid`, notifying or indicating the apps subscribed to it
+    pub fn notify(&self, uuid: Uuid, value: Vec<u8>) {
+        let mut objects = self.objects.lock().unwrap();
+        let mut found = None;
+        for (s, service) in objects.services.iter_mut().enumerate() {
+            for (c, characteristic) in service.characteristics.iter_mut().enumerate() {
+                if characteristic.uuid == uuid {
+                    characteristic.value = value.clone();
+                    found = Some(characteristic_path(s, c));
+                }
+            }
+        }
+        let Some(path) = found.filter(|path| objects.notifying.contains(path)) else {
+            return;
+        };
+        let changed = PropertiesPropertiesChanged {
+            interface_name: CHARACTERISTIC.to_string(),
+            changed_properties: HashMap::from([("Value".to_string(), variant(value))]),
+            invalidated_properties: Vec::new(),
+        };
+        let _ = self
+            .connection
+            .send(changed.to_emit_message(&Path::from(path)));
+    }
+
+    /// Stop advertising and serving
+    pub async fn close(self) -> Result<()> {
+        let advertising = self
+            .adapter_call(
+                ADVERTISING_MANAGER,
+                "UnregisterAdvertisement",
+                (advertisement_path(),),
+            )
+            .await;
+        let serving = self
+            .adapter_call(GATT_MANAGER, "UnregisterApplication", (Path::from(ROOT),))
+            .await;
+        advertising.and(serving)
+    }
+
+    async fn adapter_call(
+        &self,
+        interface: &str,
+        method: &str,
+        arguments: impl dbus::arg::AppendAll,
+    ) -> Result<()> {
+        Proxy::new(BLUEZ, &self.adapter, TIMEOUT, self.connection.clone())
+            .method_call::<(), _, _, _>(interface, method, arguments)
+            .await
+            .map_err(|e| KondisError::Bridge(format!("{method} failed: {e}")))
+    }
+}
+
+impl Drop for GattServer {
+    fn drop(&mut self) {
+        // BlueZ forgets about everything registered once the connection goes away
+        self.serving.abort();
+    }
+}
+
+/// The first adapter able to serve and advertise as a peripheral
+async fn adapter(connection: &Arc<SyncConnection>) -> Result<Path<'static>> {
+    let (objects,): (HashMap<Path<'static>, HashMap<String, PropMap>>,) =
+        Proxy::new(BLUEZ, "/", TIMEOUT, connection.clone())
+            .method_call(OBJECT_MANAGER, "GetManagedObjects", ())
+            .await
+            .map_err(|e| KondisError::BluetoothUnavailable(e.to_string()))?;
+    let mut adapters: Vec<_> = objects
+        .into_iter()
+        .filter(|(_, interfaces)| {
+            interfaces.contains_key(GATT_MANAGER) && interfaces.contains_key(ADVERTISING_MANAGER)
+        })
+        .map(|(path, _)| path)
+        .collect();
+    adapters.sort();
+    adapters.into_iter().next().ok_or_else(|| {
+        KondisError::BluetoothUnavailable(
+            "No Bluetooth adapter can serve as a peripheral".to_string(),
+        )
+    })
+}
+
+/// The reply to a method call of BlueZ, `None` for calls to anything but the server
+fn answer(
+    objects: &Mutex<Objects>,
+    writes: &mpsc::UnboundedSender<Write>,
+    message: &Message,
+) -> Option<Message> {
+    let path = message.path()?.to_string();
+    if !path.starts_with(ROOT) {
+        return None;
+    }
+    let interface = message.interface()?.to_string();
+    let method = message.member()?.to_string();
+    let mut objects = objects.lock().unwrap();
+    let reply = match (&interface[..], &method[..]) {
+        (OBJECT_MANAGER, "GetManagedObjects") => {
+            let managed: HashMap<_, _> = objects
+                .services
+                .iter()
+                .enumerate()
+                .flat_map(|(s, service)| {
+                    let mut paths = vec![service_path(s)];
+                    paths.extend(
+                        (0..service.characteristics.len()).map(|c| characteristic_path(s, c)),
+                    );
+                    paths
+                })
+                .filter_map(|path| Some((Path::from(path.clone()), objects.properties(&path)?)))
+                .collect();
+            Ok(message.method_return().append1(managed))
+        }
+        (PROPERTIES, "GetAll") => match message.read1::<&str>() {
+            Ok(asked) => Ok(message.method_return().append1(
+                objects
+                    .properties(&path)
+                    .and_then(|mut interfaces| interfaces.remove(asked))
+                    .unwrap_or_default(),
+            )),
+            Err(e) => Err(MethodErr::from(e)),
+        },
+        (PROPERTIES, "Get") => match message.read2::<&str, &str>() {
+            Ok((asked, name)) => objects
+                .properties(&path)
+                .and_then(|mut interfaces| interfaces.remove(asked)?.remove(name))
+                .map(|value| message.method_return().append1(value))
+                .ok_or_else(|| MethodErr::no_property(&name)),
+            Err(e) => Err(MethodErr::from(e)),
+        },
+        (CHARACTERISTIC, "ReadValue") => match objects.characteristic(&path) {
+            Some(characteristic) => {
+                let options: PropMap = message.read1().unwrap_or_default();
+                let offset = dbus::arg::prop_cast::<u16>(&options, "offset").copied();
+                let value = characteristic.value.get(usize::from(offset.unwrap_or(0))..);
+                Ok(message
+                    .method_return()
+                    .append1(value.unwrap_or_default().to_vec()))
+            }
+            None => Err(MethodErr::no_path(&path)),
+        },
+        (CHARACTERISTIC, "WriteValue") => match (objects.characteristic(&path), message.read1()) {
+            (Some(characteristic), Ok(value)) => {
+                let _ = writes.send(Write {
+                    uuid: characteristic.uuid,
+                    value,
+                });
+                Ok(message.method_return())
+            }
+            (None, _) => Err(MethodErr::no_path(&path)),
+            (_, Err(e)) => Err(MethodErr::from(e)),
+        },
+        (CHARACTERISTIC, "StartNotify") => {
+            objects.notifying.insert(path);
+            Ok(message.method_return())
+        }
+        (CHARACTERISTIC, "StopNotify") => {
+            objects.notifying.remove(&path);
+            Ok(message.method_return())
+        }
+        // BlueZ dropping the advertisement, like when the adapter goes away
+        (ADVERTISEMENT, "Release") => Ok(message.method_return()),
+        _ => Err(MethodErr::no_method(&method)),
+    };
+    Some(reply.unwrap_or_else(|e| e.to_message(message)))
+}
+
+impl Objects {
+    /// The properties of the object at `path` by interface, `None` if there is no object there
+    fn properties(&self, path: &str) -> Option<HashMap<String, PropMap>> {
+        if &*advertisement_path() == path {
+            let advertisement = &self.advertisement;
+            let services: Vec<String> =
+                advertisement.services.iter().map(Uuid::to_string).collect();
+            let service_data: PropMap = advertisement
+                .service_data
+                .iter()
+                .map(|(uuid, data)| (uuid.to_string(), variant(data.clone())))
+                .collect();
+            let mut properties = HashMap::from([
+                ("Type".to_string(), variant("peripheral".to_string())),
+                ("LocalName".to_string(), variant(advertisement.name.clone())),
+                ("ServiceUUIDs".to_string(), variant(services)),
+                ("ServiceData".to_string(), variant(service_data)),
+            ]);
+            if let Some(appearance) = advertisement.appearance {
+                properties.insert("Appearance".to_string(), variant(appearance));
+            }
+            return Some(HashMap::from([(ADVERTISEMENT.to_string(), properties)]));
+        }
+        let (s, service) = self
+            .services
+            .iter()
+            .enumerate()
+            .find(|(s, _)| path.starts_with(&service_path(*s)))?;
+        if path == service_path(s) {
+            let properties = HashMap::from([
+                ("UUID".to_string(), variant(service.uuid.to_string())),
+                ("Primary".to_string(), variant(true)),
+            ]);
+            return Some(HashMap::from([(SERVICE.to_string(), properties)]));
+        }
+        let characteristic = self.characteristic(path)?;
+        let flags: Vec<String> = characteristic
+            .flags
+            .iter()
+            .map(|flag| flag.to_string())
+            .collect();
+        let properties = HashMap::from([
+            ("UUID".to_string(), variant(characteristic.uuid.to_string())),
+            ("Service".to_string(), variant(Path::from(service_path(s)))),
+            ("Flags".to_string(), variant(flags)),
+            ("Value".to_string(), variant(characteristic.value.clone())),
+            (
+                "Notifying".to_string(),
+                variant(self.notifying.contains(path)),
+            ),
+        ]);
+        Some(HashMap::from([(CHARACTERISTIC.to_string(), properties)]))
+    }
+
+    fn characteristic(&self, path: &str) -> Option<&Characteristic> {
+        self.services.iter().enumerate().find_map(|(s, service)| {
+            service
+                .characteristics
+                .iter()
+                .enumerate()
+                .find_map(|(c, characteristic)| {
+                    (characteristic_path(s, c) == path).then_some(characteristic)
+                })
+        })
+    }
+}
+
+fn service_path(service: usize) -> String {
+    format!("{ROOT}/service{service}")
+}
+
+fn characteristic_path(service: usize, characteristic: usize) -> String {
+    format!("{ROOT}/service{service}/char{characteristic}")
+}
+
+fn advertisement_path() -> Path<'static> {
+    Path::from(format!("{ROOT}/advertisement"))
+}
+
+fn variant(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use btleplug::api::bleuuid::uuid_from_u16;
+
+    use super::*;
+
+    fn objects() -> Mutex<Objects> {
+        Mutex::new(Objects {
+            services: vec![Service {
+                uuid: uuid_from_u16(0x1826),
+                characteristics: vec![
+                    Characteristic {
+                        uuid: uuid_from_u16(0x2ACC),
+                        flags: &["read"],
+                        value: vec![0x01, 0x02, 0x03],
+                    },
+                    Characteristic {
+                        uuid: uuid_from_u16(0x2AD9),
+                        flags: &["write", "indicate"],
+                        value: Vec::new(),
+                    },
+                ],
+            }],
+            advertisement: Advertisement {
+                name: "kondis bridge".to_string(),
+                services: vec![uuid_from_u16(0x1826)],
+                service_data: Vec::new(),
+                appearance: None,
+            },
+            notifying: HashSet::new(),
+        })
+    }
+
+    fn call(path: &str, interface: &str, method: &str) -> Message {
+        let mut call = Message::new_method_call(":1.1", path, interface, method).unwrap();
+        // as if it was sent, for it to have something to reply to
+        call.set_serial(1);
+        call
+    }
+
+    #[test]
+    fn test_answer() {
+        let objects = objects();
+        let (writes_tx, mut writes) = mpsc::unbounded_channel();
+
+        let managed = answer(
+            &objects,
+            &writes_tx,
+            &call(ROOT, OBJECT_MANAGER, "GetManagedObjects"),
+        )
+        .unwrap();
+        let managed: HashMap<Path, HashMap<String, PropMap>> = managed.read1().unwrap();
+        assert_eq!(managed.len(), 3);
+        let control = &managed[&Path::from(characteristic_path(0, 1))][CHARACTERISTIC];
+        let flags: Vec<String> = dbus::arg::prop_cast::<Vec<String>>(control, "Flags")
+            .unwrap()
+            .clone();
+        assert_eq!(flags, ["write", "indicate"]);
+
+        let read = call(&characteristic_path(0, 0), CHARACTERISTIC, "ReadValue")
+            .append1(PropMap::from([("offset".to_string(), variant(1u16))]));
+        let value: Vec<u8> = answer(&objects, &writes_tx, &read)
+            .unwrap()
+            .read1()
+            .unwrap();
+        assert_eq!(value, [0x02, 0x03]);
+
+        let write = call(&characteristic_path(0, 1), CHARACTERISTIC, "WriteValue")
+            .append2(vec![0x00u8], PropMap::new());
+        answer(&objects, &writes_tx, &write).unwrap();
+        assert_eq!(
+            writes.try_recv().unwrap(),
+            Write {
+                uuid: uuid_from_u16(0x2AD9),
+                value: vec![0x00]
+            }
+        );
+
+        let missing = call(&characteristic_path(0, 2), CHARACTERISTIC, "ReadValue");
+        let error = answer(&objects, &writes_tx, &missing).unwrap();
+        assert_eq!(error.msg_type(), dbus::MessageType::Error);
+        // calls to anything else are left alone
+        assert!(answer(&objects, &writes_tx, &call("/other", PROPERTIES, "GetAll")).is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Serving equipment to other apps as a standard Bluetooth peripheral, with the `bridge` feature
+//!
+//! Apps like Zwift connect to the bridge like to any FTMS trainer or heart rate monitor, while kondis
+//! stays connected to the equipment itself, free to record the session and augment the data the apps
+//! get. The bridge serves
+//! through BlueZ, so it is only available on Linux, and needs an adapter able to act as a peripheral.
+
+mod encode;
+mod gatt;
+
+use std::pin::pin;
+
+use futures::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::ftms::{
+    CONTROL_POINT_UUID, FITNESS_MACHINE_FEATURE_UUID, FITNESS_MACHINE_SERVICE_UUID,
+    INDOOR_BIKE_DATA_UUID, ResultCode, SUPPORTED_POWER_RANGE_UUID,
+    SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID,
+};
+use crate::sensors::{HEART_RATE_MEASUREMENT_UUID, HEART_RATE_SERVICE_UUID, HeartRateData};
+use crate::{Equipment, FTMSData, KondisError, Result};
+use encode::Command;
+use gatt::{Advertisement, Characteristic, GattServer, Service};
+
+/// The name apps see the bridge as, unless told otherwise
+const DEFAULT_NAME: &str = "kondis bridge";
+/// The supported power range apps are told about, in W. Targets outside of what the equipment itself
+/// supports are refused as invalid.
+const POWER_RANGE: (i16, i16, u16) = (0, 2000, 1);
+/// The appearance of a heart rate belt, for apps to show the heart rate server as one
+const HEART_RATE_BELT: u16 = 0x0341;
+/// The supported resistance level range apps are told about, in 0.1 steps of the level
+const RESISTANCE_LEVEL_RANGE: (i16, i16, u16) = (0, 1000, 10);
+
+/// Serves a piece of equipment as an FTMS indoor bike to other apps
+///
+/// The data comes from a stream handed to `serve`, rather than from the equipment, so it can be the
+/// data of a `DeviceGroup`, or anything else the data got augmented with. Control point commands of
+/// apps go to the equipment, once an app took control like FTMS requires, and get answered with its
+/// result: success, or refused as unsupported, invalid or failed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use kondis::bridge::Bridge;
+/// use kondis::devices::GenericFtmsBike;
+/// use kondis::{CancellationToken, Equipment, FTMSData};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut bike = GenericFtmsBike::new(400, &shutdown).await?;
+///     bike.connect().await?;
+///     let data = bike.data_stream().await?.map(|reading| FTMSData::from(reading.data));
+///     Bridge::new(&shutdown).name("my bridge").serve(&bike, data).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    name: String,
+    shutdown: CancellationToken,
+}
+
+impl Bridge {
+    /// A bridge advertised as "kondis bridge", serving until `shutdown` is cancelled
+    pub fn new(shutdown: &CancellationToken) -> Self {
+        Bridge {
+            name: DEFAULT_NAME.to_string(),
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Advertise the bridge as `name`
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Serve `equipment` with `data`, until shut down or until `data` ends
+    ///
+    /// The features apps are told about are the capabilities of the equipment.
+    pub async fn serve<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        data: impl Stream<Item = FTMSData> + Send,
+    ) -> Result<()> {
+        let capabilities = equipment.capabilities().unwrap_or_default();
+        let (min, max, increment) = POWER_RANGE;
+        let power_range = encode::supported_range(min, max, increment);
+        let (min, max, increment) = RESISTANCE_LEVEL_RANGE;
+        let resistance_range = encode::supported_range(min, max, increment);
+        let service = Service {
+            uuid: FITNESS_MACHINE_SERVICE_UUID,
+            characteristics: vec![
+                Characteristic {
+                    uuid: FITNESS_MACHINE_FEATURE_UUID,
+                    flags: &["read"],
+                    value: encode::fitness_machine_feature(&capabilities),
+                },
+                Characteristic {
+                    uuid: INDOOR_BIKE_DATA_UUID,
+                    flags: &["notify"],
+                    value: Vec::new(),
+                },
+                Characteristic {
+                    uuid: CONTROL_POINT_UUID,
+                    flags: &["write", "indicate"],
+                    value: Vec::new(),
+                },
+                Characteristic {
+                    uuid: SUPPORTED_POWER_RANGE_UUID,
+                    flags: &["read"],
+                    value: power_range,
+                },
+                Characteristic {
+                    uuid: SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID,
+                    flags: &["read"],
+                    value: resistance_range,
+                },
+            ],
+        };
+        let advertisement = Advertisement {
+            name: self.name.clone(),
+            services: vec![FITNESS_MACHINE_SERVICE_UUID],
+            service_data: vec![(
+                FITNESS_MACHINE_SERVICE_UUID,
+                encode::indoor_bike_service_data(),
+            )],
+            appearance: None,
+        };
+        let (server, mut writes) = GattServer::register(vec![service], advertisement).await?;
+        info!(device = %self.name, "Serving as an indoor bike");
+
+        let mut data = pin!(data);
+        let mut session = Session::default();
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                data = data.next() => match data {
+                    Some(data) => server.notify(INDOOR_BIKE_DATA_UUID, encode::indoor_bike_data(&data)),
+                    None => break,
+                },
+                write = writes.recv() => match write {
+                    Some(write) if write.uuid == CONTROL_POINT_UUID => {
+                        let response = control(equipment, &write.value, &mut session).await;
+                        server.notify(CONTROL_POINT_UUID, response);
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+            }
+        }
+        server.close().await
+    }
+
+    /// Serve `data` as a standard heart rate monitor, until shut down or until `data` ends
+    ///
+    /// For apps that only get to connect to a monitor once, while kondis is connected to it too, or
+    /// for heart rate kondis got from elsewhere, like an ANT+ monitor or the grips of a bike.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use kondis::bridge::Bridge;
+    /// use kondis::devices::HeartRateMonitor;
+    /// use kondis::{CancellationToken, Equipment, MachineData};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut monitor = HeartRateMonitor::new(0, &shutdown).await?;
+    ///     monitor.connect().await?;
+    ///     let data = monitor.data_stream().await?.filter_map(async |reading| match reading.data {
+    ///         MachineData::HeartRate(data) => Some(data),
+    ///         _ => None,
+    ///     });
+    ///     Bridge::new(&shutdown).serve_heart_rate(data).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn serve_heart_rate(
+        &self,
+        data: impl Stream<Item = HeartRateData> + Send,
+    ) -> Result<()> {
+        let service = Service {
+            uuid: HEART_RATE_SERVICE_UUID,
+            characteristics: vec![Characteristic {
+                uuid: HEART_RATE_MEASUREMENT_UUID,
+                flags: &["notify"],
+                value: Vec::new(),
+            }],
+        };
+        let advertisement = Advertisement {
+            name: self.name.clone(),
+            services: vec![HEART_RATE_SERVICE_UUID],
+            service_data: Vec::new(),
+            appearance: Some(HEART_RATE_BELT),
+        };
+        // nothing of a heart rate monitor gets written to
+        let (server, _) = GattServer::register(vec![service], advertisement).await?;
+        info!(device = %self.name, "Serving as a heart rate monitor");
+
+        let mut data = pin!(data.take_until(self.shutdown.cancelled()));
+        while let Some(data) = data.next().await {
+            server.notify(
+                HEART_RATE_MEASUREMENT_UUID,
+                encode::heart_rate_measurement(&data),
+            );
+        }
+        server.close().await
+    }
+}
+
+/// What apps did through the control point so far
+#[derive(Debug, Default)]
+struct Session {
+    /// Whether an app took control, which FTMS requires before any other command
+    controlled: bool,
+    /// FTMS starts and resumes with the same command
+    paused: bool,
+}
+
+/// Run a control point command of an app on `equipment`, resolving to the indication answering it
+async fn control<E: Equipment + ?Sized>(
+    equipment: &E,
+    command: &[u8],
+    session: &mut Session,
+) -> Vec<u8> {
+    let op_code = command.first().copied().unwrap_or_default();
+    let result = match encode::parse_command(command) {
+        Ok(Command::RequestControl) => {
+            session.controlled = true;
+            Ok(())
+        }
+        Ok(_) if !session.controlled => Err(ResultCode::ControlNotPermitted),
+        Ok(command) => run(equipment, command, session).await.map_err(|e| match e {
+            KondisError::ControlRejected(rejected) => rejected.result,
+            KondisError::InvalidArgument(_) => ResultCode::InvalidParameter,
+            KondisError::Unsupported(_) => ResultCode::NotSupported,
+            _ => ResultCode::OperationFailed,
+        }),
+        Err(result) => Err(result),
+    };
+    encode::control_point_response(op_code, result.err().unwrap_or(ResultCode::Success))
+}
+
+async fn run<E: Equipment + ?Sized>(
+    equipment: &E,
+    command: Command,
+    session: &mut Session,
+) -> Result<()> {
+    match command {
+        Command::RequestControl => Ok(()),
+        Command::Reset => equipment.reset().await,
+        Command::TargetResistanceLevel(level) => {
+            equipment
+                .set_target_resistance_level(level.round() as i16)
+                .await
+        }
+        Command::TargetPower(watts) => equipment.set_target_power(watts).await,
+        Command::TargetHeartRate(bpm) => equipment.set_target_heart_rate(bpm).await,
+        Command::Start if session.paused => {
+            equipment.resume().await?;
+            session.paused = false;
+            Ok(())
+        }
+        Command::Start => equipment.start().await,
+        Command::Stop => {
+            equipment.stop().await?;
+            session.paused = false;
+            Ok(())
+        }
+        Command::Pause => {
+            equipment.pause().await?;
+            session.paused = true;
+            Ok(())
+        }
+        Command::SimulationParameters {
+            wind_speed,
+            grade,
+            crr,
+            cw,
+        } => {
+            equipment
+                .set_simulation_parameters(grade, wind_speed, crr, cw)
+                .await
+        }
+        Command::TargetCadence(rpm) => equipment.set_target_cadence(rpm.round() as i16).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::SimulatorBike;
+
+    #[tokio::test]
+    async fn test_control() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut bike = SimulatorBike::new(400, &shutdown).await?;
+        bike.connect().await?;
+        let mut session = Session::default();
+        // control has to be requested first
+        assert_eq!(
+            control(&bike, &[0x05, 0xC8, 0x00], &mut session).await,
+            [0x80, 0x05, 0x05]
+        );
+        assert_eq!(
+            control(&bike, &[0x00], &mut session).await,
+            [0x80, 0x00, 0x01]
+        );
+        assert_eq!(
+            control(&bike, &[0x05, 0xC8, 0x00], &mut session).await,
+            [0x80, 0x05, 0x01]
+        );
+        // above the maximum of the bike
+        assert_eq!(
+            control(&bike, &[0x05, 0xE8, 0x03], &mut session).await,
+            [0x80, 0x05, 0x03]
+        );
+        assert_eq!(
+            control(&bike, &[0x13, 0x01], &mut session).await,
+            [0x80, 0x13, 0x02]
+        );
+        assert_eq!(
+            control(&bike, &[0x08, 0x02], &mut session).await,
+            [0x80, 0x08, 0x01]
+        );
+        assert!(session.paused);
+        assert_eq!(
+            control(&bike, &[0x07], &mut session).await,
+            [0x80, 0x07, 0x01]
+        );
+        assert!(!session.paused);
+        shutdown.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::{KondisError, Result};
+
+/// The first line of every capture file
+const HEADER: &str = "# kondis capture";
+
+/// A notification of a device, as it was captured
+///
+/// Captures are text files, the notifications being separated by lines, each holding the seconds since
+/// the capture started, the characteristic and the value in hex:
+///
+/// ```text
+/// # kondis capture
+/// 0.984211 00002ad2-0000-1000-8000-00805f9b34fb 44020000000000
+/// ```
+///
+/// See `ScanConfig::capture` to capture the notifications of a device, and `ReplayDevice` to play them
+/// back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CapturedNotification {
+    /// When the notification arrived, from the start of the capture
+    pub offset: Duration,
+    /// The characteristic that notified
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// Parse a capture file, see `CapturedNotification`
+///
+/// # Examples
+///
+/// ```
+/// let capture = kondis::parse_capture(
+///     "# kondis capture\n0.5 00002ad2-0000-1000-8000-00805f9b34fb 44020000\n",
+/// )?;
+/// assert_eq!(capture[0].value, [0x44, 0x02, 0x00, 0x00]);
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_capture(text: &str) -> Result<Vec<CapturedNotification>> {
+    let invalid = |line: &str| KondisError::InvalidData(format!("Invalid capture line {line:?}"));
+    let mut notifications = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split_whitespace();
+        let (Some(offset), Some(uuid)) = (columns.next(), columns.next()) else {
+            return Err(invalid(line));
+        };
+        let offset = offset
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| invalid(line))?;
+        let uuid = Uuid::parse_str(uuid).map_err(|_| invalid(line))?;
+        let value = from_hex(columns.next().unwrap_or_default()).ok_or_else(|| invalid(line))?;
+        notifications.push(CapturedNotification {
+            offset,
+            uuid,
+            value,
+        });
+    }
+    Ok(notifications)
+}
+
+/// Writes every notification to a capture file as it arrives
+#[derive(Debug)]
+pub(crate) struct CaptureWriter {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+impl CaptureWriter {
+    /// Start a capture in the file at `path`, replacing anything it held
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = LineWriter::new(File::create(path)?);
+        writeln!(file, "{HEADER}")?;
+        Ok(CaptureWriter {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, uuid: Uuid, value: &[u8]) -> Result<()> {
+        let offset = self.start.elapsed().as_secs_f64();
+        writeln!(self.file, "{offset:.6} {uuid} {}", to_hex(value))?;
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ftms::INDOOR_BIKE_DATA_UUID;
+
+    #[test]
+    fn test_write_and_parse() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("kondis-capture-{}.txt", std::process::id()));
+        let mut writer = CaptureWriter::create(&path)?;
+        writer.write(INDOOR_BIKE_DATA_UUID, &[0x44, 0x02, 0xff])?;
+        writer.write(INDOOR_BIKE_DATA_UUID, &[])?;
+        drop(writer);
+
+        let capture = parse_capture(&std::fs::read_to_string(&path)?)?;
+        std::fs::remove_file(path)?;
+        assert_eq!(capture.len(), 2);
+        assert_eq!(capture[0].uuid, INDOOR_BIKE_DATA_UUID);
+        assert_eq!(capture[0].value, [0x44, 0x02, 0xff]);
+        assert!(capture[1].value.is_empty());
+        assert!(capture[0].offset <= capture[1].offset);
+
+        assert!(parse_capture("0.5 not-a-uuid 00").is_err());
+        assert!(parse_capture("0.5 00002ad2-0000-1000-8000-00805f9b34fb 0").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! CSAFE, the command protocol of the Concept2 performance monitors and plenty of older gym equipment
+//!
+//! A frame wraps one or more commands between a start and a stop flag, followed by the XOR of every
+//! byte of its contents. Bytes of the contents colliding with the flags get stuffed into two bytes.
+
+use crate::ftms::TrainingGoal;
+use crate::{KondisError, Result};
+
+const EXTENDED_START: u8 = 0xF0;
+const START: u8 = 0xF1;
+pub(crate) const STOP: u8 = 0xF2;
+const STUFF: u8 = 0xF3;
+
+/// Short commands, made of their id alone
+pub(crate) mod command {
+    pub const GET_STATUS: u8 = 0x80;
+    pub const RESET: u8 = 0x81;
+    pub const GO_IN_USE: u8 = 0x85;
+    pub const GO_FINISHED: u8 = 0x86;
+    /// Hours, minutes and seconds of work
+    pub const GET_TWORK: u8 = 0xA0;
+    /// Distance, and its unit
+    pub const GET_HORIZONTAL: u8 = 0xA1;
+    pub const GET_CALORIES: u8 = 0xA3;
+    /// Seconds per km, and the unit
+    pub const GET_PACE: u8 = 0xA6;
+    /// Strokes or revolutions per minute, and the unit
+    pub const GET_CADENCE: u8 = 0xA7;
+    /// bpm, 0 without a heart rate monitor
+    pub const GET_HR_CUR: u8 = 0xB0;
+    /// W, and the unit
+    pub const GET_POWER: u8 = 0xB4;
+}
+
+/// Long commands, followed by the length of their data and the data itself
+pub(crate) mod long {
+    pub const SET_TWORK: u8 = 0x20;
+    pub const SET_HORIZONTAL: u8 = 0x21;
+    pub const SET_CALORIES: u8 = 0x23;
+    pub const SET_PROGRAM: u8 = 0x24;
+    pub const SET_POWER: u8 = 0x34;
+}
+
+/// Bits of the status answering a frame, telling how the equipment took the frame before
+const PREVIOUS_FRAME_STATUS: u8 = 0x30;
+const PREVIOUS_FRAME_REJECTED: u8 = 0x10;
+
+/// Units of the data of commands and their responses
+pub(crate) mod unit {
+    pub const KILOMETERS: u8 = 0x21;
+    pub const METERS: u8 = 0x24;
+    pub const MILES: u8 = 0x01;
+    pub const WATTS: u8 = 0x58;
+}
+
+/// Wrap `commands` into a frame, stuffing and checksumming them
+pub(crate) fn frame(commands: &[u8]) -> Vec<u8> {
+    let checksum = commands.iter().fold(0, |checksum, byte| checksum ^ byte);
+    let mut frame = vec![START];
+    for &byte in commands.iter().chain([&checksum]) {
+        match byte {
+            EXTENDED_START..=STUFF => frame.extend([STUFF, byte - EXTENDED_START]),
+            byte => frame.push(byte),
+        }
+    }
+    frame.push(STOP);
+    frame
+}
+
+/// The contents of a frame, unstuffed and with its checksum checked and left out
+pub(crate) fn unframe(frame: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || KondisError::InvalidData(format!("Invalid CSAFE frame: {frame:02x?}"));
+    let [START | EXTENDED_START, ref stuffed @ .., STOP] = *frame else {
+        return Err(invalid());
+    };
+    let mut contents = Vec::with_capacity(stuffed.len());
+    let mut bytes = stuffed.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            STUFF => match bytes.next() {
+                Some(&stuffed @ 0..=3) => contents.push(EXTENDED_START + stuffed),
+                _ => return Err(invalid()),
+            },
+            byte => contents.push(byte),
+        }
+    }
+    let Some(checksum) = contents.pop() else {
+        return Err(invalid());
+    };
+    if contents.iter().fold(0, |checksum, byte| checksum ^ byte) != checksum {
+        return Err(invalid());
+    }
+    Ok(contents)
+}
+
+/// Pull the first whole frame out of the bytes received so far, dropping anything before its start
+pub(crate) fn take_frame(received: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = received
+        .iter()
+        .position(|&byte| byte == START || byte == EXTENDED_START)?;
+    let stop = start + received[start..].iter().position(|&byte| byte == STOP)?;
+    let frame = received[start..=stop].to_vec();
+    received.drain(..=stop);
+    Some(frame)
+}
+
+/// What equipment answers a frame with, see `responses`
+#[derive(Debug)]
+pub(crate) struct Responses<'a> {
+    /// The state of the equipment, and how it took the previous frame
+    pub status: u8,
+    /// The data answering every command, by the id of the command
+    pub data: Vec<(u8, &'a [u8])>,
+}
+
+impl Responses<'_> {
+    /// Whether the equipment refused the commands of the previous frame
+    pub fn rejected(&self) -> bool {
+        self.status & PREVIOUS_FRAME_STATUS == PREVIOUS_FRAME_REJECTED
+    }
+}
+
+/// The responses in the contents of a frame
+///
+/// Commands the equipment doesn't support are left out of the responses.
+pub(crate) fn responses(contents: &[u8]) -> Result<Responses<'_>> {
+    let invalid = || KondisError::InvalidData(format!("Invalid CSAFE response: {contents:02x?}"));
+    let [status, ref rest @ ..] = *contents else {
+        return Err(invalid());
+    };
+    let mut data = Vec::new();
+    let mut rest = rest;
+    while let [id, length, ref response @ ..] = *rest {
+        let length = usize::from(length);
+        if response.len() < length {
+            return Err(invalid());
+        }
+        data.push((id, &response[..length]));
+        rest = &response[length..];
+    }
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Responses { status, data })
+}
+
+/// The commands programming `goal` as the workout, leaving the monitor ready to start it
+pub(crate) fn goal(goal: TrainingGoal) -> Result<Vec<u8>> {
+    let mut commands = match goal {
+        TrainingGoal::Distance(meters) => {
+            let meters = u16::try_from(meters).map_err(|_| {
+                KondisError::InvalidArgument(
+                    "Distance goals must be at most 65535 meters".to_string(),
+                )
+            })?;
+            let [lsb, msb] = meters.to_le_bytes();
+            vec![long::SET_HORIZONTAL, 3, lsb, msb, unit::METERS]
+        }
+        TrainingGoal::TrainingTime(seconds) => {
+            let hours = seconds / 3600;
+            let minutes = seconds % 3600 / 60;
+            vec![
+                long::SET_TWORK,
+                3,
+                hours as u8,
+                minutes as u8,
+                (seconds % 60) as u8,
+            ]
+        }
+        TrainingGoal::ExpendedEnergy(calories) => {
+            let [lsb, msb] = calories.to_le_bytes();
+            vec![long::SET_CALORIES, 2, lsb, msb]
+        }
+    };
+    // the programmed workout
+    commands.extend([long::SET_PROGRAM, 2, 0, 0]);
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() -> Result<()> {
+        assert_eq!(frame(&[command::GO_IN_USE]), [0xF1, 0x85, 0x85, 0xF2]);
+        // 0xF1 in the contents, and 0xF2 as the checksum, both get stuffed
+        let commands = [0x21, 0x03, 0xF1, 0x00, 0x21];
+        assert_eq!(
+            frame(&commands),
+            [0xF1, 0x21, 0x03, 0xF3, 0x01, 0x00, 0x21, 0xF3, 0x02, 0xF2]
+        );
+
+        assert_eq!(
+            goal(TrainingGoal::TrainingTime(3725))?,
+            [0x20, 3, 1, 2, 5, 0x24, 2, 0, 0]
+        );
+        assert_eq!(
+            goal(TrainingGoal::Distance(2000))?[..5],
+            [0x21, 3, 0xD0, 0x07, 0x24]
+        );
+        assert!(goal(TrainingGoal::Distance(100_000)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_responses() -> Result<()> {
+        // a status, 12:34 of work and 2500 m, split across reads with noise before the first frame
+        let contents = [
+            0x05, 0xA0, 0x03, 0x00, 0x0C, 0x22, 0xA1, 0x03, 0xC4, 0x09, 0x24,
+        ];
+        let mut received = vec![0x00];
+        received.extend(frame(&contents));
+        let rest = received.split_off(6);
+        assert_eq!(take_frame(&mut received), None);
+        received.extend(rest);
+        let framed = take_frame(&mut received).expect("a whole frame");
+        assert!(received.is_empty());
+
+        let contents = unframe(&framed)?;
+        let responses = responses(&contents)?;
+        assert_eq!(responses.status, 0x05);
+        assert!(!responses.rejected());
+        assert_eq!(
+            responses.data,
+            [
+                (0xA0, &[0x00, 0x0C, 0x22][..]),
+                (0xA1, &[0xC4, 0x09, 0x24][..])
+            ]
+        );
+
+        let mut corrupt = framed.clone();
+        corrupt[2] ^= 0x01;
+        assert!(unframe(&corrupt).is_err());
+        assert!(unframe(&[0xF1, 0xF2]).is_err());
+        // cut short
+        assert!(super::responses(&contents[..9]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use btleplug::api::{Central as _, CentralEvent, Peripheral as _, ScanFilter};
+use btleplug::platform::Peripheral;
+use futures::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, adapters};
+use crate::devices::events::EventSender;
+use crate::ftms::MachineData;
+use crate::{DeviceEvent, Reading, Result};
+
+/// Decode the manufacturer data of an advertisement, into nothing when it holds no live data
+pub(crate) type DecodeAdvertisement = fn(&[u8]) -> Result<Option<MachineData>>;
+
+/// Forward the data a peripheral broadcasts in its advertisements as events, until `stop` gets cancelled
+///
+/// For equipment that only ever advertises its data, rather than letting itself be connected to. The
+/// adapters of `config` keep scanning for as long as this listens, and only the manufacturer data of
+/// `company`, a Bluetooth SIG company identifier, gets decoded.
+pub(crate) async fn listen(
+    peripheral: &Peripheral,
+    company: u16,
+    decode: DecodeAdvertisement,
+    config: &ScanConfig,
+    events_tx: EventSender,
+    stop: CancellationToken,
+) -> Result<()> {
+    let adapters = adapters(config).await?;
+    let mut events = Vec::new();
+    for adapter in &adapters {
+        events.push(adapter.events().await?);
+        adapter.start_scan(ScanFilter::default()).await?;
+    }
+    let id = peripheral.id();
+    let mut events = futures::stream::select_all(events)
+        .take_until(stop.cancelled_owned())
+        .boxed();
+    crate::runtime::spawn(async move {
+        let mut sequence = 0;
+        while let Some(event) = events.next().await {
+            let CentralEvent::ManufacturerDataAdvertisement {
+                id: advertiser,
+                manufacturer_data,
+            } = event
+            else {
+                continue;
+            };
+            let Some(data) = manufacturer_data.get(&company) else {
+                continue;
+            };
+            if advertiser != id {
+                continue;
+            }
+            match decode(data) {
+                Ok(Some(data)) => {
+                    events_tx.send(DeviceEvent::Data(Reading::new(sequence, data)));
+                    sequence += 1;
+                }
+                Ok(None) => {}
+                Err(e) => events_tx.send(DeviceEvent::Error(e.to_string())),
+            }
+        }
+        for adapter in adapters {
+            let _ = adapter.stop_scan().await;
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,465 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_serial::SerialStream;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::ant::{self, ACKNOWLEDGED_DATA, Message};
+use crate::bluetooth::within;
+use crate::devices::events::EventSender;
+use crate::devices::serial::{self, Baud};
+use crate::fec::Page;
+use crate::ftms::MachineData;
+use crate::{ConnectionState, DeviceEvent, Reading};
+use crate::{KondisError, Result};
+
+/// Messages of the stick itself, rather than of the devices it talks to
+mod id {
+    pub const CHANNEL_EVENT: u8 = 0x40;
+    pub const UNASSIGN_CHANNEL: u8 = 0x41;
+    pub const ASSIGN_CHANNEL: u8 = 0x42;
+    pub const CHANNEL_PERIOD: u8 = 0x43;
+    pub const CHANNEL_RF_FREQUENCY: u8 = 0x45;
+    pub const NETWORK_KEY: u8 = 0x46;
+    pub const RESET_SYSTEM: u8 = 0x4A;
+    pub const OPEN_CHANNEL: u8 = 0x4B;
+    pub const CLOSE_CHANNEL: u8 = 0x4C;
+    pub const REQUEST: u8 = 0x4D;
+    pub const CHANNEL_ID: u8 = 0x51;
+    pub const STARTUP: u8 = 0x6F;
+}
+
+/// Events of a channel, reported in a channel event in place of the id of a message answered
+const EVENT: u8 = 0x01;
+pub(crate) mod event {
+    pub const RX_SEARCH_TIMEOUT: u8 = 0x01;
+    pub const TRANSFER_TX_COMPLETED: u8 = 0x05;
+    pub const TRANSFER_TX_FAILED: u8 = 0x06;
+    pub const CHANNEL_CLOSED: u8 = 0x07;
+    pub const RX_FAIL_GO_TO_SEARCH: u8 = 0x08;
+}
+
+/// The network key every ANT+ device listens on
+const ANT_PLUS_NETWORK_KEY: [u8; 8] = [0xB9, 0xA5, 0x21, 0xFB, 0xBD, 0x72, 0xC3, 0x45];
+/// The network of the stick the ANT+ key is set on
+const NETWORK: u8 = 0;
+/// 2457 MHz, the frequency of ANT+
+const ANT_PLUS_FREQUENCY: u8 = 57;
+/// A channel receiving from a master, and sending back to it
+const BIDIRECTIONAL_RECEIVE: u8 = 0x00;
+/// The number of channels of the smallest sticks
+const CHANNELS: u8 = 8;
+
+/// How long the stick gets to start up after a reset, which older sticks don't report
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long the stick gets to answer a message, including acknowledged data going out with the next
+/// period of the channel
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a channel of an ANT+ device profile listens for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Profile {
+    pub device_type: u8,
+    /// 1/32768 s
+    pub period: u16,
+}
+
+/// Trainers speaking FE-C, at 4 Hz
+pub(crate) const FITNESS_EQUIPMENT: Profile = Profile {
+    device_type: 17,
+    period: 8192,
+};
+/// Heart rate monitors, at 4.06 Hz
+pub(crate) const HEART_RATE: Profile = Profile {
+    device_type: 120,
+    period: 8070,
+};
+/// Bicycle power meters, at 4.005 Hz
+pub(crate) const BICYCLE_POWER: Profile = Profile {
+    device_type: 11,
+    period: 8182,
+};
+
+/// The sticks opened so far, shared by every device using them, see `AntStick::shared`
+static STICKS: tokio::sync::Mutex<Vec<(PathBuf, Weak<AntStick>)>> =
+    tokio::sync::Mutex::const_new(Vec::new());
+
+/// An ANT USB stick, like the ANT USB-m or a Garmin USB2 stick, showing up as a serial port
+///
+/// The stick gets reset and set up with the ANT+ network key once opened, and is shared by every device
+/// talking through it from then on, each on a channel of its own, see `shared`. Every message it
+/// receives is handed to every subscriber, see `subscribe`, until the stick is dropped.
+#[derive(Debug)]
+pub(crate) struct AntStick {
+    writer: tokio::sync::Mutex<WriteHalf<SerialStream>>,
+    messages: broadcast::Sender<Message>,
+    /// The numbers of the channels in use
+    channels: Mutex<Vec<u8>>,
+    /// Stops reading from the stick once dropped
+    _reading: DropGuard,
+}
+
+impl AntStick {
+    /// The stick at `path`, like `/dev/ttyUSB0`, opened unless another device is using it already
+    ///
+    /// Opening a stick resets it, closing the channels of every other device, so devices talking
+    /// through the same stick share it instead.
+    pub async fn shared(path: &Path) -> Result<Arc<Self>> {
+        let mut sticks = STICKS.lock().await;
+        sticks.retain(|(_, stick)| stick.strong_count() > 0);
+        if let Some(stick) = sticks
+            .iter()
+            .find(|(opened, _)| opened == path)
+            .and_then(|(_, stick)| stick.upgrade())
+        {
+            return Ok(stick);
+        }
+        let stick = Arc::new(AntStick::open(path).await?);
+        sticks.push((path.to_path_buf(), Arc::downgrade(&stick)));
+        Ok(stick)
+    }
+
+    async fn open(path: &Path) -> Result<Self> {
+        let (mut reader, writer) = tokio::io::split(serial::open(path, Baud::B115200)?);
+        let (messages, _) = broadcast::channel(64);
+        let reading = CancellationToken::new();
+        let stop = reading.clone();
+        let received_tx = messages.clone();
+        crate::runtime::spawn(async move {
+            let mut received = Vec::new();
+            let mut buffer = [0; 256];
+            loop {
+                let read = tokio::select! {
+                    _ = stop.cancelled() => break,
+                    read = reader.read(&mut buffer) => read,
+                };
+                let Ok(read @ 1..) = read else {
+                    break;
+                };
+                received.extend_from_slice(&buffer[..read]);
+                while let Some(message) = ant::take_message(&mut received) {
+                    // corrupt messages are dropped, the next one starting over at its sync byte
+                    if let Ok(message) = Message::decode(&message) {
+                        let _ = received_tx.send(message);
+                    }
+                }
+            }
+        });
+        let stick = AntStick {
+            writer: tokio::sync::Mutex::new(writer),
+            messages,
+            channels: Mutex::new(Vec::new()),
+            _reading: reading.drop_guard(),
+        };
+
+        let mut messages = stick.subscribe();
+        stick.write(&Message::new(id::RESET_SYSTEM, [0])).await?;
+        let _ = crate::runtime::timeout(STARTUP_TIMEOUT, async {
+            while let Ok(message) = messages.recv().await {
+                if message.id == id::STARTUP {
+                    break;
+                }
+            }
+        })
+        .await;
+        let mut key = vec![NETWORK];
+        key.extend_from_slice(&ANT_PLUS_NETWORK_KEY);
+        stick.command(Message::new(id::NETWORK_KEY, key)).await?;
+        Ok(stick)
+    }
+
+    /// Every message received from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        self.messages.subscribe()
+    }
+
+    /// Open a channel searching for a device of `profile`, the first one found getting paired with
+    pub async fn open_channel(self: &Arc<Self>, profile: Profile) -> Result<Channel> {
+        let number = {
+            let mut channels = self.channels.lock().unwrap();
+            let Some(number) = (0..CHANNELS).find(|number| !channels.contains(number)) else {
+                return Err(KondisError::Unsupported(
+                    "Every channel of the ANT stick is in use".to_string(),
+                ));
+            };
+            channels.push(number);
+            number
+        };
+        let channel = Channel {
+            stick: self.clone(),
+            number,
+        };
+        let [period_lsb, period_msb] = profile.period.to_le_bytes();
+        let setup = [
+            Message::new(id::ASSIGN_CHANNEL, [number, BIDIRECTIONAL_RECEIVE, NETWORK]),
+            // any device number and transmission type
+            Message::new(id::CHANNEL_ID, [number, 0, 0, profile.device_type, 0]),
+            Message::new(id::CHANNEL_PERIOD, [number, period_lsb, period_msb]),
+            Message::new(id::CHANNEL_RF_FREQUENCY, [number, ANT_PLUS_FREQUENCY]),
+            Message::new(id::OPEN_CHANNEL, [number]),
+        ];
+        for message in setup {
+            if let Err(e) = self.command(message).await {
+                channel.release();
+                return Err(e);
+            }
+        }
+        Ok(channel)
+    }
+
+    async fn write(&self, message: &Message) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&message.encode()).await?;
+        Ok(writer.flush().await?)
+    }
+
+    /// Send `message` and wait for the stick to take it
+    async fn command(&self, message: Message) -> Result<()> {
+        let mut messages = self.subscribe();
+        self.write(&message).await?;
+        let [channel, ..] = message.data[..] else {
+            return Err(KondisError::InvalidArgument(
+                "ANT commands start with their channel".to_string(),
+            ));
+        };
+        let code = self
+            .wait_for(&mut messages, |received| match received.data[..] {
+                [answered, id, code]
+                    if received.id == id::CHANNEL_EVENT
+                        && answered == channel
+                        && id == message.id =>
+                {
+                    Some(code)
+                }
+                _ => None,
+            })
+            .await?;
+        if code != 0 {
+            return Err(KondisError::Unsupported(format!(
+                "The ANT stick refused message {:#04x} with code {code:#04x}",
+                message.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// The first message received that `pick` picks something out of
+    async fn wait_for<T>(
+        &self,
+        messages: &mut broadcast::Receiver<Message>,
+        pick: impl Fn(&Message) -> Option<T>,
+    ) -> Result<T> {
+        let received = crate::runtime::timeout(RESPONSE_TIMEOUT, async {
+            loop {
+                match messages.recv().await {
+                    Ok(message) => {
+                        if let Some(picked) = pick(&message) {
+                            return Ok(picked);
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => {
+                        return Err(KondisError::Disconnected(
+                            "The ANT stick went away".to_string(),
+                        ));
+                    }
+                }
+            }
+        })
+        .await;
+        received.unwrap_or_else(|_| {
+            Err(KondisError::Timeout(
+                "The ANT stick didn't answer in time".to_string(),
+            ))
+        })
+    }
+}
+
+/// A channel of an `AntStick`, paired with a single device once it is found
+#[derive(Debug, Clone)]
+pub(crate) struct Channel {
+    stick: Arc<AntStick>,
+    pub number: u8,
+}
+
+impl Channel {
+    /// Every message of the stick from now on, of this channel and every other
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        self.stick.subscribe()
+    }
+
+    /// The channel event `message` is of this channel, if it is one
+    pub fn event(&self, message: &Message) -> Option<u8> {
+        match message.data[..] {
+            [channel, EVENT, event]
+                if message.id == id::CHANNEL_EVENT && channel == self.number =>
+            {
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait for the first page of the device the channel searches for, which pairs with it, closing the
+    /// channel unless one arrives within `timeout`
+    pub async fn pair(
+        &self,
+        messages: &mut broadcast::Receiver<Message>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let first = within(timeout, KondisError::ConnectTimeout, async {
+            loop {
+                match messages.recv().await {
+                    Ok(message) => {
+                        if let Some(page) = self.page(&message) {
+                            return Ok(page.to_vec());
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err(KondisError::DeviceNotFound),
+                }
+            }
+        })
+        .await;
+        if first.is_err() {
+            let _ = self.close().await;
+        }
+        first
+    }
+
+    /// The data page `message` carries on this channel, if it carries one
+    pub fn page<'a>(&self, message: &'a Message) -> Option<&'a [u8]> {
+        message
+            .data_page()
+            .and_then(|(channel, page)| (channel == self.number).then_some(page))
+    }
+
+    /// Send a data page to the device, waiting for it to acknowledge receiving it
+    pub async fn send_page(&self, page: Page) -> Result<()> {
+        let mut messages = self.subscribe();
+        self.stick
+            .write(&Message::page(ACKNOWLEDGED_DATA, self.number, &page))
+            .await?;
+        let event = self
+            .stick
+            .wait_for(&mut messages, |message| {
+                self.event(message).filter(|event| {
+                    [event::TRANSFER_TX_COMPLETED, event::TRANSFER_TX_FAILED].contains(event)
+                })
+            })
+            .await?;
+        if event == event::TRANSFER_TX_FAILED {
+            return Err(KondisError::Timeout(
+                "The ANT device didn't acknowledge the page".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The device number of the device paired with, which tells devices of the same type apart
+    pub async fn device_number(&self) -> Result<u16> {
+        let mut messages = self.subscribe();
+        self.stick
+            .write(&Message::new(id::REQUEST, [self.number, id::CHANNEL_ID]))
+            .await?;
+        self.stick
+            .wait_for(&mut messages, |message| match message.data[..] {
+                [channel, lsb, msb, ..]
+                    if message.id == id::CHANNEL_ID && channel == self.number =>
+                {
+                    Some(u16::from_le_bytes([lsb, msb]))
+                }
+                _ => None,
+            })
+            .await
+    }
+
+    /// Close the channel, freeing it up for another device
+    pub async fn close(&self) -> Result<()> {
+        let closed = async {
+            let mut messages = self.subscribe();
+            self.stick
+                .command(Message::new(id::CLOSE_CHANNEL, [self.number]))
+                .await?;
+            // the channel can only be unassigned once it did close
+            self.stick
+                .wait_for(&mut messages, |message| {
+                    self.event(message)
+                        .filter(|event| *event == event::CHANNEL_CLOSED)
+                })
+                .await?;
+            self.stick
+                .command(Message::new(id::UNASSIGN_CHANNEL, [self.number]))
+                .await
+        };
+        let result = closed.await;
+        self.release();
+        result
+    }
+
+    fn release(&self) {
+        self.stick
+            .channels
+            .lock()
+            .unwrap()
+            .retain(|number| *number != self.number);
+    }
+}
+
+/// Turn the pages of the device paired with `channel` into readings with `decode`, starting with
+/// `first`, until `forwarding` is cancelled or the device is lost
+///
+/// The stick searching for the device again, after missing its pages for a while, shows as
+/// reconnecting. The device being lost for good disconnects.
+pub(crate) fn forward(
+    channel: Channel,
+    mut messages: broadcast::Receiver<Message>,
+    first: Vec<u8>,
+    mut decode: impl FnMut(&[u8]) -> Result<MachineData> + Send + 'static,
+    events_tx: EventSender,
+    forwarding: CancellationToken,
+) {
+    crate::runtime::spawn(async move {
+        let mut sequence = 0;
+        let mut page = Some(first);
+        loop {
+            if let Some(page) = page.take() {
+                let event = match decode(&page) {
+                    Ok(data) => DeviceEvent::Data(Reading::new(sequence, data)),
+                    Err(e) => DeviceEvent::Error(e.to_string()),
+                };
+                sequence += 1;
+                if events_tx.state() == ConnectionState::Reconnecting {
+                    events_tx.set_state(ConnectionState::Connected);
+                }
+                events_tx.send(event);
+            }
+            let message = tokio::select! {
+                _ = forwarding.cancelled() => break,
+                message = messages.recv() => message,
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    events_tx.send(DeviceEvent::Disconnected);
+                    break;
+                }
+            };
+            page = channel.page(&message).map(<[u8]>::to_vec);
+            match channel.event(&message) {
+                Some(event::RX_FAIL_GO_TO_SEARCH) => {
+                    events_tx.set_state(ConnectionState::Reconnecting);
+                }
+                Some(event::RX_SEARCH_TIMEOUT | event::CHANNEL_CLOSED) => {
+                    events_tx.send(DeviceEvent::Disconnected);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    });
+}
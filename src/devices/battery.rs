@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex};
+
+use btleplug::api::CharPropFlags;
+
+use crate::DeviceEvent;
+use crate::bluetooth::Transport;
+use crate::devices::events::EventSender;
+use crate::sensors::BATTERY_LEVEL_UUID;
+
+/// The battery level in percent below which the battery is low, see `ScanConfig::low_battery`
+pub(crate) const DEFAULT_LOW_BATTERY: u8 = 20;
+
+/// The battery level of a peripheral, read when connecting and kept up to date by its notifications
+#[derive(Debug, Clone)]
+pub(crate) struct Battery {
+    level: Arc<Mutex<Option<u8>>>,
+    /// %
+    low: u8,
+}
+
+impl Battery {
+    pub fn new(low: u8) -> Self {
+        Battery {
+            level: Arc::new(Mutex::new(None)),
+            low,
+        }
+    }
+
+    /// The latest level, unless the peripheral never reported one
+    pub fn level(&self) -> Option<u8> {
+        *self.level.lock().unwrap()
+    }
+
+    /// Read the level of a connected peripheral and subscribe to its changes, if it has a Battery Service
+    ///
+    /// Peripherals without a battery, or failing to report its level, are left at an unknown level
+    /// rather than failing to connect.
+    pub async fn connect(&self, peripheral: &impl Transport, events_tx: &EventSender) {
+        let Some(characteristic) = peripheral.characteristic(BATTERY_LEVEL_UUID) else {
+            return;
+        };
+        if characteristic.properties.contains(CharPropFlags::READ)
+            && let Ok(value) = peripheral.read(&characteristic).await
+        {
+            self.update(&value, events_tx);
+        }
+        if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+            let _ = peripheral.subscribe(&characteristic).await;
+        }
+    }
+
+    /// Take in the value of the battery level characteristic, reporting changes of the level, and the
+    /// battery dropping below the low level
+    pub fn update(&self, value: &[u8], events_tx: &EventSender) {
+        let Some(level) = value.first().map(|level| (*level).min(100)) else {
+            return;
+        };
+        let previous = self.level.lock().unwrap().replace(level);
+        if previous == Some(level) {
+            return;
+        }
+        events_tx.send(DeviceEvent::BatteryLevel(level));
+        if level < self.low && previous.is_none_or(|previous| previous >= self.low) {
+            events_tx.send(DeviceEvent::LowBattery(level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::events;
+
+    #[test]
+    fn test_battery() {
+        let events_tx = events::channel();
+        let mut events = events_tx.subscribe();
+        let battery = Battery::new(20);
+        assert_eq!(battery.level(), None);
+        for level in [25, 25, 19, 18, 21, 10] {
+            battery.update(&[level], &events_tx);
+        }
+        battery.update(&[], &events_tx);
+        assert_eq!(battery.level(), Some(10));
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(match event {
+                DeviceEvent::BatteryLevel(level) => (level, false),
+                DeviceEvent::LowBattery(level) => (level, true),
+                event => panic!("unexpected {event:?}"),
+            });
+        }
+        // unchanged levels aren't reported, and the battery only becomes low once per drop
+        assert_eq!(
+            received,
+            [
+                (25, false),
+                (19, false),
+                (19, true),
+                (18, false),
+                (21, false),
+                (10, false),
+                (10, true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::devices::ant_stick::{self, AntStick, Channel, FITNESS_EQUIPMENT};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::fec::{self, FecDecoder, Page};
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal,
+    simulation_parameters,
+};
+use crate::{ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream};
+use crate::{KondisError, Result};
+
+/// A trainer speaking ANT+ FE-C, through an ANT USB stick, for trainers without Bluetooth or with
+/// Bluetooth taken by another app.
+/// The stick to talk through is set with `ScanConfig::port`, like `/dev/ttyUSB0`, and the first
+/// trainer it finds gets paired with.
+///
+/// Readings hold `MachineData::Bike`, gathered from the general and trainer data pages. Target power,
+/// simulation parameters and resistance levels are supported, the latter as a percentage of the
+/// maximum resistance of the trainer. Available with the `ant` feature.
+#[derive(Debug, Clone)]
+pub struct AntFecBike {
+    /// "ANT+ FE-C" and the device number of the trainer once connected, the path of the stick until then
+    pub name: String,
+    path: PathBuf,
+    channel: Arc<Mutex<Option<Channel>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops forwarding the pages of the trainer, replaced with every connection
+    forwarding: CancellationToken,
+    shutdown: CancellationToken,
+    max_level: i16,
+}
+
+impl Equipment for AntFecBike {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let Some(path) = config.port.clone() else {
+            return Err(KondisError::InvalidArgument(
+                "ANT+ trainers need an ANT stick to talk through, see `ScanConfig::port`"
+                    .to_string(),
+            ));
+        };
+        Ok(AntFecBike {
+            name: path.display().to_string(),
+            path,
+            channel: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            forwarding: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let stick = AntStick::shared(&self.path).await?;
+                let channel = stick.open_channel(FITNESS_EQUIPMENT).await?;
+                let mut messages = channel.subscribe();
+                let first = channel.pair(&mut messages, self.timeouts.connect).await?;
+                self.name = format!("ANT+ FE-C {}", channel.device_number().await?);
+                *self.channel.lock().unwrap() = Some(channel.clone());
+                self.forwarding.cancel();
+                self.forwarding = self.shutdown.child_token();
+                let mut decoder = FecDecoder::new();
+                ant_stick::forward(
+                    channel,
+                    messages,
+                    first,
+                    move |page| Ok(decoder.update(page)?.into()),
+                    self.events_tx.clone(),
+                    self.forwarding.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to trainer");
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.forwarding.cancel();
+        let channel = self.channel.lock().unwrap().take();
+        if let Some(channel) = channel {
+            channel.close().await?;
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&watts) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Watts must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        self.send(fec::target_power(watts)).await
+    }
+
+    /// The level is a percentage of the maximum resistance of the trainer
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(0..=100).contains(&level) {
+            return Err(KondisError::InvalidArgument(
+                "Resistance level must be between 0 and 100 %".to_string(),
+            ));
+        }
+        self.send(fec::basic_resistance(level.into())).await
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        // validated the same as over FTMS
+        simulation_parameters(grade, wind_speed, crr, cw)?;
+        self.send(fec::wind_resistance(cw, wind_speed)).await?;
+        self.send(fec::track_resistance(grade, crr)).await
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                heart_rate: true,
+                elapsed_time: true,
+                power: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected, holding what every data page so far added to it
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl AntFecBike {
+    async fn send(&self, page: Page) -> Result<()> {
+        let channel = self.channel.lock().unwrap().clone();
+        let Some(channel) = channel else {
+            return Err(KondisError::Disconnected(format!(
+                "Not connected to {}",
+                self.name
+            )));
+        };
+        until_shutdown(&self.shutdown, channel.send_page(page)).await
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("ANT+ FE-C trainers do not support {what}"))
+}
@@ -1,107 +1,276 @@
-use std::sync::mpsc::Receiver;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use async_trait::async_trait;
 use btleplug::{
-    api::{CharPropFlags, Characteristic, Peripheral as _},
+    api::{CharPropFlags, Characteristic, Peripheral as _, ValueNotification},
     platform::Peripheral,
 };
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-use crate::bluetooth::get_peripheral;
-use crate::ftms::FTMSData;
-use crate::{Equipment, EquipmentType};
+use crate::bluetooth::{ScanConfig, get_peripheral};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, CROSS_TRAINER_DATA_UUID, Capabilities, FITNESS_MACHINE_STATUS_UUID,
+    INDOOR_BIKE_DATA_UUID, MachineData, ROWER_DATA_UUID, SpinDownResult, SpinDownStatus,
+    TREADMILL_DATA_UUID, TrainingGoal, parse_cross_trainer_data, parse_indoor_bike_data,
+    parse_machine_status, parse_rower_data, parse_treadmill_data, simulation_parameters,
+    training_goal,
+};
+use crate::sensors::{HEART_RATE_MEASUREMENT_UUID, parse_heart_rate_measurement};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream, Reading,
+};
+use crate::{KondisError, Result};
 
 /// A debug bike.
-/// Any bluetooth device containing "Console" in its name gets connected to, and every `NOTIFY` characteristic gets subscribed to.
+/// Unless told otherwise, any bluetooth device containing "Console" in its name gets connected to,
+/// and every `NOTIFY` characteristic gets subscribed to.
+///
+/// To explore the protocol of another device, point it elsewhere with `ScanConfig::name_pattern`, or
+/// with `ScanConfig::service_uuid`, which takes any device advertising the service whatever its name.
+/// Every notification gets logged as a line of hex, along with what it decodes to when it looks like
+/// a standard characteristic, and dumped to a file as well with `hexdump`:
+///
+/// ```text
+///   1.250342 00002ad2-0000-1000-8000-00805f9b34fb 44 02 c4 09 b4 00 |D.....| BikeData { .. }
+///   1.318007 0000fff1-0000-1000-8000-00805f9b34fb f0 d1 09 55 |...U| u16: 53744 21769
+/// ```
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{devices::DebugBike, CancellationToken, Equipment, ScanConfig};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let config = ScanConfig::new().name_pattern("KICKR*");
+///     let mut bike = DebugBike::with_config(32, config, &shutdown).await?;
+///     bike.hexdump("notifications.txt");
+///     bike.connect().await?;
+///     Ok(())
+/// }
+/// ```
 #[derive(Debug, Clone)]
 pub struct DebugBike {
     peripheral: Peripheral,
     /// The name of the device
     pub name: String,
     idk: Vec<Characteristic>,
+    events_tx: EventSender,
+    shutdown: CancellationToken,
     max_level: i16,
+    /// Where to dump notifications to besides the log, see `hexdump`
+    hexdump: Option<PathBuf>,
 }
 
-#[async_trait]
 impl Equipment for DebugBike {
-    async fn new(max_level: i16, shutdown_rx: &mut Receiver<()>) -> anyhow::Result<Self> {
-        let meta = get_peripheral(EquipmentType::Iconsole0028Bike, shutdown_rx).await?;
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let meta = get_peripheral(EquipmentType::DebugBike, &config, shutdown).await?;
         if meta.is_none() {
-            return Err(anyhow::anyhow!("No peripheral found"));
+            return Err(KondisError::DeviceNotFound);
         }
         let meta = meta.unwrap();
         Ok(DebugBike {
             peripheral: meta.0,
             name: meta.1,
             idk: Vec::new(),
+            events_tx: events::channel(),
+            shutdown: shutdown.clone(),
             max_level,
+            hexdump: None,
         })
     }
 
-    async fn connect(&mut self) -> anyhow::Result<bool> {
-        let is_connected = self.peripheral.is_connected().await?;
-        if !is_connected {
-            self.peripheral.connect().await?;
-        }
-        self.set_characteristics().await?;
-        self.subscribe().await?;
-        println!("Found and connected to: {}", self.name);
-        Ok(self.peripheral.is_connected().await?)
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let is_connected = self.peripheral.is_connected().await?;
+                if !is_connected {
+                    self.peripheral.connect().await?;
+                }
+                self.set_characteristics().await?;
+                self.subscribe().await?;
+                let file = match &self.hexdump {
+                    Some(path) => Some(LineWriter::new(File::create(path)?)),
+                    None => None,
+                };
+                let notifications = self.peripheral.notifications().await?;
+                crate::runtime::spawn(dump(
+                    notifications.take_until(self.shutdown.clone().cancelled_owned()),
+                    file,
+                    self.name.clone(),
+                ));
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
     }
 
-    async fn disconnect(&self) -> anyhow::Result<()> {
+    async fn disconnect(&self) -> Result<()> {
         self.cleanup().await?;
         self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
         Ok(())
     }
 
-    async fn set_target_cadence(&self, rpm: i16) -> anyhow::Result<()> {
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
         if !(1..=self.max_level).contains(&rpm) {
-            return Err(anyhow::anyhow!(
+            return Err(KondisError::InvalidArgument(format!(
                 "RPM must be between 1 and {}",
                 self.max_level
-            ));
+            )));
         }
         Ok(())
     }
 
-    async fn set_target_power(&self, watts: i16) -> anyhow::Result<()> {
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
         if !(1..=self.max_level).contains(&watts) {
-            return Err(anyhow::anyhow!(
+            return Err(KondisError::InvalidArgument(format!(
                 "Watts must be between 1 and {}",
                 self.max_level
+            )));
+        }
+        Ok(())
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&level) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Resistance level must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        Ok(())
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        if bpm == 0 {
+            return Err(KondisError::InvalidArgument(
+                "Heart rate must be between 1 and 255".to_string(),
             ));
         }
         Ok(())
     }
 
-    async fn read(&self) -> anyhow::Result<Option<FTMSData>> {
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        training_goal(goal)?;
+        Ok(())
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        simulation_parameters(grade, wind_speed, crr, cw)?;
+        Ok(())
+    }
+
+    async fn start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Debug bikes do not support spin down calibration".to_string(),
+        ))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(KondisError::Unsupported(
+            "Debug bikes do not support machine status".to_string(),
+        ))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        None
+    }
+
+    // nothing gets decoded, so only connecting and disconnecting get reported
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
         let (data, _) = self.notifications().await?;
-        println!("Received data: {data:?}");
-
-        Ok(Some(FTMSData {
-            speed: 0.0,
-            cadence: 0.0,
-            distance: 0.0,
-            resistance: 0.0,
-            power: 0,
-            calories: 0.0,
-            heart_rate: 0.0,
-            time: 0,
-        }))
+        info!(device = %self.name, "Received data: {data:?}");
+
+        Ok(Some(BikeData::default().into()))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(
+            notifications
+                .take_until(self.shutdown.clone().cancelled_owned())
+                .enumerate()
+                .map(|(sequence, data)| {
+                    info!(uuid = %data.uuid, "Received data: {:?}", data.value);
+                    Reading::new(sequence as u64, BikeData::default().into())
+                }),
+        ))
+    }
+}
+
+impl BluetoothEquipment for DebugBike {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
     }
 }
 
 impl DebugBike {
-    async fn cleanup(&self) -> anyhow::Result<()> {
+    /// Dump every notification to the file at `path` as well, once connected, replacing anything it
+    /// held with every connection
+    pub fn hexdump(&mut self, path: impl Into<PathBuf>) {
+        self.hexdump = Some(path.into());
+    }
+
+    async fn cleanup(&self) -> Result<()> {
         for characteristic in &self.idk {
             self.peripheral.unsubscribe(characteristic).await?;
         }
         Ok(())
     }
 
-    async fn set_characteristics(&mut self) -> anyhow::Result<()> {
+    async fn set_characteristics(&mut self) -> Result<()> {
         self.peripheral.discover_services().await?;
         for characteristic in self.peripheral.characteristics() {
             if characteristic.properties.contains(CharPropFlags::NOTIFY) {
@@ -111,19 +280,107 @@ impl DebugBike {
         Ok(())
     }
 
-    async fn subscribe(&self) -> anyhow::Result<()> {
+    async fn subscribe(&self) -> Result<()> {
         for characteristic in &self.idk {
             self.peripheral.subscribe(characteristic).await?;
         }
         Ok(())
     }
 
-    async fn notifications(&self) -> anyhow::Result<(Vec<u8>, Uuid)> {
-        let mut notifications = self.peripheral.notifications().await?;
-        if let Some(data) = notifications.next().await {
-            return Ok((data.value, data.uuid));
+    async fn notifications(&self) -> Result<(Vec<u8>, Uuid)> {
+        until_shutdown(&self.shutdown, async {
+            let mut notifications = self.peripheral.notifications().await?;
+            if let Some(data) = notifications.next().await {
+                return Ok((data.value, data.uuid));
+            }
+
+            Ok((Vec::new(), Uuid::nil()))
+        })
+        .await
+    }
+}
+
+/// Log and write every notification as a line of `hexdump`, until the notifications end
+async fn dump(
+    notifications: impl Stream<Item = ValueNotification>,
+    mut file: Option<LineWriter<File>>,
+    device: String,
+) {
+    let start = Instant::now();
+    let mut notifications = std::pin::pin!(notifications);
+    while let Some(data) = notifications.next().await {
+        let line = hexdump(start.elapsed(), data.uuid, &data.value);
+        info!(device = %device, "{line}");
+        // notifications go on being logged without the file
+        if let Some(writer) = &mut file
+            && writeln!(writer, "{line}").is_err()
+        {
+            file = None;
         }
+    }
+}
+
+/// A notification as a line: the seconds since connecting, the characteristic, the value in hex and in
+/// ASCII, and a guess of what it holds
+fn hexdump(offset: Duration, uuid: Uuid, value: &[u8]) -> String {
+    let hex: Vec<String> = value.iter().map(|byte| format!("{byte:02x}")).collect();
+    let ascii: String = value
+        .iter()
+        .map(|&byte| match byte {
+            b' '..=b'~' => byte as char,
+            _ => '.',
+        })
+        .collect();
+    format!(
+        "{:10.6} {uuid} {} |{ascii}| {}",
+        offset.as_secs_f64(),
+        hex.join(" "),
+        guess(uuid, value)
+    )
+}
+
+/// What a notification decodes to when it's of a standard characteristic, or its bytes read as
+/// little-endian words otherwise, the way most equipment sends its values
+fn guess(uuid: Uuid, value: &[u8]) -> String {
+    let decoded = match uuid {
+        INDOOR_BIKE_DATA_UUID => parse_indoor_bike_data(value).map(|data| format!("{data:?}")),
+        TREADMILL_DATA_UUID => parse_treadmill_data(value).map(|data| format!("{data:?}")),
+        ROWER_DATA_UUID => parse_rower_data(value).map(|data| format!("{data:?}")),
+        CROSS_TRAINER_DATA_UUID => parse_cross_trainer_data(value).map(|data| format!("{data:?}")),
+        FITNESS_MACHINE_STATUS_UUID => parse_machine_status(value).map(|data| format!("{data:?}")),
+        HEART_RATE_MEASUREMENT_UUID => {
+            parse_heart_rate_measurement(value).map(|data| format!("{data:?}"))
+        }
+        _ => Err(KondisError::Unsupported(String::new())),
+    };
+    decoded.unwrap_or_else(|_| {
+        let words: Vec<String> = value
+            .chunks_exact(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]).to_string())
+            .collect();
+        format!("u16: {}", words.join(" "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        let offset = Duration::from_millis(1250);
+        let bike = hexdump(
+            offset,
+            INDOOR_BIKE_DATA_UUID,
+            &[0x40, 0x00, 0xc4, 0x09, 0xc8, 0x00],
+        );
+        assert!(bike.starts_with(
+            "  1.250000 00002ad2-0000-1000-8000-00805f9b34fb 40 00 c4 09 c8 00 |@.....| BikeData {"
+        ));
+        assert!(bike.contains("power: Some(200)"));
 
-        Ok((Vec::new(), Uuid::nil()))
+        let unknown = Uuid::from_u128(0xfff1);
+        let line = hexdump(offset, unknown, &[0xf0, 0xd1, 0x09, 0x55, 0x41]);
+        assert!(line.ends_with("f0 d1 09 55 41 |...UA| u16: 53744 21769"));
     }
 }
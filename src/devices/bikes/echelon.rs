@@ -0,0 +1,387 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use uuid::{Uuid, uuid};
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, get_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+    TrainingGoal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// Commands get written to this characteristic of the Echelon service (0bf669f1-45f2-11e7-9598-0800200c9a66)
+const COMMAND_UUID: Uuid = uuid!("0bf669f2-45f2-11e7-9598-0800200c9a66");
+/// The bike notifies its metrics and resistance changes on this characteristic
+const DATA_UUID: Uuid = uuid!("0bf669f4-45f2-11e7-9598-0800200c9a66");
+/// Every frame starts with this byte, both ways
+const FRAME_START: u8 = 0xF0;
+/// Commands written to the bike
+const INIT: u8 = 0xA1;
+const START_NOTIFICATIONS: u8 = 0xA3;
+const POLL: u8 = 0xA0;
+const SET_RESISTANCE: u8 = 0xB1;
+/// Frames notified by the bike
+const METRICS: u8 = 0xD1;
+const RESISTANCE: u8 = 0xD2;
+/// The highest resistance level of Echelon Connect bikes
+const MAX_RESISTANCE: i16 = 32;
+/// How often the bike gets polled, as it stops notifying without being polled
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An Echelon Connect bike, speaking the proprietary protocol of Echelon rather than FTMS.
+/// The first device with "ECH" in its name gets connected to.
+///
+/// The bike only reports its cadence and resistance level, so readings hold `MachineData::Bike` with
+/// just those, and only the resistance level can be controlled, from 1 to 32.
+#[derive(Debug, Clone)]
+pub struct EchelonBike {
+    peripheral: Peripheral,
+    /// The name of the bike, or its address if it doesn't advertise a name
+    pub name: String,
+    command: Option<Characteristic>,
+    data: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    /// Stops polling the bike, replaced with every connection
+    polling: CancellationToken,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for EchelonBike {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some((peripheral, name)) =
+            get_peripheral(EquipmentType::EchelonBike, &config, shutdown).await?
+        else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(EchelonBike {
+            peripheral,
+            name,
+            command: None,
+            data: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(&config),
+            timeouts: Timeouts::new(&config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            device_info: None,
+            polling: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.command = bluetooth::find_characteristic(&self.peripheral, COMMAND_UUID);
+                self.data = Some(bluetooth::subscribe(&self.peripheral, DATA_UUID).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                let mut resistance = None;
+                events::forward_notifications(
+                    &self.peripheral,
+                    DATA_UUID,
+                    move |data| decode(data, &mut resistance),
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.write(&frame(INIT, &[])).await?;
+                self.write(&frame(START_NOTIFICATIONS, &[])).await?;
+                self.poll();
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to bike");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.polling.cancel();
+        if let Some(data) = &self.data {
+            self.peripheral.unsubscribe(data).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(1..=MAX_RESISTANCE).contains(&level) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Resistance level must be between 1 and {MAX_RESISTANCE}"
+            )));
+        }
+        self.write(&frame(SET_RESISTANCE, &[level as u8])).await
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                resistance: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected, holding the latest resistance level along with the
+    /// cadence
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl BluetoothEquipment for EchelonBike {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl EchelonBike {
+    /// Keep polling the bike until disconnected, or until a poll fails
+    fn poll(&mut self) {
+        self.polling.cancel();
+        self.polling = self.shutdown.child_token();
+        let polling = self.polling.clone();
+        let bike = self.clone();
+        crate::runtime::spawn(async move {
+            let mut interval = crate::runtime::interval(POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = polling.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                if bike.write(&frame(POLL, &[0x01])).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        let Some(command) = &self.command else {
+            return Err(KondisError::CharacteristicMissing("command".to_string()));
+        };
+        until_shutdown(&self.shutdown, async {
+            Ok(self
+                .peripheral
+                .write(command, data, WriteType::WithResponse)
+                .await?)
+        })
+        .await
+    }
+}
+
+/// Frame a command for the bike: the start byte, the command, the length of the payload, the payload,
+/// and a checksum of every byte before it
+fn frame(command: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![FRAME_START, command, payload.len() as u8];
+    frame.extend_from_slice(payload);
+    frame.push(checksum(&frame));
+    frame
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Decode a frame notified by the bike, into its cadence along with the latest resistance level
+///
+/// Resistance frames only update `resistance`, which metrics frames hold on to, as the bike reports a
+/// change of resistance once rather than with every metrics frame.
+fn decode(data: &[u8], resistance: &mut Option<f64>) -> Result<MachineData> {
+    let invalid = || KondisError::InvalidData(format!("Invalid Echelon frame: {data:02x?}"));
+    let [FRAME_START, kind, .., sum] = *data else {
+        return Err(invalid());
+    };
+    if checksum(&data[..data.len() - 1]) != sum {
+        return Err(invalid());
+    }
+    let cadence = match kind {
+        METRICS if data.len() >= 13 => Some(f32::from(data[10])),
+        RESISTANCE if data.len() >= 5 => {
+            *resistance = Some(f64::from(data[3]));
+            None
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(BikeData {
+        cadence,
+        resistance: *resistance,
+        ..Default::default()
+    }
+    .into())
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Echelon bikes do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() -> Result<()> {
+        assert_eq!(frame(INIT, &[]), [0xF0, 0xA1, 0x00, 0x91]);
+        assert_eq!(frame(SET_RESISTANCE, &[12]), [0xF0, 0xB1, 0x01, 0x0C, 0xAE]);
+
+        let mut resistance = None;
+        let changed = frame(RESISTANCE, &[12]);
+        let MachineData::Bike(bike) = decode(&changed, &mut resistance)? else {
+            panic!("expected bike data");
+        };
+        assert_eq!((bike.cadence, bike.resistance), (None, Some(12.)));
+        let metrics = frame(METRICS, &[0, 0, 0, 0, 0, 0, 0, 85, 0]);
+        let MachineData::Bike(bike) = decode(&metrics, &mut resistance)? else {
+            panic!("expected bike data");
+        };
+        assert_eq!((bike.cadence, bike.resistance), (Some(85.), Some(12.)));
+
+        let mut corrupt = metrics;
+        corrupt[10] = 90;
+        assert!(decode(&corrupt, &mut resistance).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use btleplug::platform::Peripheral;
+use std::sync::mpsc::Sender;
+
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{DeviceInfo, ScanConfig};
+use crate::devices::ftms_peripheral::FtmsPeripheral;
+use crate::ftms::{
+    Capabilities, INDOOR_BIKE_DATA_UUID, MAX_CADENCE, MachineData, SpinDownResult, SpinDownStatus,
+    SupportedRanges, TrainingGoal, parse_indoor_bike_data, simulation_parameters, target_cadence,
+    target_heart_rate, target_power, target_resistance_level, training_goal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// Any standards-compliant FTMS smart trainer or bike.
+/// The first device advertising the Fitness Machine Service (0x1826) as an indoor bike gets connected to.
+#[derive(Debug, Clone)]
+pub struct GenericFtmsBike {
+    ftms: FtmsPeripheral,
+    /// The name of the device, or its address if it doesn't advertise a name
+    pub name: String,
+    max_level: i16,
+}
+
+impl Equipment for GenericFtmsBike {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let ftms = FtmsPeripheral::find(
+            EquipmentType::GenericFtmsBike,
+            INDOOR_BIKE_DATA_UUID,
+            decode,
+            &config,
+            shutdown,
+        )
+        .await?;
+        Ok(GenericFtmsBike {
+            name: ftms.name.clone(),
+            ftms,
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let connected = self.ftms.connect().await?;
+        info!(device = %self.name, "Found and connected to bike");
+        Ok(connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.ftms.disconnect().await
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        if !(1..=MAX_CADENCE).contains(&rpm) {
+            return Err(KondisError::InvalidArgument(format!(
+                "RPM must be between 1 and {MAX_CADENCE}"
+            )));
+        }
+        self.ftms.write(&target_cadence(rpm)?).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let watts = self.ftms.target_power(watts, self.max_level)?;
+        self.ftms.write(&target_power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        let level = self.ftms.target_resistance_level(level, self.max_level)?;
+        self.ftms.write(&target_resistance_level(level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.ftms.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.ftms.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.ftms
+            .write(&simulation_parameters(grade, wind_speed, crr, cw)?)
+            .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.ftms.stop().await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.ftms.pause().await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.ftms.reset().await
+    }
+
+    async fn spin_down(&self, status_tx: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        self.ftms.spin_down(status_tx).await
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        self.ftms.machine_status().await
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.ftms.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ftms.ranges
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.ftms.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.ftms.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.ftms.events()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.ftms.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let data = self.ftms.notification().await?;
+        Ok(parse_indoor_bike_data(&data).ok().map(MachineData::from))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        self.ftms.data_stream().await
+    }
+}
+
+impl BluetoothEquipment for GenericFtmsBike {
+    fn peripheral(&self) -> &Peripheral {
+        self.ftms.peripheral()
+    }
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_indoor_bike_data(data)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FTMSData;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        // speed, cadence and power
+        let data = decode(&[0x44, 0x00, 0xc4, 0x09, 0xb4, 0x00, 0xfa, 0x00])?;
+        let data = FTMSData::from(data);
+        assert_eq!(data.speed, Some(25.));
+        assert_eq!(data.cadence, Some(90.));
+        assert_eq!(data.power, Some(250));
+        assert!(decode(&[0x44, 0x00, 0xc4]).is_err());
+        Ok(())
+    }
+}
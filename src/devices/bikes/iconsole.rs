@@ -0,0 +1,633 @@
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{CharPropFlags, Characteristic, Peripheral as _},
+    platform::Peripheral,
+};
+use futures::{StreamExt, future};
+use tokio::sync::{broadcast, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, get_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, FTMSControlOpCode, INDOOR_BIKE_DATA_UUID, MachineData, SpinDownResult,
+    SpinDownStatus, StopCode, TrainingGoal, parse_fitness_machine_feature, parse_indoor_bike_data,
+    simulation_parameters, target_heart_rate, training_goal,
+};
+use crate::power_curve::PowerController;
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    FTMSData, MachineStatusStream, PowerCurve, Reading,
+};
+use crate::{KondisError, Result};
+
+static FTMS_SERVICE_UUID: &str = "00001826"; // FTMS service
+static FTMS_STATS_UUID: &str = "00002ad2"; // FTMS read?
+static FTMS_FEATURE_UUID: &str = "00002acc"; // FTMS feature
+
+/// The speed in km/h target powers are bounded at, about as fast as anyone rides an exercise bike
+const TOP_SPEED: f64 = 40.;
+
+/// What sets the FTMS dialect of an iConsole+ model apart from the others
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IconsoleQuirks {
+    /// The name the console advertises, like iConsole+0028
+    pub name: &'static str,
+    /// The equipment type the console is scanned for as
+    pub equipment_type: EquipmentType,
+    /// How many steps the console splits a resistance level into, 10 when it takes tenths of a level
+    pub resistance_steps: i16,
+    /// How many steps the console splits an rpm of target cadence into
+    pub cadence_steps: i16,
+    /// How much power each resistance level takes, when fitted for the model, see
+    /// `IconsoleBike::set_power_curve`
+    pub power_curve: Option<PowerCurve>,
+}
+
+/// An iConsole+ model, picking the quirks `IconsoleBike` speaks to the console with
+///
+/// Models not known to this crate can be added by implementing it for a type of your own.
+pub trait IconsoleModel: std::fmt::Debug + Clone + Send + Sync + 'static {
+    /// The quirks of the model
+    const QUIRKS: IconsoleQuirks;
+}
+
+/// The iConsole+0010, taking whole resistance levels and rpm
+#[derive(Debug, Clone, Copy)]
+pub struct Iconsole0010;
+
+impl IconsoleModel for Iconsole0010 {
+    const QUIRKS: IconsoleQuirks = IconsoleQuirks {
+        name: "iConsole+0010",
+        equipment_type: EquipmentType::Iconsole0010Bike,
+        resistance_steps: 1,
+        cadence_steps: 1,
+        // not fitted yet
+        power_curve: None,
+    };
+}
+
+/// The iConsole+0028, taking tenths of a resistance level and of an rpm
+#[derive(Debug, Clone, Copy)]
+pub struct Iconsole0028;
+
+impl IconsoleModel for Iconsole0028 {
+    const QUIRKS: IconsoleQuirks = IconsoleQuirks {
+        name: "iConsole+0028",
+        equipment_type: EquipmentType::Iconsole0028Bike,
+        resistance_steps: 10,
+        cadence_steps: 10,
+        power_curve: Some(PowerCurve::ICONSOLE_0028),
+    };
+}
+
+/// The iConsole+0051, taking tenths of a resistance level and the standard half rpm of FTMS
+#[derive(Debug, Clone, Copy)]
+pub struct Iconsole0051;
+
+impl IconsoleModel for Iconsole0051 {
+    const QUIRKS: IconsoleQuirks = IconsoleQuirks {
+        name: "iConsole+0051",
+        equipment_type: EquipmentType::Iconsole0051Bike,
+        resistance_steps: 10,
+        cadence_steps: 2,
+        // not fitted yet
+        power_curve: None,
+    };
+}
+
+/// The quirks of every model known to this crate
+pub(crate) const MODELS: [IconsoleQuirks; 3] = [
+    Iconsole0010::QUIRKS,
+    Iconsole0028::QUIRKS,
+    Iconsole0051::QUIRKS,
+];
+
+/// An iConsole+0010 bike
+pub type Iconsole0010Bike = IconsoleBike<Iconsole0010>;
+/// An iConsole+0028 bike
+pub type Iconsole0028Bike = IconsoleBike<Iconsole0028>;
+/// An iConsole+0051 bike
+pub type Iconsole0051Bike = IconsoleBike<Iconsole0051>;
+
+/// An [iConsole+](https://www.iconsole.plus/about-iconsole/) exercise equipment, of the model `M`.
+///
+/// The bike only takes resistance levels, so target powers get reached by adjusting the level with
+/// every reading, going by its power curve, see `set_power_curve`. Models without a fitted curve
+/// fail target powers as unsupported, until given one. Notifications are standard Indoor Bike Data,
+/// without a checksum on any model known to this crate, so ones that can't be decoded are dropped.
+///
+/// Unlike FTMS equipment, the bike isn't stopped when dropped, and the task holding its target power
+/// keeps running until it disconnects or `shutdown` is cancelled, so close the bike once done with it.
+#[derive(Debug, Clone)]
+pub struct IconsoleBike<M: IconsoleModel> {
+    peripheral: Peripheral,
+    /// The name of the bike, like iConsole+0028
+    pub name: String,
+    control: Option<Characteristic>,
+    stats: Option<Characteristic>,
+    capabilities: Option<Capabilities>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+    max_level: i16,
+    power_curve: Option<PowerCurve>,
+    target_power: Arc<watch::Sender<Option<i16>>>,
+    model: PhantomData<M>,
+}
+
+impl<M: IconsoleModel> Equipment for IconsoleBike<M> {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        // models of other crates are looked for by their own name
+        let mut config = config;
+        if config.name_pattern.is_none() {
+            config.name_pattern = Some(M::QUIRKS.name.to_string());
+        }
+        let meta = get_peripheral(M::QUIRKS.equipment_type, &config, shutdown).await?;
+        if meta.is_none() {
+            return Err(KondisError::DeviceNotFound);
+        }
+        let meta = meta.unwrap();
+        let bike = IconsoleBike {
+            peripheral: meta.0,
+            name: meta.1,
+            control: None,
+            stats: None,
+            capabilities: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(&config),
+            timeouts: Timeouts::new(&config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            device_info: None,
+            shutdown: shutdown.clone(),
+            max_level,
+            power_curve: M::QUIRKS.power_curve,
+            target_power: Arc::new(watch::Sender::new(None)),
+            model: PhantomData,
+        };
+        Ok(bike)
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.set_characteristics().await?;
+                self.subscribe().await?;
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                events::forward_notifications(
+                    &self.peripheral,
+                    INDOOR_BIKE_DATA_UUID,
+                    decode,
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.request_control().await?;
+                self.control_power();
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to bike");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.cleanup().await?;
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&rpm) {
+            return Err(KondisError::InvalidArgument(format!(
+                "RPM must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        self.set_cadence(rpm).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let Some(curve) = &self.power_curve else {
+            return Err(KondisError::Unsupported(format!(
+                "The power curve of the {} isn't known, see IconsoleBike::set_power_curve",
+                M::QUIRKS.name
+            )));
+        };
+        check_target_power(curve, self.max_level, watts)?;
+        self.target_power.send_replace(Some(watts));
+        Ok(())
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&level) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Resistance level must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        self.target_power.send_replace(None);
+        self.set_resistance_level(level).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.write(&simulation_parameters(grade, wind_speed, crr, cw)?)
+            .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Start as u8]).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+            .await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Pause as u8])
+            .await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Start as u8]).await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Reset as u8]).await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(format!(
+            "The {} does not support spin down calibration",
+            M::QUIRKS.name
+        )))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(KondisError::Unsupported(format!(
+            "The {} does not support machine status",
+            M::QUIRKS.name
+        )))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let data = self.notification().await?;
+        // frames that can't be decoded (e.g. cut short or corrupted) are skipped
+        Ok(decode(&data).ok())
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(
+            notifications
+                .take_until(self.shutdown.clone().cancelled_owned())
+                .filter(|data| future::ready(data.uuid == INDOOR_BIKE_DATA_UUID))
+                .enumerate()
+                .filter_map(move |(sequence, data)| {
+                    let reading = decode(&data.value)
+                        .ok()
+                        .map(|data| Reading::new(sequence as u64, data));
+                    future::ready(reading)
+                }),
+        ))
+    }
+}
+
+impl<M: IconsoleModel> BluetoothEquipment for IconsoleBike<M> {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl<M: IconsoleModel> IconsoleBike<M> {
+    /// Reach target powers going by `curve`, rather than the curve of the model
+    pub fn set_power_curve(&mut self, curve: PowerCurve) {
+        self.power_curve = Some(curve);
+    }
+
+    async fn cleanup(&self) -> Result<()> {
+        if let Some(stats) = &self.stats {
+            self.peripheral.unsubscribe(stats).await?;
+        }
+        // stop the bike even when shutting down, rather than leave it running
+        self.write_unchecked(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+            .await
+    }
+
+    async fn set_characteristics(&mut self) -> Result<()> {
+        for characteristic in self.peripheral.characteristics() {
+            if characteristic
+                .service_uuid
+                .to_string()
+                .starts_with(FTMS_SERVICE_UUID)
+                && characteristic.properties.contains(CharPropFlags::WRITE)
+                && characteristic.properties.contains(CharPropFlags::INDICATE)
+            {
+                self.control = Some(characteristic.clone());
+            }
+            if characteristic.uuid.to_string().starts_with(FTMS_STATS_UUID) {
+                self.stats = Some(characteristic.clone());
+            }
+            if characteristic
+                .uuid
+                .to_string()
+                .starts_with(FTMS_FEATURE_UUID)
+            {
+                let data = self.peripheral.read(&characteristic).await?;
+                self.capabilities = Some(parse_fitness_machine_feature(&data)?);
+            }
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<()> {
+        if let Some(stats) = &self.stats {
+            self.peripheral.subscribe(stats).await?;
+        } else {
+            return Err(KondisError::CharacteristicMissing("stats".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Wait for the next indoor bike data notification, skipping control point indications
+    async fn notification(&self) -> Result<Vec<u8>> {
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                let mut notifications = self.peripheral.notifications().await?;
+                while let Some(data) = notifications.next().await {
+                    if data.uuid == INDOOR_BIKE_DATA_UUID {
+                        return Ok(data.value);
+                    }
+                }
+
+                Ok(Vec::new())
+            }),
+        )
+        .await
+    }
+
+    async fn request_control(&self) -> Result<()> {
+        let request_control = [FTMSControlOpCode::RequestControl as u8];
+        self.write(&request_control).await
+    }
+
+    async fn set_cadence(&self, rpm: i16) -> Result<()> {
+        self.write(&target_cadence(&M::QUIRKS, rpm)?).await
+    }
+
+    async fn set_resistance_level(&self, level: i16) -> Result<()> {
+        self.write(&resistance_level(&M::QUIRKS, level)?).await
+    }
+
+    /// Keep adjusting the resistance level towards the target power with every reading, until
+    /// disconnected
+    fn control_power(&self) {
+        let Some(curve) = self.power_curve else {
+            return;
+        };
+        let mut events = self.events_tx.subscribe();
+        let target_power = self.target_power.subscribe();
+        let mut controller = PowerController::new(curve, self.max_level);
+        let bike = self.clone();
+        crate::runtime::spawn(async move {
+            let mut level = None;
+            loop {
+                let event = tokio::select! {
+                    _ = bike.shutdown.cancelled() => break,
+                    event = events.recv() => event,
+                };
+                let reading = match event {
+                    Ok(DeviceEvent::Data(reading)) => reading,
+                    Ok(DeviceEvent::Disconnected) | Err(broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                    _ => continue,
+                };
+                let Some(target) = *target_power.borrow() else {
+                    level = None;
+                    continue;
+                };
+                let data = FTMSData::from(reading.data);
+                let Some(speed) = data.speed else {
+                    continue;
+                };
+                let next =
+                    controller.update(target.into(), speed.into(), data.power.map(f64::from));
+                if level != Some(next) && bike.set_resistance_level(next).await.is_ok() {
+                    level = Some(next);
+                }
+            }
+        });
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        until_shutdown(&self.shutdown, self.write_unchecked(data)).await
+    }
+
+    async fn write_unchecked(&self, data: &[u8]) -> Result<()> {
+        if let Some(control) = &self.control {
+            self.peripheral
+                .write(control, data, btleplug::api::WriteType::WithResponse)
+                .await?;
+        } else {
+            return Err(KondisError::CharacteristicMissing("control".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_indoor_bike_data(data)?.into())
+}
+
+/// Fail for targets the power curve can't reach at the top level, even at `TOP_SPEED`
+fn check_target_power(curve: &PowerCurve, max_level: i16, watts: i16) -> Result<()> {
+    let max = curve.watts(max_level.into(), TOP_SPEED) as i16;
+    if !(1..=max).contains(&watts) {
+        return Err(KondisError::InvalidArgument(format!(
+            "Watts must be between 1 and {max}"
+        )));
+    }
+    Ok(())
+}
+
+/// Encode a target cadence command, in the steps of an rpm the model takes
+fn target_cadence(quirks: &IconsoleQuirks, rpm: i16) -> Result<[u8; 3]> {
+    let Some(value) = rpm.checked_mul(quirks.cadence_steps) else {
+        return Err(KondisError::InvalidArgument(format!(
+            "RPM must be at most {} on the {}",
+            i16::MAX / quirks.cadence_steps,
+            quirks.name
+        )));
+    };
+    let [low, high] = value.to_le_bytes();
+    Ok([FTMSControlOpCode::TargetCadence as u8, low, high])
+}
+
+/// Encode a target resistance level command, in the steps of a level the model takes
+fn resistance_level(quirks: &IconsoleQuirks, level: i16) -> Result<[u8; 3]> {
+    let Some(value) = level.checked_mul(quirks.resistance_steps) else {
+        return Err(KondisError::InvalidArgument(format!(
+            "Resistance level must be at most {} on the {}",
+            i16::MAX / quirks.resistance_steps,
+            quirks.name
+        )));
+    };
+    let [low, high] = value.to_le_bytes();
+    Ok([FTMSControlOpCode::TargetResistanceLevel as u8, low, high])
+}
+
+/// The quirks of the model advertising `name`
+pub(crate) fn model_named(name: &str) -> Option<&'static IconsoleQuirks> {
+    MODELS.iter().find(|quirks| name.contains(quirks.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quirks() -> Result<()> {
+        assert_eq!(
+            resistance_level(&Iconsole0028::QUIRKS, 12)?,
+            [FTMSControlOpCode::TargetResistanceLevel as u8, 120, 0]
+        );
+        assert_eq!(
+            resistance_level(&Iconsole0010::QUIRKS, 12)?,
+            [FTMSControlOpCode::TargetResistanceLevel as u8, 12, 0]
+        );
+        assert_eq!(
+            target_cadence(&Iconsole0051::QUIRKS, 90)?,
+            [FTMSControlOpCode::TargetCadence as u8, 180, 0]
+        );
+        assert!(matches!(
+            resistance_level(&Iconsole0028::QUIRKS, i16::MAX / 10 + 1),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            target_cadence(&Iconsole0028::QUIRKS, 5000),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        assert_eq!(
+            model_named("iConsole+0051").map(|quirks| quirks.equipment_type),
+            Some(EquipmentType::Iconsole0051Bike)
+        );
+        assert!(model_named("iConsole+0099").is_none());
+        assert!(Iconsole0028::QUIRKS.power_curve.is_some());
+        assert!(Iconsole0010::QUIRKS.power_curve.is_none());
+        assert!(Iconsole0051::QUIRKS.power_curve.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_power() {
+        let curve = PowerCurve::ICONSOLE_0028;
+        assert!(check_target_power(&curve, 32, 150).is_ok());
+        assert!(check_target_power(&curve, 32, 600).is_ok());
+        assert!(check_target_power(&curve, 32, 0).is_err());
+        assert!(check_target_power(&curve, 32, 700).is_err());
+
+        // 150 W is reached within the levels of the bike
+        let mut controller = PowerController::new(curve, 32);
+        let level = controller.update(150., 25., None);
+        assert!((1..32).contains(&level), "level {level}");
+        let watts_per_level = curve.watts(1., 25.) - curve.watts(0., 25.);
+        assert!((curve.watts(level.into(), 25.) - 150.).abs() <= watts_per_level);
+    }
+
+    #[test]
+    fn test_frame() {
+        // a notification of the 0028, every field present, at 25 km/h, 90 rpm and 150 W, 1.2 km
+        // and 10 minutes in
+        let frame = [
+            0xfe, 0x1f, 0xc4, 0x09, 0x00, 0x00, 0xb4, 0x00, 0x00, 0x00, 0xb0, 0x04, 0x00, 0x08,
+            0x00, 0x96, 0x00, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x58, 0x02,
+            0x00, 0x00,
+        ];
+        let data = FTMSData::from(decode(&frame).unwrap());
+        assert_eq!(data.speed, Some(25.));
+        assert_eq!(data.cadence, Some(90.));
+        assert_eq!(data.distance, Some(1.2));
+        assert_eq!(data.power, Some(150));
+        assert_eq!(data.time, Some(600));
+    }
+}
@@ -0,0 +1,288 @@
+use std::sync::mpsc::Sender;
+
+use btleplug::platform::Peripheral;
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, get_peripheral, within};
+use crate::devices::advertisements;
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+    TrainingGoal,
+};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType, MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// The Bluetooth SIG company identifier of Keiser, keying the manufacturer data of its advertisements
+pub(crate) const KEISER_COMPANY_ID: u16 = 0x0102;
+/// The data type of a broadcast holding live data, rather than the review of a finished ride
+const REAL_TIME: u8 = 0;
+/// km per mile, for bikes set to imperial units
+const KM_PER_MILE: f32 = 1.609344;
+
+/// A Keiser M3i bike, or any other Keiser M series bike broadcasting the same data.
+/// The first device with "M3" in its name gets listened to.
+///
+/// These bikes never get connected to: they broadcast their data in the manufacturer data of their
+/// advertisements, which connecting only listens for. Readings hold `MachineData::Bike`, with the gear
+/// as the resistance level. Any number of apps can read the same bike at once, so in a room full of
+/// them the bike to read is best picked by its address, see `ScanConfig::address`. The bike can't be
+/// controlled, so every target and session command is unsupported.
+#[derive(Debug, Clone)]
+pub struct KeiserM3iBike {
+    peripheral: Peripheral,
+    /// The name of the bike, or its address if it doesn't advertise a name
+    pub name: String,
+    /// The adapters to listen with
+    config: ScanConfig,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops listening to the advertisements, replaced with every connection
+    listening: CancellationToken,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for KeiserM3iBike {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some((peripheral, name)) =
+            get_peripheral(EquipmentType::KeiserM3iBike, &config, shutdown).await?
+        else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(KeiserM3iBike {
+            peripheral,
+            name,
+            timeouts: Timeouts::new(&config),
+            config,
+            events_tx: events::channel(),
+            listening: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    /// Start listening to the advertisements of the bike, without connecting to it
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                self.listening.cancel();
+                self.listening = self.shutdown.child_token();
+                advertisements::listen(
+                    &self.peripheral,
+                    KEISER_COMPANY_ID,
+                    decode,
+                    &self.config,
+                    self.events_tx.clone(),
+                    self.listening.clone(),
+                )
+                .await?;
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Listening to bike");
+                Ok(true)
+            }))
+            .await
+    }
+
+    /// Stop listening to the advertisements of the bike
+    async fn disconnect(&self) -> Result<()> {
+        self.listening.cancel();
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                resistance: true,
+                expended_energy: true,
+                heart_rate: true,
+                elapsed_time: true,
+                power: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once listening
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once listening
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+/// Decode the manufacturer data of a Keiser M series broadcast, following the company identifier
+///
+/// Broadcasts reviewing a finished ride decode to nothing. Older firmware, without the gear, is
+/// decoded as well.
+fn decode(data: &[u8]) -> Result<Option<MachineData>> {
+    if data.len() < 16 {
+        return Err(KondisError::InvalidData(format!(
+            "Keiser broadcast too short: {} bytes",
+            data.len()
+        )));
+    }
+    if data[2] != REAL_TIME {
+        return Ok(None);
+    }
+    let u16_at = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+    let heart_rate = u16_at(6);
+    // the top bit of the distance tells whether the bike is set to kilometers
+    let distance = u16_at(14);
+    let km = f32::from(distance & 0x7FFF) / 10.;
+    let km = if distance & 0x8000 != 0 {
+        km
+    } else {
+        km * KM_PER_MILE
+    };
+    Ok(Some(
+        BikeData {
+            cadence: Some(f32::from(u16_at(4)) / 10.),
+            distance: Some(km),
+            resistance: data.get(16).map(|gear| f64::from(*gear)),
+            power: Some(u16_at(8) as i16),
+            calories: Some(f64::from(u16_at(10))),
+            // nothing is measured without a heart rate strap
+            heart_rate: (heart_rate != 0).then(|| f64::from(heart_rate) / 10.),
+            time: Some(u32::from(data[12]) * 60 + u32::from(data[13])),
+            ..Default::default()
+        }
+        .into(),
+    ))
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Keiser bikes do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        // version 6.40, real time, bike 12, 85.5 rpm, no heart rate, 210 W, 95 kcal, 12:34, 4.2 km,
+        // gear 14
+        let data = [
+            0x06, 0x40, 0x00, 0x0C, 0x57, 0x03, 0x00, 0x00, 0xD2, 0x00, 0x5F, 0x00, 0x0C, 0x22,
+            0x2A, 0x80, 0x0E,
+        ];
+        let Some(MachineData::Bike(bike)) = decode(&data)? else {
+            panic!("expected bike data");
+        };
+        assert_eq!(bike.cadence, Some(85.5));
+        assert_eq!(bike.heart_rate, None);
+        assert_eq!(bike.power, Some(210));
+        assert_eq!(bike.calories, Some(95.));
+        assert_eq!(bike.time, Some(754));
+        assert_eq!(bike.distance, Some(4.2));
+        assert_eq!(bike.resistance, Some(14.));
+
+        // the same ride in miles, reviewed once finished
+        let mut review = data;
+        review[15] = 0x00;
+        assert!(decode(&review)?.is_some());
+        review[2] = 0x80;
+        assert!(decode(&review)?.is_none());
+        assert!(decode(&data[..10]).is_err());
+        Ok(())
+    }
+}
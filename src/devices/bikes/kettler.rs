@@ -0,0 +1,348 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::devices::events::{self, EventSender};
+use crate::devices::serial::{Baud, SerialPort};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+    TargetCapabilities, TrainingGoal,
+};
+use crate::{ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream, Reading};
+use crate::{KondisError, Result};
+
+/// How often the ergometer gets asked for its status
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Puts the ergometer under the control of the computer, answered with "ACK" or "RUN"
+const COMMAND_MODE: &str = "CM";
+/// Resets the ergometer, answered with "ACK"
+const RESET: &str = "RS";
+/// Asks for the status line, see `parse_status`
+const STATUS: &str = "ST";
+/// Sets the target power in W, answered with the status line
+const POWER: &str = "PW";
+
+/// The range of target power of Kettler ergometers, in steps of `POWER_STEP`
+const MIN_POWER: i16 = 25;
+const MAX_POWER: i16 = 400;
+const POWER_STEP: i16 = 5;
+
+/// kJ per kcal
+const KILOJOULES_PER_KILOCALORIE: f64 = 4.184;
+
+/// An older Kettler ergometer, like the X1, X7 or Ergorace, speaking the text protocol of Kettler over
+/// its RS-232 port, rather than Bluetooth.
+/// The port to talk to is set with `ScanConfig::port`, there being nothing to scan for.
+///
+/// The ergometer gets polled for its status every second, and readings hold `MachineData::Bike` with
+/// power, cadence, speed, distance, energy, heart rate and time. Only target power is supported, from 25
+/// to 400 W in steps of 5 W, rounded to the nearest step.
+#[derive(Debug, Clone)]
+pub struct KettlerBike {
+    /// The path of the port
+    pub name: String,
+    path: PathBuf,
+    port: Arc<Mutex<Option<SerialPort>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops polling the ergometer, replaced with every connection
+    polling: CancellationToken,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for KettlerBike {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some(path) = config.port.clone() else {
+            return Err(KondisError::InvalidArgument(
+                "Kettler ergometers need a port to talk to, see `ScanConfig::port`".to_string(),
+            ));
+        };
+        Ok(KettlerBike {
+            name: path.display().to_string(),
+            path,
+            port: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            polling: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                *self.port.lock().await = Some(SerialPort::open(&self.path, Baud::B9600)?);
+                // the ergometer answering is all there is to connecting
+                let timeouts = self.timeouts;
+                let answered = within(timeouts.connect, KondisError::ConnectTimeout, async {
+                    self.send(RESET).await?;
+                    self.send(COMMAND_MODE).await
+                })
+                .await;
+                if let Err(e) = answered {
+                    self.port.lock().await.take();
+                    return Err(e);
+                }
+                self.poll();
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to ergometer");
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.polling.cancel();
+        self.port.lock().await.take();
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        if !(MIN_POWER..=MAX_POWER).contains(&watts) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Watts must be between {MIN_POWER} and {MAX_POWER}"
+            )));
+        }
+        let watts = (watts + POWER_STEP / 2) / POWER_STEP * POWER_STEP;
+        self.send(&format!("{POWER} {watts}")).await.map(|_| ())
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    /// Reset the counters of the ergometer, which leaves command mode and gets put back in it
+    async fn reset(&self) -> Result<()> {
+        self.send(RESET).await?;
+        self.send(COMMAND_MODE).await.map(|_| ())
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            targets: TargetCapabilities {
+                power: true,
+                ..Default::default()
+            },
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                power: true,
+                expended_energy: true,
+                heart_rate: true,
+                elapsed_time: true,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next poll of the ergometer, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every poll of the ergometer from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl KettlerBike {
+    /// Keep polling the ergometer until disconnected, or until a poll fails, which disconnects
+    fn poll(&mut self) {
+        self.polling.cancel();
+        self.polling = self.shutdown.child_token();
+        let polling = self.polling.clone();
+        let bike = self.clone();
+        crate::runtime::spawn(async move {
+            let mut interval = crate::runtime::interval(POLL_INTERVAL);
+            for sequence in 0.. {
+                tokio::select! {
+                    _ = polling.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let data = match bike.send(STATUS).await.and_then(|s| parse_status(&s)) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        bike.events_tx.send(DeviceEvent::Error(e.to_string()));
+                        let _ = bike.disconnect().await;
+                        break;
+                    }
+                };
+                let reading = Reading::new(sequence, data.into());
+                bike.events_tx.send(DeviceEvent::Data(reading));
+            }
+        });
+    }
+
+    /// Send `command`, resolving to the line answering it
+    async fn send(&self, command: &str) -> Result<String> {
+        let mut port = self.port.lock().await;
+        let Some(port) = port.as_mut() else {
+            return Err(KondisError::Disconnected(format!(
+                "Not connected to {}",
+                self.name
+            )));
+        };
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                // an answer arriving after its command timed out would be taken for this one's
+                port.discard_input()?;
+                port.write(format!("{command}\r\n").as_bytes()).await?;
+                let line = port.read(take_line).await?;
+                Ok(String::from_utf8_lossy(&line).trim().to_string())
+            }),
+        )
+        .await
+    }
+}
+
+/// Pull the first whole line out of the bytes received so far
+fn take_line(received: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let end = received.iter().position(|&byte| byte == b'\n')?;
+    let mut line: Vec<u8> = received.drain(..=end).collect();
+    line.pop();
+    Some(line)
+}
+
+/// The bike data of a status line, tab separated: heart rate, cadence, speed in 0.1 km/h, distance in
+/// 0.1 km, target power, energy in kJ, time as mm:ss, and power
+fn parse_status(line: &str) -> Result<BikeData> {
+    let invalid = || KondisError::InvalidData(format!("Invalid Kettler status: {line:?}"));
+    let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+    let [heart_rate, cadence, speed, distance, _, energy, time, power] = fields[..] else {
+        return Err(invalid());
+    };
+    let number = |field: &str| field.parse::<u16>().map_err(|_| invalid());
+    let (minutes, seconds) = time.split_once(':').ok_or_else(invalid)?;
+    let heart_rate = number(heart_rate)?;
+    Ok(BikeData {
+        speed: Some(f32::from(number(speed)?) / 10.),
+        cadence: Some(number(cadence)?.into()),
+        distance: Some(f32::from(number(distance)?) / 10.),
+        power: Some(i16::try_from(number(power)?).unwrap_or(i16::MAX)),
+        calories: Some(f64::from(number(energy)?) / KILOJOULES_PER_KILOCALORIE),
+        heart_rate: (heart_rate != 0).then(|| heart_rate.into()),
+        time: Some(u32::from(number(minutes)?) * 60 + u32::from(number(seconds)?)),
+        ..Default::default()
+    })
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Kettler ergometers do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() -> Result<()> {
+        let mut received = b"ACK\r\n000\t085\t2".to_vec();
+        assert_eq!(take_line(&mut received), Some(b"ACK\r".to_vec()));
+        assert_eq!(take_line(&mut received), None);
+        received.extend(b"73\t012\t150\t0042\t05:30\t148\r\n");
+        let line = take_line(&mut received).expect("a whole line");
+        let line = String::from_utf8_lossy(&line);
+
+        let data = parse_status(line.trim())?;
+        assert_eq!(data.speed, Some(27.3));
+        assert_eq!(data.cadence, Some(85.));
+        assert_eq!(data.distance, Some(1.2));
+        assert_eq!(data.power, Some(148));
+        assert_eq!(data.heart_rate, None);
+        assert_eq!(data.time, Some(330));
+        assert!((data.calories.unwrap() - 10.04).abs() < 0.01);
+
+        assert!(parse_status("ACK").is_err());
+        assert!(parse_status("000\t085\t273\t012\t150\t0042\t0530\t148").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use btleplug::platform::Peripheral;
+use std::sync::mpsc::Sender;
+
+use btleplug::api::{Characteristic, Peripheral as _, WriteType};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::{Uuid, uuid};
+
+use crate::bluetooth::{self, DeviceInfo, ScanConfig};
+use crate::devices::ftms_peripheral::FtmsPeripheral;
+use crate::ftms::{
+    Capabilities, INDOOR_BIKE_DATA_UUID, MAX_CADENCE, MachineData, SpinDownResult, SpinDownStatus,
+    SupportedRanges, TrainingGoal, parse_indoor_bike_data, simulation_parameters, target_cadence,
+    target_heart_rate, target_power, target_resistance_level, training_goal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// The Wahoo trainer control characteristic, in the Cycling Power Service of Wahoo trainers
+const WAHOO_CONTROL_UUID: Uuid = uuid!("a026e005-0a7d-4ab3-97fa-f1500f9feb8b");
+/// Op codes of the Wahoo trainer control characteristic
+const UNLOCK: u8 = 0x20;
+const LEVEL_MODE: u8 = 0x41;
+const ERG_MODE: u8 = 0x42;
+const SIM_MODE: u8 = 0x43;
+const GRADE: u8 = 0x46;
+const WHEEL_CIRCUMFERENCE: u8 = 0x48;
+const WIND_SPEED: u8 = 0x4A;
+/// The rider weight the trainer simulates unless told otherwise, in kg
+const DEFAULT_RIDER_WEIGHT: f32 = 75.;
+/// The highest level of the level mode
+const MAX_LEVEL: u8 = 9;
+
+/// A Wahoo KICKR smart trainer.
+/// The first device with "KICKR" in its name gets connected to.
+///
+/// Data and every standard command go through FTMS, like for a `GenericFtmsBike`. Target power and
+/// simulation parameters go through the proprietary trainer control characteristic of Wahoo instead,
+/// when the trainer has it, which also reaches what FTMS can't: the rider weight simulated, see
+/// `rider_weight`, the wheel circumference and the fixed levels of the trainer.
+#[derive(Debug, Clone)]
+pub struct KickrBike {
+    ftms: FtmsPeripheral,
+    /// The name of the trainer, or its address if it doesn't advertise a name
+    pub name: String,
+    wahoo: Option<Characteristic>,
+    /// kg
+    rider_weight: f32,
+    max_level: i16,
+}
+
+impl KickrBike {
+    /// The weight in kg of the rider and the bike together, simulated along with the grade, 75 kg by
+    /// default
+    pub fn rider_weight(mut self, kg: f32) -> Self {
+        self.rider_weight = kg;
+        self
+    }
+
+    /// Tell the trainer the circumference of the wheel in millimeters, which the speed it reports goes by
+    pub async fn set_wheel_circumference(&self, mm: f32) -> Result<()> {
+        if !(0.0..=6553.5).contains(&mm) {
+            return Err(KondisError::InvalidArgument(
+                "Wheel circumference must be between 0 and 6553.5 mm".to_string(),
+            ));
+        }
+        self.write_wahoo(&scaled(WHEEL_CIRCUMFERENCE, mm * 10.))
+            .await
+    }
+
+    /// Switch the trainer to one of its fixed levels, from 0 to 9, which resist like the gears of a
+    /// fluid trainer
+    pub async fn set_level(&self, level: u8) -> Result<()> {
+        if level > MAX_LEVEL {
+            return Err(KondisError::InvalidArgument(format!(
+                "Level must be between 0 and {MAX_LEVEL}"
+            )));
+        }
+        self.write_wahoo(&[LEVEL_MODE, level]).await
+    }
+
+    async fn write_wahoo(&self, data: &[u8]) -> Result<()> {
+        let Some(wahoo) = &self.wahoo else {
+            return Err(KondisError::CharacteristicMissing(
+                "Wahoo trainer control".to_string(),
+            ));
+        };
+        Ok(self
+            .ftms
+            .peripheral()
+            .write(wahoo, data, WriteType::WithResponse)
+            .await?)
+    }
+}
+
+impl Equipment for KickrBike {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let ftms = FtmsPeripheral::find(
+            EquipmentType::KickrBike,
+            INDOOR_BIKE_DATA_UUID,
+            decode,
+            &config,
+            shutdown,
+        )
+        .await?;
+        Ok(KickrBike {
+            name: ftms.name.clone(),
+            ftms,
+            wahoo: None,
+            rider_weight: DEFAULT_RIDER_WEIGHT,
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let connected = self.ftms.connect().await?;
+        let peripheral = self.ftms.peripheral();
+        self.wahoo = bluetooth::find_characteristic(peripheral, WAHOO_CONTROL_UUID);
+        if let Some(wahoo) = &self.wahoo {
+            // commands are only taken once responses are indicated, and the trainer is unlocked
+            peripheral.subscribe(wahoo).await?;
+            self.write_wahoo(&[UNLOCK, 0xEE, 0xFC]).await?;
+        }
+        info!(device = %self.name, "Found and connected to trainer");
+        Ok(connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.ftms.disconnect().await
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        if !(1..=MAX_CADENCE).contains(&rpm) {
+            return Err(KondisError::InvalidArgument(format!(
+                "RPM must be between 1 and {MAX_CADENCE}"
+            )));
+        }
+        self.ftms.write(&target_cadence(rpm)?).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let watts = self.ftms.target_power(watts, self.max_level)?;
+        if self.wahoo.is_some() {
+            return self.write_wahoo(&erg_mode(watts)).await;
+        }
+        self.ftms.write(&target_power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        let level = self.ftms.target_resistance_level(level, self.max_level)?;
+        self.ftms.write(&target_resistance_level(level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.ftms.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.ftms.write(&training_goal(goal)?).await
+    }
+
+    /// In the simulation mode of Wahoo when the trainer has it, simulating the rider weight as well
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        // validated the same either way
+        let parameters = simulation_parameters(grade, wind_speed, crr, cw)?;
+        if self.wahoo.is_none() {
+            return self.ftms.write(&parameters).await;
+        }
+        self.write_wahoo(&sim_mode(self.rider_weight, crr, cw))
+            .await?;
+        self.write_wahoo(&scaled(WIND_SPEED, (wind_speed + 32.768) * 1000.))
+            .await?;
+        self.write_wahoo(&scaled(GRADE, (grade / 100. + 1.) * 32768.))
+            .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.ftms.stop().await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.ftms.pause().await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.ftms.reset().await
+    }
+
+    async fn spin_down(&self, status_tx: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        self.ftms.spin_down(status_tx).await
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        self.ftms.machine_status().await
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.ftms.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ftms.ranges
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.ftms.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.ftms.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.ftms.events()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.ftms.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let data = self.ftms.notification().await?;
+        Ok(parse_indoor_bike_data(&data).ok().map(MachineData::from))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        self.ftms.data_stream().await
+    }
+}
+
+impl BluetoothEquipment for KickrBike {
+    fn peripheral(&self) -> &Peripheral {
+        self.ftms.peripheral()
+    }
+}
+
+fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_indoor_bike_data(data)?.into())
+}
+
+/// Encode a Wahoo command taking a single value, rounded and saturated into a uint16
+fn scaled(op_code: u8, value: f32) -> [u8; 3] {
+    let [low, high] = (value.round() as u16).to_le_bytes();
+    [op_code, low, high]
+}
+
+fn erg_mode(watts: i16) -> [u8; 3] {
+    scaled(ERG_MODE, watts.max(0).into())
+}
+
+/// Encode the Wahoo simulation mode, with the weight in kg, the rolling resistance coefficient and the
+/// wind resistance coefficient in kg/m
+fn sim_mode(weight: f32, crr: f32, cw: f32) -> [u8; 7] {
+    let [weight_low, weight_high] = ((weight * 100.).round() as u16).to_le_bytes();
+    let [crr_low, crr_high] = ((crr * 10000.).round() as u16).to_le_bytes();
+    let [cw_low, cw_high] = ((cw * 1000.).round() as u16).to_le_bytes();
+    [
+        SIM_MODE,
+        weight_low,
+        weight_high,
+        crr_low,
+        crr_high,
+        cw_low,
+        cw_high,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wahoo_commands() {
+        assert_eq!(erg_mode(250), [ERG_MODE, 0xFA, 0x00]);
+        assert_eq!(
+            sim_mode(80., 0.004, 0.51),
+            [SIM_MODE, 0x40, 0x1F, 0x28, 0x00, 0xFE, 0x01]
+        );
+        // flat ground sits halfway through the range, and 100% at its very end
+        assert_eq!(
+            scaled(GRADE, (0. / 100. + 1.) * 32768.),
+            [GRADE, 0x00, 0x80]
+        );
+        assert_eq!(
+            scaled(GRADE, (100. / 100. + 1.) * 32768.),
+            [GRADE, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            scaled(WIND_SPEED, (0. + 32.768) * 1000.),
+            [WIND_SPEED, 0x00, 0x80]
+        );
+    }
+}
@@ -1,2 +1,13 @@
+#[cfg(feature = "ant")]
+pub mod ant_fec;
 pub mod debug;
-pub mod iconsole_0028;
+pub mod echelon;
+pub mod generic_ftms;
+pub mod iconsole;
+pub mod keiser_m3i;
+pub mod kettler;
+pub mod kickr;
+#[cfg(feature = "quirks")]
+pub mod quirk;
+pub mod simulator;
+pub mod tacx_fec;
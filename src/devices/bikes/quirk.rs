@@ -0,0 +1,376 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, find_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{Capabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal};
+use crate::quirks::{DeviceQuirks, parse_quirks};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// A bike talked to as described by `DeviceQuirks`, for clones and variants of bikes this crate
+/// doesn't know, with the `quirks` feature. The first device advertising the name of the quirks,
+/// and their service if they name one, gets connected to.
+///
+/// Readings hold `MachineData::Bike` with the metrics of the quirks, and the resistance level, start
+/// and stop are the only controls, if the quirks have commands for them. Unlike FTMS equipment, the
+/// bike isn't stopped when dropped, so close it once done with it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{devices::QuirkBike, CancellationToken, Equipment, ScanConfig};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut bike = QuirkBike::open("clone-bike.toml", ScanConfig::default(), &shutdown).await?;
+///     bike.connect().await?;
+///     bike.set_target_resistance_level(8).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct QuirkBike {
+    peripheral: Peripheral,
+    /// The name of the bike, or its address if it doesn't advertise a name
+    pub name: String,
+    quirks: Arc<DeviceQuirks>,
+    control: Option<Characteristic>,
+    data: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for QuirkBike {
+    async fn with_config(_: i16, _: ScanConfig, _: &CancellationToken) -> Result<Self> {
+        Err(KondisError::Unsupported(
+            "A quirk bike is described by a quirk file, see QuirkBike::open".to_string(),
+        ))
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.control = self
+                    .quirks
+                    .control
+                    .and_then(|uuid| bluetooth::find_characteristic(&self.peripheral, uuid));
+                self.data = Some(bluetooth::subscribe(&self.peripheral, self.quirks.data).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                let quirks = self.quirks.clone();
+                events::forward_notifications(
+                    &self.peripheral,
+                    self.quirks.data,
+                    move |data| decode(&quirks, data),
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                for command in &self.quirks.commands.init {
+                    self.write(&self.quirks.command(command)).await?;
+                }
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to bike");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(data) = &self.data {
+            self.peripheral.unsubscribe(data).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(self.unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(self.unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.write(&resistance_command(&self.quirks, level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(self.unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(self.unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(self.unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        let Some(start) = &self.quirks.commands.start else {
+            return Err(self.unsupported("starting"));
+        };
+        self.write(&self.quirks.command(start)).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        let Some(stop) = &self.quirks.commands.stop else {
+            return Err(self.unsupported("stopping"));
+        };
+        self.write(&self.quirks.command(stop)).await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(self.unsupported("pausing"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(self.unsupported("resuming"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(self.unsupported("resetting"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(self.unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(self.unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(self.quirks.capabilities())
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl BluetoothEquipment for QuirkBike {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl QuirkBike {
+    /// Scan for the bike described by the quirk file at `path`, see `parse_quirks`
+    pub async fn open(
+        path: impl AsRef<Path>,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let quirks = parse_quirks(&std::fs::read_to_string(path)?)?;
+        Self::find(quirks, config, shutdown).await
+    }
+
+    /// Scan for the bike described by `quirks`, narrowed down by `config`
+    ///
+    /// The name and service of `config` replace the ones of the quirks.
+    pub async fn find(
+        quirks: DeviceQuirks,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let mut config = config;
+        if config.name_pattern.is_none() {
+            config.name_pattern = Some(quirks.name.clone());
+        }
+        if config.service_uuid.is_none() {
+            config.service_uuid = quirks.service;
+        }
+        let Some((peripheral, name)) = find_peripheral(&config, shutdown).await? else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(QuirkBike {
+            peripheral,
+            name,
+            quirks: Arc::new(quirks),
+            control: None,
+            data: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(&config),
+            timeouts: Timeouts::new(&config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        let Some(control) = &self.control else {
+            return Err(KondisError::CharacteristicMissing("control".to_string()));
+        };
+        until_shutdown(&self.shutdown, async {
+            Ok(self
+                .peripheral
+                .write(control, data, WriteType::WithResponse)
+                .await?)
+        })
+        .await
+    }
+
+    fn unsupported(&self, what: &str) -> KondisError {
+        KondisError::Unsupported(format!("{} does not support {what}", self.quirks.name))
+    }
+}
+
+/// Decode a notification of the data characteristic
+fn decode(quirks: &DeviceQuirks, data: &[u8]) -> Result<MachineData> {
+    Ok(quirks.decode(data)?.into())
+}
+
+/// The command setting the resistance level, failing for levels out of the range of the bike
+fn resistance_command(quirks: &DeviceQuirks, level: i16) -> Result<Vec<u8>> {
+    let max = quirks.max_resistance;
+    if !(1..=max).contains(&level) {
+        return Err(KondisError::InvalidArgument(format!(
+            "Resistance level must be between 1 and {max}"
+        )));
+    }
+    quirks.resistance_command(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FTMSData;
+
+    const SAMPLE: &str = include_str!("../../../quirks/clone-bike.toml");
+
+    #[test]
+    fn test_notifications() -> Result<()> {
+        let quirks = parse_quirks(SAMPLE)?;
+        // 25 km/h, 90 rpm, level 8 and 150 W
+        let data = FTMSData::from(decode(
+            &quirks,
+            &[0xf0, 0xd1, 0xc4, 0x09, 90, 8, 0xdc, 0x05],
+        )?);
+        assert_eq!(data.speed, Some(25.));
+        assert_eq!(data.cadence, Some(90.));
+        assert_eq!(data.resistance, Some(8.));
+        assert_eq!(data.power, Some(150));
+        assert_eq!(data.heart_rate, None);
+        assert!(decode(&quirks, &[0xf0, 0xd1, 0xc4, 0x09, 90, 8, 0xdc]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resistance_command() -> Result<()> {
+        let quirks = parse_quirks(SAMPLE)?;
+        assert_eq!(resistance_command(&quirks, 12)?, vec![0xf0, 0xa6, 12, 0xa2]);
+        assert_eq!(resistance_command(&quirks, 24)?, vec![0xf0, 0xa6, 24, 0xae]);
+        assert!(resistance_command(&quirks, 0).is_err());
+        assert!(resistance_command(&quirks, 25).is_err());
+        assert_eq!(
+            quirks.command(&quirks.commands.init[1]),
+            vec![0xf0, 0xa5, 0x01, 0x96]
+        );
+
+        let mut quirks = quirks;
+        quirks.commands.resistance = None;
+        assert!(matches!(
+            resistance_command(&quirks, 12),
+            Err(KondisError::Unsupported(_))
+        ));
+        Ok(())
+    }
+}
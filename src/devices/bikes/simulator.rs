@@ -0,0 +1,646 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+    TargetCapabilities, TrainingGoal, parse_indoor_bike_data, simulation_parameters, training_goal,
+};
+use crate::physics::AIR_DENSITY;
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Reading,
+    Result, RideModel, ScanConfig, UserProfile,
+};
+
+/// The longest step the ride gets simulated in
+const STEP: Duration = Duration::from_millis(100);
+/// bpm, of a rider at rest
+const RESTING_HEART_RATE: f64 = 60.;
+/// How long the heart rate takes to get most of the way to what the power calls for
+const HEART_RATE_RESPONSE: Duration = Duration::from_secs(30);
+/// How long one wobble of the cadence around what the rider prefers takes
+const CADENCE_PERIOD: Duration = Duration::from_secs(7);
+
+/// Something going wrong on a `SimulatorBike`, on cue, for testing how apps cope with it
+///
+/// Readings are counted by their sequence number, over every connection of the bike, and commands from
+/// 0 for the first one the bike gets, over every connection as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fault {
+    /// The notification of the reading never arrives, leaving a gap in the sequence numbers
+    DropReading(u64),
+    /// The notification of the reading arrives malformed, turning into an error event instead
+    MalformedReading(u64),
+    /// The bike disconnects right after the reading, failing every command until connected to again
+    DisconnectAfter(u64),
+    /// The response to the command takes `delay` to arrive
+    DelayCommand { command: u64, delay: Duration },
+}
+
+/// How the rider rides, see the builders of `SimulatorBike`
+#[derive(Debug, Clone, Copy)]
+struct Rider {
+    profile: UserProfile,
+    /// W, when the bike doesn't set the power
+    effort: f64,
+    /// rpm
+    cadence: f64,
+    /// rpm, around `cadence`
+    cadence_variation: f64,
+    /// how long the power and cadence take to get most of the way to their targets
+    responsiveness: Duration,
+}
+
+/// What the bike is told to do
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// The rider rides at their own effort on the flat
+    Free,
+    /// The bike holds the power, in W
+    Erg(f64),
+    /// The power grows with the level, the rider's own effort halfway to the highest level
+    Resistance(f64),
+    /// The rider rides at their own effort on the grade, in percent
+    Simulation(f64),
+}
+
+/// The state of the ride, worked out step by step
+#[derive(Debug, Clone)]
+struct Ride {
+    mode: Mode,
+    model: RideModel,
+    pedaling: bool,
+    /// rpm, when set by the bike
+    target_cadence: Option<f64>,
+    /// W
+    power: f64,
+    /// rpm
+    cadence: f64,
+    /// m/s
+    speed: f64,
+    /// m
+    distance: f64,
+    /// J
+    work: f64,
+    /// bpm
+    heart_rate: f64,
+    elapsed: Duration,
+    /// when the ride was last worked out
+    updated: Instant,
+}
+
+impl Ride {
+    fn new(rider: &Rider) -> Self {
+        Ride {
+            mode: Mode::Free,
+            model: RideModel::new(&rider.profile),
+            pedaling: true,
+            target_cadence: None,
+            power: 0.,
+            cadence: 0.,
+            speed: 0.,
+            distance: 0.,
+            work: 0.,
+            heart_rate: RESTING_HEART_RATE,
+            elapsed: Duration::ZERO,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Work out the ride up to now
+    fn update(&mut self, rider: &Rider, max_level: i16) {
+        let now = Instant::now();
+        self.advance(rider, max_level, now - self.updated);
+        self.updated = now;
+    }
+
+    /// Ride on for `duration`, in steps of at most `STEP`
+    fn advance(&mut self, rider: &Rider, max_level: i16, mut duration: Duration) {
+        let mass = rider.profile.weight_kg + rider.profile.bike_weight_kg;
+        while !duration.is_zero() {
+            let step = duration.min(STEP);
+            duration -= step;
+            let dt = step.as_secs_f64();
+            // the share of the way to a target covered in this step
+            let approach = |response: Duration| 1. - (-dt / response.as_secs_f64().max(dt)).exp();
+
+            let (target_power, grade) = match self.mode {
+                _ if !self.pedaling => (0., 0.),
+                Mode::Free => (rider.effort, 0.),
+                Mode::Erg(watts) => (watts, 0.),
+                Mode::Resistance(level) => (rider.effort * 2. * level / f64::from(max_level), 0.),
+                Mode::Simulation(grade) => (rider.effort, grade),
+            };
+            let wobble = (self.elapsed.as_secs_f64() / CADENCE_PERIOD.as_secs_f64()
+                * std::f64::consts::TAU)
+                .sin();
+            let target_cadence = match self.target_cadence {
+                _ if !self.pedaling => 0.,
+                Some(rpm) => rpm,
+                None => rider.cadence + rider.cadence_variation * wobble,
+            };
+            self.power += (target_power - self.power) * approach(rider.responsiveness);
+            self.cadence += (target_cadence - self.cadence) * approach(rider.responsiveness);
+
+            // the power left over from holding the speed speeds the bike up, or slows it down
+            let resisting = self.model.power(self.speed * 3.6, grade);
+            let acceleration = (self.power - resisting) / (mass * self.speed.max(1.));
+            self.speed = (self.speed + acceleration * dt).max(0.);
+            self.distance += self.speed * dt;
+            self.work += self.power * dt;
+
+            let max_heart_rate = rider.profile.max_heart_rate.unwrap_or(200.);
+            let target_heart_rate = (RESTING_HEART_RATE + 0.4 * self.power).min(max_heart_rate);
+            self.heart_rate +=
+                (target_heart_rate - self.heart_rate) * approach(HEART_RATE_RESPONSE);
+            if self.pedaling {
+                self.elapsed += step;
+            }
+        }
+    }
+
+    fn data(&self) -> MachineData {
+        MachineData::Bike(BikeData {
+            speed: Some((self.speed * 3.6) as f32),
+            cadence: Some(self.cadence as f32),
+            distance: Some((self.distance / 1000.) as f32),
+            resistance: match self.mode {
+                Mode::Resistance(level) => Some(level),
+                _ => None,
+            },
+            power: Some(self.power.round() as i16),
+            // at the usual efficiency of about 24 %, every kJ of work burns about a kcal
+            calories: Some(self.work / 1000.),
+            heart_rate: Some(self.heart_rate.round()),
+            time: Some(self.elapsed.as_secs() as u32),
+        })
+    }
+}
+
+/// A simulated bike with a simulated rider on it, for trying out apps without riding
+///
+/// The rider pedals from the start at their own effort, 150 W by default, on the flat. Target powers
+/// take over the power, like an ERG trainer, resistance levels scale the effort of the rider and
+/// simulation parameters put the rider on a grade, in the wind. Power and cadence get to their targets
+/// as quickly as `responsiveness` says, and the speed follows from the power the way it would riding
+/// outside, for the weight in the profile. `stop` and `pause` have the rider stop pedaling, and `reset`
+/// starts the distance, time and calories over.
+///
+/// With `faults`, the bike misbehaves like real ones do, exactly when scripted to.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use kondis::{devices::SimulatorBike, CancellationToken, Equipment, UserProfile};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let profile = UserProfile {
+///         weight_kg: 68.,
+///         ..Default::default()
+///     };
+///     let mut bike = SimulatorBike::new(400, &shutdown)
+///         .await?
+///         .profile(profile)
+///         .cadence(85.)
+///         .responsiveness(Duration::from_millis(500));
+///     bike.connect().await?;
+///     bike.set_target_power(250).await?;
+///     println!("{:?}", bike.read().await?);
+///     bike.disconnect().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SimulatorBike {
+    /// The name of the device
+    pub name: String,
+    max_level: i16,
+    interval: Duration,
+    rider: Rider,
+    ride: Arc<Mutex<Ride>>,
+    faults: Arc<[Fault]>,
+    /// the sequence number of the next reading
+    sequence: Arc<AtomicU64>,
+    /// the index of the next command
+    commands: Arc<AtomicU64>,
+    connected: Arc<AtomicBool>,
+    /// stops the readings of the current connection
+    connection: Arc<Mutex<Option<CancellationToken>>>,
+    events_tx: EventSender,
+    shutdown: CancellationToken,
+}
+
+impl SimulatorBike {
+    /// The rider and their bike, for their weights, 75 and 10 kg by default
+    pub fn profile(mut self, profile: UserProfile) -> Self {
+        self.rider.profile = profile;
+        self.update_ride(|ride| ride.model = RideModel::new(&profile));
+        self
+    }
+
+    /// The power in watts the rider rides at when the bike doesn't set it, 150 W by default
+    pub fn effort(mut self, watts: f64) -> Self {
+        self.rider.effort = watts.max(0.);
+        self
+    }
+
+    /// The cadence the rider prefers in rpm, 90 by default
+    pub fn cadence(mut self, rpm: f64) -> Self {
+        self.rider.cadence = rpm.max(0.);
+        self
+    }
+
+    /// How far the cadence of the rider wobbles around the cadence they prefer in rpm, 3 by default
+    pub fn cadence_variation(mut self, rpm: f64) -> Self {
+        self.rider.cadence_variation = rpm.abs();
+        self
+    }
+
+    /// How long the power and cadence take to get most of the way to their targets, 2 s by
+    /// default
+    pub fn responsiveness(mut self, responsiveness: Duration) -> Self {
+        self.rider.responsiveness = responsiveness;
+        self
+    }
+
+    /// How often the bike sends a reading, every second by default
+    pub fn interval(mut self, interval: Duration) -> Self {
+        // tokio can't tick at no interval at all
+        self.interval = interval.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Misbehave as scripted by `faults`, see `Fault`
+    pub fn faults(mut self, faults: impl IntoIterator<Item = Fault>) -> Self {
+        self.faults = faults.into_iter().collect();
+        self
+    }
+
+    /// Count a command in, failing it when disconnected by a fault and holding it back as scripted
+    async fn command(&self) -> Result<()> {
+        let command = self.commands.fetch_add(1, Ordering::SeqCst);
+        if !self.connected.load(Ordering::SeqCst) && self.connection.lock().unwrap().is_some() {
+            return Err(KondisError::Disconnected(format!(
+                "{} disconnected",
+                self.name
+            )));
+        }
+        let delay = self.faults.iter().find_map(|fault| match *fault {
+            Fault::DelayCommand {
+                command: index,
+                delay,
+            } if index == command => Some(delay),
+            _ => None,
+        });
+        if let Some(delay) = delay {
+            until_shutdown(&self.shutdown, async {
+                crate::runtime::sleep(delay).await;
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn stop_readings(&self) {
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            connection.cancel();
+        }
+    }
+
+    /// Work out the ride up to now, then change it
+    fn update_ride<T>(&self, change: impl FnOnce(&mut Ride) -> T) -> T {
+        let mut ride = self.ride.lock().unwrap();
+        ride.update(&self.rider, self.max_level);
+        change(&mut ride)
+    }
+
+    fn check_level(&self, value: i16, what: &str) -> Result<()> {
+        if !(1..=self.max_level).contains(&value) {
+            return Err(KondisError::InvalidArgument(format!(
+                "{what} must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Equipment for SimulatorBike {
+    async fn with_config(
+        max_level: i16,
+        _: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let rider = Rider {
+            profile: UserProfile::default(),
+            effort: 150.,
+            cadence: 90.,
+            cadence_variation: 3.,
+            responsiveness: Duration::from_secs(2),
+        };
+        Ok(SimulatorBike {
+            name: "simulator bike".to_string(),
+            max_level,
+            interval: Duration::from_secs(1),
+            rider,
+            ride: Arc::new(Mutex::new(Ride::new(&rider))),
+            faults: Arc::new([]),
+            sequence: Arc::new(AtomicU64::new(0)),
+            commands: Arc::new(AtomicU64::new(0)),
+            connected: Arc::new(AtomicBool::new(false)),
+            connection: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            shutdown: shutdown.clone(),
+        })
+    }
+    async fn connect(&mut self) -> Result<bool> {
+        if self.connected.swap(true, Ordering::SeqCst) {
+            return Ok(true);
+        }
+        self.events_tx.set_state(ConnectionState::Connecting);
+        self.stop_readings();
+        let connection = self.shutdown.child_token();
+        *self.connection.lock().unwrap() = Some(connection.clone());
+        self.events_tx.send(DeviceEvent::Connected);
+        // a reading every interval, for as long as the bike is connected
+        let bike = self.clone();
+        crate::runtime::spawn(async move {
+            let mut interval = crate::runtime::interval(bike.interval);
+            loop {
+                tokio::select! {
+                    _ = connection.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let sequence = bike.sequence.fetch_add(1, Ordering::SeqCst);
+                let data = bike.update_ride(|ride| ride.data());
+                let event = if bike.faults.contains(&Fault::DropReading(sequence)) {
+                    None
+                } else if bike.faults.contains(&Fault::MalformedReading(sequence)) {
+                    // the flags promise a speed, but nothing follows them
+                    let error = parse_indoor_bike_data(&[0x00, 0x00]).err();
+                    Some(DeviceEvent::Error(
+                        error.map(|e| e.to_string()).unwrap_or_default(),
+                    ))
+                } else {
+                    Some(DeviceEvent::Data(Reading::new(sequence, data)))
+                };
+                if let Some(event) = event {
+                    bike.events_tx.send(event);
+                }
+                if bike.faults.contains(&Fault::DisconnectAfter(sequence)) {
+                    // keeping the connection around fails commands until connected to again
+                    bike.connected.store(false, Ordering::SeqCst);
+                    bike.events_tx.send(DeviceEvent::Disconnected);
+                    break;
+                }
+            }
+        });
+        Ok(true)
+    }
+    async fn disconnect(&self) -> Result<()> {
+        self.stop_readings();
+        if self.connected.swap(false, Ordering::SeqCst) {
+            self.events_tx.send(DeviceEvent::Disconnected);
+        }
+        Ok(())
+    }
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        self.command().await?;
+        self.check_level(rpm, "RPM")?;
+        self.update_ride(|ride| ride.target_cadence = Some(rpm.into()));
+        Ok(())
+    }
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        self.command().await?;
+        self.check_level(watts, "Watts")?;
+        self.update_ride(|ride| ride.mode = Mode::Erg(watts.into()));
+        Ok(())
+    }
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.command().await?;
+        self.check_level(level, "Resistance level")?;
+        self.update_ride(|ride| ride.mode = Mode::Resistance(level.into()));
+        Ok(())
+    }
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.command().await?;
+        Err(KondisError::Unsupported(format!(
+            "The rider of {} can't ride to {bpm} bpm",
+            self.name
+        )))
+    }
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.command().await?;
+        // checked like any other bike would, though nothing keeps track of the goal
+        training_goal(goal)?;
+        Ok(())
+    }
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.command().await?;
+        simulation_parameters(grade, wind_speed, crr, cw)?;
+        let model = RideModel::new(&self.rider.profile)
+            .wind_speed(wind_speed.into())
+            .crr(crr.into())
+            .cda(f64::from(cw) / (0.5 * AIR_DENSITY));
+        self.update_ride(|ride| {
+            ride.mode = Mode::Simulation(grade.into());
+            ride.model = model;
+        });
+        Ok(())
+    }
+    async fn start(&self) -> Result<()> {
+        self.command().await?;
+        self.update_ride(|ride| ride.pedaling = true);
+        Ok(())
+    }
+    async fn stop(&self) -> Result<()> {
+        self.command().await?;
+        self.update_ride(|ride| ride.pedaling = false);
+        Ok(())
+    }
+    async fn pause(&self) -> Result<()> {
+        self.command().await?;
+        self.update_ride(|ride| ride.pedaling = false);
+        Ok(())
+    }
+    async fn resume(&self) -> Result<()> {
+        self.command().await?;
+        self.update_ride(|ride| ride.pedaling = true);
+        Ok(())
+    }
+    async fn reset(&self) -> Result<()> {
+        self.command().await?;
+        self.update_ride(|ride| {
+            ride.distance = 0.;
+            ride.work = 0.;
+            ride.elapsed = Duration::ZERO;
+        });
+        Ok(())
+    }
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "A simulated bike needs no calibration".to_string(),
+        ))
+    }
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        // nobody pushes any buttons on a simulated bike
+        Ok(Box::pin(futures::stream::empty()))
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            targets: TargetCapabilities {
+                resistance: true,
+                power: true,
+                simulation: true,
+                cadence: true,
+                ..Default::default()
+            },
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                resistance: true,
+                expended_energy: true,
+                heart_rate: true,
+                elapsed_time: true,
+                power: true,
+                ..Default::default()
+            },
+        })
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Ok(Some(self.update_ride(|ride| ride.data())))
+    }
+    /// Every reading of the bike from now on, until it gets disconnected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_simulated_ride() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let bike = SimulatorBike::new(400, &shutdown)
+            .await?
+            .cadence_variation(0.);
+        let rider = bike.rider;
+        let mut ride = Ride::new(&rider);
+        ride.advance(&rider, 400, Duration::from_secs(120));
+        // settled at the effort of the rider, and the speed that takes on the flat
+        assert!((ride.power - 150.).abs() < 1., "{} W", ride.power);
+        assert!((ride.cadence - 90.).abs() < 1., "{} rpm", ride.cadence);
+        let flat = ride.speed * 3.6;
+        assert!((29. ..34.).contains(&flat), "{flat} km/h");
+        assert!(ride.distance > 500.);
+        assert!(ride.heart_rate > 110.);
+
+        ride.mode = Mode::Simulation(6.);
+        ride.advance(&rider, 400, Duration::from_secs(120));
+        assert!(ride.speed * 3.6 < flat / 2.);
+        ride.mode = Mode::Erg(300.);
+        ride.advance(&rider, 400, Duration::from_secs(20));
+        assert!((ride.power - 300.).abs() < 1.);
+
+        ride.pedaling = false;
+        let elapsed = ride.elapsed;
+        ride.advance(&rider, 400, Duration::from_secs(60));
+        assert_eq!(ride.elapsed, elapsed);
+        assert!(ride.power < 1. && ride.cadence < 1.);
+
+        assert!(bike.set_target_power(0).await.is_err());
+        bike.set_target_power(250).await?;
+        assert_eq!(bike.ride.lock().unwrap().mode, Mode::Erg(250.));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_faults() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut bike = SimulatorBike::new(400, &shutdown)
+            .await?
+            .interval(Duration::from_millis(5))
+            .faults([
+                Fault::DropReading(1),
+                Fault::MalformedReading(2),
+                Fault::DisconnectAfter(3),
+                Fault::DelayCommand {
+                    command: 1,
+                    delay: Duration::from_millis(50),
+                },
+            ]);
+        let mut events = bike.events();
+        bike.connect().await?;
+        let mut received = Vec::new();
+        loop {
+            match events.recv().await {
+                Ok(DeviceEvent::Data(reading)) => received.push(Some(reading.sequence)),
+                Ok(DeviceEvent::Error(_)) => received.push(None),
+                Ok(DeviceEvent::Disconnected) => break,
+                Ok(_) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(received, [Some(0), None, Some(3)]);
+        assert!(matches!(
+            bike.set_target_power(200).await,
+            Err(KondisError::Disconnected(_))
+        ));
+
+        bike.connect().await?;
+        let start = Instant::now();
+        bike.set_target_power(200).await?;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        for state in [ConnectionState::Connecting, ConnectionState::Connected] {
+            assert!(matches!(events.recv().await, Ok(DeviceEvent::StateChanged(s)) if s == state));
+        }
+        assert!(matches!(events.recv().await, Ok(DeviceEvent::Connected)));
+        assert!(matches!(
+            events.recv().await,
+            Ok(DeviceEvent::StateChanged(ConnectionState::Streaming))
+        ));
+        assert!(
+            matches!(events.recv().await, Ok(DeviceEvent::Data(reading)) if reading.sequence == 4)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use uuid::{Uuid, uuid};
+
+use crate::ant::{BROADCAST_DATA, Message};
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, get_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::fec::{self, FecDecoder, Page};
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal,
+    simulation_parameters,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// FE-C messages get notified by the trainer on this characteristic of the Tacx FE-C service
+/// (6e40fec1-b5a3-f393-e0a9-e50e24dcca9e)
+const FEC_TX_UUID: Uuid = uuid!("6e40fec2-b5a3-f393-e0a9-e50e24dcca9e");
+/// FE-C messages get written to the trainer on this characteristic
+const FEC_RX_UUID: Uuid = uuid!("6e40fec3-b5a3-f393-e0a9-e50e24dcca9e");
+/// The ANT channel FE-C pages are tunnelled through
+const CHANNEL: u8 = 0x05;
+
+/// A Tacx trainer tunnelling ANT+ FE-C over BLE, like the Neo, Flux and Vortex Smart, including those
+/// whose firmware predates FTMS.
+/// The first device with "Tacx" in its name gets connected to.
+///
+/// Readings hold `MachineData::Bike`, gathered from the general and trainer data pages. Target power,
+/// simulation parameters and resistance levels are supported, the latter as a percentage of the
+/// maximum resistance of the trainer.
+#[derive(Debug, Clone)]
+pub struct TacxFecBike {
+    peripheral: Peripheral,
+    /// The name of the trainer, or its address if it doesn't advertise a name
+    pub name: String,
+    tx: Option<Characteristic>,
+    rx: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+    max_level: i16,
+}
+
+impl Equipment for TacxFecBike {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let Some((peripheral, name)) =
+            get_peripheral(EquipmentType::TacxFecBike, &config, shutdown).await?
+        else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(TacxFecBike {
+            peripheral,
+            name,
+            tx: None,
+            rx: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(&config),
+            timeouts: Timeouts::new(&config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            device_info: None,
+            shutdown: shutdown.clone(),
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.rx = bluetooth::find_characteristic(&self.peripheral, FEC_RX_UUID);
+                if self.rx.is_none() {
+                    return Err(KondisError::CharacteristicMissing("FE-C RX".to_string()));
+                }
+                self.tx = Some(bluetooth::subscribe(&self.peripheral, FEC_TX_UUID).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                let mut decoder = FecDecoder::new();
+                events::forward_notifications(
+                    &self.peripheral,
+                    FEC_TX_UUID,
+                    move |data| Ok(decoder.update(&page(data)?)?.into()),
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to trainer");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(tx) = &self.tx {
+            self.peripheral.unsubscribe(tx).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&watts) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Watts must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        self.write(fec::target_power(watts)).await
+    }
+
+    /// The level is a percentage of the maximum resistance of the trainer
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(0..=100).contains(&level) {
+            return Err(KondisError::InvalidArgument(
+                "Resistance level must be between 0 and 100 %".to_string(),
+            ));
+        }
+        self.write(fec::basic_resistance(level.into())).await
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        // validated the same as over FTMS
+        simulation_parameters(grade, wind_speed, crr, cw)?;
+        self.write(fec::wind_resistance(cw, wind_speed)).await?;
+        self.write(fec::track_resistance(grade, crr)).await
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                heart_rate: true,
+                elapsed_time: true,
+                power: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected, holding what every data page so far added to it
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl BluetoothEquipment for TacxFecBike {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl TacxFecBike {
+    async fn write(&self, page: Page) -> Result<()> {
+        let Some(rx) = &self.rx else {
+            return Err(KondisError::CharacteristicMissing("FE-C RX".to_string()));
+        };
+        until_shutdown(&self.shutdown, async {
+            Ok(self
+                .peripheral
+                .write(rx, &message(page), WriteType::WithResponse)
+                .await?)
+        })
+        .await
+    }
+}
+
+/// Wrap a data page into an ANT broadcast data message
+fn message(page: Page) -> Vec<u8> {
+    Message::page(BROADCAST_DATA, CHANNEL, &page).encode()
+}
+
+/// The data page of an ANT data message
+fn page(message: &[u8]) -> Result<Vec<u8>> {
+    let message = Message::decode(message)?;
+    match message.data_page() {
+        Some((_, page)) => Ok(page.to_vec()),
+        None => Err(KondisError::InvalidData(format!(
+            "Not an ANT data page: {message:02x?}"
+        ))),
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Tacx FE-C trainers do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() -> Result<()> {
+        let target = fec::target_power(250);
+        let message = message(target);
+        assert_eq!(message[..4], [0xA4, 0x09, 0x4E, 0x05]);
+        assert_eq!(message.len(), 13);
+        assert_eq!(page(&message)?, target);
+
+        let mut corrupt = message.clone();
+        corrupt[5] ^= 0x01;
+        assert!(page(&corrupt).is_err());
+        assert!(page(&message[..8]).is_err());
+        Ok(())
+    }
+}
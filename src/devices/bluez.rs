@@ -0,0 +1,488 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use bluer::gatt::CharacteristicFlags;
+use bluer::gatt::WriteOp;
+use bluer::gatt::remote::{self, CharacteristicWriteRequest};
+use bluer::{
+    Adapter, AdapterEvent, Device, DeviceProperty, DiscoveryFilter, DiscoveryTransport, ErrorKind,
+    Session,
+};
+use btleplug::api::{CharPropFlags, Characteristic, PeripheralProperties, ValueNotification};
+use futures::StreamExt;
+use tokio::sync::{broadcast, broadcast::error::RecvError};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::bluetooth::{
+    DeviceInfo, NotificationStream, ScanConfig, Transport, is_match, pick_adapters, within,
+};
+use crate::devices::bikes::generic_ftms::decode;
+use crate::devices::ftms_peripheral::FtmsPeripheral;
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, FITNESS_MACHINE_SERVICE_UUID, INDOOR_BIKE_DATA_UUID, MAX_CADENCE, MachineData,
+    SpinDownResult, SpinDownStatus, SupportedRanges, TrainingGoal, machine_type,
+    parse_indoor_bike_data, simulation_parameters, target_cadence, target_heart_rate, target_power,
+    target_resistance_level, training_goal,
+};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Result,
+};
+
+/// Any standards-compliant FTMS smart trainer or bike, talked to through BlueZ directly, with the
+/// `bluer` feature on Linux
+///
+/// Does what `GenericFtmsBike` does, for the adapters btleplug struggles with, going through the same
+/// FTMS plumbing with BlueZ as its transport. BlueZ names its adapters like `hci0`, which is what
+/// `ScanConfig::adapter_name` matches. The other options of `ScanConfig` narrow down the bike the same
+/// way.
+#[derive(Debug, Clone)]
+pub struct BluezFtmsBike {
+    ftms: FtmsPeripheral<BluezPeripheral>,
+    /// The name of the device, or its address if it doesn't advertise a name
+    pub name: String,
+    max_level: i16,
+}
+
+impl Equipment for BluezFtmsBike {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let session = Session::new().await.map_err(classify)?;
+        let adapters = adapters(&session, &config).await?;
+        let (device, name) = until_shutdown(shutdown, find(&adapters, &config)).await?;
+        let ftms = FtmsPeripheral::new(
+            BluezPeripheral::new(device),
+            name,
+            INDOOR_BIKE_DATA_UUID,
+            decode,
+            &config,
+            shutdown,
+        );
+        Ok(BluezFtmsBike {
+            name: ftms.name.clone(),
+            ftms,
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let connected = self.ftms.connect().await?;
+        info!(device = %self.name, "Found and connected to bike");
+        Ok(connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.ftms.disconnect().await
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        if !(1..=MAX_CADENCE).contains(&rpm) {
+            return Err(KondisError::InvalidArgument(format!(
+                "RPM must be between 1 and {MAX_CADENCE}"
+            )));
+        }
+        self.ftms.write(&target_cadence(rpm)?).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let watts = self.ftms.target_power(watts, self.max_level)?;
+        self.ftms.write(&target_power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        let level = self.ftms.target_resistance_level(level, self.max_level)?;
+        self.ftms.write(&target_resistance_level(level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.ftms.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.ftms.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.ftms
+            .write(&simulation_parameters(grade, wind_speed, crr, cw)?)
+            .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.ftms.stop().await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.ftms.pause().await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.ftms.reset().await
+    }
+
+    async fn spin_down(&self, status_tx: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        self.ftms.spin_down(status_tx).await
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        self.ftms.machine_status().await
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.ftms.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ftms.ranges
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.ftms.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.ftms.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.ftms.events()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.ftms.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let data = self.ftms.notification().await?;
+        Ok(parse_indoor_bike_data(&data).ok().map(MachineData::from))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        self.ftms.data_stream().await
+    }
+}
+
+/// A device talked to through BlueZ, as the transport under `FtmsPeripheral`
+///
+/// BlueZ notifies every characteristic on a stream of its own, which get merged here into the one
+/// stream of `Transport::notifications`, the way btleplug hands them out.
+#[derive(Debug, Clone)]
+pub(crate) struct BluezPeripheral {
+    device: Device,
+    /// Found by `discover_services`, along with the BlueZ characteristic each stands for
+    characteristics: Arc<Mutex<Vec<(Characteristic, remote::Characteristic)>>>,
+    notifications: broadcast::Sender<ValueNotification>,
+    /// Cancelled on unsubscribing, which ends forwarding the notifications of the characteristic
+    subscriptions: Arc<Mutex<HashMap<Uuid, CancellationToken>>>,
+}
+
+impl BluezPeripheral {
+    fn new(device: Device) -> Self {
+        BluezPeripheral {
+            device,
+            characteristics: Arc::new(Mutex::new(Vec::new())),
+            notifications: broadcast::channel(64).0,
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The BlueZ characteristic standing for one found by `discover_services`
+    fn remote(&self, characteristic: &Characteristic) -> Result<remote::Characteristic> {
+        self.characteristics
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(found, _)| {
+                found.uuid == characteristic.uuid
+                    && found.service_uuid == characteristic.service_uuid
+            })
+            .map(|(_, remote)| remote.clone())
+            .ok_or_else(|| KondisError::CharacteristicMissing(characteristic.uuid.to_string()))
+    }
+}
+
+impl Transport for BluezPeripheral {
+    fn address(&self) -> String {
+        self.device.address().to_string()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.device.is_connected().await?)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        Ok(self.device.connect().await?)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        for (_, subscription) in self.subscriptions.lock().unwrap().drain() {
+            subscription.cancel();
+        }
+        Ok(self.device.disconnect().await?)
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        let mut characteristics = Vec::new();
+        for service in self.device.services().await? {
+            let service_uuid = service.uuid().await?;
+            for remote in service.characteristics().await? {
+                let characteristic = Characteristic {
+                    uuid: remote.uuid().await?,
+                    service_uuid,
+                    properties: properties(remote.flags().await?),
+                    descriptors: BTreeSet::new(),
+                };
+                characteristics.push((characteristic, remote));
+            }
+        }
+        *self.characteristics.lock().unwrap() = characteristics;
+        Ok(())
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.characteristics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(characteristic, _)| characteristic.clone())
+            .collect()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        Ok(self.remote(characteristic)?.read().await?)
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        let request = CharacteristicWriteRequest {
+            op_type: WriteOp::Request,
+            ..Default::default()
+        };
+        Ok(self
+            .remote(characteristic)?
+            .write_ext(data, &request)
+            .await?)
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let values = self.remote(characteristic)?.notify().await?;
+        let subscription = CancellationToken::new();
+        // subscribing again replaces the previous subscription, rather than notify everything twice
+        if let Some(previous) = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .insert(characteristic.uuid, subscription.clone())
+        {
+            previous.cancel();
+        }
+        let uuid = characteristic.uuid;
+        let notifications = self.notifications.clone();
+        crate::runtime::spawn(async move {
+            let mut values = std::pin::pin!(values.take_until(subscription.cancelled_owned()));
+            while let Some(value) = values.next().await {
+                let _ = notifications.send(ValueNotification { uuid, value });
+            }
+        });
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        if let Some(subscription) = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(&characteristic.uuid)
+        {
+            subscription.cancel();
+        }
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        let changes = self.device.events().await?;
+        let disconnected = async move {
+            let mut changes = std::pin::pin!(changes);
+            while let Some(change) = changes.next().await {
+                if let bluer::DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) =
+                    change
+                {
+                    return;
+                }
+            }
+        };
+        let notifications = self.notifications.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(notifications, |mut notifications| async move {
+                loop {
+                    match notifications.recv().await {
+                        Ok(notification) => return Some((notification, notifications)),
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .take_until(Box::pin(disconnected)),
+        ))
+    }
+
+    async fn rssi(&self) -> Result<Option<i16>> {
+        Ok(self.device.rssi().await?)
+    }
+}
+
+/// What btleplug calls the flags of a BlueZ characteristic
+fn properties(flags: CharacteristicFlags) -> CharPropFlags {
+    let mut properties = CharPropFlags::empty();
+    for (set, property) in [
+        (flags.broadcast, CharPropFlags::BROADCAST),
+        (flags.read, CharPropFlags::READ),
+        (
+            flags.write_without_response,
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        ),
+        (flags.write, CharPropFlags::WRITE),
+        (flags.notify, CharPropFlags::NOTIFY),
+        (flags.indicate, CharPropFlags::INDICATE),
+        (
+            flags.authenticated_signed_writes,
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+        ),
+        (
+            flags.extended_properties,
+            CharPropFlags::EXTENDED_PROPERTIES,
+        ),
+    ] {
+        properties.set(property, set);
+    }
+    properties
+}
+
+/// The adapters to scan with, all of them unless the config picks one, picked the way
+/// `bluetooth::adapters` picks them
+async fn adapters(session: &Session, config: &ScanConfig) -> Result<Vec<Adapter>> {
+    let names = session.adapter_names().await.map_err(classify)?;
+    let picked = pick_adapters(names.len(), &names, config)?;
+    let mut adapters = Vec::with_capacity(picked.len());
+    for index in picked {
+        let adapter = session.adapter(&names[index]).map_err(classify)?;
+        if !adapter.is_powered().await.map_err(classify)? {
+            return Err(KondisError::AdapterPoweredOff(format!(
+                "{} is powered off, power it on with `bluetoothctl power on`",
+                adapter.name()
+            )));
+        }
+        adapters.push(adapter);
+    }
+    Ok(adapters)
+}
+
+/// Discover devices until one matches the config as a generic FTMS bike, resolving to it and its name,
+/// or its address if it doesn't advertise a name
+async fn find(adapters: &[Adapter], config: &ScanConfig) -> Result<(Device, String)> {
+    // a known address needs no filtering, and may belong to a bike that leaves the service out
+    let filter = DiscoveryFilter {
+        uuids: match config.service_uuid {
+            Some(uuid) => [uuid].into(),
+            None if config.address.is_none() => [FITNESS_MACHINE_SERVICE_UUID].into(),
+            None => Default::default(),
+        },
+        transport: DiscoveryTransport::Le,
+        ..Default::default()
+    };
+    let mut discoveries = Vec::with_capacity(adapters.len());
+    for (index, adapter) in adapters.iter().enumerate() {
+        adapter
+            .set_discovery_filter(filter.clone())
+            .await
+            .map_err(classify)?;
+        // remember which adapter added each device, so it gets looked up on the right one
+        let devices = adapter.discover_devices().await.map_err(classify)?;
+        discoveries.push(devices.map(move |event| (index, event)));
+    }
+    debug!(services = ?filter.uuids, "Scanning on {} adapters", adapters.len());
+    let mut discoveries = futures::stream::select_all(discoveries);
+
+    let scanning = async {
+        while let Some((index, event)) = discoveries.next().await {
+            let AdapterEvent::DeviceAdded(address) = event else {
+                continue;
+            };
+            let device = adapters[index].device(address)?;
+            let properties = PeripheralProperties {
+                address: address.0.into(),
+                local_name: device.name().await?,
+                services: device
+                    .uuids()
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                service_data: device.service_data().await?.unwrap_or_default(),
+                ..Default::default()
+            };
+            trace!(address = %address, name = ?properties.local_name, "Discovered");
+            if is_match(&properties, config, Some(machine_type::INDOOR_BIKE), "bike") {
+                let name = properties.local_name.unwrap_or_else(|| address.to_string());
+                info!(address = %address, device = %name, "Found");
+                return Ok((device, name));
+            }
+        }
+        Err(KondisError::DeviceNotFound)
+    };
+    match config.timeout {
+        Some(timeout) => within(timeout, KondisError::ScanTimeout, scanning).await,
+        None => scanning.await,
+    }
+}
+
+/// What BlueZ failing to give access to its adapters means for the equipment
+fn classify(error: bluer::Error) -> KondisError {
+    match error.kind {
+        ErrorKind::NotReady | ErrorKind::NotAvailable => {
+            KondisError::BluetoothUnavailable(error.to_string())
+        }
+        ErrorKind::NotAuthorized | ErrorKind::NotPermitted => {
+            KondisError::PermissionDenied(error.to_string())
+        }
+        _ => KondisError::from(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_properties() {
+        let flags = CharacteristicFlags {
+            write: true,
+            indicate: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            properties(flags),
+            CharPropFlags::WRITE | CharPropFlags::INDICATE
+        );
+        assert!(properties(CharacteristicFlags::default()).is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::runtime::Instant;
+use btleplug::api::Characteristic;
+use futures::StreamExt;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::bluetooth::Transport;
+use crate::ftms::{CONTROL_POINT_UUID, FTMSControlOpCode, StopCode, parse_control_point_response};
+use crate::{KondisError, Result};
+
+/// How long the machine gets to respond to a control point command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The least amount of time between two control point writes
+const MIN_WRITE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Commands which jump ahead of pending target changes
+const PRIORITY_OP_CODES: [u8; 2] = [
+    FTMSControlOpCode::Reset as u8,
+    FTMSControlOpCode::Stop as u8,
+];
+/// Commands where only the latest pending one matters
+const TARGET_OP_CODES: [u8; 7] = [
+    FTMSControlOpCode::TargetSpeed as u8,
+    FTMSControlOpCode::TargetInclination as u8,
+    FTMSControlOpCode::TargetResistanceLevel as u8,
+    FTMSControlOpCode::TargetPower as u8,
+    FTMSControlOpCode::TargetHeartRate as u8,
+    FTMSControlOpCode::SimulationParameters as u8,
+    FTMSControlOpCode::TargetCadence as u8,
+];
+
+#[derive(Debug)]
+struct Queued {
+    data: Vec<u8>,
+    responder: oneshot::Sender<Result<Vec<u8>>>,
+}
+
+/// A serialized, rate limited queue of control point commands.
+///
+/// A single background task writes the commands one at a time, waiting for the machine to respond to
+/// each before moving on. Target changes still waiting to be written are coalesced, so only the latest
+/// target reaches the machine, and stop and reset commands are written before any pending targets.
+#[derive(Debug, Clone)]
+pub(crate) struct CommandQueue {
+    queue: Arc<Mutex<VecDeque<Queued>>>,
+    wake_tx: mpsc::UnboundedSender<()>,
+    /// Whether to stop the machine once every handle is dropped, see `CommandQueue::spawn`
+    stop_on_drop: Arc<AtomicBool>,
+}
+
+impl CommandQueue {
+    /// Start writing commands to the control point, until every handle to the queue is dropped
+    ///
+    /// The machine gets stopped and disconnected from once they are, if it's still connected, so
+    /// equipment dropped without disconnecting doesn't keep holding its target.
+    pub fn spawn(peripheral: impl Transport, control: Characteristic) -> Self {
+        let queue: Arc<Mutex<VecDeque<Queued>>> = Arc::new(Mutex::new(VecDeque::new()));
+        let (wake_tx, mut wake_rx) = mpsc::unbounded_channel();
+        let worker_queue = Arc::clone(&queue);
+        let stop_on_drop = Arc::new(AtomicBool::new(true));
+        let worker_stop_on_drop = Arc::clone(&stop_on_drop);
+        crate::runtime::spawn(async move {
+            let mut last_write: Option<Instant> = None;
+            while wake_rx.recv().await.is_some() {
+                loop {
+                    let next = worker_queue.lock().unwrap().pop_front();
+                    let Some(queued) = next else {
+                        break;
+                    };
+                    if let Some(last_write) = last_write {
+                        crate::runtime::sleep_until(last_write + MIN_WRITE_INTERVAL).await;
+                    }
+                    last_write = Some(Instant::now());
+                    let response = send(&peripheral, &control, &queued.data).await;
+                    let _ = queued.responder.send(response);
+                }
+            }
+            if worker_stop_on_drop.load(Ordering::SeqCst)
+                && peripheral.is_connected().await.unwrap_or(false)
+            {
+                warn!("Dropped while connected, stopping and disconnecting");
+                let stop = [FTMSControlOpCode::Stop as u8, StopCode::Stop as u8];
+                let _ = send(&peripheral, &control, &stop).await;
+                let _ = peripheral.disconnect().await;
+            }
+        });
+        CommandQueue {
+            queue,
+            wake_tx,
+            stop_on_drop,
+        }
+    }
+
+    /// Leave the machine be once every handle is dropped, like when another queue took over
+    pub fn release(&self) {
+        self.stop_on_drop.store(false, Ordering::SeqCst);
+    }
+
+    /// Queue a command and wait for the machine to respond to it
+    ///
+    /// Resolves to the response parameters, or to nothing if a newer target replaced the command
+    /// before it got written.
+    pub async fn submit(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (responder, response) = oneshot::channel();
+        enqueue(
+            &mut self.queue.lock().unwrap(),
+            Queued {
+                data: data.to_vec(),
+                responder,
+            },
+        );
+        self.wake_tx.send(()).map_err(|_| {
+            KondisError::Disconnected("The command queue has shut down".to_string())
+        })?;
+        response
+            .await
+            .map_err(|_| KondisError::Disconnected("The command queue has shut down".to_string()))?
+    }
+}
+
+fn enqueue(queue: &mut VecDeque<Queued>, queued: Queued) {
+    let op_code = queued.data.first().copied();
+    if let Some(op_code) = op_code {
+        if TARGET_OP_CODES.contains(&op_code)
+            && let Some(pending) = queue
+                .iter_mut()
+                .find(|pending| pending.data.first() == Some(&op_code))
+        {
+            let superseded = std::mem::replace(pending, queued);
+            let _ = superseded.responder.send(Ok(Vec::new()));
+            return;
+        }
+        if PRIORITY_OP_CODES.contains(&op_code) {
+            let position = queue
+                .iter()
+                .position(|pending| {
+                    !pending
+                        .data
+                        .first()
+                        .is_some_and(|op_code| PRIORITY_OP_CODES.contains(op_code))
+                })
+                .unwrap_or(queue.len());
+            queue.insert(position, queued);
+            return;
+        }
+    }
+    queue.push_back(queued);
+}
+
+/// Write a command to the control point and wait for the machine to respond to it
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        err(level = "warn"),
+        fields(address = %peripheral.address(), op_code = ?data.first())
+    )
+)]
+async fn send(
+    peripheral: &impl Transport,
+    control: &Characteristic,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let Some(&op_code) = data.first() else {
+        return Err(KondisError::InvalidArgument(
+            "Empty control point command".to_string(),
+        ));
+    };
+    // listen before writing, so the response can't slip past
+    let mut notifications = peripheral.notifications().await?;
+    debug!(address = %peripheral.address(), uuid = %control.uuid, "Writing {data:02x?}");
+    peripheral.write(control, data).await?;
+
+    let response = crate::runtime::timeout(RESPONSE_TIMEOUT, async {
+        while let Some(notification) = notifications.next().await {
+            if notification.uuid != CONTROL_POINT_UUID {
+                continue;
+            }
+            if let Some(response) = parse_control_point_response(&notification.value, op_code) {
+                return Some(response);
+            }
+        }
+        None
+    })
+    .await;
+    match response {
+        Ok(Some(response)) => Ok(response?),
+        Ok(None) => Err(KondisError::Disconnected(
+            "Notifications ended before the control point responded".to_string(),
+        )),
+        Err(_) => Err(KondisError::Timeout(format!(
+            "No control point response to {op_code:#04x} within {RESPONSE_TIMEOUT:?}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{Operation, RecordingTransport};
+    use crate::ftms::{ControlPointError, ResultCode};
+
+    /// A queue on a connected transport, which already wrote a target power
+    async fn spawned() -> Result<(RecordingTransport, CommandQueue)> {
+        let transport = RecordingTransport::new();
+        transport.connect().await?;
+        let control = transport.characteristic(CONTROL_POINT_UUID).unwrap();
+        let queue = CommandQueue::spawn(transport.clone(), control);
+        queue.submit(&[0x05, 100, 0]).await?;
+        Ok((transport, queue))
+    }
+
+    fn queued(data: &[u8]) -> (Queued, oneshot::Receiver<Result<Vec<u8>>>) {
+        let (responder, response) = oneshot::channel();
+        (
+            Queued {
+                data: data.to_vec(),
+                responder,
+            },
+            response,
+        )
+    }
+
+    #[test]
+    fn test_coalesce_and_prioritize() {
+        let mut queue = VecDeque::new();
+        let (first_power, mut first_response) = queued(&[0x05, 100, 0]);
+        let (cadence, _cadence_response) = queued(&[0x14, 180, 0]);
+        let (second_power, _second_power_response) = queued(&[0x05, 200, 0]);
+        let (stop, _stop_response) = queued(&[0x08, 0x01]);
+        enqueue(&mut queue, first_power);
+        enqueue(&mut queue, cadence);
+        enqueue(&mut queue, second_power);
+        enqueue(&mut queue, stop);
+
+        let order: Vec<Vec<u8>> = queue.iter().map(|queued| queued.data.clone()).collect();
+        assert_eq!(
+            order,
+            vec![vec![0x08, 0x01], vec![0x05, 200, 0], vec![0x14, 180, 0]]
+        );
+        // the superseded target resolves without ever being written
+        assert!(first_response.try_recv().unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejected() -> Result<()> {
+        let (transport, queue) = spawned().await?;
+        transport.respond_with(ResultCode::ControlNotPermitted);
+        let rejected = queue.submit(&[0x05, 200, 0]).await;
+        let Err(KondisError::ControlRejected(error)) = rejected else {
+            panic!("{rejected:?} wasn't rejected");
+        };
+        assert_eq!(
+            error,
+            ControlPointError {
+                op_code: 0x05,
+                result: ResultCode::ControlNotPermitted,
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_on_drop() -> Result<()> {
+        let (transport, queue) = spawned().await?;
+        drop(queue);
+        crate::runtime::sleep(Duration::from_secs(1)).await;
+        assert_eq!(transport.commands(), [vec![0x05, 100, 0], vec![0x08, 0x01]]);
+        assert_eq!(transport.operations().last(), Some(&Operation::Disconnect));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_release() -> Result<()> {
+        let (transport, queue) = spawned().await?;
+        queue.release();
+        drop(queue);
+        crate::runtime::sleep(Duration::from_secs(1)).await;
+        assert_eq!(transport.commands(), [vec![0x05, 100, 0]]);
+        assert!(!transport.operations().contains(&Operation::Disconnect));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_stop_once_disconnected() -> Result<()> {
+        let (transport, queue) = spawned().await?;
+        transport.disconnect().await?;
+        drop(queue);
+        crate::runtime::sleep(Duration::from_secs(1)).await;
+        assert_eq!(transport.commands(), [vec![0x05, 100, 0]]);
+        assert_eq!(
+            transport
+                .operations()
+                .iter()
+                .filter(|operation| **operation == Operation::Disconnect)
+                .count(),
+            1
+        );
+        Ok(())
+    }
+}
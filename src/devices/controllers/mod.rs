@@ -0,0 +1,20 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod zwift;
+
+/// A button on a handlebar controller, see `DeviceEvent::ButtonPressed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControllerButton {
+    /// The plus button of a Click, or the paddle of the right Play controller
+    ShiftUp,
+    /// The minus button of a Click, or the paddle of the left Play controller
+    ShiftDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Y,
+    Z,
+}
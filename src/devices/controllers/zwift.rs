@@ -0,0 +1,368 @@
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::{Uuid, uuid};
+
+use super::ControllerButton;
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, find_characteristic, get_peripheral, read_device_info,
+};
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{Capabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// Notified with the state of the buttons, whenever it changes
+const ASYNC_UUID: Uuid = uuid!("00000002-19ca-4651-86e5-fa29dcdd09d1");
+/// Written to, for the handshake
+const SYNC_RX_UUID: Uuid = uuid!("00000003-19ca-4651-86e5-fa29dcdd09d1");
+/// Indicated with the answers to what got written
+const SYNC_TX_UUID: Uuid = uuid!("00000004-19ca-4651-86e5-fa29dcdd09d1");
+/// The handshake asking for the button states unencrypted, written without a key
+const RIDE_ON: &[u8] = b"RideOn";
+/// Message types of the async characteristic
+const CLICK_BUTTONS: u8 = 0x37;
+const PLAY_BUTTONS: u8 = 0x07;
+/// The protobuf field of Play messages telling which side the controller is on, and its value for the
+/// left side. The other fields are the states of the buttons, also of the plus and minus of a Click.
+const PLAY_SIDE: u64 = 1;
+const PLAY_LEFT: u64 = 1;
+/// Button states, as protobuf values
+const PRESSED: u64 = 0;
+
+/// A Zwift Click or Zwift Play handlebar controller.
+/// The first device with "Zwift" in its name gets connected to.
+///
+/// Every press and release of a button comes as a `DeviceEvent::ButtonPressed` and
+/// `DeviceEvent::ButtonReleased` of `events`, to shift the gears of a `VirtualDrivetrain` with, see
+/// `VirtualDrivetrain::follow`, or to steer a user interface with. The controller measures nothing of
+/// a machine, so every reading, target and session command is unsupported. The controller gets asked
+/// for its button states unencrypted, which firmware requiring the encrypted protocol of Zwift doesn't
+/// answer.
+#[derive(Debug, Clone)]
+pub struct ZwiftController {
+    peripheral: Peripheral,
+    /// The name of the controller, or its address if it doesn't advertise a name
+    pub name: String,
+    buttons: Option<Characteristic>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for ZwiftController {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let timeouts = Timeouts::new(&config);
+        let battery = Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY));
+        let Some((peripheral, name)) =
+            get_peripheral(EquipmentType::ZwiftController, &config, shutdown).await?
+        else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(ZwiftController {
+            peripheral,
+            name,
+            buttons: None,
+            events_tx: events::channel(),
+            timeouts,
+            battery,
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.buttons = Some(bluetooth::subscribe(&self.peripheral, ASYNC_UUID).await?);
+                bluetooth::subscribe(&self.peripheral, SYNC_TX_UUID).await?;
+                let Some(sync_rx) = find_characteristic(&self.peripheral, SYNC_RX_UUID) else {
+                    return Err(KondisError::CharacteristicMissing(SYNC_RX_UUID.to_string()));
+                };
+                let notifications = self.peripheral.notifications().await?;
+                self.peripheral
+                    .write(&sync_rx, RIDE_ON, WriteType::WithResponse)
+                    .await?;
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let events_tx = self.events_tx.clone();
+                let mut notifications = notifications
+                    .take_until(self.shutdown.clone().cancelled_owned())
+                    .boxed();
+                crate::runtime::spawn(async move {
+                    let mut buttons = Buttons::default();
+                    while let Some(data) = notifications.next().await {
+                        if data.uuid != ASYNC_UUID {
+                            continue;
+                        }
+                        for event in buttons.update(&decode(&data.value)) {
+                            events_tx.send(event);
+                        }
+                    }
+                });
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(buttons) = &self.buttons {
+            self.peripheral.unsubscribe(buttons).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities::default())
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Err(unsupported("machine data, only button events"))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        Err(unsupported("machine data, only button events"))
+    }
+}
+
+impl BluetoothEquipment for ZwiftController {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+/// The buttons held down, to tell presses and releases apart from the states notified
+#[derive(Debug, Default)]
+struct Buttons {
+    pressed: Vec<ControllerButton>,
+}
+
+impl Buttons {
+    /// The events of the buttons whose state changed
+    fn update(&mut self, states: &[(ControllerButton, bool)]) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        for &(button, pressed) in states {
+            let held = self.pressed.contains(&button);
+            if pressed && !held {
+                self.pressed.push(button);
+                events.push(DeviceEvent::ButtonPressed(button));
+            } else if !pressed && held {
+                self.pressed.retain(|&held| held != button);
+                events.push(DeviceEvent::ButtonReleased(button));
+            }
+        }
+        events
+    }
+}
+
+/// The state of every button in a notification, with whether it's pressed
+///
+/// Messages of other types, like keep alives, and fields of no button, like the power buttons and the
+/// analog paddles of the Play, hold no states.
+fn decode(data: &[u8]) -> Vec<(ControllerButton, bool)> {
+    let Some((&message_type, message)) = data.split_first() else {
+        return Vec::new();
+    };
+    let fields = protobuf_fields(message);
+    let button: fn(u64) -> Option<ControllerButton> = match message_type {
+        CLICK_BUTTONS => |field| match field {
+            1 => Some(ControllerButton::ShiftUp),
+            2 => Some(ControllerButton::ShiftDown),
+            _ => None,
+        },
+        PLAY_BUTTONS if fields.contains(&(PLAY_SIDE, PLAY_LEFT)) => |field| match field {
+            2 => Some(ControllerButton::Up),
+            3 => Some(ControllerButton::Left),
+            4 => Some(ControllerButton::Right),
+            5 => Some(ControllerButton::Down),
+            6 => Some(ControllerButton::ShiftDown),
+            _ => None,
+        },
+        PLAY_BUTTONS => |field| match field {
+            2 => Some(ControllerButton::Y),
+            3 => Some(ControllerButton::Z),
+            4 => Some(ControllerButton::A),
+            5 => Some(ControllerButton::B),
+            6 => Some(ControllerButton::ShiftUp),
+            _ => None,
+        },
+        _ => return Vec::new(),
+    };
+    fields
+        .into_iter()
+        .filter_map(|(field, value)| Some((button(field)?, value == PRESSED)))
+        .collect()
+}
+
+/// The varint fields of a protobuf message, as field numbers and values, skipping fields of other wire
+/// types and stopping at anything cut short
+fn protobuf_fields(mut message: &[u8]) -> Vec<(u64, u64)> {
+    let mut fields = Vec::new();
+    while let Some(key) = varint(&mut message) {
+        match key & 0x07 {
+            0 => match varint(&mut message) {
+                Some(value) => fields.push((key >> 3, value)),
+                None => break,
+            },
+            2 => match varint(&mut message) {
+                Some(length) if length as usize <= message.len() => {
+                    message = &message[length as usize..];
+                }
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    fields
+}
+
+fn varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Handlebar controllers do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ControllerButton::*;
+
+    #[test]
+    fn test_decode() {
+        // plus pressed, minus released
+        assert_eq!(
+            decode(&[0x37, 0x08, 0x00, 0x10, 0x01]),
+            [(ShiftUp, true), (ShiftDown, false)]
+        );
+        // the left Play controller, with up pressed and the analog paddle left out
+        assert_eq!(
+            decode(&[0x07, 0x08, 0x01, 0x10, 0x00, 0x18, 0x01, 0x40, 0x02]),
+            [(Up, true), (Left, false)]
+        );
+        // the right Play controller, with its paddle pressed
+        assert_eq!(
+            decode(&[0x07, 0x08, 0x00, 0x30, 0x00, 0x38, 0x01]),
+            [(ShiftUp, true)]
+        );
+        assert!(decode(&[0x15]).is_empty());
+        assert!(decode(&[0x37, 0x08]).is_empty());
+    }
+
+    #[test]
+    fn test_buttons() {
+        let mut buttons = Buttons::default();
+        let pressed = buttons.update(&[(ShiftUp, true), (ShiftDown, false)]);
+        assert!(matches!(pressed[..], [DeviceEvent::ButtonPressed(ShiftUp)]));
+        // held down, nothing changed
+        assert!(buttons.update(&[(ShiftUp, true)]).is_empty());
+        let released = buttons.update(&[(ShiftUp, false)]);
+        assert!(matches!(
+            released[..],
+            [DeviceEvent::ButtonReleased(ShiftUp)]
+        ));
+    }
+}
@@ -0,0 +1 @@
+pub mod generic_ftms;
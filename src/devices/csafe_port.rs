@@ -0,0 +1,90 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+
+use crate::csafe::{self, STOP};
+use crate::devices::serial::{Baud, SerialPort};
+use crate::{KondisError, Result};
+
+/// Reports Concept2 monitors take CSAFE frames in over USB, by report id and size including the id
+const HID_REPORTS: [(u8, usize); 3] = [(0x01, 21), (0x04, 63), (0x02, 121)];
+
+/// A serial port or USB HID device speaking CSAFE, one frame answering every frame sent
+///
+/// Concept2 monitors show up as a HID device rather than a serial port, like `/dev/hidraw0`, wrapping
+/// frames in fixed size reports.
+#[derive(Debug)]
+pub(crate) enum CsafePort {
+    Serial(SerialPort),
+    #[cfg(unix)]
+    Hid(AsyncFd<std::fs::File>),
+}
+
+impl CsafePort {
+    /// Open the port at `path`, telling HID devices apart by their `hidraw` name
+    pub fn open(path: &Path) -> Result<Self> {
+        let hid = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("hidraw"));
+        if !hid {
+            return Ok(CsafePort::Serial(SerialPort::open(path, Baud::B9600)?));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            // non-blocking, so reads waiting on the monitor can be cancelled
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)?;
+            Ok(CsafePort::Hid(AsyncFd::new(file)?))
+        }
+        #[cfg(not(unix))]
+        Err(KondisError::Unsupported(
+            "HID devices are only supported on unix".to_string(),
+        ))
+    }
+
+    /// Send `commands` in a frame, resolving to the contents of the frame answering it
+    pub async fn send(&mut self, commands: &[u8]) -> Result<Vec<u8>> {
+        let frame = csafe::frame(commands);
+        let hid = match self {
+            CsafePort::Serial(port) => {
+                port.write(&frame).await?;
+                return csafe::unframe(&port.read(csafe::take_frame).await?);
+            }
+            #[cfg(unix)]
+            CsafePort::Hid(hid) => hid,
+        };
+        let Some(&(id, size)) = HID_REPORTS.iter().find(|(_, size)| frame.len() < *size) else {
+            return Err(KondisError::InvalidArgument(
+                "Too many CSAFE commands for a single frame".to_string(),
+            ));
+        };
+        let mut report = vec![0; size];
+        report[0] = id;
+        report[1..=frame.len()].copy_from_slice(&frame);
+        // every write and read is a whole report, the frame followed by padding
+        loop {
+            let mut guard = hid.writable().await?;
+            if let Ok(written) = guard.try_io(|hid| hid.get_ref().write(&report)) {
+                written?;
+                break;
+            }
+        }
+        let mut buffer = [0; 128];
+        let read = loop {
+            let mut guard = hid.readable().await?;
+            if let Ok(read) = guard.try_io(|hid| hid.get_ref().read(&mut buffer)) {
+                break read?;
+            }
+        };
+        let report = buffer.get(1..read).unwrap_or_default();
+        let end = report.iter().position(|&byte| byte == STOP);
+        csafe::unframe(&report[..end.map_or(report.len(), |end| end + 1)])
+    }
+}
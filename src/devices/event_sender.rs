@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, watch};
+
+use crate::{ConnectionState, DeviceEvent, Result};
+
+/// How many events a slow receiver may fall behind before it starts missing them
+const EVENT_CAPACITY: usize = 64;
+
+pub(crate) fn channel() -> EventSender {
+    EventSender {
+        tx: broadcast::channel(EVENT_CAPACITY).0,
+        state: Arc::new(watch::Sender::new(ConnectionState::Idle)),
+    }
+}
+
+/// Sends the events of a piece of equipment, keeping track of the state of its connection they tell
+/// about, see `Equipment::state`
+#[derive(Debug, Clone)]
+pub(crate) struct EventSender {
+    tx: broadcast::Sender<DeviceEvent>,
+    state: Arc<watch::Sender<ConnectionState>>,
+}
+
+impl EventSender {
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Send `event`, moving to the state it tells about: connected, disconnected, or streaming with the
+    /// first data once connected
+    ///
+    /// Nobody listening is fine, someone may subscribe later.
+    pub fn send(&self, event: DeviceEvent) {
+        let state = match (&event, self.state()) {
+            (DeviceEvent::Connected, _) => Some(ConnectionState::Connected),
+            (DeviceEvent::Disconnected, _) => Some(ConnectionState::Disconnected),
+            (DeviceEvent::Data(_), ConnectionState::Connected) => Some(ConnectionState::Streaming),
+            _ => None,
+        };
+        if let Some(state) = state {
+            self.set_state(state);
+        }
+        let _ = self.tx.send(event);
+    }
+
+    /// Move to `state`, reporting it as `DeviceEvent::StateChanged` unless it already was the state
+    pub fn set_state(&self, state: ConnectionState) {
+        if self.state.send_replace(state) != state {
+            let _ = self.tx.send(DeviceEvent::StateChanged(state));
+        }
+    }
+
+    /// Connect with `connect`, in the connecting state until it succeeds, or disconnected if it fails
+    ///
+    /// Connecting tells about having connected itself, by sending `DeviceEvent::Connected`.
+    pub async fn connecting<T>(&self, connect: impl Future<Output = Result<T>>) -> Result<T> {
+        self.set_state(ConnectionState::Connecting);
+        let result = connect.await;
+        if result.is_err() {
+            self.set_state(ConnectionState::Disconnected);
+        }
+        result
+    }
+}
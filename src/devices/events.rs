@@ -0,0 +1,503 @@
+use std::time::Duration;
+
+use crate::runtime::Instant;
+use btleplug::api::{CharPropFlags, ValueNotification};
+use futures::{Stream, StreamExt, future};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::bluetooth::{self, NotificationStream, ScanConfig, Timeouts, Transport};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::Battery;
+pub(crate) use crate::devices::event_sender::{EventSender, channel};
+use crate::ftms::{
+    CONTROL_POINT_UUID, FITNESS_MACHINE_STATUS_UUID, FTMSControlOpCode, MachineData,
+    parse_machine_status,
+};
+use crate::sensors::BATTERY_LEVEL_UUID;
+use crate::{ConnectionState, DataStream, DeviceEvent, Reading, Result};
+
+/// How long the data may go silent for while connected before it is stale, see `ScanConfig::stale_after`
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(10);
+/// How often the signal strength of a connected peripheral gets sampled
+const RSSI_INTERVAL: Duration = Duration::from_secs(5);
+/// How many reconnects in a row may fail before the watchdog gives up on the peripheral
+const MAX_RECONNECT_FAILURES: u32 = 3;
+
+/// Decodes a notification of a data characteristic
+pub(crate) type Decode = fn(&[u8]) -> Result<MachineData>;
+
+/// Notices and recovers from a subscription that went silent, see `ScanConfig::stale_after`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Watchdog {
+    stale_after: Duration,
+    reconnect: bool,
+    /// For reconnecting
+    timeouts: Timeouts,
+}
+
+impl Watchdog {
+    pub fn new(config: &ScanConfig) -> Self {
+        Watchdog {
+            stale_after: config.stale_after.unwrap_or(DEFAULT_STALE_AFTER),
+            reconnect: config.reconnect_when_stale,
+            timeouts: Timeouts::new(config),
+        }
+    }
+}
+
+/// What gets done with notifications on top of decoding them, see `forward_notifications`
+pub(crate) struct Forwarding {
+    /// Where every notification gets written to first
+    pub capture: Option<CaptureWriter>,
+    /// Updated by battery level notifications
+    pub battery: Battery,
+    pub watchdog: Watchdog,
+}
+
+/// Forward every notification of the peripheral as an event, until its notifications end or `shutdown`
+/// gets cancelled
+///
+/// Unlike `Decode`, `decode` may keep state between notifications, like sensors working out rates from
+/// counters. Once the data characteristic goes silent for longer than the watchdog allows while
+/// connected, `DeviceEvent::DataStale` gets sent, and the peripheral reconnected to if the watchdog is
+/// told to. Reconnecting is tried again every time the data stays silent for as long, sending
+/// `DeviceEvent::Error` for every failure, until it succeeds or `MAX_RECONNECT_FAILURES` failed in a
+/// row. The peripheral gets disconnected from then, ending in `ConnectionState::Disconnected`, rather
+/// than be retried forever with nobody riding.
+pub(crate) async fn forward_notifications<T: Transport>(
+    peripheral: &T,
+    data_uuid: Uuid,
+    mut decode: impl FnMut(&[u8]) -> Result<MachineData> + Send + 'static,
+    events_tx: EventSender,
+    forwarding: Forwarding,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let Forwarding {
+        mut capture,
+        battery,
+        watchdog,
+    } = forwarding;
+    let mut notifications = peripheral
+        .notifications()
+        .await?
+        .take_until(shutdown.clone().cancelled_owned())
+        .boxed();
+    let peripheral = peripheral.clone();
+    crate::runtime::spawn(async move {
+        let mut sequence = 0;
+        let mut stale_at = Instant::now() + watchdog.stale_after;
+        let mut stale = false;
+        let mut failures = 0;
+        loop {
+            let data = tokio::select! {
+                data = notifications.next() => data,
+                _ = crate::runtime::sleep_until(stale_at), if !stale => {
+                    if matches!(
+                        events_tx.state(),
+                        ConnectionState::Idle | ConnectionState::Disconnected
+                    ) {
+                        return;
+                    }
+                    if events_tx.state() != ConnectionState::Reconnecting {
+                        events_tx.send(DeviceEvent::DataStale);
+                    }
+                    stale_at = Instant::now() + watchdog.stale_after;
+                    if !watchdog.reconnect {
+                        stale = true;
+                        events_tx.set_state(ConnectionState::Connected);
+                        continue;
+                    }
+                    events_tx.set_state(ConnectionState::Reconnecting);
+                    warn!(uuid = %data_uuid, "No data for {:?}, reconnecting", watchdog.stale_after);
+                    match reconnect(&peripheral, data_uuid, watchdog.timeouts).await {
+                        Ok(resumed) => {
+                            notifications = resumed.take_until(shutdown.clone().cancelled_owned()).boxed();
+                            failures = 0;
+                            events_tx.set_state(ConnectionState::Connected);
+                        }
+                        Err(e) => {
+                            failures += 1;
+                            // the old notifications died with the connection, wait for the next try
+                            notifications = futures::stream::pending().boxed();
+                            events_tx.send(DeviceEvent::Error(format!("Reconnecting failed: {e}")));
+                            if failures >= MAX_RECONNECT_FAILURES {
+                                warn!(uuid = %data_uuid, "Reconnecting failed {failures} times, giving up");
+                                let _ = peripheral.disconnect().await;
+                                events_tx.send(DeviceEvent::Disconnected);
+                                return;
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            let Some(data) = data else {
+                break;
+            };
+            trace!(uuid = %data.uuid, "Notified {:02x?}", data.value);
+            if let Some(writer) = &mut capture
+                && let Err(e) = writer.write(data.uuid, &data.value)
+            {
+                // notifications go on without the capture
+                events_tx.send(DeviceEvent::Error(format!("Capture failed: {e}")));
+                capture = None;
+            }
+            let event = if data.uuid == data_uuid {
+                stale = false;
+                stale_at = Instant::now() + watchdog.stale_after;
+                let reading = decode(&data.value).map(|data| Reading::new(sequence, data));
+                sequence += 1;
+                match reading {
+                    Ok(reading) => DeviceEvent::Data(reading),
+                    Err(e) => DeviceEvent::Error(e.to_string()),
+                }
+            } else if data.uuid == FITNESS_MACHINE_STATUS_UUID {
+                match parse_machine_status(&data.value) {
+                    Ok(status) => DeviceEvent::MachineStatus(status),
+                    Err(e) => DeviceEvent::Error(e.to_string()),
+                }
+            } else {
+                if data.uuid == BATTERY_LEVEL_UUID {
+                    battery.update(&data.value, &events_tx);
+                }
+                continue;
+            };
+            events_tx.send(event);
+        }
+    });
+    Ok(())
+}
+
+/// Connect to the peripheral all over again, resolving to its notifications once it is
+///
+/// Every characteristic that notifies or indicates gets subscribed to again, as it was when the
+/// equipment connected, and a fitness machine gets asked for control again, as it hands it back when
+/// the connection drops.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        skip_all,
+        err(level = "warn"),
+        fields(address = %peripheral.address(), uuid = %data_uuid)
+    )
+)]
+async fn reconnect(
+    peripheral: &impl Transport,
+    data_uuid: Uuid,
+    timeouts: Timeouts,
+) -> Result<NotificationStream> {
+    let _ = peripheral.disconnect().await;
+    bluetooth::connect_within(peripheral, timeouts).await?;
+    let notifications = peripheral.notifications().await?;
+    bluetooth::subscribe_to(peripheral, data_uuid).await?;
+    for characteristic in peripheral.characteristics() {
+        if characteristic.uuid != data_uuid
+            && characteristic
+                .properties
+                .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+        {
+            // the data is what the connection is for, the rest is optional
+            let _ = peripheral.subscribe(&characteristic).await;
+        }
+    }
+    if let Some(control) = peripheral.characteristic(CONTROL_POINT_UUID) {
+        peripheral
+            .write(&control, &[FTMSControlOpCode::RequestControl as u8])
+            .await?;
+    }
+    Ok(notifications)
+}
+
+/// Report the signal strength of the peripheral every few seconds, until it disconnects or `shutdown`
+/// gets cancelled
+///
+/// Samples the platform doesn't know the signal strength for are skipped.
+pub(crate) fn monitor_rssi(
+    peripheral: impl Transport,
+    events_tx: EventSender,
+    shutdown: CancellationToken,
+) {
+    crate::runtime::spawn(async move {
+        let mut interval = crate::runtime::interval(RSSI_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            if !peripheral.is_connected().await.unwrap_or(false) {
+                return;
+            }
+            if let Ok(Some(rssi)) = peripheral.rssi().await {
+                events_tx.send(DeviceEvent::Rssi(rssi));
+            }
+        }
+    });
+}
+
+/// Number and decode the notifications of the data characteristic, skipping those that can't be decoded
+pub(crate) fn readings(
+    notifications: impl Stream<Item = ValueNotification> + Send + 'static,
+    data_uuid: Uuid,
+    decode: Decode,
+) -> DataStream {
+    Box::pin(
+        notifications
+            .filter(move |data| future::ready(data.uuid == data_uuid))
+            .enumerate()
+            .filter_map(move |(sequence, data)| async move {
+                let data = decode(&data.value).ok()?;
+                Some(Reading::new(sequence as u64, data))
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::devices::{Operation, RecordingTransport};
+    use crate::ftms::{INDOOR_BIKE_DATA_UUID, parse_indoor_bike_data};
+    use crate::{BikeData, FTMSData, KondisError};
+
+    /// Indoor bike data of 25 km/h
+    const BIKE_DATA: [u8; 4] = [0x00, 0x00, 0xc4, 0x09];
+    const STALE_AFTER: Duration = Duration::from_secs(5);
+
+    fn decode(data: &[u8]) -> Result<MachineData> {
+        Ok(parse_indoor_bike_data(data)?.into())
+    }
+
+    /// Forward the notifications of a connected transport, with the watchdog of `config`
+    async fn forwarding(
+        transport: &RecordingTransport,
+        config: ScanConfig,
+    ) -> Result<broadcast::Receiver<DeviceEvent>> {
+        transport.connect().await?;
+        let events_tx = channel();
+        events_tx.send(DeviceEvent::Connected);
+        let events = events_tx.subscribe();
+        let forwarding = Forwarding {
+            capture: None,
+            battery: Battery::new(20),
+            watchdog: Watchdog::new(&config.stale_after(STALE_AFTER)),
+        };
+        forward_notifications(
+            transport,
+            INDOOR_BIKE_DATA_UUID,
+            decode,
+            events_tx,
+            forwarding,
+            CancellationToken::new(),
+        )
+        .await?;
+        Ok(events)
+    }
+
+    /// The next event that isn't a change of state, and the states changed to before it
+    async fn next_event(
+        events: &mut broadcast::Receiver<DeviceEvent>,
+    ) -> (Vec<ConnectionState>, DeviceEvent) {
+        let mut states = Vec::new();
+        loop {
+            match events.recv().await.unwrap() {
+                DeviceEvent::StateChanged(state) => states.push(state),
+                event => return (states, event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readings() {
+        let notification = |uuid, value: &[u8]| ValueNotification {
+            uuid,
+            value: value.to_vec(),
+        };
+        let notifications = futures::stream::iter([
+            notification(INDOOR_BIKE_DATA_UUID, &BIKE_DATA),
+            notification(CONTROL_POINT_UUID, &[0x80, 0x00, 0x01]),
+            notification(INDOOR_BIKE_DATA_UUID, &[0x00]),
+            notification(INDOOR_BIKE_DATA_UUID, &BIKE_DATA),
+        ]);
+        let readings: Vec<Reading> = readings(notifications, INDOOR_BIKE_DATA_UUID, decode)
+            .collect()
+            .await;
+        // every data notification counts, decoded or not
+        let sequences: Vec<u64> = readings.iter().map(|reading| reading.sequence).collect();
+        assert_eq!(sequences, [0, 2]);
+        assert_eq!(FTMSData::from(readings[0].data.clone()).speed, Some(25.));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_stale() -> Result<()> {
+        let transport = RecordingTransport::new();
+        let mut events = forwarding(&transport, ScanConfig::new()).await?;
+        transport.notify(INDOOR_BIKE_DATA_UUID, &BIKE_DATA);
+        let (states, event) = next_event(&mut events).await;
+        assert_eq!(states, [ConnectionState::Streaming]);
+        assert!(matches!(event, DeviceEvent::Data(_)));
+
+        let start = Instant::now();
+        let (states, event) = next_event(&mut events).await;
+        assert!(states.is_empty());
+        assert!(matches!(event, DeviceEvent::DataStale));
+        assert_eq!(start.elapsed(), STALE_AFTER);
+        // without reconnecting, staleness is reported once and data resumes on the same connection
+        transport.notify(INDOOR_BIKE_DATA_UUID, &BIKE_DATA);
+        let (states, event) = next_event(&mut events).await;
+        assert_eq!(
+            states,
+            [ConnectionState::Connected, ConnectionState::Streaming]
+        );
+        assert!(matches!(event, DeviceEvent::Data(_)));
+        assert!(!transport.operations().contains(&Operation::Disconnect));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_reconnect() -> Result<()> {
+        let transport = RecordingTransport::new();
+        let mut events = forwarding(&transport, ScanConfig::new().reconnect_when_stale()).await?;
+        let (_, event) = next_event(&mut events).await;
+        assert!(matches!(event, DeviceEvent::DataStale));
+        let mut states = Vec::new();
+        while states.last() != Some(&ConnectionState::Connected) {
+            if let DeviceEvent::StateChanged(state) = events.recv().await.unwrap() {
+                states.push(state);
+            }
+        }
+        assert_eq!(
+            states,
+            [ConnectionState::Reconnecting, ConnectionState::Connected]
+        );
+
+        // data resumes on the new connection
+        transport.notify(INDOOR_BIKE_DATA_UUID, &BIKE_DATA);
+        let (states, event) = next_event(&mut events).await;
+        assert_eq!(states, [ConnectionState::Streaming]);
+        assert!(matches!(event, DeviceEvent::Data(_)));
+        let operations = transport.operations();
+        assert_eq!(
+            operations[1..4],
+            [
+                Operation::Disconnect,
+                Operation::Connect,
+                Operation::Subscribe(INDOOR_BIKE_DATA_UUID)
+            ]
+        );
+        // control gets asked for again
+        assert_eq!(
+            transport.commands(),
+            [vec![FTMSControlOpCode::RequestControl as u8]]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_reconnect_failure() -> Result<()> {
+        let transport = RecordingTransport::new();
+        let mut events = forwarding(&transport, ScanConfig::new().reconnect_when_stale()).await?;
+        transport.fail_connects(usize::MAX);
+        let (_, event) = next_event(&mut events).await;
+        assert!(matches!(event, DeviceEvent::DataStale));
+
+        let start = Instant::now();
+        for failure in 0..MAX_RECONNECT_FAILURES {
+            let (states, event) = next_event(&mut events).await;
+            if failure == 0 {
+                assert_eq!(states, [ConnectionState::Reconnecting]);
+            } else {
+                // staleness isn't reported again while reconnecting
+                assert!(states.is_empty());
+            }
+            assert!(matches!(event, DeviceEvent::Error(e) if e.starts_with("Reconnecting failed")));
+        }
+        assert_eq!(start.elapsed(), STALE_AFTER * (MAX_RECONNECT_FAILURES - 1));
+        let (states, event) = next_event(&mut events).await;
+        assert_eq!(states, [ConnectionState::Disconnected]);
+        assert!(matches!(event, DeviceEvent::Disconnected));
+
+        // nothing is retried after giving up
+        let attempts = transport.operations().len();
+        crate::runtime::sleep(STALE_AFTER * 10).await;
+        assert_eq!(transport.operations().len(), attempts);
+        assert!(events.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_monitor_rssi() -> Result<()> {
+        let transport = RecordingTransport::new();
+        transport.connect().await?;
+        let events_tx = channel();
+        let mut events = events_tx.subscribe();
+        monitor_rssi(transport.clone(), events_tx, CancellationToken::new());
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            let (_, event) = next_event(&mut events).await;
+            assert!(matches!(event, DeviceEvent::Rssi(-60)));
+        }
+        assert_eq!(start.elapsed(), RSSI_INTERVAL * 2);
+        // monitoring ends with the connection
+        transport.disconnect().await?;
+        crate::runtime::sleep(RSSI_INTERVAL * 3).await;
+        assert!(events.try_recv().is_err());
+        assert!(events.is_closed());
+
+        let shutdown = CancellationToken::new();
+        let events_tx = channel();
+        let mut events = events_tx.subscribe();
+        transport.connect().await?;
+        monitor_rssi(transport, events_tx, shutdown.clone());
+        let (_, event) = next_event(&mut events).await;
+        assert!(matches!(event, DeviceEvent::Rssi(-60)));
+        shutdown.cancel();
+        crate::runtime::sleep(RSSI_INTERVAL * 3).await;
+        assert!(events.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connection_state() {
+        let events_tx = channel();
+        let mut events = events_tx.subscribe();
+        assert_eq!(events_tx.state(), ConnectionState::Idle);
+
+        let connected: Result<()> = events_tx
+            .connecting(async {
+                events_tx.send(DeviceEvent::Connected);
+                Ok(())
+            })
+            .await;
+        assert!(connected.is_ok());
+        for _ in 0..2 {
+            let reading = Reading::new(0, BikeData::default().into());
+            events_tx.send(DeviceEvent::Data(reading));
+        }
+        assert_eq!(events_tx.state(), ConnectionState::Streaming);
+        let failed: Result<()> = events_tx
+            .connecting(async { Err(KondisError::DeviceNotFound) })
+            .await;
+        assert!(failed.is_err());
+
+        let mut states = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let DeviceEvent::StateChanged(state) = event {
+                states.push(state);
+            }
+        }
+        assert_eq!(
+            states,
+            [
+                ConnectionState::Connecting,
+                ConnectionState::Connected,
+                ConnectionState::Streaming,
+                ConnectionState::Connecting,
+                ConnectionState::Disconnected,
+            ]
+        );
+    }
+}
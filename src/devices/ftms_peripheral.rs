@@ -0,0 +1,613 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use btleplug::{api::Characteristic, platform::Peripheral};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, Transport, get_peripheral, read_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::command_queue::CommandQueue;
+use crate::devices::events::{self, Decode, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    CONTROL_POINT_UUID, Capabilities, FITNESS_MACHINE_FEATURE_UUID, FITNESS_MACHINE_STATUS_UUID,
+    FTMSControlOpCode, MachineStatus, SUPPORTED_INCLINATION_RANGE_UUID, SUPPORTED_POWER_RANGE_UUID,
+    SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID, SUPPORTED_SPEED_RANGE_UUID, SpinDownControl,
+    SpinDownResult, SpinDownStatus, StopCode, SupportedRanges, parse_fitness_machine_feature,
+    parse_machine_status, parse_spin_down_target, parse_supported_inclination_range,
+    parse_supported_power_range, parse_supported_resistance_level_range,
+    parse_supported_speed_range,
+};
+use crate::{ConnectionState, DataStream, DeviceEvent, EquipmentType, MachineStatusStream};
+use crate::{KondisError, Result};
+
+/// The shared plumbing of every standards-compliant fitness machine.
+///
+/// Finds the machine, subscribes to its data characteristic, takes control through the control point,
+/// and writes control point commands. The generic FTMS devices wrap this and only decode their own data.
+/// Every GATT operation goes through `T`, btleplug unless the device picks another transport.
+#[derive(Debug, Clone)]
+pub(crate) struct FtmsPeripheral<T: Transport = Peripheral> {
+    peripheral: T,
+    pub name: String,
+    data_uuid: Uuid,
+    decode: Decode,
+    control: Option<Characteristic>,
+    data: Option<Characteristic>,
+    status: Option<Characteristic>,
+    queue: Option<CommandQueue>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    pub battery: Battery,
+    shutdown: CancellationToken,
+    /// Read from the fitness machine feature characteristic when connecting, if the machine has one
+    pub capabilities: Option<Capabilities>,
+    /// Read from the supported range characteristics the machine has when connecting
+    pub ranges: Option<SupportedRanges>,
+    /// Read from the Device Information Service when connecting, if the machine has one
+    pub device_info: Option<DeviceInfo>,
+}
+
+impl FtmsPeripheral {
+    pub async fn find(
+        equipment_type: EquipmentType,
+        data_uuid: Uuid,
+        decode: Decode,
+        config: &ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let meta = get_peripheral(equipment_type, config, shutdown).await?;
+        if meta.is_none() {
+            return Err(KondisError::DeviceNotFound);
+        }
+        let meta = meta.unwrap();
+        Ok(FtmsPeripheral::new(
+            meta.0, meta.1, data_uuid, decode, config, shutdown,
+        ))
+    }
+
+    /// The peripheral of the machine, for talking to it beyond FTMS
+    pub fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl<T: Transport> FtmsPeripheral<T> {
+    /// Wrap a peripheral found through some transport, to be connected to
+    pub fn new(
+        peripheral: T,
+        name: String,
+        data_uuid: Uuid,
+        decode: Decode,
+        config: &ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Self {
+        FtmsPeripheral {
+            peripheral,
+            name,
+            data_uuid,
+            decode,
+            control: None,
+            data: None,
+            status: None,
+            queue: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(config),
+            timeouts: Timeouts::new(config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            shutdown: shutdown.clone(),
+            capabilities: None,
+            ranges: None,
+            device_info: None,
+        }
+    }
+
+    pub async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, self.connect_inner()))
+            .await
+    }
+
+    async fn connect_inner(&mut self) -> Result<bool> {
+        bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+        self.set_characteristics();
+        self.read_features().await?;
+        self.device_info = read_info(&self.peripheral).await;
+        self.subscribe().await?;
+        self.battery
+            .connect(&self.peripheral, &self.events_tx)
+            .await;
+        let capture = self
+            .capture
+            .as_deref()
+            .map(CaptureWriter::create)
+            .transpose()?;
+        events::forward_notifications(
+            &self.peripheral,
+            self.data_uuid,
+            self.decode,
+            self.events_tx.clone(),
+            Forwarding {
+                capture,
+                battery: self.battery.clone(),
+                watchdog: self.watchdog,
+            },
+            self.shutdown.clone(),
+        )
+        .await?;
+        events::monitor_rssi(
+            self.peripheral.clone(),
+            self.events_tx.clone(),
+            self.shutdown.clone(),
+        );
+        self.write(&[FTMSControlOpCode::RequestControl as u8])
+            .await?;
+        self.events_tx.send(DeviceEvent::Connected);
+        self.peripheral.is_connected().await
+    }
+
+    /// Stop the machine, unsubscribe from everything and disconnect, going through every step even
+    /// when one fails, failing with the first failure
+    pub async fn disconnect(&self) -> Result<()> {
+        // stop the machine first, even when shutting down, rather than leave it running
+        let mut result = match &self.queue {
+            Some(queue) => queue
+                .submit(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+                .await
+                .map(drop),
+            None => Ok(()),
+        };
+        for characteristic in [&self.data, &self.control, &self.status]
+            .into_iter()
+            .flatten()
+        {
+            let unsubscribed = self.peripheral.unsubscribe(characteristic).await;
+            result = result.and(unsubscribed);
+        }
+        let disconnected = self.peripheral.disconnect().await;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        result.and(disconnected)
+    }
+
+    /// Every event from now on, see `Equipment::events`
+    pub fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// The state of the connection, see `Equipment::state`
+    pub fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    fn set_characteristics(&mut self) {
+        for characteristic in self.peripheral.characteristics() {
+            if characteristic.uuid == CONTROL_POINT_UUID {
+                let queue = CommandQueue::spawn(self.peripheral.clone(), characteristic.clone());
+                // connecting again, which the previous queue mustn't disconnect from
+                if let Some(previous) = self.queue.replace(queue) {
+                    previous.release();
+                }
+                self.control = Some(characteristic.clone());
+            }
+            if characteristic.uuid == self.data_uuid {
+                self.data = Some(characteristic.clone());
+            }
+            if characteristic.uuid == FITNESS_MACHINE_STATUS_UUID {
+                self.status = Some(characteristic.clone());
+            }
+        }
+    }
+
+    /// Read the characteristics describing what the machine supports, all of which are optional
+    async fn read_features(&mut self) -> Result<()> {
+        if let Some(data) = self.read(FITNESS_MACHINE_FEATURE_UUID).await? {
+            self.capabilities = Some(parse_fitness_machine_feature(&data)?);
+        }
+        let mut ranges = SupportedRanges::default();
+        if let Some(data) = self.read(SUPPORTED_POWER_RANGE_UUID).await? {
+            ranges.power = Some(parse_supported_power_range(&data)?);
+        }
+        if let Some(data) = self.read(SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID).await? {
+            ranges.resistance = Some(parse_supported_resistance_level_range(&data)?);
+        }
+        if let Some(data) = self.read(SUPPORTED_SPEED_RANGE_UUID).await? {
+            ranges.speed = Some(parse_supported_speed_range(&data)?);
+        }
+        if let Some(data) = self.read(SUPPORTED_INCLINATION_RANGE_UUID).await? {
+            ranges.inclination = Some(parse_supported_inclination_range(&data)?);
+        }
+        // machines without any of the characteristics don't advertise ranges at all
+        self.ranges = (ranges != SupportedRanges::default()).then_some(ranges);
+        Ok(())
+    }
+
+    async fn read(&self, uuid: Uuid) -> Result<Option<Vec<u8>>> {
+        match self.peripheral.characteristic(uuid) {
+            Some(characteristic) => Ok(Some(self.peripheral.read(&characteristic).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The power to target, clamped into the advertised power range when the machine has one, and
+    /// validated against `max_level` when it doesn't
+    pub fn target_power(&self, watts: i16, max_level: i16) -> Result<i16> {
+        if let Some(range) = self.ranges.and_then(|ranges| ranges.power) {
+            return Ok(range.clamp(watts as f32) as i16);
+        }
+        if !(1..=max_level).contains(&watts) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Watts must be between 1 and {}",
+                max_level
+            )));
+        }
+        Ok(watts)
+    }
+
+    /// The resistance level to target, clamped into the advertised resistance level range when the
+    /// machine has one, and validated against `max_level` when it doesn't
+    pub fn target_resistance_level(&self, level: i16, max_level: i16) -> Result<i16> {
+        if let Some(range) = self.ranges.and_then(|ranges| ranges.resistance) {
+            return Ok(range.clamp(level as f32).round() as i16);
+        }
+        if !(1..=max_level).contains(&level) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Resistance level must be between 1 and {}",
+                max_level
+            )));
+        }
+        Ok(level)
+    }
+
+    async fn subscribe(&self) -> Result<()> {
+        if let Some(data) = &self.data {
+            self.peripheral.subscribe(data).await?;
+        } else {
+            return Err(KondisError::CharacteristicMissing(format!(
+                "data ({})",
+                self.data_uuid
+            )));
+        }
+        // the control point only accepts writes once its indications are enabled
+        if let Some(control) = &self.control {
+            self.peripheral.subscribe(control).await?;
+        } else {
+            return Err(KondisError::CharacteristicMissing("control".to_string()));
+        }
+        // the machine status is optional
+        if let Some(status) = &self.status {
+            self.peripheral.subscribe(status).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next notification of the data characteristic, skipping control point indications
+    pub async fn notification(&self) -> Result<Vec<u8>> {
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                let mut notifications = self.peripheral.notifications().await?;
+                while let Some(data) = notifications.next().await {
+                    if data.uuid == self.data_uuid {
+                        return Ok(data.value);
+                    }
+                }
+
+                Ok(Vec::new())
+            }),
+        )
+        .await
+    }
+
+    /// Every notification of the data characteristic from now on, decoded
+    pub async fn data_stream(&self) -> Result<DataStream> {
+        let notifications = self.peripheral.notifications().await?;
+        Ok(events::readings(
+            notifications.take_until(self.shutdown.clone().cancelled_owned()),
+            self.data_uuid,
+            self.decode,
+        ))
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Start as u8]).await
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+            .await
+    }
+
+    pub async fn pause(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Pause as u8])
+            .await
+    }
+
+    pub async fn reset(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Reset as u8]).await
+    }
+
+    /// Every Fitness Machine Status notification received from now on
+    pub async fn machine_status(&self) -> Result<MachineStatusStream> {
+        if self.status.is_none() {
+            return Err(KondisError::CharacteristicMissing(
+                "fitness machine status".to_string(),
+            ));
+        }
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(
+            notifications
+                .take_until(self.shutdown.clone().cancelled_owned())
+                .filter_map(|data| async move {
+                    if data.uuid != FITNESS_MACHINE_STATUS_UUID {
+                        return None;
+                    }
+                    parse_machine_status(&data.value).ok()
+                }),
+        ))
+    }
+
+    /// Run a spin down calibration, forwarding every status update to `status_tx`
+    ///
+    /// Resolves once the machine reports the calibration as succeeded or failed.
+    pub async fn spin_down(&self, status_tx: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        if self.status.is_none() {
+            return Err(KondisError::CharacteristicMissing(
+                "fitness machine status".to_string(),
+            ));
+        }
+        // listen before writing, so no status can slip past
+        let notifications = self
+            .peripheral
+            .notifications()
+            .await?
+            .take_until(self.shutdown.cancelled());
+        let mut notifications = std::pin::pin!(notifications);
+        let parameters = self
+            .command(&[
+                FTMSControlOpCode::SpinDownControl as u8,
+                SpinDownControl::Start as u8,
+            ])
+            .await?;
+        let target = parse_spin_down_target(&parameters)?;
+        let _ = status_tx.send(target);
+
+        let mut result = SpinDownResult {
+            success: false,
+            target_speed_low: 0.0,
+            target_speed_high: 0.0,
+        };
+        if let SpinDownStatus::TargetSpeed { low, high } = target {
+            result.target_speed_low = low;
+            result.target_speed_high = high;
+        }
+        while let Some(data) = notifications.next().await {
+            if data.uuid != FITNESS_MACHINE_STATUS_UUID {
+                continue;
+            }
+            let status = match parse_machine_status(&data.value) {
+                Ok(MachineStatus::SpinDown(status)) => status,
+                _ => continue,
+            };
+            // the receiving end going away doesn't stop the calibration
+            let _ = status_tx.send(status);
+            match status {
+                SpinDownStatus::Success => {
+                    result.success = true;
+                    return Ok(result);
+                }
+                SpinDownStatus::Error => return Ok(result),
+                _ => {}
+            }
+        }
+
+        if self.shutdown.is_cancelled() {
+            return Err(KondisError::Shutdown);
+        }
+        Err(KondisError::Disconnected(
+            "Notifications ended during spin down".to_string(),
+        ))
+    }
+
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        self.command(data).await?;
+        Ok(())
+    }
+
+    /// Queue a command for the control point and wait for the machine to respond to it
+    ///
+    /// Resolves to the response parameters if the machine accepted the command, and fails with
+    /// `KondisError::ControlRejected` if it refused.
+    pub async fn command(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.queue {
+            Some(queue) => until_shutdown(&self.shutdown, queue.submit(data)).await,
+            None => Err(KondisError::CharacteristicMissing("control".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::bikes::generic_ftms::decode;
+    use crate::devices::{Operation, RecordingTransport};
+    use crate::ftms::{INDOOR_BIKE_DATA_UUID, SupportedRange};
+
+    fn peripheral(
+        transport: RecordingTransport,
+        shutdown: &CancellationToken,
+    ) -> FtmsPeripheral<RecordingTransport> {
+        FtmsPeripheral::new(
+            transport,
+            "Bike".to_string(),
+            INDOOR_BIKE_DATA_UUID,
+            decode,
+            &ScanConfig::default(),
+            shutdown,
+        )
+    }
+
+    /// The next event, other than the connection state changing or the signal strength
+    async fn next_event(events: &mut broadcast::Receiver<DeviceEvent>) -> DeviceEvent {
+        loop {
+            match events.recv().await.unwrap() {
+                DeviceEvent::StateChanged(_) | DeviceEvent::Rssi(_) => continue,
+                event => return event,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ranges() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut without_ranges = peripheral(RecordingTransport::new(), &shutdown);
+        assert!(without_ranges.connect().await?);
+        assert_eq!(without_ranges.ranges, None);
+        assert!(without_ranges.target_power(500, 400).is_err());
+
+        let transport = RecordingTransport::new().with_value(
+            SUPPORTED_POWER_RANGE_UUID,
+            &[0x0a, 0x00, 0xe8, 0x03, 0x01, 0x00],
+        );
+        let mut with_ranges = peripheral(transport, &shutdown);
+        assert!(with_ranges.connect().await?);
+        let power = SupportedRange {
+            min: 10.,
+            max: 1000.,
+            increment: 1.,
+        };
+        assert_eq!(
+            with_ranges.ranges,
+            Some(SupportedRanges {
+                power: Some(power),
+                ..SupportedRanges::default()
+            })
+        );
+        assert_eq!(with_ranges.target_power(1500, 400)?, 1000);
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_target_resistance_level() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let transport = RecordingTransport::new();
+        let mut ftms = peripheral(transport.clone(), &shutdown);
+        assert!(ftms.connect().await?);
+        assert_eq!(ftms.target_resistance_level(12, 32)?, 12);
+        assert!(ftms.target_resistance_level(0, 32).is_err());
+        assert!(ftms.target_resistance_level(33, 32).is_err());
+        ftms.write(&crate::ftms::target_resistance_level(12)?)
+            .await?;
+        assert_eq!(transport.commands().last(), Some(&vec![0x04, 120, 0]));
+
+        // a resistance range of 0 to 20 in steps of 0.5 overrides the max level
+        ftms.ranges = Some(SupportedRanges {
+            resistance: parse_supported_resistance_level_range(&[0, 0, 200, 0, 5, 0]).ok(),
+            ..SupportedRanges::default()
+        });
+        assert_eq!(ftms.target_resistance_level(0, 32)?, 0);
+        assert_eq!(ftms.target_resistance_level(33, 32)?, 20);
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session_control() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let transport = RecordingTransport::new();
+        let mut ftms = peripheral(transport.clone(), &shutdown);
+        assert!(ftms.connect().await?);
+        ftms.start().await?;
+        ftms.pause().await?;
+        ftms.stop().await?;
+        ftms.reset().await?;
+        assert_eq!(
+            transport.commands(),
+            [
+                vec![0x00],
+                vec![0x07],
+                vec![0x08, 0x02],
+                vec![0x08, 0x01],
+                vec![0x01],
+            ]
+        );
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let transport = RecordingTransport::new();
+        let mut ftms = peripheral(transport.clone(), &shutdown);
+        let mut events = ftms.events();
+        assert!(ftms.connect().await?);
+        assert!(matches!(
+            next_event(&mut events).await,
+            DeviceEvent::Connected
+        ));
+
+        // speed, cadence and power
+        transport.notify(
+            INDOOR_BIKE_DATA_UUID,
+            &[0x44, 0x00, 0xc4, 0x09, 0xb4, 0x00, 0xfa, 0x00],
+        );
+        transport.notify(FITNESS_MACHINE_STATUS_UUID, &[0x04]);
+        let DeviceEvent::Data(reading) = next_event(&mut events).await else {
+            panic!("No reading");
+        };
+        assert_eq!(crate::FTMSData::from(reading.data).power, Some(250));
+        assert!(matches!(
+            next_event(&mut events).await,
+            DeviceEvent::MachineStatus(MachineStatus::StartedOrResumedByUser)
+        ));
+
+        ftms.disconnect().await?;
+        assert!(matches!(
+            next_event(&mut events).await,
+            DeviceEvent::Disconnected
+        ));
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_and_disconnect() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let transport = RecordingTransport::new();
+        let mut ftms = peripheral(transport.clone(), &shutdown);
+        assert!(ftms.connect().await?);
+        ftms.disconnect().await?;
+        assert_eq!(
+            transport.operations(),
+            [
+                Operation::Connect,
+                Operation::Subscribe(INDOOR_BIKE_DATA_UUID),
+                Operation::Subscribe(CONTROL_POINT_UUID),
+                Operation::Subscribe(FITNESS_MACHINE_STATUS_UUID),
+                Operation::Write(CONTROL_POINT_UUID, vec![0x00]),
+                Operation::Write(CONTROL_POINT_UUID, vec![0x08, 0x01]),
+                Operation::Unsubscribe(INDOOR_BIKE_DATA_UUID),
+                Operation::Unsubscribe(CONTROL_POINT_UUID),
+                Operation::Unsubscribe(FITNESS_MACHINE_STATUS_UUID),
+                Operation::Disconnect,
+            ]
+        );
+        assert_eq!(ftms.state(), ConnectionState::Disconnected);
+        shutdown.cancel();
+        Ok(())
+    }
+}
@@ -1,5 +1,68 @@
+mod advertisements;
+#[cfg(feature = "ant")]
+mod ant_stick;
+mod battery;
 mod bikes;
+#[cfg(all(feature = "bluer", target_os = "linux"))]
+mod bluez;
+mod command_queue;
+mod controllers;
+mod cross_trainers;
+mod csafe_port;
+mod event_sender;
+mod events;
+mod ftms_peripheral;
 mod non_bluetooth_device;
+#[cfg(test)]
+mod recording_equipment;
+#[cfg(test)]
+mod recording_transport;
+mod remote_equipment;
+mod replay_device;
+mod rowers;
+mod sensors;
+mod serial;
+mod shutdown;
+mod treadmills;
+#[cfg(feature = "ant")]
+pub use bikes::ant_fec::AntFecBike;
 pub use bikes::debug::DebugBike;
-pub use bikes::iconsole_0028::Iconsole0028Bike;
+pub use bikes::echelon::EchelonBike;
+pub use bikes::generic_ftms::GenericFtmsBike;
+pub(crate) use bikes::iconsole::model_named as iconsole_model_named;
+pub use bikes::iconsole::{
+    Iconsole0010, Iconsole0010Bike, Iconsole0028, Iconsole0028Bike, Iconsole0051, Iconsole0051Bike,
+    IconsoleBike, IconsoleModel, IconsoleQuirks,
+};
+pub(crate) use bikes::keiser_m3i::KEISER_COMPANY_ID;
+pub use bikes::keiser_m3i::KeiserM3iBike;
+pub use bikes::kettler::KettlerBike;
+pub use bikes::kickr::KickrBike;
+#[cfg(feature = "quirks")]
+pub use bikes::quirk::QuirkBike;
+pub use bikes::simulator::{Fault, SimulatorBike};
+pub use bikes::tacx_fec::TacxFecBike;
+#[cfg(all(feature = "bluer", target_os = "linux"))]
+pub use bluez::BluezFtmsBike;
+pub use controllers::ControllerButton;
+pub use controllers::zwift::ZwiftController;
+pub use cross_trainers::generic_ftms::GenericFtmsCrossTrainer;
 pub use non_bluetooth_device::NonBluetoothDevice;
+#[cfg(test)]
+pub(crate) use recording_equipment::RecordingEquipment;
+#[cfg(test)]
+pub(crate) use recording_transport::{Operation, RecordingTransport};
+pub use remote_equipment::RemoteEquipment;
+pub use replay_device::ReplayDevice;
+pub use rowers::concept2_pm5::Pm5Rower;
+pub use rowers::csafe::CsafeRower;
+pub use rowers::generic_ftms::GenericFtmsRower;
+pub use sensors::SteeringEvent;
+#[cfg(feature = "ant")]
+pub use sensors::ant_heart_rate_monitor::AntHeartRateMonitor;
+#[cfg(feature = "ant")]
+pub use sensors::ant_power_meter::AntPowerMeter;
+pub use sensors::heart_rate_monitor::HeartRateMonitor;
+pub use sensors::speed_cadence_sensor::SpeedCadenceSensor;
+pub use sensors::sterzo::SterzoSteering;
+pub use treadmills::generic_ftms::GenericFtmsTreadmill;
@@ -1,8 +1,21 @@
-use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 
-use crate::{Equipment, ftms::FTMSData};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Reading,
+    Result, ScanConfig,
+    devices::events::{self, EventSender},
+    ftms::{
+        BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+        TargetCapabilities, TrainingGoal, simulation_parameters, training_goal,
+    },
+};
 
 /// A bogus non-Bluetooth device, which only prints debug information at the moment.
 #[derive(Debug, Clone)]
@@ -10,71 +23,234 @@ pub struct NonBluetoothDevice {
     /// The name of the device
     pub name: String,
     max_level: i16,
-    start_time: std::time::Instant,
+    start_time: Instant,
+    connected: Arc<AtomicBool>,
+    events_tx: EventSender,
+    shutdown: CancellationToken,
 }
 
-#[async_trait]
 impl Equipment for NonBluetoothDevice {
-    async fn new(max_level: i16, _: &mut Receiver<()>) -> anyhow::Result<Self> {
+    async fn with_config(
+        max_level: i16,
+        _: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
         Ok(NonBluetoothDevice {
             name: "some hypothetical non-bluetooth device".to_string(),
             max_level,
-            start_time: std::time::Instant::now(),
+            start_time: Instant::now(),
+            connected: Arc::new(AtomicBool::new(false)),
+            events_tx: events::channel(),
+            shutdown: shutdown.clone(),
         })
     }
-    async fn connect(&mut self) -> anyhow::Result<bool> {
+    async fn connect(&mut self) -> Result<bool> {
         // Simulate a connection to a non-Bluetooth device
-        println!("Connecting to: {}", self.name);
+        info!(device = %self.name, "Connecting");
+        self.events_tx.set_state(ConnectionState::Connecting);
+        self.connected.store(true, Ordering::SeqCst);
+        self.events_tx.send(DeviceEvent::Connected);
+        // Simulate a notification every second, for as long as the device is connected
+        let connected = Arc::downgrade(&self.connected);
+        let events_tx = self.events_tx.clone();
+        let start_time = self.start_time;
+        let shutdown = self.shutdown.clone();
+        crate::runtime::spawn(async move {
+            let mut interval = crate::runtime::interval(Duration::from_secs(1));
+            for sequence in 0.. {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                if !connected
+                    .upgrade()
+                    .is_some_and(|connected| connected.load(Ordering::SeqCst))
+                {
+                    break;
+                }
+                let reading = Reading::new(sequence, sample(start_time));
+                events_tx.send(DeviceEvent::Data(reading));
+            }
+        });
         Ok(true)
     }
-    async fn disconnect(&self) -> anyhow::Result<()> {
+    async fn disconnect(&self) -> Result<()> {
         // Simulate disconnection from a non-Bluetooth device
-        println!("Disconnecting from: {}", self.name);
+        info!(device = %self.name, "Disconnecting");
+        self.connected.store(false, Ordering::SeqCst);
+        self.events_tx.send(DeviceEvent::Disconnected);
         Ok(())
     }
-    async fn set_target_cadence(&self, rpm: i16) -> anyhow::Result<()> {
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
         if !(1..=self.max_level).contains(&rpm) {
-            return Err(anyhow::anyhow!(
+            return Err(KondisError::InvalidArgument(format!(
                 "RPM must be between 1 and {}",
                 self.max_level
-            ));
+            )));
         }
         let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
         // Simulate setting the rpm on a non-Bluetooth device
-        println!(
-            "Setting target RPM on: {} to {} at {}",
-            self.name, rpm, seconds_elapsed
-        );
+        debug!(device = %self.name, "Setting target RPM to {} at {}", rpm, seconds_elapsed);
         Ok(())
     }
 
-    async fn set_target_power(&self, watts: i16) -> anyhow::Result<()> {
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
         if !(1..=self.max_level).contains(&watts) {
-            return Err(anyhow::anyhow!(
+            return Err(KondisError::InvalidArgument(format!(
                 "Watts must be between 1 and {}",
                 self.max_level
-            ));
+            )));
         }
         let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
         // Simulate setting the watts on a non-Bluetooth device
-        println!(
-            "Setting level on: {} to {} at {}",
-            self.name, watts, seconds_elapsed
+        debug!(device = %self.name, "Setting level to {} at {}", watts, seconds_elapsed);
+        Ok(())
+    }
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&level) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Resistance level must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
+        // Simulate setting the resistance level on a non-Bluetooth device
+        debug!(
+            device = %self.name,
+            "Setting resistance level to {} at {}",
+            level, seconds_elapsed
         );
         Ok(())
     }
-    async fn read(&self) -> anyhow::Result<Option<FTMSData>> {
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        if bpm == 0 {
+            return Err(KondisError::InvalidArgument(
+                "Heart rate must be between 1 and 255".to_string(),
+            ));
+        }
+        let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
+        // Simulate setting the heart rate on a non-Bluetooth device
+        debug!(device = %self.name, "Setting target heart rate to {} at {}", bpm, seconds_elapsed);
+        Ok(())
+    }
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        training_goal(goal)?;
+        let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
+        // Simulate setting a goal on a non-Bluetooth device
+        debug!(device = %self.name, "Setting goal to {:?} at {}", goal, seconds_elapsed);
+        Ok(())
+    }
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        simulation_parameters(grade, wind_speed, crr, cw)?;
+        let seconds_elapsed = self.start_time.elapsed().as_secs_f32();
+        // Simulate setting the simulation parameters on a non-Bluetooth device
+        debug!(
+            device = %self.name,
+            "Setting simulation to {}% grade, {} m/s wind, crr {}, cw {} at {}",
+            grade, wind_speed, crr, cw, seconds_elapsed
+        );
+        Ok(())
+    }
+    async fn start(&self) -> Result<()> {
+        // Simulate session control on a non-Bluetooth device
+        debug!(device = %self.name, "Starting session");
+        Ok(())
+    }
+    async fn stop(&self) -> Result<()> {
+        // Simulate session control on a non-Bluetooth device
+        debug!(device = %self.name, "Stopping session");
+        Ok(())
+    }
+    async fn pause(&self) -> Result<()> {
+        // Simulate session control on a non-Bluetooth device
+        debug!(device = %self.name, "Pausing session");
+        Ok(())
+    }
+    async fn resume(&self) -> Result<()> {
+        // Simulate session control on a non-Bluetooth device
+        debug!(device = %self.name, "Resuming session");
+        Ok(())
+    }
+    async fn reset(&self) -> Result<()> {
+        // Simulate session control on a non-Bluetooth device
+        debug!(device = %self.name, "Resetting");
+        Ok(())
+    }
+    async fn spin_down(&self, status_tx: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        // Simulate a spin down calibration which immediately succeeds
+        debug!(device = %self.name, "Calibrating");
+        for status in [
+            SpinDownStatus::TargetSpeed {
+                low: 30.0,
+                high: 35.0,
+            },
+            SpinDownStatus::Requested,
+            SpinDownStatus::StopPedaling,
+            SpinDownStatus::Success,
+        ] {
+            let _ = status_tx.send(status);
+        }
+        Ok(SpinDownResult {
+            success: true,
+            target_speed_low: 30.0,
+            target_speed_high: 35.0,
+        })
+    }
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        // Nobody pushes any buttons on a non-Bluetooth device
+        Ok(Box::pin(futures::stream::empty()))
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            targets: TargetCapabilities {
+                resistance: true,
+                power: true,
+                simulation: true,
+                spin_down: true,
+                cadence: true,
+                ..Default::default()
+            },
+            data: DataCapabilities {
+                elapsed_time: true,
+                ..Default::default()
+            },
+        })
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+    async fn read(&self) -> Result<Option<MachineData>> {
         // Simulate reading data from a non-Bluetooth device
         //println!("Reading data from: {}", self.name);
-        Ok(Some(FTMSData {
-            speed: f32::default(),
-            cadence: f32::default(),
-            distance: f32::default(),
-            resistance: f64::default(),
-            power: u8::default(),
-            calories: f64::default(),
-            heart_rate: f64::default(),
-            time: self.start_time.elapsed().as_secs() as u16,
-        }))
+        Ok(Some(sample(self.start_time)))
+    }
+    async fn data_stream(&self) -> Result<DataStream> {
+        // Simulate a notification every second
+        let interval = crate::runtime::interval(Duration::from_secs(1));
+        let start_time = self.start_time;
+        Ok(Box::pin(
+            futures::stream::unfold((interval, 0), move |(mut interval, sequence)| async move {
+                interval.tick().await;
+                let reading = Reading::new(sequence, sample(start_time));
+                Some((reading, (interval, sequence + 1)))
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
     }
 }
+
+fn sample(start_time: Instant) -> MachineData {
+    MachineData::Bike(BikeData {
+        time: Some(start_time.elapsed().as_secs() as u32),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::events::{self, EventSender};
+use crate::ftms::{Capabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal};
+use crate::remote::Request;
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Result,
+    ScanConfig,
+};
+
+/// Equipment for tests, remembering every command and when it came, on the tokio clock
+#[derive(Debug, Clone)]
+pub(crate) struct RecordingEquipment {
+    start: Instant,
+    requests: Arc<Mutex<Vec<(Duration, Request)>>>,
+    events_tx: EventSender,
+    /// Whether disconnecting hangs, like equipment that stopped responding
+    unresponsive: Arc<AtomicBool>,
+}
+
+impl RecordingEquipment {
+    pub fn new() -> Self {
+        RecordingEquipment {
+            start: Instant::now(),
+            requests: Arc::default(),
+            events_tx: events::channel(),
+            unresponsive: Arc::default(),
+        }
+    }
+
+    /// Hang on disconnecting from now on
+    pub fn stop_responding(&self) {
+        self.unresponsive.store(true, Ordering::SeqCst);
+    }
+
+    /// Every command so far, with how long after creating the equipment it came
+    pub fn requests(&self) -> Vec<(Duration, Request)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Send `event` to everyone subscribed to the events of the equipment
+    pub fn send(&self, event: DeviceEvent) {
+        self.events_tx.send(event);
+    }
+
+    fn record(&self, request: Request) -> Result<()> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((self.start.elapsed(), request));
+        Ok(())
+    }
+}
+
+impl Equipment for RecordingEquipment {
+    async fn with_config(_: i16, _: ScanConfig, _: &CancellationToken) -> Result<Self> {
+        Ok(RecordingEquipment::new())
+    }
+    async fn connect(&mut self) -> Result<bool> {
+        self.events_tx.send(DeviceEvent::Connected);
+        Ok(true)
+    }
+    async fn disconnect(&self) -> Result<()> {
+        if self.unresponsive.load(Ordering::SeqCst) {
+            std::future::pending::<()>().await;
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        self.record(Request::Cadence(rpm))
+    }
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        self.record(Request::Power(watts))
+    }
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.record(Request::Resistance(level))
+    }
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.record(Request::HeartRate(bpm))
+    }
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.record(Request::Goal(goal))
+    }
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.record(Request::Simulation {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        })
+    }
+    async fn start(&self) -> Result<()> {
+        self.record(Request::Start)
+    }
+    async fn stop(&self) -> Result<()> {
+        self.record(Request::Stop)
+    }
+    async fn pause(&self) -> Result<()> {
+        self.record(Request::Pause)
+    }
+    async fn resume(&self) -> Result<()> {
+        self.record(Request::Resume)
+    }
+    async fn reset(&self) -> Result<()> {
+        self.record(Request::Reset)
+    }
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Recording equipment does not calibrate".to_string(),
+        ))
+    }
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Ok(Box::pin(futures::stream::empty()))
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        None
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Ok(None)
+    }
+    async fn data_stream(&self) -> Result<DataStream> {
+        Ok(Box::pin(futures::stream::empty()))
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use btleplug::api::{CharPropFlags, Characteristic, ValueNotification};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::bluetooth::{NotificationStream, Transport};
+use crate::ftms::{
+    CONTROL_POINT_UUID, FITNESS_MACHINE_SERVICE_UUID, FITNESS_MACHINE_STATUS_UUID,
+    FTMSControlOpCode, INDOOR_BIKE_DATA_UUID, ResultCode,
+};
+use crate::{KondisError, Result};
+
+/// What got asked of a `RecordingTransport`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operation {
+    Connect,
+    Disconnect,
+    Subscribe(Uuid),
+    Unsubscribe(Uuid),
+    Write(Uuid, Vec<u8>),
+}
+
+/// A transport for tests, with the characteristics of an FTMS bike, remembering every operation and
+/// accepting every control point command, unless told to answer otherwise
+#[derive(Debug, Clone)]
+pub(crate) struct RecordingTransport {
+    connected: Arc<AtomicBool>,
+    characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    /// What reading each of the readable characteristics returns
+    values: Arc<Mutex<HashMap<Uuid, Vec<u8>>>>,
+    operations: Arc<Mutex<Vec<Operation>>>,
+    notifications: broadcast::Sender<ValueNotification>,
+    /// Cancelled on disconnecting, which ends the notifications of the connection
+    connection: Arc<Mutex<CancellationToken>>,
+    /// How many of the next connects fail
+    failing_connects: Arc<AtomicUsize>,
+    /// The result code control point commands get answered with
+    result: Arc<AtomicU8>,
+}
+
+impl RecordingTransport {
+    pub fn new() -> Self {
+        let transport = RecordingTransport {
+            connected: Arc::default(),
+            characteristics: Arc::default(),
+            values: Arc::default(),
+            operations: Arc::default(),
+            notifications: broadcast::channel(64).0,
+            connection: Arc::default(),
+            failing_connects: Arc::default(),
+            result: Arc::new(AtomicU8::new(ResultCode::Success.into())),
+        };
+        transport.add(INDOOR_BIKE_DATA_UUID, CharPropFlags::NOTIFY);
+        transport.add(
+            CONTROL_POINT_UUID,
+            CharPropFlags::WRITE | CharPropFlags::INDICATE,
+        );
+        transport.add(FITNESS_MACHINE_STATUS_UUID, CharPropFlags::NOTIFY);
+        transport
+    }
+
+    /// Add a readable characteristic, which reads as `value`
+    pub fn with_value(self, uuid: Uuid, value: &[u8]) -> Self {
+        self.add(uuid, CharPropFlags::READ);
+        self.values.lock().unwrap().insert(uuid, value.to_vec());
+        self
+    }
+
+    /// Fail the next `count` connects
+    pub fn fail_connects(&self, count: usize) {
+        self.failing_connects.store(count, Ordering::SeqCst);
+    }
+
+    /// Answer control point commands with `result` from now on
+    pub fn respond_with(&self, result: ResultCode) {
+        self.result.store(result.into(), Ordering::SeqCst);
+    }
+
+    /// Notify `value` on the characteristic `uuid`
+    pub fn notify(&self, uuid: Uuid, value: &[u8]) {
+        let _ = self.notifications.send(ValueNotification {
+            uuid,
+            value: value.to_vec(),
+        });
+    }
+
+    /// Every operation so far
+    pub fn operations(&self) -> Vec<Operation> {
+        self.operations.lock().unwrap().clone()
+    }
+
+    /// Every command written to the control point so far
+    pub fn commands(&self) -> Vec<Vec<u8>> {
+        self.operations()
+            .into_iter()
+            .filter_map(|operation| match operation {
+                Operation::Write(CONTROL_POINT_UUID, data) => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn add(&self, uuid: Uuid, properties: CharPropFlags) {
+        self.characteristics.lock().unwrap().insert(Characteristic {
+            uuid,
+            service_uuid: FITNESS_MACHINE_SERVICE_UUID,
+            properties,
+            descriptors: BTreeSet::new(),
+        });
+    }
+
+    fn record(&self, operation: Operation) {
+        self.operations.lock().unwrap().push(operation);
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn address(&self) -> String {
+        "C0:FF:EE:00:00:01".to_string()
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.connected.load(Ordering::SeqCst))
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.record(Operation::Connect);
+        let failing = self.failing_connects.load(Ordering::SeqCst);
+        if failing > 0 {
+            self.failing_connects.store(failing - 1, Ordering::SeqCst);
+            return Err(KondisError::DeviceNotFound);
+        }
+        *self.connection.lock().unwrap() = CancellationToken::new();
+        self.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.record(Operation::Disconnect);
+        self.connection.lock().unwrap().cancel();
+        self.connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.characteristics.lock().unwrap().clone()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let values = self.values.lock().unwrap();
+        Ok(values
+            .get(&characteristic.uuid)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8]) -> Result<()> {
+        self.record(Operation::Write(characteristic.uuid, data.to_vec()));
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(KondisError::Disconnected("Not connected".to_string()));
+        }
+        if characteristic.uuid == CONTROL_POINT_UUID
+            && let Some(&op_code) = data.first()
+        {
+            self.notify(
+                CONTROL_POINT_UUID,
+                &[
+                    FTMSControlOpCode::Success as u8,
+                    op_code,
+                    self.result.load(Ordering::SeqCst),
+                ],
+            );
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.record(Operation::Subscribe(characteristic.uuid));
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.record(Operation::Unsubscribe(characteristic.uuid));
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        let notifications = self.notifications.subscribe();
+        let connection = self.connection.lock().unwrap().clone();
+        Ok(Box::pin(
+            futures::stream::unfold(notifications, |mut notifications| async move {
+                let notification = notifications.recv().await.ok()?;
+                Some((notification, notifications))
+            })
+            .take_until(connection.cancelled_owned()),
+        ))
+    }
+
+    async fn rssi(&self) -> Result<Option<i16>> {
+        Ok(Some(-60))
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{Timeouts, within};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{Capabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal};
+use crate::remote::{self, DEFAULT_PORT, Request};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Reading,
+    Result, ScanConfig,
+};
+
+/// Requests waiting for their reply, by number
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<()>>>>>;
+
+/// Equipment connected to another machine, served over TCP by a `RemoteServer`
+///
+/// The server is the one at `ScanConfig::address`, as `host:port`, and at port 7878 of the local
+/// machine unless told otherwise. Readings and the battery level come from the equipment of the
+/// server, for as long as connected, and every target and session command goes to it, failing like
+/// it fails on the server. Spin downs and machine status notifications don't go over the network, so
+/// they are unsupported.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{devices::RemoteEquipment, CancellationToken, Equipment, ScanConfig};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let config = ScanConfig::new().address("garage-pi.local:7878");
+///     let mut bike = RemoteEquipment::with_config(400, config, &shutdown).await?;
+///     bike.connect().await?;
+///     bike.set_target_power(200).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RemoteEquipment {
+    /// The address of the server, `host:port`
+    pub name: String,
+    writer: Arc<tokio::sync::Mutex<Option<OwnedWriteHalf>>>,
+    pending: Pending,
+    next_id: Arc<AtomicU64>,
+    capabilities: Arc<Mutex<Option<Capabilities>>>,
+    battery_level: Arc<Mutex<Option<u8>>>,
+    /// stops reading from the current connection
+    reading: Arc<Mutex<Option<CancellationToken>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    shutdown: CancellationToken,
+}
+
+impl RemoteEquipment {
+    async fn send(&self, request: Request) -> Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, reply_tx);
+        let line = format!("{}\n", remote::request(id, &request));
+        let sent = async {
+            let mut writer = self.writer.lock().await;
+            let Some(writer) = writer.as_mut() else {
+                return Err(KondisError::Disconnected(format!(
+                    "Not connected to {}",
+                    self.name
+                )));
+            };
+            writer.write_all(line.as_bytes()).await?;
+            Ok(())
+        };
+        let answered = async {
+            sent.await?;
+            reply_rx.await.unwrap_or_else(|_| {
+                Err(KondisError::Disconnected(format!(
+                    "{} went away before answering",
+                    self.name
+                )))
+            })
+        };
+        let result = until_shutdown(
+            &self.shutdown,
+            within(self.timeouts.notification, no_answer, answered),
+        )
+        .await;
+        self.pending.lock().unwrap().remove(&id);
+        result
+    }
+}
+
+impl Equipment for RemoteEquipment {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let name = config
+            .address
+            .clone()
+            .unwrap_or_else(|| format!("localhost:{DEFAULT_PORT}"));
+        Ok(RemoteEquipment {
+            name,
+            writer: Arc::new(tokio::sync::Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            capabilities: Arc::new(Mutex::new(None)),
+            battery_level: Arc::new(Mutex::new(None)),
+            reading: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let stream = within(self.timeouts.connect, KondisError::ConnectTimeout, async {
+                    Ok(TcpStream::connect(&self.name).await?)
+                })
+                .await?;
+                let (reader, writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let hello = within(self.timeouts.notification, no_answer, async {
+                    Ok(lines.next_line().await?.unwrap_or_default())
+                })
+                .await?;
+                *self.capabilities.lock().unwrap() = remote::parse_hello(&hello)?;
+                *self.writer.lock().await = Some(writer);
+
+                let reading = self.shutdown.child_token();
+                if let Some(previous) = self.reading.lock().unwrap().replace(reading.clone()) {
+                    previous.cancel();
+                }
+                let pending = self.pending.clone();
+                let battery_level = self.battery_level.clone();
+                let events_tx = self.events_tx.clone();
+                crate::runtime::spawn(async move {
+                    loop {
+                        let line = tokio::select! {
+                            _ = reading.cancelled() => return,
+                            line = lines.next_line() => line,
+                        };
+                        let Ok(Some(line)) = line else {
+                            break;
+                        };
+                        if let Some(data) = line.strip_prefix("data ") {
+                            match remote::parse_data(data) {
+                                Ok((sequence, data)) => {
+                                    events_tx.send(DeviceEvent::Data(Reading::new(sequence, data)));
+                                }
+                                Err(e) => events_tx.send(DeviceEvent::Error(e.to_string())),
+                            }
+                        } else if let Some(percent) = line.strip_prefix("battery ") {
+                            if let Ok(percent) = percent.parse() {
+                                *battery_level.lock().unwrap() = Some(percent);
+                                events_tx.send(DeviceEvent::BatteryLevel(percent));
+                            }
+                        } else if let Some((id, result)) = remote::parse_reply(&line)
+                            && let Some(reply_tx) = pending.lock().unwrap().remove(&id)
+                        {
+                            let _ = reply_tx.send(result);
+                        }
+                    }
+                    // the server went away, failing what's waiting on it
+                    pending.lock().unwrap().clear();
+                    events_tx.send(DeviceEvent::Disconnected);
+                });
+                self.events_tx.send(DeviceEvent::Connected);
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(reading) = self.reading.lock().unwrap().take() {
+            reading.cancel();
+        }
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            // the server noticing is all that's left to do, whether or not it does
+            let _ = writer.shutdown().await;
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        self.send(Request::Cadence(rpm)).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        self.send(Request::Power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.send(Request::Resistance(level)).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.send(Request::HeartRate(bpm)).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.send(Request::Goal(goal)).await
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.send(Request::Simulation {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        })
+        .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.send(Request::Start).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.send(Request::Stop).await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.send(Request::Pause).await
+    }
+
+    async fn resume(&self) -> Result<()> {
+        self.send(Request::Resume).await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.send(Request::Reset).await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Remote equipment does not support spin down calibration".to_string(),
+        ))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(KondisError::Unsupported(
+            "Remote equipment does not support machine status".to_string(),
+        ))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        *self.capabilities.lock().unwrap()
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        *self.battery_level.lock().unwrap()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        until_shutdown(
+            &self.shutdown,
+            within(
+                self.timeouts.notification,
+                KondisError::NotificationTimeout,
+                async {
+                    loop {
+                        match events.recv().await {
+                            Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                            Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => {
+                                return Ok(None);
+                            }
+                            Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        }
+                    }
+                },
+            ),
+        )
+        .await
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+fn no_answer(timeout: std::time::Duration) -> KondisError {
+    KondisError::Timeout(format!("No answer from the server within {timeout:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RemoteServer;
+    use crate::devices::SimulatorBike;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_remote_equipment() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut bike = SimulatorBike::new(400, &shutdown).await?;
+        bike.connect().await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        let server = RemoteServer::new(&shutdown);
+        let client = async {
+            let config = ScanConfig::new().address(&address);
+            let mut remote = RemoteEquipment::with_config(400, config, &shutdown).await?;
+            assert!(remote.connect().await?);
+            assert_eq!(remote.capabilities(), bike.capabilities());
+            let mut data = remote.data_stream().await?;
+            remote.set_target_power(200).await?;
+            // above the maximum of the bike
+            assert!(matches!(
+                remote.set_target_power(1000).await,
+                Err(KondisError::InvalidArgument(_))
+            ));
+            assert!(matches!(
+                data.next().await.map(|reading| reading.data),
+                Some(MachineData::Bike(_))
+            ));
+            remote.disconnect().await?;
+            assert_eq!(remote.state(), ConnectionState::Disconnected);
+            shutdown.cancel();
+            Ok::<_, KondisError>(())
+        };
+        let (served, checked) = tokio::join!(server.serve(&bike, listener), client);
+        served.and(checked)
+    }
+}
@@ -0,0 +1,381 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::events::{self, EventSender};
+use crate::ftms::{
+    CROSS_TRAINER_DATA_UUID, Capabilities, FITNESS_MACHINE_STATUS_UUID, INDOOR_BIKE_DATA_UUID,
+    MachineData, MachineStatus, ROWER_DATA_UUID, SpinDownResult, SpinDownStatus,
+    TREADMILL_DATA_UUID, TrainingGoal, parse_cross_trainer_data, parse_indoor_bike_data,
+    parse_machine_status, parse_rower_data, parse_treadmill_data,
+};
+use crate::sensors::{
+    CSC_MEASUREMENT_UUID, CscCalculator, DEFAULT_WHEEL_CIRCUMFERENCE, HEART_RATE_MEASUREMENT_UUID,
+    parse_csc_measurement, parse_heart_rate_measurement,
+};
+use crate::{
+    CapturedNotification, ConnectionState, DataStream, DeviceEvent, Equipment, KondisError,
+    MachineStatusStream, Reading, Result, ScanConfig, parse_capture, parse_fit,
+};
+
+/// Something the replayed device reports
+#[derive(Debug, Clone)]
+enum Replayed {
+    /// A notification of a data characteristic, or why it couldn't be decoded
+    Data(std::result::Result<MachineData, String>),
+    Status(std::result::Result<MachineStatus, String>),
+}
+
+/// Equipment playing back a capture, see `ScanConfig::capture`, or a past activity
+///
+/// Connecting starts the playback from the start of the capture, in real time or faster, with every
+/// data and machine status notification turning into events as it did on the real device. Once the
+/// capture is over, the device disconnects.
+///
+/// Activities, like those of FIT or TCX files, play back their records as bike data instead, for
+/// testing apps without riding, or riding against a past ride.
+///
+/// Nothing is listening to what the device is told, so every target and session command succeeds
+/// without doing anything.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::{devices::ReplayDevice, parse_capture, CancellationToken, Equipment};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let capture = parse_capture(
+///         "0.0 00002ad2-0000-1000-8000-00805f9b34fb 4400c409b400c800\n\
+///          1.0 00002ad2-0000-1000-8000-00805f9b34fb 4400c409b400d200\n",
+///     )?;
+///     let mut device = ReplayDevice::from_capture(&capture, &shutdown).speed(10.);
+///     let mut data = device.data_stream().await?;
+///     device.connect().await?;
+///     while let Some(reading) = data.next().await {
+///         println!("{:?}", reading.data);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplayDevice {
+    /// The name of the device
+    pub name: String,
+    timeline: Arc<[(Duration, Replayed)]>,
+    speed: f64,
+    connected: Arc<AtomicBool>,
+    /// stops the playback of the current connection
+    playback: Arc<Mutex<Option<CancellationToken>>>,
+    latest: Arc<Mutex<Option<MachineData>>>,
+    events_tx: EventSender,
+    shutdown: CancellationToken,
+}
+
+impl ReplayDevice {
+    /// A device playing back the capture, FIT or TCX file at `path`, going by what the file holds
+    ///
+    /// TCX files need the `tcx` feature.
+    pub fn open(path: impl AsRef<Path>, shutdown: &CancellationToken) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let mut device = if bytes.get(8..12) == Some(b".FIT") {
+            Self::from_activity(&parse_fit(&bytes)?, shutdown)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| {
+                KondisError::InvalidData(format!("{} is neither text nor FIT", path.display()))
+            })?;
+            if text.trim_start().starts_with('<') {
+                Self::from_activity(&parse_activity_xml(&text)?, shutdown)
+            } else {
+                Self::from_capture(&parse_capture(&text)?, shutdown)
+            }
+        };
+        device.name = path.display().to_string();
+        Ok(device)
+    }
+
+    /// A device playing back the records of an activity, see `parse_fit`
+    pub fn from_activity(
+        records: &[(Duration, MachineData)],
+        shutdown: &CancellationToken,
+    ) -> Self {
+        let timeline = records
+            .iter()
+            .map(|(offset, data)| (*offset, Replayed::Data(Ok(data.clone()))))
+            .collect();
+        Self::from_timeline(timeline, shutdown)
+    }
+
+    /// A device playing back `capture`
+    pub fn from_capture(capture: &[CapturedNotification], shutdown: &CancellationToken) -> Self {
+        // speed and cadence measurements only count revolutions, the rates coming from those before
+        let mut calculator = CscCalculator::new(DEFAULT_WHEEL_CIRCUMFERENCE);
+        let timeline = capture
+            .iter()
+            .filter_map(|notification| {
+                let value = &notification.value;
+                let decoded = match notification.uuid {
+                    INDOOR_BIKE_DATA_UUID => parse_indoor_bike_data(value).map(MachineData::from),
+                    TREADMILL_DATA_UUID => parse_treadmill_data(value).map(MachineData::from),
+                    ROWER_DATA_UUID => parse_rower_data(value).map(MachineData::from),
+                    CROSS_TRAINER_DATA_UUID => {
+                        parse_cross_trainer_data(value).map(MachineData::from)
+                    }
+                    HEART_RATE_MEASUREMENT_UUID => {
+                        parse_heart_rate_measurement(value).map(MachineData::from)
+                    }
+                    CSC_MEASUREMENT_UUID => parse_csc_measurement(value).map(|measurement| {
+                        calculator.update(&measurement, notification.offset).into()
+                    }),
+                    FITNESS_MACHINE_STATUS_UUID => {
+                        let status = parse_machine_status(value).map_err(|e| e.to_string());
+                        return Some((notification.offset, Replayed::Status(status)));
+                    }
+                    // like control point indications, which the device only answered to commands with
+                    _ => return None,
+                };
+                let data = decoded.map_err(|e| e.to_string());
+                Some((notification.offset, Replayed::Data(data)))
+            })
+            .collect();
+        Self::from_timeline(timeline, shutdown)
+    }
+
+    fn from_timeline(timeline: Arc<[(Duration, Replayed)]>, shutdown: &CancellationToken) -> Self {
+        ReplayDevice {
+            name: "replay".to_string(),
+            timeline,
+            speed: 1.,
+            connected: Arc::new(AtomicBool::new(false)),
+            playback: Arc::new(Mutex::new(None)),
+            latest: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Play back `factor` times as fast as the capture went, 1 by default
+    pub fn speed(mut self, factor: f64) -> Self {
+        self.speed = factor.max(f64::MIN_POSITIVE);
+        self
+    }
+
+    /// How long the playback takes, at its speed
+    pub fn duration(&self) -> Duration {
+        self.timeline
+            .last()
+            .map_or(Duration::ZERO, |(offset, _)| offset.div_f64(self.speed))
+    }
+
+    fn stop_playback(&self) {
+        if let Some(playback) = self.playback.lock().unwrap().take() {
+            playback.cancel();
+        }
+    }
+}
+
+#[cfg(feature = "tcx")]
+fn parse_activity_xml(xml: &str) -> Result<Vec<(Duration, MachineData)>> {
+    crate::parse_tcx(xml)
+}
+
+#[cfg(not(feature = "tcx"))]
+fn parse_activity_xml(_: &str) -> Result<Vec<(Duration, MachineData)>> {
+    Err(KondisError::Unsupported(
+        "Replaying TCX files needs the tcx feature".to_string(),
+    ))
+}
+
+impl Equipment for ReplayDevice {
+    async fn with_config(_: i16, _: ScanConfig, _: &CancellationToken) -> Result<Self> {
+        Err(KondisError::Unsupported(
+            "A replay device plays back a file, see ReplayDevice::open".to_string(),
+        ))
+    }
+    async fn connect(&mut self) -> Result<bool> {
+        self.events_tx.set_state(ConnectionState::Connecting);
+        self.stop_playback();
+        let playback = self.shutdown.child_token();
+        *self.playback.lock().unwrap() = Some(playback.clone());
+        self.connected.store(true, Ordering::SeqCst);
+        self.events_tx.send(DeviceEvent::Connected);
+
+        let timeline = self.timeline.clone();
+        let speed = self.speed;
+        let connected = self.connected.clone();
+        let latest = self.latest.clone();
+        let events_tx = self.events_tx.clone();
+        crate::runtime::spawn(async move {
+            let start = crate::runtime::Instant::now();
+            let mut sequence = 0;
+            for (offset, replayed) in timeline.iter() {
+                tokio::select! {
+                    _ = playback.cancelled() => return,
+                    _ = crate::runtime::sleep_until(start + offset.div_f64(speed)) => {}
+                }
+                let event = match replayed {
+                    Replayed::Data(data) => {
+                        let event = match data {
+                            Ok(data) => {
+                                *latest.lock().unwrap() = Some(data.clone());
+                                DeviceEvent::Data(Reading::new(sequence, data.clone()))
+                            }
+                            Err(e) => DeviceEvent::Error(e.clone()),
+                        };
+                        sequence += 1;
+                        event
+                    }
+                    Replayed::Status(Ok(status)) => DeviceEvent::MachineStatus(status.clone()),
+                    Replayed::Status(Err(e)) => DeviceEvent::Error(e.clone()),
+                };
+                events_tx.send(event);
+            }
+            connected.store(false, Ordering::SeqCst);
+            events_tx.send(DeviceEvent::Disconnected);
+        });
+        Ok(true)
+    }
+    async fn disconnect(&self) -> Result<()> {
+        self.stop_playback();
+        if self.connected.swap(false, Ordering::SeqCst) {
+            self.events_tx.send(DeviceEvent::Disconnected);
+        }
+        Ok(())
+    }
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Ok(())
+    }
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Ok(())
+    }
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Ok(())
+    }
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Ok(())
+    }
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Ok(())
+    }
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Ok(())
+    }
+    async fn start(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn stop(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn pause(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn reset(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "A replay device can't calibrate".to_string(),
+        ))
+    }
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::MachineStatus(status)) => return Some((status, events)),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        None
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Ok(self.latest.lock().unwrap().clone())
+    }
+    /// Every reading of the playback from now on, ending with it
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FTMSData;
+
+    #[tokio::test]
+    async fn test_replay() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let notification = |millis, value: &[u8]| CapturedNotification {
+            offset: Duration::from_millis(millis),
+            uuid: INDOOR_BIKE_DATA_UUID,
+            value: value.to_vec(),
+        };
+        let capture = [
+            notification(0, &[0x44, 0x00, 0xC4, 0x09, 0xB4, 0x00, 0xC8, 0x00]),
+            // too short to decode
+            notification(500, &[0x44]),
+            notification(1000, &[0x44, 0x00, 0xC4, 0x09, 0xB4, 0x00, 0xD2, 0x00]),
+        ];
+        let mut device = ReplayDevice::from_capture(&capture, &shutdown).speed(100.);
+        assert_eq!(device.duration(), Duration::from_millis(10));
+        let mut events = device.events();
+        device.connect().await?;
+
+        for state in [ConnectionState::Connecting, ConnectionState::Connected] {
+            assert!(matches!(events.recv().await, Ok(DeviceEvent::StateChanged(s)) if s == state));
+        }
+        assert!(matches!(events.recv().await, Ok(DeviceEvent::Connected)));
+        let mut sequences = Vec::new();
+        loop {
+            match events.recv().await {
+                Ok(DeviceEvent::Data(reading)) => sequences.push(reading.sequence),
+                Ok(DeviceEvent::Error(_) | DeviceEvent::StateChanged(_)) => {}
+                Ok(DeviceEvent::Disconnected) => break,
+                event => panic!("unexpected {event:?}"),
+            }
+        }
+        // the notification that couldn't be decoded left a gap
+        assert_eq!(sequences, [0, 2]);
+        assert_eq!(device.state(), ConnectionState::Disconnected);
+        let latest = FTMSData::from(device.read().await?.unwrap());
+        assert_eq!(latest.power, Some(210));
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, get_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::csafe::{self, command};
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::reader::Reader;
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, RowerData, SpinDownResult, SpinDownStatus,
+    TrainingGoal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream, Rower,
+};
+use crate::{KondisError, Result};
+
+/// A characteristic of the services of Concept2, all sharing the base UUID
+/// CE06xxxx-43E5-11E4-916C-0800200C9A66
+const fn concept2_uuid(short: u16) -> Uuid {
+    Uuid::from_u128(0xCE060000_43E5_11E4_916C_0800200C9A66 | (short as u128) << 96)
+}
+
+/// CSAFE frames get written to the monitor on this characteristic of the control service (0x0020)
+const CONTROL_RECEIVE_UUID: Uuid = concept2_uuid(0x0021);
+/// Every status characteristic of the rowing service (0x0030) gets notified through this one, its
+/// first byte being the short UUID of the characteristic it carries
+const MULTIPLEXED_UUID: Uuid = concept2_uuid(0x0080);
+
+/// What the multiplexed characteristic carries, by the short UUIDs of the rowing service
+mod status {
+    pub const GENERAL: u8 = 0x31;
+    pub const ADDITIONAL: u8 = 0x32;
+    pub const ADDITIONAL_2: u8 = 0x33;
+    pub const ADDITIONAL_STROKE: u8 = 0x36;
+}
+
+/// The monitor reports this heart rate without a heart rate monitor paired
+const NO_HEART_RATE: u8 = 0xFF;
+
+/// A Concept2 rowing machine, ski erg or bike erg with a PM5 performance monitor, speaking the
+/// proprietary services of Concept2 rather than FTMS.
+/// The first device with "PM5" in its name gets connected to.
+///
+/// Readings hold `MachineData::Rower`, gathered from the rowing status, stroke data and power
+/// notifications of the monitor. Resistance is set by the damper, so there is nothing to target, but
+/// distance, time and calorie goals can be programmed as a workout, which starts with the first stroke.
+#[derive(Debug, Clone)]
+pub struct Pm5Rower {
+    peripheral: Peripheral,
+    /// The name of the monitor, like "PM5 430000000", or its address if it doesn't advertise a name
+    pub name: String,
+    multiplexed: Option<Characteristic>,
+    control: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for Pm5Rower {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some((peripheral, name)) =
+            get_peripheral(EquipmentType::Concept2Pm5Rower, &config, shutdown).await?
+        else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(Pm5Rower {
+            peripheral,
+            name,
+            multiplexed: None,
+            control: None,
+            events_tx: events::channel(),
+            capture: config.capture.clone(),
+            watchdog: Watchdog::new(&config),
+            timeouts: Timeouts::new(&config),
+            battery: Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY)),
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.control =
+                    bluetooth::find_characteristic(&self.peripheral, CONTROL_RECEIVE_UUID);
+                self.multiplexed =
+                    Some(bluetooth::subscribe(&self.peripheral, MULTIPLEXED_UUID).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                let mut decoder = Pm5Decoder::default();
+                events::forward_notifications(
+                    &self.peripheral,
+                    MULTIPLEXED_UUID,
+                    move |data| Ok(decoder.update(data)?.into()),
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to rower");
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(multiplexed) = &self.multiplexed {
+            self.peripheral.unsubscribe(multiplexed).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target stroke rate"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported(
+            "a target resistance level, which is set by the damper",
+        ))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    /// Program the goal as a workout, starting with the first stroke. Distance goals are at most
+    /// 65535 m.
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        let mut commands = csafe::goal(goal)?;
+        commands.push(command::GO_IN_USE);
+        self.write(&commands).await
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.write(&[command::GO_IN_USE]).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.write(&[command::GO_FINISHED]).await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("pausing"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("resuming"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.write(&[command::RESET]).await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                power: true,
+                expended_energy: true,
+                heart_rate: true,
+                elapsed_time: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected, holding what every status notification so far added
+    /// to it
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl BluetoothEquipment for Pm5Rower {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+impl Rower for Pm5Rower {
+    async fn read_rower(&self) -> Result<Option<RowerData>> {
+        Ok(match self.read().await? {
+            Some(MachineData::Rower(data)) => Some(data),
+            _ => None,
+        })
+    }
+}
+
+impl Pm5Rower {
+    async fn write(&self, commands: &[u8]) -> Result<()> {
+        let Some(control) = &self.control else {
+            return Err(KondisError::CharacteristicMissing(
+                "PM5 control receive".to_string(),
+            ));
+        };
+        until_shutdown(&self.shutdown, async {
+            Ok(self
+                .peripheral
+                .write(control, &csafe::frame(commands), WriteType::WithResponse)
+                .await?)
+        })
+        .await
+    }
+}
+
+/// The rower data of a PM5, as every notification of the multiplexed characteristic fills in the
+/// fields of the status it carries
+#[derive(Debug, Default)]
+struct Pm5Decoder {
+    data: RowerData,
+}
+
+impl Pm5Decoder {
+    /// Take in a notification, resolving to the rower data so far. Statuses holding nothing the data
+    /// holds leave it as it is.
+    fn update(&mut self, notification: &[u8]) -> Result<RowerData> {
+        let mut reader = Reader::new(notification);
+        let id = reader.u8()?;
+        if matches!(
+            id,
+            status::GENERAL | status::ADDITIONAL | status::ADDITIONAL_2 | status::ADDITIONAL_STROKE
+        ) {
+            // 0.01 s
+            let elapsed = reader.u24()?;
+            self.data.time = Some(elapsed / 100);
+        }
+        let data = &mut self.data;
+        match id {
+            status::GENERAL => {
+                // 0.1 m
+                data.distance = Some(reader.u24()? as f32 / 10_000.);
+            }
+            status::ADDITIONAL => {
+                let _speed = reader.u16()?;
+                data.stroke_rate = Some(reader.u8()?.into());
+                data.heart_rate = match reader.u8()? {
+                    NO_HEART_RATE => None,
+                    heart_rate => Some(heart_rate.into()),
+                };
+                // 0.01 s per 500 m
+                data.pace = Some(reader.u16()? / 100);
+            }
+            status::ADDITIONAL_2 => {
+                let _interval = reader.u8()?;
+                let _average_power = reader.u16()?;
+                data.calories = Some(reader.u16()?.into());
+            }
+            status::ADDITIONAL_STROKE => {
+                data.power = Some(i16::try_from(reader.u16()?).unwrap_or(i16::MAX));
+                let _calories_per_hour = reader.u16()?;
+                data.stroke_count = Some(reader.u16()?);
+            }
+            _ => {}
+        }
+        Ok(data.clone())
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Concept2 PM5 monitors do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() -> Result<()> {
+        let mut decoder = Pm5Decoder::default();
+        // 62.5 s in, 250.3 m
+        let general = [0x31, 0x6A, 0x18, 0x00, 0xC7, 0x09, 0x00, 0x00];
+        let data = decoder.update(&general)?;
+        assert_eq!(data.time, Some(62));
+        assert_eq!(data.distance, Some(0.2503));
+
+        // 28 spm, no heart rate, at 2:05.40 per 500 m
+        let additional = [0x32, 0x6A, 0x18, 0x00, 0x00, 0x10, 28, 0xFF, 0xFC, 0x30];
+        let data = decoder.update(&additional)?;
+        assert_eq!(data.stroke_rate, Some(28.));
+        assert_eq!(data.heart_rate, None);
+        assert_eq!(data.pace, Some(125));
+        // still there from the general status
+        assert_eq!(data.distance, Some(0.2503));
+
+        // 180 W on stroke 31
+        let stroke = [0x36, 0x6A, 0x18, 0x00, 0xB4, 0x00, 0x20, 0x03, 31, 0x00];
+        let data = decoder.update(&stroke)?;
+        assert_eq!(data.power, Some(180));
+        assert_eq!(data.stroke_count, Some(31));
+
+        let unknown = [0x3A, 0x00];
+        assert_eq!(decoder.update(&unknown)?, data);
+        assert!(decoder.update(&general[..5]).is_err());
+        Ok(())
+    }
+}
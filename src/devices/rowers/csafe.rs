@@ -0,0 +1,395 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::csafe::{self, command, long, unit};
+use crate::devices::csafe_port::CsafePort;
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, RowerData, SpinDownResult, SpinDownStatus,
+    TrainingGoal,
+};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream, Reading, Rower,
+};
+use crate::{KondisError, Result};
+
+/// How often the equipment gets asked for its data
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Everything asked for with every poll
+const POLL: [u8; 7] = [
+    command::GET_TWORK,
+    command::GET_HORIZONTAL,
+    command::GET_CALORIES,
+    command::GET_PACE,
+    command::GET_CADENCE,
+    command::GET_HR_CUR,
+    command::GET_POWER,
+];
+
+/// Equipment speaking CSAFE over a wire rather than Bluetooth, like a Concept2 PM3, PM4 or PM5 over
+/// USB, or gym equipment with a CSAFE serial port.
+/// The port to talk to is set with `ScanConfig::port`, there being nothing to scan for.
+///
+/// The equipment gets polled for its data every second, and readings hold `MachineData::Rower`, as
+/// CSAFE reports the work time, distance, pace, cadence, power, calories and heart rate of every kind
+/// of equipment alike. Distance, time and calorie goals can be programmed as a workout, and target
+/// power is supported by the equipment that supports it.
+#[derive(Debug, Clone)]
+pub struct CsafeRower {
+    /// The path of the port
+    pub name: String,
+    path: PathBuf,
+    port: Arc<Mutex<Option<CsafePort>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops polling the equipment, replaced with every connection
+    polling: CancellationToken,
+    shutdown: CancellationToken,
+    max_level: i16,
+}
+
+impl Equipment for CsafeRower {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let Some(path) = config.port.clone() else {
+            return Err(KondisError::InvalidArgument(
+                "CSAFE equipment needs a port to talk to, see `ScanConfig::port`".to_string(),
+            ));
+        };
+        Ok(CsafeRower {
+            name: path.display().to_string(),
+            path,
+            port: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            polling: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let mut port = CsafePort::open(&self.path)?;
+                // the equipment answering is all there is to connecting
+                within(
+                    self.timeouts.connect,
+                    KondisError::ConnectTimeout,
+                    port.send(&[command::GET_STATUS]),
+                )
+                .await?;
+                *self.port.lock().await = Some(port);
+                self.poll();
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to CSAFE equipment");
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.polling.cancel();
+        self.port.lock().await.take();
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        if !(1..=self.max_level).contains(&watts) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Watts must be between 1 and {}",
+                self.max_level
+            )));
+        }
+        let [lsb, msb] = watts.to_le_bytes();
+        self.command(
+            &[long::SET_POWER, 3, lsb, msb, unit::WATTS],
+            "a target power",
+        )
+        .await
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    /// Program the goal as a workout and start it. Distance goals are at most 65535 m.
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        let mut commands = csafe::goal(goal)?;
+        commands.push(command::GO_IN_USE);
+        self.command(&commands, "training goals").await
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.command(&[command::GO_IN_USE], "session control").await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.command(&[command::GO_FINISHED], "session control")
+            .await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("pausing"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("resuming"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.command(&[command::RESET], "session control").await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                power: true,
+                expended_energy: true,
+                heart_rate: true,
+                elapsed_time: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next poll of the equipment, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every poll of the equipment from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl Rower for CsafeRower {
+    async fn read_rower(&self) -> Result<Option<RowerData>> {
+        Ok(match self.read().await? {
+            Some(MachineData::Rower(data)) => Some(data),
+            _ => None,
+        })
+    }
+}
+
+impl CsafeRower {
+    /// Keep polling the equipment until disconnected, or until a poll fails, which disconnects
+    fn poll(&mut self) {
+        self.polling.cancel();
+        self.polling = self.shutdown.child_token();
+        let polling = self.polling.clone();
+        let equipment = self.clone();
+        crate::runtime::spawn(async move {
+            let mut interval = crate::runtime::interval(POLL_INTERVAL);
+            for sequence in 0.. {
+                tokio::select! {
+                    _ = polling.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let data = match equipment.send(&POLL).await.and_then(|r| rower_data(&r)) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        equipment.events_tx.send(DeviceEvent::Error(e.to_string()));
+                        let _ = equipment.disconnect().await;
+                        break;
+                    }
+                };
+                let reading = Reading::new(sequence, data.into());
+                equipment.events_tx.send(DeviceEvent::Data(reading));
+            }
+        });
+    }
+
+    /// Send `commands`, failing as unsupported when the equipment rejects them or leaves the first of
+    /// them unanswered
+    async fn command(&self, commands: &[u8], what: &str) -> Result<()> {
+        let contents = self.send(commands).await?;
+        let responses = csafe::responses(&contents)?;
+        let answered = responses
+            .data
+            .iter()
+            .any(|(id, _)| Some(id) == commands.first());
+        if responses.rejected() || !answered {
+            return Err(unsupported(what));
+        }
+        Ok(())
+    }
+
+    async fn send(&self, commands: &[u8]) -> Result<Vec<u8>> {
+        let mut port = self.port.lock().await;
+        let Some(port) = port.as_mut() else {
+            return Err(KondisError::Disconnected(format!(
+                "Not connected to {}",
+                self.name
+            )));
+        };
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(
+                timeout,
+                KondisError::NotificationTimeout,
+                port.send(commands),
+            ),
+        )
+        .await
+    }
+}
+
+/// The rower data answering a poll, leaving out whatever the equipment doesn't report
+fn rower_data(contents: &[u8]) -> Result<RowerData> {
+    let mut data = RowerData::default();
+    for (id, response) in csafe::responses(contents)?.data {
+        match (id, response) {
+            (command::GET_TWORK, &[hours, minutes, seconds]) => {
+                data.time =
+                    Some(u32::from(hours) * 3600 + u32::from(minutes) * 60 + u32::from(seconds));
+            }
+            (command::GET_HORIZONTAL, &[lsb, msb, unit]) => {
+                data.distance = kilometers(u16::from_le_bytes([lsb, msb]), unit);
+            }
+            (command::GET_CALORIES, &[lsb, msb]) => {
+                data.calories = Some(u16::from_le_bytes([lsb, msb]).into());
+            }
+            (command::GET_PACE, &[lsb, msb, _]) => {
+                // per km, rather than per 500 m
+                data.pace = Some(u16::from_le_bytes([lsb, msb]) / 2);
+            }
+            (command::GET_CADENCE, &[lsb, msb, _]) => {
+                data.stroke_rate = Some(u16::from_le_bytes([lsb, msb]).into());
+            }
+            (command::GET_HR_CUR, &[heart_rate]) => {
+                data.heart_rate = (heart_rate != 0).then(|| heart_rate.into());
+            }
+            (command::GET_POWER, &[lsb, msb, _]) => {
+                let power = u16::from_le_bytes([lsb, msb]);
+                data.power = Some(i16::try_from(power).unwrap_or(i16::MAX));
+            }
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+fn kilometers(distance: u16, distance_unit: u8) -> Option<f32> {
+    let distance = f32::from(distance);
+    match distance_unit {
+        unit::KILOMETERS => Some(distance),
+        unit::METERS => Some(distance / 1000.),
+        unit::MILES => Some(distance * 1.609_344),
+        _ => None,
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("This CSAFE equipment does not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rower_data() -> Result<()> {
+        let contents = [
+            0x09, // status
+            0xA0, 0x03, 0x00, 0x04, 0x1E, // 4:30
+            0xA1, 0x03, 0x2C, 0x04, 0x24, // 1068 m
+            0xA3, 0x02, 0x3C, 0x00, // 60 calories
+            0xA6, 0x03, 0xFA, 0x00, 0x39, // 250 s per km
+            0xA7, 0x03, 0x1A, 0x00, 0x54, // 26 spm
+            0xB0, 0x01, 0x00, // no heart rate
+            0xB4, 0x03, 0xC8, 0x00, 0x58, // 200 W
+        ];
+        let data = rower_data(&contents)?;
+        assert_eq!(
+            data,
+            RowerData {
+                stroke_rate: Some(26.),
+                distance: Some(1.068),
+                pace: Some(125),
+                power: Some(200),
+                calories: Some(60.),
+                time: Some(270),
+                ..Default::default()
+            }
+        );
+        // equipment reporting nothing
+        assert_eq!(rower_data(&[0x09])?, RowerData::default());
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use btleplug::platform::Peripheral;
+use std::sync::mpsc::Sender;
+
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{DeviceInfo, ScanConfig};
+use crate::devices::ftms_peripheral::FtmsPeripheral;
+use crate::ftms::{
+    Capabilities, MachineData, ROWER_DATA_UUID, RowerData, SpinDownResult, SpinDownStatus,
+    SupportedRanges, TrainingGoal, parse_rower_data, target_heart_rate, target_power,
+    target_resistance_level, training_goal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream, Rower,
+};
+use crate::{KondisError, Result};
+
+/// Any standards-compliant FTMS rowing machine.
+/// The first device advertising the Fitness Machine Service (0x1826) as a rower gets connected to.
+///
+/// When read through `Equipment::read`, the stroke rate is reported as cadence.
+#[derive(Debug, Clone)]
+pub struct GenericFtmsRower {
+    ftms: FtmsPeripheral,
+    /// The name of the device, or its address if it doesn't advertise a name
+    pub name: String,
+    max_level: i16,
+}
+
+impl Equipment for GenericFtmsRower {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let ftms = FtmsPeripheral::find(
+            EquipmentType::GenericFtmsRower,
+            ROWER_DATA_UUID,
+            decode,
+            &config,
+            shutdown,
+        )
+        .await?;
+        Ok(GenericFtmsRower {
+            name: ftms.name.clone(),
+            ftms,
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let connected = self.ftms.connect().await?;
+        info!(device = %self.name, "Found and connected to rower");
+        Ok(connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.ftms.disconnect().await
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Rowers do not support a target cadence".to_string(),
+        ))
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let watts = self.ftms.target_power(watts, self.max_level)?;
+        self.ftms.write(&target_power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        let level = self.ftms.target_resistance_level(level, self.max_level)?;
+        self.ftms.write(&target_resistance_level(level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.ftms.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.ftms.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Rowers do not support simulation parameters".to_string(),
+        ))
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.ftms.stop().await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.ftms.pause().await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.ftms.reset().await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Rowers do not support spin down calibration".to_string(),
+        ))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        self.ftms.machine_status().await
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.ftms.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ftms.ranges
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.ftms.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.ftms.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.ftms.events()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.ftms.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Ok(self.read_rower().await?.map(MachineData::from))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        self.ftms.data_stream().await
+    }
+}
+
+impl BluetoothEquipment for GenericFtmsRower {
+    fn peripheral(&self) -> &Peripheral {
+        self.ftms.peripheral()
+    }
+}
+
+impl Rower for GenericFtmsRower {
+    async fn read_rower(&self) -> Result<Option<RowerData>> {
+        let data = self.ftms.notification().await?;
+        Ok(parse_rower_data(&data).ok())
+    }
+}
+
+fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_rower_data(data)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        // stroke rate and count, distance, pace and power
+        let data = decode(&[
+            0x2C, 0x00, 0x30, 0x2A, 0x00, 0x20, 0x03, 0x00, 0x78, 0x00, 0xB4, 0x00,
+        ])?;
+        let MachineData::Rower(rower) = data else {
+            panic!("{data:?} isn't rower data");
+        };
+        assert_eq!(rower.stroke_count, Some(42));
+        assert!(decode(&[0x2C]).is_err());
+        Ok(())
+    }
+}
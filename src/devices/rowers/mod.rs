@@ -0,0 +1,3 @@
+pub mod concept2_pm5;
+pub mod csafe;
+pub mod generic_ftms;
@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::devices::ant_stick::{self, AntStick, Channel, HEART_RATE};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal,
+};
+use crate::sensors::HeartRateData;
+use crate::{ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream};
+use crate::{KondisError, Result};
+
+/// The page holding the time of the beat before the last one, besides what every page holds
+const PREVIOUS_HEART_BEAT: u8 = 0x04;
+
+/// A heart rate monitor broadcasting ANT+, like the older Garmin chest straps, through an ANT USB stick.
+/// The stick to listen with is set with `ScanConfig::port`, like `/dev/ttyUSB0`, and the first monitor
+/// it hears gets paired with. The stick can be shared with other ANT+ devices, like an
+/// `AntFecBike` or an `AntPowerMeter`.
+///
+/// Readings hold `MachineData::HeartRate`, with the RR intervals of the beats the stick heard. There is
+/// nothing to control on a heart rate monitor, so every target and session command is unsupported.
+/// Available with the `ant` feature.
+#[derive(Debug, Clone)]
+pub struct AntHeartRateMonitor {
+    /// "ANT+ HRM" and the device number of the monitor once connected, the path of the stick until then
+    pub name: String,
+    path: PathBuf,
+    channel: Arc<Mutex<Option<Channel>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops forwarding the pages of the monitor, replaced with every connection
+    forwarding: CancellationToken,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for AntHeartRateMonitor {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some(path) = config.port.clone() else {
+            return Err(KondisError::InvalidArgument(
+                "ANT+ monitors need an ANT stick to listen with, see `ScanConfig::port`"
+                    .to_string(),
+            ));
+        };
+        Ok(AntHeartRateMonitor {
+            name: path.display().to_string(),
+            path,
+            channel: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            forwarding: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let stick = AntStick::shared(&self.path).await?;
+                let channel = stick.open_channel(HEART_RATE).await?;
+                let mut messages = channel.subscribe();
+                let first = channel.pair(&mut messages, self.timeouts.connect).await?;
+                self.name = format!("ANT+ HRM {}", channel.device_number().await?);
+                *self.channel.lock().unwrap() = Some(channel.clone());
+                self.forwarding.cancel();
+                self.forwarding = self.shutdown.child_token();
+                let mut decoder = HeartRateDecoder::default();
+                ant_stick::forward(
+                    channel,
+                    messages,
+                    first,
+                    move |page| Ok(decoder.update(page)?.into()),
+                    self.events_tx.clone(),
+                    self.forwarding.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to heart rate monitor");
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.forwarding.cancel();
+        let channel = self.channel.lock().unwrap().take();
+        if let Some(channel) = channel {
+            channel.close().await?;
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                heart_rate: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+/// Turns the pages of an ANT+ heart rate monitor into `HeartRateData`
+///
+/// Every page ends in the time of the last beat, in 1/1024 s, a count of the beats and the heart rate.
+/// The RR interval of a beat is only known when the beat before it was heard too, or when the page
+/// holds the time of the beat before it.
+#[derive(Debug, Default)]
+struct HeartRateDecoder {
+    /// The count and time of the last beat heard
+    last: Option<(u8, u16)>,
+}
+
+impl HeartRateDecoder {
+    fn update(&mut self, page: &[u8]) -> Result<HeartRateData> {
+        let &[
+            number,
+            _,
+            previous_lsb,
+            previous_msb,
+            time_lsb,
+            time_msb,
+            count,
+            heart_rate,
+        ] = page
+        else {
+            return Err(KondisError::InvalidData(format!(
+                "ANT+ pages hold 8 bytes, not {}",
+                page.len()
+            )));
+        };
+        let time = u16::from_le_bytes([time_lsb, time_msb]);
+        // pages keep repeating the last beat until the next one
+        let beat = self.last.is_none_or(|(last, _)| last != count);
+        let previous = match self.last {
+            _ if !beat => None,
+            // the highest bit of the page number toggles every 4 pages
+            _ if number & 0x7F == PREVIOUS_HEART_BEAT => {
+                Some(u16::from_le_bytes([previous_lsb, previous_msb]))
+            }
+            Some((last, last_time)) if count == last.wrapping_add(1) => Some(last_time),
+            _ => None,
+        };
+        let rr_intervals = previous
+            .map(|previous| Duration::from_secs_f64(f64::from(time.wrapping_sub(previous)) / 1024.))
+            .into_iter()
+            .collect();
+        self.last = Some((count, time));
+        Ok(HeartRateData {
+            heart_rate: heart_rate.into(),
+            rr_intervals,
+            ..Default::default()
+        })
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Heart rate monitors do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() -> Result<()> {
+        let mut decoder = HeartRateDecoder::default();
+        // a beat at 1 s, the one before it unheard
+        let first = decoder.update(&[0x00, 0xFF, 0xFF, 0xFF, 0x00, 0x04, 0x05, 0x48])?;
+        assert_eq!(first.heart_rate, 72.);
+        assert!(first.rr_intervals.is_empty());
+        // the same beat again
+        let again = decoder.update(&[0x80, 0xFF, 0xFF, 0xFF, 0x00, 0x04, 0x05, 0x48])?;
+        assert!(again.rr_intervals.is_empty());
+        // the next beat, 0.75 s later
+        let next = decoder.update(&[0x80, 0xFF, 0xFF, 0xFF, 0x00, 0x07, 0x06, 0x50])?;
+        assert_eq!(next.rr_intervals, [Duration::from_millis(750)]);
+        // two beats later, the page holding the time of the one before it
+        let skipped = decoder.update(&[0x84, 0xFF, 0x00, 0x0A, 0x00, 0x0C, 0x08, 0x50])?;
+        assert_eq!(skipped.rr_intervals, [Duration::from_millis(500)]);
+        assert!(decoder.update(&[0x04, 0x00]).is_err());
+        Ok(())
+    }
+}
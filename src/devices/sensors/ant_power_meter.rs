@@ -0,0 +1,292 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::devices::ant_stick::{self, AntStick, BICYCLE_POWER, Channel};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    BikeData, Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus,
+    TrainingGoal,
+};
+use crate::{ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream};
+use crate::{KondisError, Result};
+
+/// The page holding the power and cadence, which every power meter broadcasts
+const STANDARD_POWER_ONLY: u8 = 0x10;
+/// The cadence of power meters that don't measure it
+const NO_CADENCE: u8 = 0xFF;
+
+/// A bicycle power meter broadcasting ANT+, like pedals, cranks and hubs, through an ANT USB stick.
+/// The stick to listen with is set with `ScanConfig::port`, like `/dev/ttyUSB0`, and the first power
+/// meter it hears gets paired with. The stick can be shared with other ANT+ devices, like an
+/// `AntFecBike` or an `AntPowerMeter`.
+///
+/// Readings hold `MachineData::Bike`, with the power and, for power meters measuring it, the cadence.
+/// Calibration and the other commands of power meters are unsupported, like every target and session
+/// command. Available with the `ant` feature.
+#[derive(Debug, Clone)]
+pub struct AntPowerMeter {
+    /// "ANT+ power meter" and the device number of the power meter once connected, the path of the stick until then
+    pub name: String,
+    path: PathBuf,
+    channel: Arc<Mutex<Option<Channel>>>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    /// Stops forwarding the pages of the power meter, replaced with every connection
+    forwarding: CancellationToken,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for AntPowerMeter {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let Some(path) = config.port.clone() else {
+            return Err(KondisError::InvalidArgument(
+                "ANT+ power meters need an ANT stick to listen with, see `ScanConfig::port`"
+                    .to_string(),
+            ));
+        };
+        Ok(AntPowerMeter {
+            name: path.display().to_string(),
+            path,
+            channel: Arc::new(Mutex::new(None)),
+            events_tx: events::channel(),
+            timeouts: Timeouts::new(&config),
+            forwarding: shutdown.child_token(),
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                let stick = AntStick::shared(&self.path).await?;
+                let channel = stick.open_channel(BICYCLE_POWER).await?;
+                let mut messages = channel.subscribe();
+                let first = channel.pair(&mut messages, self.timeouts.connect).await?;
+                self.name = format!("ANT+ power meter {}", channel.device_number().await?);
+                *self.channel.lock().unwrap() = Some(channel.clone());
+                self.forwarding.cancel();
+                self.forwarding = self.shutdown.child_token();
+                let mut decoder = PowerDecoder::default();
+                ant_stick::forward(
+                    channel,
+                    messages,
+                    first,
+                    move |page| Ok(decoder.update(page)?.into()),
+                    self.events_tx.clone(),
+                    self.forwarding.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                info!(device = %self.name, "Found and connected to power meter");
+                Ok(true)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.forwarding.cancel();
+        let channel = self.channel.lock().unwrap().take();
+        if let Some(channel) = channel {
+            channel.close().await?;
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                power: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+/// Turns the pages of an ANT+ power meter into `BikeData`
+///
+/// The power is averaged over the power events since the last page the stick heard, from the
+/// accumulated power, so pages missed in between are still accounted for.
+#[derive(Debug, Default)]
+struct PowerDecoder {
+    /// The event count and accumulated power, in W, of the last standard power page
+    last: Option<(u8, u16)>,
+    data: BikeData,
+}
+
+impl PowerDecoder {
+    /// Take in a data page, resolving to the power and cadence of the last standard power page
+    ///
+    /// Pages holding anything else, like the torque of the cranks, leave the data as is.
+    fn update(&mut self, page: &[u8]) -> Result<BikeData> {
+        let &[
+            number,
+            count,
+            _,
+            cadence,
+            accumulated_lsb,
+            accumulated_msb,
+            power_lsb,
+            power_msb,
+        ] = page
+        else {
+            return Err(KondisError::InvalidData(format!(
+                "ANT+ pages hold 8 bytes, not {}",
+                page.len()
+            )));
+        };
+        if number != STANDARD_POWER_ONLY {
+            return Ok(self.data.clone());
+        }
+        let accumulated = u16::from_le_bytes([accumulated_lsb, accumulated_msb]);
+        let power = match self.last {
+            Some((last, _)) if last == count => self.data.power,
+            Some((last, last_accumulated)) => {
+                let events = count.wrapping_sub(last);
+                Some((accumulated.wrapping_sub(last_accumulated) / u16::from(events)) as i16)
+            }
+            None => Some(u16::from_le_bytes([power_lsb, power_msb]) as i16),
+        };
+        self.last = Some((count, accumulated));
+        self.data.power = power;
+        self.data.cadence = (cadence != NO_CADENCE).then_some(cadence.into());
+        Ok(self.data.clone())
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Power meters do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() -> Result<()> {
+        let mut decoder = PowerDecoder::default();
+        // 200 W at 90 rpm
+        let first = decoder.update(&[0x10, 0x01, 0xFF, 0x5A, 0xC8, 0x00, 0xC8, 0x00])?;
+        assert_eq!((first.power, first.cadence), (Some(200), Some(90.)));
+        // two events later, the one in between missed, 250 W on average
+        let missed = decoder.update(&[0x10, 0x03, 0xFF, 0x5A, 0xBC, 0x02, 0x2C, 0x01])?;
+        assert_eq!(missed.power, Some(250));
+        // the same event again
+        let again = decoder.update(&[0x10, 0x03, 0xFF, 0x5A, 0xBC, 0x02, 0x2C, 0x01])?;
+        assert_eq!(again.power, Some(250));
+        // the count and accumulated power wrapping around, on a power meter not measuring cadence
+        decoder.last = Some((0xFF, 0xFFCE));
+        let wrapped = decoder.update(&[0x10, 0x00, 0xFF, 0xFF, 0x64, 0x00, 0x96, 0x00])?;
+        assert_eq!((wrapped.power, wrapped.cadence), (Some(150), None));
+        // the torque of the cranks
+        let torque = decoder.update(&[0x12, 0x01, 0x02, 0x5A, 0x00, 0x01, 0x00, 0x02])?;
+        assert_eq!(torque, wrapped);
+        Ok(())
+    }
+}
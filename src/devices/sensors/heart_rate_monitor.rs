@@ -0,0 +1,249 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, find_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal,
+};
+use crate::sensors::{
+    HEART_RATE_MEASUREMENT_UUID, HEART_RATE_SERVICE_UUID, parse_heart_rate_measurement,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// Any standards-compliant heart rate monitor, like chest straps and watches broadcasting heart rate.
+/// The first device advertising the Heart Rate Service (0x180D) gets connected to.
+///
+/// Readings hold `MachineData::HeartRate`, with the RR intervals of every beat when the monitor measures
+/// them. There is nothing to control on a heart rate monitor, so every target and session command is
+/// unsupported.
+#[derive(Debug, Clone)]
+pub struct HeartRateMonitor {
+    peripheral: Peripheral,
+    /// The name of the monitor, or its address if it doesn't advertise a name
+    pub name: String,
+    measurement: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl Equipment for HeartRateMonitor {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let capture = config.capture.clone();
+        let watchdog = Watchdog::new(&config);
+        let timeouts = Timeouts::new(&config);
+        let battery = Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY));
+        // monitors are told apart by the service they advertise, unless told otherwise
+        let config = match config.service_uuid {
+            Some(_) => config,
+            None => config.service_uuid(HEART_RATE_SERVICE_UUID),
+        };
+        let Some((peripheral, name)) = find_peripheral(&config, shutdown).await? else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(HeartRateMonitor {
+            peripheral,
+            name,
+            measurement: None,
+            events_tx: events::channel(),
+            capture,
+            watchdog,
+            timeouts,
+            battery,
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.measurement = Some(
+                    bluetooth::subscribe(&self.peripheral, HEART_RATE_MEASUREMENT_UUID).await?,
+                );
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                events::forward_notifications(
+                    &self.peripheral,
+                    HEART_RATE_MEASUREMENT_UUID,
+                    decode,
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(measurement) = &self.measurement {
+            self.peripheral.unsubscribe(measurement).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                heart_rate: true,
+                expended_energy: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                let mut notifications =
+                    bluetooth::notifications(&self.peripheral, HEART_RATE_MEASUREMENT_UUID).await?;
+                // measurements that can't be decoded (e.g. cut short) are skipped
+                Ok(notifications
+                    .next()
+                    .await
+                    .and_then(|data| decode(&data).ok()))
+            }),
+        )
+        .await
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        let notifications = self.peripheral.notifications().await?;
+        Ok(events::readings(
+            notifications.take_until(self.shutdown.clone().cancelled_owned()),
+            HEART_RATE_MEASUREMENT_UUID,
+            decode,
+        ))
+    }
+}
+
+impl BluetoothEquipment for HeartRateMonitor {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_heart_rate_measurement(data)?.into())
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Heart rate monitors do not support {what}"))
+}
@@ -0,0 +1,18 @@
+#[cfg(feature = "ant")]
+pub mod ant_heart_rate_monitor;
+#[cfg(feature = "ant")]
+pub mod ant_power_meter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod heart_rate_monitor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod speed_cadence_sensor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sterzo;
+
+/// Where the handlebars of the rider point, from a steering plate
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SteeringEvent {
+    /// degrees off straight ahead, negative when steering left
+    pub angle: f32,
+}
@@ -0,0 +1,275 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{
+    self, DeviceInfo, ScanConfig, Timeouts, find_peripheral, read_device_info, within,
+};
+use crate::capture::CaptureWriter;
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender, Forwarding, Watchdog};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    Capabilities, DataCapabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal,
+};
+use crate::sensors::{
+    CSC_MEASUREMENT_UUID, CYCLING_SPEED_AND_CADENCE_SERVICE_UUID, CscCalculator,
+    DEFAULT_WHEEL_CIRCUMFERENCE, parse_csc_measurement,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream,
+};
+use crate::{KondisError, Result};
+
+/// Any standards-compliant speed, cadence, or combined speed and cadence sensor.
+/// The first device advertising the Cycling Speed and Cadence Service (0x1816) gets connected to.
+///
+/// Sensors only count revolutions, so readings hold `MachineData::SpeedCadence` worked out from every
+/// two measurements in a row: the speed going by the wheel circumference, see `wheel_circumference`,
+/// and the cadence of the crank. Either drops to 0 once the sensor stops counting new revolutions for a
+/// few seconds. There is nothing to control on a sensor, so every target and session command is
+/// unsupported.
+#[derive(Debug, Clone)]
+pub struct SpeedCadenceSensor {
+    peripheral: Peripheral,
+    /// The name of the sensor, or its address if it doesn't advertise a name
+    pub name: String,
+    /// m
+    wheel_circumference: f64,
+    measurement: Option<Characteristic>,
+    events_tx: EventSender,
+    /// Where to capture notifications to, see `ScanConfig::capture`
+    capture: Option<PathBuf>,
+    watchdog: Watchdog,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl SpeedCadenceSensor {
+    /// The circumference of the wheel in meters, 2.105 m of a 700x25c road tyre by default, taking effect
+    /// when connecting
+    pub fn wheel_circumference(mut self, meters: f64) -> Self {
+        self.wheel_circumference = meters;
+        self
+    }
+}
+
+impl Equipment for SpeedCadenceSensor {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let capture = config.capture.clone();
+        let watchdog = Watchdog::new(&config);
+        let timeouts = Timeouts::new(&config);
+        let battery = Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY));
+        // sensors are told apart by the service they advertise, unless told otherwise
+        let config = match config.service_uuid {
+            Some(_) => config,
+            None => config.service_uuid(CYCLING_SPEED_AND_CADENCE_SERVICE_UUID),
+        };
+        let Some((peripheral, name)) = find_peripheral(&config, shutdown).await? else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(SpeedCadenceSensor {
+            peripheral,
+            name,
+            wheel_circumference: DEFAULT_WHEEL_CIRCUMFERENCE,
+            measurement: None,
+            events_tx: events::channel(),
+            capture,
+            watchdog,
+            timeouts,
+            battery,
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.measurement =
+                    Some(bluetooth::subscribe(&self.peripheral, CSC_MEASUREMENT_UUID).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                let capture = self
+                    .capture
+                    .as_deref()
+                    .map(CaptureWriter::create)
+                    .transpose()?;
+                let mut calculator = CscCalculator::new(self.wheel_circumference);
+                let start = Instant::now();
+                events::forward_notifications(
+                    &self.peripheral,
+                    CSC_MEASUREMENT_UUID,
+                    move |data| {
+                        let measurement = parse_csc_measurement(data)?;
+                        Ok(calculator.update(&measurement, start.elapsed()).into())
+                    },
+                    self.events_tx.clone(),
+                    Forwarding {
+                        capture,
+                        battery: self.battery.clone(),
+                        watchdog: self.watchdog,
+                    },
+                    self.shutdown.clone(),
+                )
+                .await?;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(measurement) = &self.measurement {
+            self.peripheral.unsubscribe(measurement).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities {
+            data: DataCapabilities {
+                cadence: true,
+                distance: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected, worked out like those of `events`
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl BluetoothEquipment for SpeedCadenceSensor {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Speed and cadence sensors do not support {what}"))
+}
@@ -0,0 +1,240 @@
+use std::sync::mpsc::Sender;
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _},
+    platform::Peripheral,
+};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use uuid::{Uuid, uuid};
+
+use super::SteeringEvent;
+use crate::bluetooth::{self, DeviceInfo, ScanConfig, Timeouts, find_peripheral, read_device_info};
+use crate::devices::battery::{Battery, DEFAULT_LOW_BATTERY};
+use crate::devices::events::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{Capabilities, MachineData, SpinDownResult, SpinDownStatus, TrainingGoal};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream,
+    SteeringStream,
+};
+use crate::{KondisError, Result};
+
+/// The proprietary steering service of Elite
+const STERZO_SERVICE_UUID: Uuid = uuid!("347b0001-7635-408b-8918-8ff3949ce592");
+/// Notified with the steering angle, as a little-endian float of degrees
+const STEERING_ANGLE_UUID: Uuid = uuid!("347b0030-7635-408b-8918-8ff3949ce592");
+
+/// An Elite Sterzo steering plate, which the front wheel of the bike rests on.
+/// The first device advertising the steering service of Elite gets connected to.
+///
+/// The plate measures nothing of a machine, so every reading, target and session command is
+/// unsupported: the angles it measures come through `steering`. Newer firmware asks for a challenge to
+/// be answered before notifying any angle, which isn't supported.
+#[derive(Debug, Clone)]
+pub struct SterzoSteering {
+    peripheral: Peripheral,
+    /// The name of the plate, or its address if it doesn't advertise a name
+    pub name: String,
+    steering: Option<Characteristic>,
+    events_tx: EventSender,
+    timeouts: Timeouts,
+    battery: Battery,
+    device_info: Option<DeviceInfo>,
+    shutdown: CancellationToken,
+}
+
+impl SterzoSteering {
+    /// Every steering angle measured from now on, until shut down
+    pub async fn steering(&self) -> Result<SteeringStream> {
+        if self.steering.is_none() {
+            return Err(KondisError::Disconnected(
+                "Steering before connecting".to_string(),
+            ));
+        }
+        let notifications = self.peripheral.notifications().await?;
+        Ok(Box::pin(
+            notifications
+                .take_until(self.shutdown.clone().cancelled_owned())
+                .filter_map(|data| async move {
+                    if data.uuid != STEERING_ANGLE_UUID {
+                        return None;
+                    }
+                    decode(&data.value).ok()
+                }),
+        ))
+    }
+}
+
+impl Equipment for SterzoSteering {
+    async fn with_config(_: i16, config: ScanConfig, shutdown: &CancellationToken) -> Result<Self> {
+        let timeouts = Timeouts::new(&config);
+        let battery = Battery::new(config.low_battery.unwrap_or(DEFAULT_LOW_BATTERY));
+        // plates are told apart by the service they advertise, unless told otherwise
+        let config = match config.service_uuid {
+            Some(_) => config,
+            None => config.service_uuid(STERZO_SERVICE_UUID),
+        };
+        let Some((peripheral, name)) = find_peripheral(&config, shutdown).await? else {
+            return Err(KondisError::DeviceNotFound);
+        };
+        Ok(SterzoSteering {
+            peripheral,
+            name,
+            steering: None,
+            events_tx: events::channel(),
+            timeouts,
+            battery,
+            device_info: None,
+            shutdown: shutdown.clone(),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        events_tx
+            .connecting(until_shutdown(&shutdown, async {
+                bluetooth::connect_within(&self.peripheral, self.timeouts).await?;
+                self.steering =
+                    Some(bluetooth::subscribe(&self.peripheral, STEERING_ANGLE_UUID).await?);
+                self.battery
+                    .connect(&self.peripheral, &self.events_tx)
+                    .await;
+                self.device_info = read_device_info(&self.peripheral).await;
+                events::monitor_rssi(
+                    self.peripheral.clone(),
+                    self.events_tx.clone(),
+                    self.shutdown.clone(),
+                );
+                self.events_tx.send(DeviceEvent::Connected);
+                Ok(self.peripheral.is_connected().await?)
+            }))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(steering) = &self.steering {
+            self.peripheral.unsubscribe(steering).await?;
+        }
+        self.peripheral.disconnect().await?;
+        self.events_tx.send(DeviceEvent::Disconnected);
+        Ok(())
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target cadence"))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target power"))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(unsupported("a target resistance level"))
+    }
+
+    async fn set_target_heart_rate(&self, _: u8) -> Result<()> {
+        Err(unsupported("a target heart rate"))
+    }
+
+    async fn set_goal(&self, _: TrainingGoal) -> Result<()> {
+        Err(unsupported("training goals"))
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(unsupported("simulation parameters"))
+    }
+
+    async fn start(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn stop(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn pause(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn resume(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn reset(&self) -> Result<()> {
+        Err(unsupported("session control"))
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(unsupported("spin down calibration"))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        Err(unsupported("machine status"))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        Some(Capabilities::default())
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Err(unsupported("machine data, only steering"))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        Err(unsupported("machine data, only steering"))
+    }
+}
+
+impl BluetoothEquipment for SterzoSteering {
+    fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+}
+
+fn decode(data: &[u8]) -> Result<SteeringEvent> {
+    let Some(&angle) = data.first_chunk::<4>() else {
+        return Err(KondisError::InvalidData(format!(
+            "Steering angle too short: {} bytes",
+            data.len()
+        )));
+    };
+    Ok(SteeringEvent {
+        angle: f32::from_le_bytes(angle),
+    })
+}
+
+fn unsupported(what: &str) -> KondisError {
+    KondisError::Unsupported(format!("Steering plates do not support {what}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        assert_eq!(decode(&(-12.5f32).to_le_bytes())?.angle, -12.5);
+        assert_eq!(decode(&[0, 0, 0, 0])?.angle, 0.);
+        assert!(decode(&[0, 0]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort as _, SerialPortBuilderExt,
+    SerialStream, StopBits,
+};
+
+use crate::{KondisError, Result};
+
+/// The speeds serial ports of fitness equipment talk at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Baud {
+    /// CSAFE, Kettler
+    B9600,
+    /// ANT USB sticks
+    #[cfg(feature = "ant")]
+    B115200,
+}
+
+impl Baud {
+    fn rate(self) -> u32 {
+        match self {
+            Baud::B9600 => 9600,
+            #[cfg(feature = "ant")]
+            Baud::B115200 => 115_200,
+        }
+    }
+}
+
+/// Open the serial port at `path`, set to `baud`, 8N1 without flow control, passing every byte through
+/// untouched
+pub(crate) fn open(path: &Path, baud: Baud) -> Result<SerialStream> {
+    let port = tokio_serial::new(path.to_string_lossy(), baud.rate())
+        .data_bits(DataBits::Eight)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .flow_control(FlowControl::None)
+        .open_native_async()
+        .map_err(std::io::Error::from)?;
+    Ok(port)
+}
+
+/// A serial port, answering every message written to it
+#[derive(Debug)]
+pub(crate) struct SerialPort {
+    port: SerialStream,
+    /// Bytes received ahead of the message being waited for
+    received: Vec<u8>,
+}
+
+impl SerialPort {
+    pub fn open(path: &Path, baud: Baud) -> Result<Self> {
+        Ok(SerialPort {
+            port: open(path, baud)?,
+            received: Vec::new(),
+        })
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.port.write_all(data).await?;
+        Ok(self.port.flush().await?)
+    }
+
+    /// Drop every byte received but not read yet, like the late answer to a message given up on
+    pub fn discard_input(&mut self) -> Result<()> {
+        self.received.clear();
+        self.port
+            .clear(ClearBuffer::Input)
+            .map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    /// Read until `take` pulls a whole message out of the bytes received so far
+    ///
+    /// Nothing is lost when the read gets cancelled, the bytes received so far are kept for the next.
+    pub async fn read(&mut self, take: fn(&mut Vec<u8>) -> Option<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut buffer = [0; 128];
+        loop {
+            if let Some(message) = take(&mut self.received) {
+                return Ok(message);
+            }
+            let read = self.port.read(&mut buffer).await?;
+            if read == 0 {
+                return Err(KondisError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.received.extend_from_slice(&buffer[..read]);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{KondisError, Result};
+
+/// Run `future` to completion, unless `shutdown` gets cancelled first
+pub(crate) async fn until_shutdown<T>(
+    shutdown: &CancellationToken,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        _ = shutdown.cancelled() => Err(KondisError::Shutdown),
+        result = future => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_until_shutdown() {
+        let shutdown = CancellationToken::new();
+        assert_eq!(until_shutdown(&shutdown, async { Ok(1) }).await.unwrap(), 1);
+        shutdown.cancel();
+        let pending = until_shutdown(&shutdown, futures::future::pending::<Result<()>>());
+        assert!(pending.await.is_err());
+    }
+}
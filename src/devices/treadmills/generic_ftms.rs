@@ -0,0 +1,224 @@
+use btleplug::platform::Peripheral;
+use std::sync::mpsc::Sender;
+
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::bluetooth::{DeviceInfo, ScanConfig};
+use crate::devices::ftms_peripheral::FtmsPeripheral;
+use crate::ftms::{
+    Capabilities, FTMSControlOpCode, MachineData, SpinDownResult, SpinDownStatus, SupportedRanges,
+    TREADMILL_DATA_UUID, TrainingGoal, TreadmillData, parse_treadmill_data, target_heart_rate,
+    training_goal,
+};
+use crate::{
+    BluetoothEquipment, ConnectionState, DataStream, DeviceEvent, Equipment, EquipmentType,
+    MachineStatusStream, Treadmill,
+};
+use crate::{KondisError, Result};
+
+/// Any standards-compliant FTMS treadmill.
+/// The first device advertising the Fitness Machine Service (0x1826) as a treadmill gets connected to.
+///
+/// `max_level` is the highest speed in km/h the treadmill may be set to.
+#[derive(Debug, Clone)]
+pub struct GenericFtmsTreadmill {
+    ftms: FtmsPeripheral,
+    /// The name of the device, or its address if it doesn't advertise a name
+    pub name: String,
+    max_level: i16,
+}
+
+impl Equipment for GenericFtmsTreadmill {
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let ftms = FtmsPeripheral::find(
+            EquipmentType::GenericFtmsTreadmill,
+            TREADMILL_DATA_UUID,
+            decode,
+            &config,
+            shutdown,
+        )
+        .await?;
+        Ok(GenericFtmsTreadmill {
+            name: ftms.name.clone(),
+            ftms,
+            max_level,
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let connected = self.ftms.connect().await?;
+        info!(device = %self.name, "Found and connected to treadmill");
+        Ok(connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.ftms.disconnect().await
+    }
+
+    async fn set_target_cadence(&self, _: i16) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Treadmills do not support a target cadence".to_string(),
+        ))
+    }
+
+    async fn set_target_power(&self, _: i16) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Treadmills do not support a target power".to_string(),
+        ))
+    }
+
+    async fn set_target_resistance_level(&self, _: i16) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Treadmills do not support a target resistance level".to_string(),
+        ))
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.ftms.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.ftms.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(&self, _: f32, _: f32, _: f32, _: f32) -> Result<()> {
+        Err(KondisError::Unsupported(
+            "Treadmills do not support simulation parameters".to_string(),
+        ))
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.ftms.stop().await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.ftms.pause().await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.ftms.start().await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.ftms.reset().await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Treadmills do not support spin down calibration".to_string(),
+        ))
+    }
+
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        self.ftms.machine_status().await
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.ftms.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ftms.ranges
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        self.ftms.battery.level()
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.ftms.device_info.clone()
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.ftms.events()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.ftms.state()
+    }
+
+    async fn read(&self) -> Result<Option<MachineData>> {
+        Ok(self.read_treadmill().await?.map(MachineData::from))
+    }
+
+    async fn data_stream(&self) -> Result<DataStream> {
+        self.ftms.data_stream().await
+    }
+}
+
+impl BluetoothEquipment for GenericFtmsTreadmill {
+    fn peripheral(&self) -> &Peripheral {
+        self.ftms.peripheral()
+    }
+}
+
+impl Treadmill for GenericFtmsTreadmill {
+    async fn set_target_speed(&self, kmh: f32) -> Result<()> {
+        if !(0.0..=self.max_level as f32).contains(&kmh) {
+            return Err(KondisError::InvalidArgument(format!(
+                "Speed must be between 0 and {} km/h",
+                self.max_level
+            )));
+        }
+        // targeted speed has a resolution of 0.01 km/h
+        let value = ((kmh * 100.).round() as u16).to_le_bytes();
+        self.ftms
+            .write(&[FTMSControlOpCode::TargetSpeed as u8, value[0], value[1]])
+            .await
+    }
+
+    async fn set_target_inclination(&self, percent: f32) -> Result<()> {
+        if !(-100.0..=100.0).contains(&percent) {
+            return Err(KondisError::InvalidArgument(
+                "Inclination must be between -100 and 100 %".to_string(),
+            ));
+        }
+        // targeted inclination has a resolution of 0.1 %
+        let value = ((percent * 10.).round() as i16).to_le_bytes();
+        self.ftms
+            .write(&[
+                FTMSControlOpCode::TargetInclination as u8,
+                value[0],
+                value[1],
+            ])
+            .await
+    }
+
+    async fn read_treadmill(&self) -> Result<Option<TreadmillData>> {
+        let data = self.ftms.notification().await?;
+        Ok(parse_treadmill_data(&data).ok())
+    }
+}
+
+fn decode(data: &[u8]) -> Result<MachineData> {
+    Ok(parse_treadmill_data(data)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        // speed, distance and inclination
+        let data = decode(&[
+            0x0C, 0x00, 0x58, 0x02, 0xF4, 0x01, 0x00, 0x19, 0x00, 0x0E, 0x00,
+        ])?;
+        let MachineData::Treadmill(treadmill) = data else {
+            panic!("{data:?} isn't treadmill data");
+        };
+        assert_eq!(treadmill.inclination, Some(2.5));
+        assert!(decode(&[0x2C]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,547 @@
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use futures::StreamExt;
+use js_sys::{DataView, JsString, Uint8Array};
+use send_wrapper::SendWrapper;
+use tokio::sync::{Mutex, broadcast, broadcast::error::RecvError};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use wasm_bindgen::{JsCast, JsValue, closure::Closure};
+use web_sys::{
+    BluetoothDevice, BluetoothLeScanFilterInit, BluetoothRemoteGattCharacteristic,
+    BluetoothRemoteGattService, DomException, RequestDeviceOptions,
+};
+
+use crate::bluetooth::{ScanConfig, Timeouts, within};
+use crate::devices::event_sender::{self, EventSender};
+use crate::devices::shutdown::until_shutdown;
+use crate::ftms::{
+    CONTROL_POINT_UUID, Capabilities, FITNESS_MACHINE_FEATURE_UUID, FITNESS_MACHINE_SERVICE_UUID,
+    FITNESS_MACHINE_STATUS_UUID, FTMSControlOpCode, INDOOR_BIKE_DATA_UUID, MAX_CADENCE,
+    MachineData, SUPPORTED_POWER_RANGE_UUID, SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID, SpinDownResult,
+    SpinDownStatus, StopCode, SupportedRanges, TrainingGoal, parse_control_point_response,
+    parse_fitness_machine_feature, parse_indoor_bike_data, parse_machine_status,
+    parse_supported_power_range, parse_supported_resistance_level_range, simulation_parameters,
+    target_cadence, target_heart_rate, target_power, target_resistance_level, training_goal,
+};
+use crate::{
+    ConnectionState, DataStream, DeviceEvent, Equipment, KondisError, MachineStatusStream, Reading,
+    Result,
+};
+
+/// How long the bike gets to respond to a control point command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Any standards-compliant FTMS smart trainer or bike, picked by the user in the browser
+///
+/// `connect` asks the browser for a device advertising the Fitness Machine Service (0x1826), which
+/// shows the user a chooser, and therefore only works while handling a user gesture like a click.
+/// `ScanConfig::name_pattern` narrows the chooser down to names starting with it. Everything of the
+/// browser runs on the single thread of the page, which the bike never leaves.
+pub struct WebFtmsBike {
+    /// The name the device advertises, once connected
+    pub name: String,
+    max_level: i16,
+    name_prefix: Option<String>,
+    timeouts: Timeouts,
+    shutdown: CancellationToken,
+    gatt: Option<SendWrapper<Gatt>>,
+    capabilities: Option<Capabilities>,
+    ranges: Option<SupportedRanges>,
+    events_tx: EventSender,
+    /// Every control point indication, for the command waiting on its response
+    indications: broadcast::Sender<Vec<u8>>,
+    /// One command at a time, since responses don't tell which command they answer but by op code
+    commands: Mutex<()>,
+}
+
+/// The device picked in the browser and what's listening to it, stopping to listen once dropped
+struct Gatt {
+    device: BluetoothDevice,
+    control: BluetoothRemoteGattCharacteristic,
+    listeners: Vec<(BluetoothRemoteGattCharacteristic, Closure<dyn FnMut()>)>,
+    on_disconnected: Closure<dyn FnMut()>,
+}
+
+impl Drop for Gatt {
+    fn drop(&mut self) {
+        for (characteristic, _) in &self.listeners {
+            characteristic.set_oncharacteristicvaluechanged(None);
+        }
+        self.device.set_ongattserverdisconnected(None);
+    }
+}
+
+impl fmt::Debug for WebFtmsBike {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebFtmsBike")
+            .field("name", &self.name)
+            .field("max_level", &self.max_level)
+            .field("connected", &self.gatt.is_some())
+            .field("capabilities", &self.capabilities)
+            .field("ranges", &self.ranges)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Equipment for WebFtmsBike {
+    /// Nothing gets asked of the browser until `connect`
+    async fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        Ok(WebFtmsBike {
+            name: String::new(),
+            max_level,
+            name_prefix: config.name_pattern.clone(),
+            timeouts: Timeouts::new(&config),
+            shutdown: shutdown.clone(),
+            gatt: None,
+            capabilities: None,
+            ranges: None,
+            events_tx: event_sender::channel(),
+            indications: broadcast::channel(16).0,
+            commands: Mutex::new(()),
+        })
+    }
+
+    async fn connect(&mut self) -> Result<bool> {
+        let shutdown = self.shutdown.clone();
+        let events_tx = self.events_tx.clone();
+        SendWrapper::new(events_tx.connecting(until_shutdown(&shutdown, self.connect_inner())))
+            .await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        let Some(gatt) = &self.gatt else {
+            return Ok(());
+        };
+        // stop the bike first, even when shutting down, rather than leave it running
+        let result = self
+            .command(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+            .await
+            .map(drop);
+        gatt.device.set_ongattserverdisconnected(None);
+        if let Some(server) = gatt.device.gatt() {
+            server.disconnect();
+        }
+        self.events_tx.send(DeviceEvent::Disconnected);
+        result
+    }
+
+    async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        if !(1..=MAX_CADENCE).contains(&rpm) {
+            return Err(KondisError::InvalidArgument(format!(
+                "RPM must be between 1 and {MAX_CADENCE}"
+            )));
+        }
+        self.write(&target_cadence(rpm)?).await
+    }
+
+    async fn set_target_power(&self, watts: i16) -> Result<()> {
+        let watts = match self.ranges.and_then(|ranges| ranges.power) {
+            Some(range) => range.clamp(watts as f32) as i16,
+            None if (1..=self.max_level).contains(&watts) => watts,
+            None => {
+                return Err(KondisError::InvalidArgument(format!(
+                    "Watts must be between 1 and {}",
+                    self.max_level
+                )));
+            }
+        };
+        self.write(&target_power(watts)).await
+    }
+
+    async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        let level = match self.ranges.and_then(|ranges| ranges.resistance) {
+            Some(range) => range.clamp(level as f32).round() as i16,
+            None if (1..=self.max_level).contains(&level) => level,
+            None => {
+                return Err(KondisError::InvalidArgument(format!(
+                    "Resistance level must be between 1 and {}",
+                    self.max_level
+                )));
+            }
+        };
+        self.write(&target_resistance_level(level)?).await
+    }
+
+    async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.write(&target_heart_rate(bpm)?).await
+    }
+
+    async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.write(&training_goal(goal)?).await
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.write(&simulation_parameters(grade, wind_speed, crr, cw)?)
+            .await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Start as u8]).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Stop as u8])
+            .await
+    }
+
+    async fn pause(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Stop as u8, StopCode::Pause as u8])
+            .await
+    }
+
+    // the FTMS start op code doubles as resume
+    async fn resume(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Start as u8]).await
+    }
+
+    async fn reset(&self) -> Result<()> {
+        self.write(&[FTMSControlOpCode::Reset as u8]).await
+    }
+
+    async fn spin_down(&self, _: &Sender<SpinDownStatus>) -> Result<SpinDownResult> {
+        Err(KondisError::Unsupported(
+            "Spin down calibration isn't available in the browser".to_string(),
+        ))
+    }
+
+    /// Every Fitness Machine Status notification received from now on
+    async fn machine_status(&self) -> Result<MachineStatusStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::MachineStatus(status)) => return Some((status, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.ranges
+    }
+
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events_tx.subscribe()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.events_tx.state()
+    }
+
+    /// The next reading, once connected
+    async fn read(&self) -> Result<Option<MachineData>> {
+        let mut events = self.events_tx.subscribe();
+        let timeout = self.timeouts.notification;
+        until_shutdown(
+            &self.shutdown,
+            within(timeout, KondisError::NotificationTimeout, async {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Ok(Some(reading.data)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return Ok(None),
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Every reading from now on, once connected
+    async fn data_stream(&self) -> Result<DataStream> {
+        let events = self.events_tx.subscribe();
+        Ok(Box::pin(
+            futures::stream::unfold(events, |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(DeviceEvent::Data(reading)) => return Some((reading, events)),
+                        Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    }
+                }
+            })
+            .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+}
+
+impl WebFtmsBike {
+    async fn connect_inner(&mut self) -> Result<bool> {
+        let device = self.request_device().await?;
+        self.name = device.name().unwrap_or_else(|| device.id());
+        let Some(server) = device.gatt() else {
+            return Err(KondisError::Unsupported(format!(
+                "{} has no GATT server",
+                self.name
+            )));
+        };
+        debug!(device = %self.name, "Connecting");
+        within(self.timeouts.connect, KondisError::ConnectTimeout, async {
+            server.connect().await.map_err(js_error)
+        })
+        .await?;
+        let (service, data, control, status) = within(
+            self.timeouts.discovery,
+            KondisError::DiscoveryTimeout,
+            async {
+                let service = server
+                    .get_primary_service_with_str(&FITNESS_MACHINE_SERVICE_UUID.to_string())
+                    .await
+                    .map_err(js_error)?;
+                let data = characteristic(&service, INDOOR_BIKE_DATA_UUID).await?;
+                let control = characteristic(&service, CONTROL_POINT_UUID).await?;
+                let status = characteristic(&service, FITNESS_MACHINE_STATUS_UUID).await?;
+                Ok((service, data, control, status))
+            },
+        )
+        .await?;
+        let Some(data) = data else {
+            return Err(KondisError::CharacteristicMissing(format!(
+                "data ({INDOOR_BIKE_DATA_UUID})"
+            )));
+        };
+        let Some(control) = control else {
+            return Err(KondisError::CharacteristicMissing("control".to_string()));
+        };
+        self.read_features(&service).await?;
+
+        let mut listeners = vec![(data.clone(), self.on_data(&data))];
+        listeners.push((control.clone(), self.on_indication(&control)));
+        // the machine status is optional
+        if let Some(status) = &status {
+            listeners.push((status.clone(), self.on_status(status)));
+        }
+        for (characteristic, listener) in &listeners {
+            characteristic
+                .set_oncharacteristicvaluechanged(Some(listener.as_ref().unchecked_ref()));
+            characteristic
+                .start_notifications()
+                .await
+                .map_err(js_error)?;
+        }
+        let events_tx = self.events_tx.clone();
+        let on_disconnected = Closure::<dyn FnMut()>::new(move || {
+            events_tx.send(DeviceEvent::Disconnected);
+        });
+        device.set_ongattserverdisconnected(Some(on_disconnected.as_ref().unchecked_ref()));
+        self.gatt = Some(SendWrapper::new(Gatt {
+            device,
+            control,
+            listeners,
+            on_disconnected,
+        }));
+
+        self.write(&[FTMSControlOpCode::RequestControl as u8])
+            .await?;
+        info!(device = %self.name, "Found and connected to bike");
+        self.events_tx.send(DeviceEvent::Connected);
+        Ok(server.connected())
+    }
+
+    /// Ask the browser for a device advertising the Fitness Machine Service, which the user picks
+    async fn request_device(&self) -> Result<BluetoothDevice> {
+        let Some(bluetooth) = web_sys::window().and_then(|window| window.navigator().bluetooth())
+        else {
+            return Err(KondisError::BluetoothUnavailable(
+                "The browser doesn't support Web Bluetooth".to_string(),
+            ));
+        };
+        let filter = BluetoothLeScanFilterInit::new();
+        filter.set_services(&[JsString::from(FITNESS_MACHINE_SERVICE_UUID.to_string())]);
+        if let Some(prefix) = &self.name_prefix {
+            filter.set_name_prefix(prefix);
+        }
+        let options = RequestDeviceOptions::new();
+        options.set_filters(&[filter]);
+        bluetooth.request_device(&options).await.map_err(js_error)
+    }
+
+    /// Read the characteristics describing what the bike supports, all of which are optional
+    async fn read_features(&mut self, service: &BluetoothRemoteGattService) -> Result<()> {
+        if let Some(data) = read(service, FITNESS_MACHINE_FEATURE_UUID).await? {
+            self.capabilities = Some(parse_fitness_machine_feature(&data)?);
+        }
+        let mut ranges = SupportedRanges::default();
+        if let Some(data) = read(service, SUPPORTED_POWER_RANGE_UUID).await? {
+            ranges.power = Some(parse_supported_power_range(&data)?);
+        }
+        if let Some(data) = read(service, SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID).await? {
+            ranges.resistance = Some(parse_supported_resistance_level_range(&data)?);
+        }
+        self.ranges = Some(ranges);
+        Ok(())
+    }
+
+    /// Number and decode every notification of the data characteristic, as events
+    fn on_data(&self, data: &BluetoothRemoteGattCharacteristic) -> Closure<dyn FnMut()> {
+        let (data, events_tx) = (data.clone(), self.events_tx.clone());
+        let mut sequence = 0;
+        Closure::new(move || {
+            let Some(value) = data.value() else {
+                return;
+            };
+            let value = bytes(&value);
+            trace!(uuid = %INDOOR_BIKE_DATA_UUID, "Notified {value:02x?}");
+            let event = match parse_indoor_bike_data(&value) {
+                Ok(bike) => DeviceEvent::Data(Reading::new(sequence, bike.into())),
+                Err(e) => DeviceEvent::Error(e.to_string()),
+            };
+            sequence += 1;
+            events_tx.send(event);
+        })
+    }
+
+    /// Decode every notification of the machine status characteristic, as events
+    fn on_status(&self, status: &BluetoothRemoteGattCharacteristic) -> Closure<dyn FnMut()> {
+        let (status, events_tx) = (status.clone(), self.events_tx.clone());
+        Closure::new(move || {
+            let Some(value) = status.value() else {
+                return;
+            };
+            events_tx.send(match parse_machine_status(&bytes(&value)) {
+                Ok(status) => DeviceEvent::MachineStatus(status),
+                Err(e) => DeviceEvent::Error(e.to_string()),
+            });
+        })
+    }
+
+    /// Hand every control point indication to whichever command waits for its response
+    fn on_indication(&self, control: &BluetoothRemoteGattCharacteristic) -> Closure<dyn FnMut()> {
+        let (control, indications) = (control.clone(), self.indications.clone());
+        Closure::new(move || {
+            if let Some(value) = control.value() {
+                let _ = indications.send(bytes(&value));
+            }
+        })
+    }
+
+    async fn write(&self, data: &[u8]) -> Result<()> {
+        self.command(data).await?;
+        Ok(())
+    }
+
+    /// Write a command to the control point and wait for the bike to respond to it
+    ///
+    /// Resolves to the response parameters if the bike accepted the command, and fails with
+    /// `KondisError::ControlRejected` if it refused.
+    async fn command(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let Some(gatt) = &self.gatt else {
+            return Err(KondisError::CharacteristicMissing("control".to_string()));
+        };
+        let Some(&op_code) = data.first() else {
+            return Err(KondisError::InvalidArgument(
+                "Empty control point command".to_string(),
+            ));
+        };
+        let _command = self.commands.lock().await;
+        // listen before writing, so the response can't slip past
+        let mut indications = self.indications.subscribe();
+        debug!(device = %self.name, "Writing {data:02x?}");
+        let written = SendWrapper::new(async {
+            let promise = gatt
+                .control
+                .write_value_with_response_with_u8_slice(data)
+                .map_err(js_error)?;
+            promise.await.map_err(js_error)
+        });
+        until_shutdown(&self.shutdown, written).await?;
+
+        let response = crate::runtime::timeout(RESPONSE_TIMEOUT, async {
+            loop {
+                match indications.recv().await {
+                    Ok(indication) => {
+                        if let Some(response) = parse_control_point_response(&indication, op_code) {
+                            return Some(response);
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        match until_shutdown(&self.shutdown, async { Ok(response.await) }).await? {
+            Ok(Some(response)) => Ok(response?),
+            Ok(None) => Err(KondisError::Disconnected(
+                "Indications ended before the control point responded".to_string(),
+            )),
+            Err(_) => Err(KondisError::Timeout(format!(
+                "No control point response to {op_code:#04x} within {RESPONSE_TIMEOUT:?}"
+            ))),
+        }
+    }
+}
+
+/// A characteristic of the service, or `None` if the bike doesn't have it
+async fn characteristic(
+    service: &BluetoothRemoteGattService,
+    uuid: Uuid,
+) -> Result<Option<BluetoothRemoteGattCharacteristic>> {
+    match service.get_characteristic_with_str(&uuid.to_string()).await {
+        Ok(characteristic) => Ok(Some(characteristic)),
+        Err(e) if error_name(&e).as_deref() == Some("NotFoundError") => Ok(None),
+        Err(e) => Err(js_error(e)),
+    }
+}
+
+/// The value of a characteristic of the service, or `None` if the bike doesn't have it
+async fn read(service: &BluetoothRemoteGattService, uuid: Uuid) -> Result<Option<Vec<u8>>> {
+    match characteristic(service, uuid).await? {
+        Some(characteristic) => {
+            let value = characteristic.read_value().await.map_err(js_error)?;
+            Ok(Some(bytes(&value)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn bytes(view: &DataView) -> Vec<u8> {
+    Uint8Array::new_with_byte_offset_and_length(
+        &view.buffer(),
+        view.byte_offset() as u32,
+        view.byte_length() as u32,
+    )
+    .to_vec()
+}
+
+fn error_name(error: &JsValue) -> Option<String> {
+    error
+        .dyn_ref::<DomException>()
+        .map(|exception| exception.name())
+}
+
+/// What Web Bluetooth failing with `error` means for the bike
+fn js_error(error: JsValue) -> KondisError {
+    let message = match error.dyn_ref::<DomException>() {
+        Some(exception) => exception.message(),
+        None => format!("{error:?}"),
+    };
+    match error_name(&error).as_deref() {
+        // the user closed the chooser without picking anything
+        Some("NotFoundError") => KondisError::DeviceNotFound,
+        Some("SecurityError" | "NotAllowedError") => KondisError::PermissionDenied(message),
+        Some("NetworkError") => KondisError::Disconnected(message),
+        _ => KondisError::BluetoothUnavailable(message),
+    }
+}
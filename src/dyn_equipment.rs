@@ -0,0 +1,287 @@
+use std::sync::mpsc::Sender;
+
+use futures::future::BoxFuture;
+use tokio::sync::broadcast;
+
+use crate::bluetooth::DeviceInfo;
+use crate::ftms::{
+    Capabilities, MachineData, SpinDownResult, SpinDownStatus, SupportedRanges, TrainingGoal,
+};
+use crate::{ConnectionState, DataStream, DeviceEvent, Equipment, MachineStatusStream, Result};
+
+/// `Equipment` as a trait object, boxing the futures of its methods
+///
+/// Every equipment is one, so `Box<dyn DynEquipment>` holds any equipment picked at runtime, like the
+/// equipment created by `Registry`. `dyn DynEquipment` is an `Equipment` in turn, so it goes wherever
+/// equipment does, only creating it is left to the concrete types. Like equipment, it can be moved to
+/// and shared between tasks.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, DynEquipment, Equipment};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut equipment: Box<dyn DynEquipment> =
+///         Box::new(NonBluetoothDevice::new(32, &shutdown).await?);
+///     equipment.connect().await?;
+///     equipment.set_target_power(25).await?;
+///     Ok(())
+/// }
+/// ```
+pub trait DynEquipment: Send + Sync {
+    /// See `Equipment::connect`
+    fn connect(&mut self) -> BoxFuture<'_, Result<bool>>;
+    /// See `Equipment::disconnect`
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::close`
+    fn close(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_target_cadence`
+    fn set_target_cadence(&self, rpm: i16) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_target_power`
+    fn set_target_power(&self, watts: i16) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_target_resistance_level`
+    fn set_target_resistance_level(&self, level: i16) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_target_heart_rate`
+    fn set_target_heart_rate(&self, bpm: u8) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_goal`
+    fn set_goal(&self, goal: TrainingGoal) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::set_simulation_parameters`
+    fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::start`
+    fn start(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::stop`
+    fn stop(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::pause`
+    fn pause(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::resume`
+    fn resume(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::reset`
+    fn reset(&self) -> BoxFuture<'_, Result<()>>;
+    /// See `Equipment::spin_down`
+    fn spin_down<'a>(
+        &'a self,
+        status_tx: &'a Sender<SpinDownStatus>,
+    ) -> BoxFuture<'a, Result<SpinDownResult>>;
+    /// See `Equipment::machine_status`
+    fn machine_status(&self) -> BoxFuture<'_, Result<MachineStatusStream>>;
+    /// See `Equipment::capabilities`
+    fn capabilities(&self) -> Option<Capabilities>;
+    /// See `Equipment::battery_level`
+    fn battery_level(&self) -> Option<u8>;
+    /// See `Equipment::device_info`
+    fn device_info(&self) -> Option<DeviceInfo>;
+    /// See `Equipment::supported_ranges`
+    fn supported_ranges(&self) -> Option<SupportedRanges>;
+    /// See `Equipment::events`
+    fn events(&self) -> broadcast::Receiver<DeviceEvent>;
+    /// See `Equipment::state`
+    fn state(&self) -> ConnectionState;
+    /// See `Equipment::read`
+    fn read(&self) -> BoxFuture<'_, Result<Option<MachineData>>>;
+    /// See `Equipment::data_stream`
+    fn data_stream(&self) -> BoxFuture<'_, Result<DataStream>>;
+}
+
+impl<E: Equipment> DynEquipment for E {
+    fn connect(&mut self) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(Equipment::connect(self))
+    }
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::disconnect(self))
+    }
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::close(self))
+    }
+    fn set_target_cadence(&self, rpm: i16) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_target_cadence(self, rpm))
+    }
+    fn set_target_power(&self, watts: i16) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_target_power(self, watts))
+    }
+    fn set_target_resistance_level(&self, level: i16) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_target_resistance_level(self, level))
+    }
+    fn set_target_heart_rate(&self, bpm: u8) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_target_heart_rate(self, bpm))
+    }
+    fn set_goal(&self, goal: TrainingGoal) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_goal(self, goal))
+    }
+    fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::set_simulation_parameters(
+            self, grade, wind_speed, crr, cw,
+        ))
+    }
+    fn start(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::start(self))
+    }
+    fn stop(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::stop(self))
+    }
+    fn pause(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::pause(self))
+    }
+    fn resume(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::resume(self))
+    }
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(Equipment::reset(self))
+    }
+    fn spin_down<'a>(
+        &'a self,
+        status_tx: &'a Sender<SpinDownStatus>,
+    ) -> BoxFuture<'a, Result<SpinDownResult>> {
+        Box::pin(Equipment::spin_down(self, status_tx))
+    }
+    fn machine_status(&self) -> BoxFuture<'_, Result<MachineStatusStream>> {
+        Box::pin(Equipment::machine_status(self))
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        Equipment::capabilities(self)
+    }
+    fn battery_level(&self) -> Option<u8> {
+        Equipment::battery_level(self)
+    }
+    fn device_info(&self) -> Option<DeviceInfo> {
+        Equipment::device_info(self)
+    }
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        Equipment::supported_ranges(self)
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        Equipment::events(self)
+    }
+    fn state(&self) -> ConnectionState {
+        Equipment::state(self)
+    }
+    fn read(&self) -> BoxFuture<'_, Result<Option<MachineData>>> {
+        Box::pin(Equipment::read(self))
+    }
+    fn data_stream(&self) -> BoxFuture<'_, Result<DataStream>> {
+        Box::pin(Equipment::data_stream(self))
+    }
+}
+
+/// Equipment picked at runtime, created through the concrete type rather than `Equipment::new`
+impl Equipment for dyn DynEquipment + '_ {
+    fn connect(&mut self) -> impl Future<Output = Result<bool>> + Send {
+        DynEquipment::connect(self)
+    }
+    fn disconnect(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::disconnect(self)
+    }
+    fn close(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::close(self)
+    }
+    fn set_target_cadence(&self, rpm: i16) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_target_cadence(self, rpm)
+    }
+    fn set_target_power(&self, watts: i16) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_target_power(self, watts)
+    }
+    fn set_target_resistance_level(&self, level: i16) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_target_resistance_level(self, level)
+    }
+    fn set_target_heart_rate(&self, bpm: u8) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_target_heart_rate(self, bpm)
+    }
+    fn set_goal(&self, goal: TrainingGoal) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_goal(self, goal)
+    }
+    fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::set_simulation_parameters(self, grade, wind_speed, crr, cw)
+    }
+    fn start(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::start(self)
+    }
+    fn stop(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::stop(self)
+    }
+    fn pause(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::pause(self)
+    }
+    fn resume(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::resume(self)
+    }
+    fn reset(&self) -> impl Future<Output = Result<()>> + Send {
+        DynEquipment::reset(self)
+    }
+    fn spin_down<'a>(
+        &'a self,
+        status_tx: &'a Sender<SpinDownStatus>,
+    ) -> impl Future<Output = Result<SpinDownResult>> + Send {
+        DynEquipment::spin_down(self, status_tx)
+    }
+    fn machine_status(&self) -> impl Future<Output = Result<MachineStatusStream>> + Send {
+        DynEquipment::machine_status(self)
+    }
+    fn capabilities(&self) -> Option<Capabilities> {
+        DynEquipment::capabilities(self)
+    }
+    fn battery_level(&self) -> Option<u8> {
+        DynEquipment::battery_level(self)
+    }
+    fn device_info(&self) -> Option<DeviceInfo> {
+        DynEquipment::device_info(self)
+    }
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        DynEquipment::supported_ranges(self)
+    }
+    fn events(&self) -> broadcast::Receiver<DeviceEvent> {
+        DynEquipment::events(self)
+    }
+    fn state(&self) -> ConnectionState {
+        DynEquipment::state(self)
+    }
+    fn read(&self) -> impl Future<Output = Result<Option<MachineData>>> + Send {
+        DynEquipment::read(self)
+    }
+    fn data_stream(&self) -> impl Future<Output = Result<DataStream>> + Send {
+        DynEquipment::data_stream(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CancellationToken;
+    use crate::devices::NonBluetoothDevice;
+
+    async fn power<E: Equipment + ?Sized>(equipment: &E, watts: i16) -> Result<()> {
+        equipment.set_target_power(watts).await
+    }
+
+    #[tokio::test]
+    async fn test_dyn_equipment() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut equipment: Box<dyn DynEquipment> =
+            Box::new(NonBluetoothDevice::new(32, &shutdown).await?);
+        assert!(equipment.connect().await?);
+        assert_eq!(equipment.state(), ConnectionState::Connected);
+        power(&*equipment, 20).await?;
+        assert!(power(&*equipment, 40).await.is_err());
+        assert!(equipment.read().await?.is_some());
+        equipment.disconnect().await
+    }
+}
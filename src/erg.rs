@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{DeviceEvent, Equipment, FTMSData, Result};
+
+/// How often the target power sent to the machine steps towards the target
+const STEP_INTERVAL: Duration = Duration::from_millis(500);
+/// The share of the target power held while the cadence is below the minimum
+const RECOVERY_FACTOR: f64 = 0.5;
+
+/// ERG mode, holding a target power and ramping the machine towards it
+///
+/// Many trainers respond badly to abrupt jumps in target power, so every change of target is sent to
+/// the machine in steps, at most `ramp` watts per second apart. The first target is sent as is.
+///
+/// Holding a target power at a collapsing cadence takes ever more resistance, until the user can't turn
+/// the pedals at all. With `min_cadence`, the target power is halved whenever the cadence drops below
+/// it, and ramped back up once the cadence recovers.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, ErgController};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let erg = ErgController::new(25.).min_cadence(60.);
+///     erg.set_target_power(150);
+///     // stepping up from 150 to 200 W takes 2 seconds at 25 W/s
+///     let ramp = async {
+///         tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+///         erg.set_target_power(200);
+///         shutdown.cancel();
+///     };
+///     let (result, _) = tokio::join!(erg.run(&device, &shutdown), ramp);
+///     result?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ErgController {
+    /// W/s
+    ramp: f64,
+    /// rpm
+    min_cadence: Option<f64>,
+    target_tx: watch::Sender<Option<i16>>,
+}
+
+impl ErgController {
+    /// A controller stepping the target power at most `ramp` watts per second
+    pub fn new(ramp: f64) -> Self {
+        ErgController {
+            ramp,
+            min_cadence: None,
+            target_tx: watch::Sender::new(None),
+        }
+    }
+
+    /// Lower the target power while the cadence is below `rpm`, letting it recover
+    pub fn min_cadence(mut self, rpm: f64) -> Self {
+        self.min_cadence = Some(rpm);
+        self
+    }
+
+    /// Change the power to ramp towards, in watts
+    pub fn set_target_power(&self, watts: i16) {
+        self.target_tx.send_replace(Some(watts));
+    }
+
+    /// The power being ramped towards, if any has been set yet
+    pub fn target_power(&self) -> Option<i16> {
+        *self.target_tx.borrow()
+    }
+
+    /// Keep sending the target power to `equipment`, until `shutdown` gets cancelled
+    ///
+    /// Fails as soon as the equipment refuses a target power.
+    pub async fn run<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut target_rx = self.target_tx.subscribe();
+        // the cadence only matters with a minimum to keep it above
+        let mut events = self.min_cadence.map(|_| equipment.events());
+        let max_step = self.ramp * STEP_INTERVAL.as_secs_f64();
+        let mut current: Option<f64> = None;
+        let mut recovering = false;
+        loop {
+            let target = target_rx.borrow_and_update().map(|watts| {
+                let watts = f64::from(watts);
+                if recovering {
+                    watts * RECOVERY_FACTOR
+                } else {
+                    watts
+                }
+            });
+            let mut ramping = false;
+            if let Some(target) = target {
+                let next = match current {
+                    // dropping the power is what lets the cadence recover, so it isn't ramped down
+                    Some(current) if !(recovering && target < current) => {
+                        step(current, target, max_step)
+                    }
+                    _ => target,
+                };
+                if current != Some(next) {
+                    equipment.set_target_power(next.round() as i16).await?;
+                    current = Some(next);
+                }
+                ramping = next != target;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = crate::runtime::sleep(STEP_INTERVAL), if ramping => {}
+                _ = target_rx.changed(), if !ramping => {}
+                cadence = next_cadence(&mut events) => {
+                    recovering = self.min_cadence.is_some_and(|min_cadence| cadence < min_cadence);
+                }
+            }
+        }
+    }
+}
+
+/// The next cadence reported in `events`, never resolving without any events to listen to
+async fn next_cadence(events: &mut Option<broadcast::Receiver<DeviceEvent>>) -> f64 {
+    if let Some(receiver) = events {
+        loop {
+            match receiver.recv().await {
+                Ok(DeviceEvent::Data(reading)) => {
+                    if let Some(cadence) = FTMSData::from(reading.data).cadence {
+                        return cadence.into();
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+        *events = None;
+    }
+    std::future::pending().await
+}
+
+/// Move from `current` towards `target`, by no more than `max_step`
+fn step(current: f64, target: f64, max_step: f64) -> f64 {
+    current + (target - current).clamp(-max_step, max_step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::RecordingEquipment;
+    use crate::remote::Request;
+    use crate::{BikeData, Reading};
+
+    fn cadence(rpm: f32) -> DeviceEvent {
+        let data = BikeData {
+            cadence: Some(rpm),
+            ..Default::default()
+        };
+        DeviceEvent::Data(Reading::new(0, data.into()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_min_cadence() {
+        let shutdown = CancellationToken::new();
+        let equipment = RecordingEquipment::new();
+        // 10 W every step
+        let erg = ErgController::new(20.).min_cadence(60.);
+        erg.set_target_power(200);
+        let feed = async {
+            for rpm in [40., 50., 80.] {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                equipment.send(cadence(rpm));
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            shutdown.cancel();
+        };
+        let (result, _) = tokio::join!(erg.run(&equipment, &shutdown), feed);
+        result.unwrap();
+
+        let targets: Vec<(f64, i16)> = equipment
+            .requests()
+            .into_iter()
+            .filter_map(|(at, request)| match request {
+                Request::Power(watts) => Some((at.as_secs_f64(), watts)),
+                _ => None,
+            })
+            .collect();
+        // halved at once when the cadence drops, held while it stays low, and ramped back up once it
+        // recovers
+        let mut expected = vec![(0., 200), (1., 100)];
+        expected.extend((1..=10).map(|step| (2.5 + 0.5 * f64::from(step), 100 + 10 * step as i16)));
+        assert_eq!(targets, expected);
+    }
+
+    #[test]
+    fn test_step() {
+        assert_eq!(step(100., 200., 25.), 125.);
+        assert_eq!(step(200., 100., 25.), 175.);
+        assert_eq!(step(190., 200., 25.), 200.);
+        assert_eq!(step(200., 200., 25.), 200.);
+    }
+}
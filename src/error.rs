@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::ControlPointError;
+
+/// Everything that can go wrong talking to equipment
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{devices::GenericFtmsBike, CancellationToken, Equipment, KondisError};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     match GenericFtmsBike::new(400, &shutdown).await {
+///         Ok(bike) => println!("found {}", bike.name),
+///         Err(KondisError::DeviceNotFound) => println!("no bike around"),
+///         Err(e) => return Err(e.into()),
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
+#[non_exhaustive]
+pub enum KondisError {
+    /// Scanning ended without finding a matching device
+    #[error("No peripheral found")]
+    DeviceNotFound,
+    /// Scanning took longer than `ScanConfig::timeout`
+    #[error("No matching peripheral found within {0:?}")]
+    ScanTimeout(Duration),
+    /// Connecting took longer than `ScanConfig::connect_timeout`
+    #[error("Connecting took longer than {0:?}")]
+    ConnectTimeout(Duration),
+    /// Discovering the services of the device took longer than `ScanConfig::discovery_timeout`
+    #[error("Discovering services took longer than {0:?}")]
+    DiscoveryTimeout(Duration),
+    /// No notification arrived within `ScanConfig::notification_timeout`
+    #[error("No notification received within {0:?}")]
+    NotificationTimeout(Duration),
+    /// There is no usable Bluetooth adapter
+    #[error("Bluetooth is unavailable: {0}")]
+    BluetoothUnavailable(String),
+    /// The Bluetooth adapter is powered off, see `bluetooth::diagnose`
+    #[error("Bluetooth is off: {0}")]
+    AdapterPoweredOff(String),
+    /// The process isn't allowed to use Bluetooth, see `bluetooth::diagnose`
+    #[error("Bluetooth permission denied: {0}")]
+    PermissionDenied(String),
+    /// The device lacks a characteristic it needs
+    #[error("No {0} characteristic found")]
+    CharacteristicMissing(String),
+    /// The machine refused a control point command
+    #[error(transparent)]
+    ControlRejected(#[from] ControlPointError),
+    /// The machine didn't respond in time
+    #[error("{0}")]
+    Timeout(String),
+    /// The device went away mid-operation
+    #[error("{0}")]
+    Disconnected(String),
+    /// The shutdown token got cancelled
+    #[error("Shut down")]
+    Shutdown,
+    /// A target or parameter is out of range
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// The equipment can't do what was asked
+    #[error("{0}")]
+    Unsupported(String),
+    /// Data received from the device, or read from a file, couldn't be decoded
+    #[error("{0}")]
+    InvalidData(String),
+    /// Reading or writing the session store failed, see `storage::SessionStore`
+    #[error("Storage failed: {0}")]
+    Storage(String),
+    /// Serving equipment to other apps failed, see `bridge::Bridge`
+    #[error("Bridge failed: {0}")]
+    Bridge(String),
+    /// Exporting samples failed, like an InfluxDB server refusing them, see `InfluxSink`
+    #[error("Export failed: {0}")]
+    Export(String),
+    /// Remote equipment failed to do what was asked, see `devices::RemoteEquipment`
+    #[error("Remote equipment failed: {0}")]
+    Remote(String),
+    /// No equipment is registered under the name, see `Registry`
+    #[error("No equipment registered as {0}")]
+    UnknownEquipment(String),
+    /// Reading or writing a file failed, like a capture
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Any other error of the Bluetooth stack
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error(transparent)]
+    Bluetooth(#[from] btleplug::Error),
+    /// Any other error of BlueZ, talked to directly with the `bluer` feature
+    #[cfg(all(feature = "bluer", target_os = "linux"))]
+    #[error(transparent)]
+    Bluez(#[from] bluer::Error),
+}
+
+/// The result of everything talking to equipment
+pub type Result<T, E = KondisError> = std::result::Result<T, E>;
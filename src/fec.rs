@@ -0,0 +1,157 @@
+//! ANT+ Fitness Equipment Control (FE-C), the data pages trainers speak over ANT+, and over BLE on
+//! trainers tunnelling them
+
+use crate::{BikeData, KondisError, Result};
+
+/// Data pages sent by trainers
+const GENERAL_FE_DATA: u8 = 0x10;
+const SPECIFIC_TRAINER_DATA: u8 = 0x19;
+/// Data pages sent to trainers
+const BASIC_RESISTANCE: u8 = 0x30;
+const TARGET_POWER: u8 = 0x31;
+const WIND_RESISTANCE: u8 = 0x32;
+const TRACK_RESISTANCE: u8 = 0x33;
+/// Marks a field of a page as not set
+const UNSET: u8 = 0xFF;
+
+/// An 8 byte FE-C data page
+pub(crate) type Page = [u8; 8];
+
+/// Encode a target power page, in W
+pub(crate) fn target_power(watts: i16) -> Page {
+    // quarter watts
+    let [low, high] = (watts.max(0) as u16).saturating_mul(4).to_le_bytes();
+    [TARGET_POWER, UNSET, UNSET, UNSET, UNSET, UNSET, low, high]
+}
+
+/// Encode a basic resistance page, in percent of the maximum resistance of the trainer
+pub(crate) fn basic_resistance(percent: f32) -> Page {
+    // half percents
+    let resistance = (percent.clamp(0., 100.) * 2.).round() as u8;
+    [
+        BASIC_RESISTANCE,
+        UNSET,
+        UNSET,
+        UNSET,
+        UNSET,
+        UNSET,
+        UNSET,
+        resistance,
+    ]
+}
+
+/// Encode a wind resistance page, with the wind resistance coefficient in kg/m and the wind speed in m/s
+pub(crate) fn wind_resistance(cw: f32, wind_speed: f32) -> Page {
+    let cw = (cw * 100.).round().clamp(0., 186.) as u8;
+    // km/h, offset by 127
+    let wind_speed = (wind_speed * 3.6 + 127.).round().clamp(0., 254.) as u8;
+    [
+        WIND_RESISTANCE,
+        UNSET,
+        UNSET,
+        UNSET,
+        UNSET,
+        cw,
+        wind_speed,
+        // no drafting
+        100,
+    ]
+}
+
+/// Encode a track resistance page, with the grade in percent and the rolling resistance coefficient
+pub(crate) fn track_resistance(grade: f32, crr: f32) -> Page {
+    // hundredths of a percent, offset by 200%
+    let [low, high] = (((grade.clamp(-200., 200.) + 200.) * 100.).round() as u16).to_le_bytes();
+    // 5 * 10^-5
+    let crr = (crr / 0.00005).round().clamp(0., 254.) as u8;
+    [TRACK_RESISTANCE, UNSET, UNSET, UNSET, UNSET, low, high, crr]
+}
+
+/// Turns the data pages of a trainer into bike data, holding on to what every page adds to it
+///
+/// Elapsed time and distance roll over in their pages after a minute and 256 m, which get added up
+/// into totals here.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FecDecoder {
+    data: BikeData,
+    /// The last elapsed time in 1/4 s and distance in m, as sent in the page
+    last: Option<(u8, u8)>,
+    /// 1/4 s
+    elapsed: u32,
+    /// m
+    distance: u32,
+}
+
+impl FecDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in a data page, resolving to the bike data of every page so far
+    ///
+    /// Pages holding anything else, like the manufacturer of the trainer, leave the data as is.
+    pub fn update(&mut self, page: &[u8]) -> Result<BikeData> {
+        let page: Page = page.try_into().map_err(|_| {
+            KondisError::InvalidData(format!("FE-C pages hold 8 bytes, not {}", page.len()))
+        })?;
+        match page[0] {
+            GENERAL_FE_DATA => {
+                let (elapsed, distance) = (page[2], page[3]);
+                if let Some((last_elapsed, last_distance)) = self.last {
+                    self.elapsed += u32::from(elapsed.wrapping_sub(last_elapsed));
+                    self.distance += u32::from(distance.wrapping_sub(last_distance));
+                }
+                self.last = Some((elapsed, distance));
+                // mm/s
+                let speed = u16::from_le_bytes([page[4], page[5]]);
+                self.data.speed = Some(f32::from(speed) * 3.6 / 1000.);
+                self.data.distance = Some(self.distance as f32 / 1000.);
+                self.data.time = Some(self.elapsed / 4);
+                self.data.heart_rate = (page[6] != 0 && page[6] != UNSET).then_some(page[6].into());
+            }
+            SPECIFIC_TRAINER_DATA => {
+                self.data.cadence = (page[2] != UNSET).then_some(page[2].into());
+                let power = u16::from_le_bytes([page[5], page[6] & 0x0F]);
+                self.data.power = (power != 0x0FFF).then_some(power as i16);
+            }
+            _ => {}
+        }
+        Ok(self.data.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fec() -> Result<()> {
+        assert_eq!(
+            target_power(250),
+            [0x31, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE8, 0x03]
+        );
+        // 5% on 0.004 crr
+        assert_eq!(
+            track_resistance(5., 0.004),
+            [0x33, 0xFF, 0xFF, 0xFF, 0xFF, 0x14, 0x50, 0x50]
+        );
+
+        let mut decoder = FecDecoder::new();
+        // bike trainer, at 62 s and 250 m in, 36 km/h, no heart rate
+        decoder.update(&[0x10, 0x19, 0xF8, 0xFA, 0x10, 0x27, 0x00, 0x24])?;
+        // 90 rpm at 215 W
+        let data = decoder.update(&[0x19, 0x01, 0x5A, 0x00, 0x00, 0xD7, 0x30, 0x20])?;
+        assert_eq!(data.cadence, Some(90.));
+        assert_eq!(data.power, Some(215));
+        assert_eq!(data.speed, Some(36.));
+        assert_eq!(data.heart_rate, None);
+        // 2 s and 20 m later, across the rollover of both
+        let data = decoder.update(&[0x10, 0x19, 0x00, 0x0E, 0x10, 0x27, 0x8C, 0x24])?;
+        assert_eq!(data.distance, Some(0.02));
+        assert_eq!(data.time, Some(2));
+        assert_eq!(data.heart_rate, Some(140.));
+        assert_eq!(data.cadence, Some(90.));
+        assert!(decoder.update(&[0x10]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! Bindings for Kotlin and Swift apps, through UniFFI, with the `uniffi` feature
+//!
+//! Apps scan for devices with `scan`, connect to equipment by its name in `Registry` with `connect`,
+//! and then read and control it through the returned `Trainer`, a thin wrapper of `EquipmentHandle`.
+//! The bindings get generated from the library built with the feature, by the `uniffi-bindgen`
+//! binary of the `uniffi-bindgen` feature. Every async function runs on a tokio runtime of UniFFI's,
+//! so the apps await them on their own executors, like Kotlin coroutines or Swift tasks.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{EquipmentHandle, EquipmentType, FTMSData, KondisError, Registry, Result, ScanConfig};
+
+/// A device found by `scan`
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Device {
+    /// The advertised name of the device, if it has one
+    pub name: Option<String>,
+    /// The address of the device, to pass on to `ConnectOptions::address`
+    pub address: String,
+    /// The signal strength of the last advertisement received, in dBm
+    pub rssi: Option<i16>,
+    /// The UUIDs of the services the device advertises
+    pub services: Vec<String>,
+    /// The equipment type the device most likely is, going by its advertisement
+    pub probable_type: Option<EquipmentType>,
+}
+
+/// What `connect` connects to
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct ConnectOptions {
+    /// The name of the equipment in `Registry`, like `generic-ftms-bike`, see `equipment_names`
+    pub equipment: String,
+    /// See `Equipment::with_config`
+    pub max_level: i16,
+    /// See `ScanConfig::name_pattern`
+    #[uniffi(default = None)]
+    pub name_pattern: Option<String>,
+    /// See `ScanConfig::address`
+    #[uniffi(default = None)]
+    pub address: Option<String>,
+    /// How long to scan for the equipment at most, in seconds, see `ScanConfig::timeout`
+    #[uniffi(default = None)]
+    pub timeout: Option<f64>,
+}
+
+/// A reading of the equipment, see `kondis::Reading`
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Reading {
+    /// Seconds since the first reading of the app
+    pub timestamp: f64,
+    pub sequence: u64,
+    /// km/h
+    pub speed: Option<f32>,
+    /// rpm, or strokes or steps per minute
+    pub cadence: Option<f32>,
+    /// km
+    pub distance: Option<f32>,
+    pub resistance: Option<f64>,
+    /// watts
+    pub power: Option<i16>,
+    /// kcal
+    pub calories: Option<f64>,
+    /// bpm
+    pub heart_rate: Option<f64>,
+    /// seconds
+    pub time: Option<u32>,
+}
+
+impl From<crate::Reading> for Reading {
+    fn from(reading: crate::Reading) -> Self {
+        let data = FTMSData::from(reading.data);
+        Reading {
+            timestamp: reading.timestamp.as_secs_f64(),
+            sequence: reading.sequence,
+            speed: data.speed,
+            cadence: data.cadence,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+/// The names `ConnectOptions::equipment` takes, see `Registry::names`
+#[uniffi::export]
+pub fn equipment_names() -> Vec<String> {
+    Registry::default()
+        .names()
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Scan for nearby devices for `timeout` seconds, or the default of `bluetooth::scan`
+#[uniffi::export(async_runtime = "tokio")]
+pub async fn scan(timeout: Option<f64>) -> Result<Vec<Device>> {
+    let mut config = ScanConfig::new();
+    if let Some(timeout) = timeout {
+        config = config.timeout(seconds(timeout)?);
+    }
+    Ok(crate::scan(config)
+        .await?
+        .into_iter()
+        .map(|device| Device {
+            name: device.name,
+            address: device.address,
+            rssi: device.rssi,
+            services: device.services.iter().map(ToString::to_string).collect(),
+            probable_type: device.probable_type,
+        })
+        .collect())
+}
+
+/// Find and connect to the equipment of `options`
+#[uniffi::export(async_runtime = "tokio")]
+pub async fn connect(options: ConnectOptions) -> Result<Arc<Trainer>> {
+    let mut config = ScanConfig::new();
+    if let Some(pattern) = options.name_pattern {
+        config = config.name_pattern(pattern);
+    }
+    if let Some(address) = options.address {
+        config = config.address(address);
+    }
+    if let Some(timeout) = options.timeout {
+        config = config.timeout(seconds(timeout)?);
+    }
+    let shutdown = CancellationToken::new();
+    let equipment = Registry::default()
+        .create(&options.equipment, options.max_level, config, &shutdown)
+        .await?;
+    let handle = EquipmentHandle::connect(equipment, &shutdown).await?;
+    Ok(Arc::new(Trainer { handle, shutdown }))
+}
+
+/// Connected equipment, closed once `close` is called or the app lets go of it
+#[derive(Debug, uniffi::Object)]
+pub struct Trainer {
+    handle: EquipmentHandle,
+    shutdown: CancellationToken,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl Trainer {
+    /// The latest reading, if the equipment reported any yet
+    pub fn latest(&self) -> Option<Reading> {
+        self.handle.latest().map(Reading::from)
+    }
+
+    /// Wait for the next reading, resolving to `None` once the equipment is closed
+    pub async fn next_reading(&self) -> Option<Reading> {
+        self.handle.next_reading().await.map(Reading::from)
+    }
+
+    /// Whether the equipment got closed, after which every command fails
+    pub fn is_closed(&self) -> bool {
+        self.handle.is_closed()
+    }
+
+    /// See `Equipment::set_target_power`
+    pub async fn set_target_power(&self, watts: i16) -> Result<()> {
+        self.handle.set_target_power(watts).await
+    }
+
+    /// See `Equipment::set_target_cadence`
+    pub async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        self.handle.set_target_cadence(rpm).await
+    }
+
+    /// See `Equipment::set_target_resistance_level`
+    pub async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.handle.set_target_resistance_level(level).await
+    }
+
+    /// See `Equipment::set_target_heart_rate`
+    pub async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.handle.set_target_heart_rate(bpm).await
+    }
+
+    /// See `Equipment::set_simulation_parameters`
+    pub async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.handle
+            .set_simulation_parameters(grade, wind_speed, crr, cw)
+            .await
+    }
+
+    /// See `Equipment::start`
+    pub async fn start(&self) -> Result<()> {
+        self.handle.start().await
+    }
+
+    /// See `Equipment::stop`
+    pub async fn stop(&self) -> Result<()> {
+        self.handle.stop().await
+    }
+
+    /// See `Equipment::pause`
+    pub async fn pause(&self) -> Result<()> {
+        self.handle.pause().await
+    }
+
+    /// See `Equipment::resume`
+    pub async fn resume(&self) -> Result<()> {
+        self.handle.resume().await
+    }
+
+    /// See `Equipment::reset`
+    pub async fn reset(&self) -> Result<()> {
+        self.handle.reset().await
+    }
+
+    /// Stop and disconnect from the equipment, see `Equipment::close`
+    pub async fn close(&self) -> Result<()> {
+        self.handle.close().await
+    }
+}
+
+impl Drop for Trainer {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+fn seconds(seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|e| KondisError::InvalidArgument(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trainer() -> Result<()> {
+        assert!(equipment_names().contains(&"non-bluetooth-device".to_string()));
+        let trainer = connect(ConnectOptions {
+            equipment: "non-bluetooth-device".to_string(),
+            max_level: 400,
+            name_pattern: None,
+            address: None,
+            timeout: None,
+        })
+        .await?;
+        let reading = trainer.next_reading().await.unwrap();
+        assert!(reading.time.is_some());
+        trainer.set_target_power(150).await?;
+        trainer.close().await?;
+        assert!(trainer.set_target_power(150).await.is_err());
+
+        let unknown = ConnectOptions {
+            equipment: "unknown".to_string(),
+            max_level: 400,
+            name_pattern: None,
+            address: None,
+            timeout: Some(-1.0),
+        };
+        assert!(matches!(
+            connect(unknown).await,
+            Err(KondisError::InvalidArgument(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,695 @@
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{BikeData, FTMSData, KondisError, MachineData, Reading, Result, SessionStats};
+
+/// Seconds from the Unix epoch to the FIT epoch, 1989-12-31 00:00 UTC
+const FIT_EPOCH: u64 = 631_065_600;
+const PROTOCOL_VERSION: u8 = 0x10;
+const PROFILE_VERSION: u16 = 2100;
+
+const MESG_FILE_ID: u16 = 0;
+const MESG_SESSION: u16 = 18;
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_ACTIVITY: u16 = 34;
+
+const FILE_ACTIVITY: u8 = 4;
+const MANUFACTURER_DEVELOPMENT: u16 = 255;
+const EVENT_SESSION: u8 = 8;
+const EVENT_LAP: u8 = 9;
+const EVENT_ACTIVITY: u8 = 26;
+const EVENT_TYPE_STOP: u8 = 1;
+
+/// Writes sessions as Garmin FIT activity files, for Strava, Garmin Connect and the like to import
+///
+/// Every reading becomes a record, and the session is summed up per lap and as a whole, the way
+/// `SessionStats` sums it up. The sport follows the kind of machine the readings came from.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, FitRecorder};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///     let mut recorder = FitRecorder::new();
+///     let mut data = device.data_stream().await?.take(2);
+///     while let Some(reading) = data.next().await {
+///         recorder.record(&reading);
+///     }
+///     let mut file = Vec::new();
+///     recorder.write(&mut file)?;
+///     assert_eq!(&file[8..12], b".FIT");
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FitRecorder {
+    start_time: SystemTime,
+    readings: Vec<Reading>,
+    /// the index of the first reading of every lap after the first
+    laps: Vec<usize>,
+}
+
+impl FitRecorder {
+    /// A recorder for a session starting now
+    pub fn new() -> Self {
+        FitRecorder {
+            start_time: SystemTime::now(),
+            readings: Vec::new(),
+            laps: Vec::new(),
+        }
+    }
+
+    /// When the first reading was taken, now by default
+    pub fn start_time(mut self, start_time: SystemTime) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    pub fn record(&mut self, reading: &Reading) {
+        self.readings.push(reading.clone());
+    }
+
+    /// End the current lap, the next reading starting another one
+    ///
+    /// Does nothing while the current lap has no readings.
+    pub fn lap(&mut self) {
+        let start = self.laps.last().copied().unwrap_or(0);
+        if self.readings.len() > start {
+            self.laps.push(self.readings.len());
+        }
+    }
+
+    /// Write the session so far as a FIT file
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// The session so far as a FIT file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (sport, sub_sport) = self
+            .readings
+            .first()
+            .map_or((0, 0), |reading| sport(&reading.data));
+        let mut file = FitWriter::default();
+        file.message(
+            MESG_FILE_ID,
+            &[
+                (0, Value::Enum(FILE_ACTIVITY)),
+                (1, Value::U16(Some(MANUFACTURER_DEVELOPMENT))),
+                (2, Value::U16(Some(0))),
+                (4, Value::U32(Some(self.fit_time(self.first_timestamp())))),
+            ],
+        );
+
+        let mut bounds = vec![0];
+        bounds.extend(&self.laps);
+        bounds.push(self.readings.len());
+        let mut session = SessionStats::new();
+        let mut laps = 0;
+        for window in bounds.windows(2) {
+            let readings = &self.readings[window[0]..window[1]];
+            if readings.is_empty() {
+                continue;
+            }
+            let mut stats = SessionStats::new();
+            for reading in readings {
+                session.record(reading);
+                stats.record(reading);
+                let data = FTMSData::from(reading.data.clone());
+                file.message(
+                    MESG_RECORD,
+                    &[
+                        (253, Value::U32(Some(self.fit_time(reading.timestamp)))),
+                        (3, Value::U8(data.heart_rate.and_then(byte))),
+                        (4, Value::U8(data.cadence.and_then(|rpm| byte(rpm.into())))),
+                        (
+                            5,
+                            Value::U32(data.distance.and_then(|km| centimeters(km.into()))),
+                        ),
+                        (6, Value::U16(data.speed.and_then(|kmh| speed(kmh.into())))),
+                        (
+                            7,
+                            Value::U16(data.power.and_then(|watts| word(watts.into()))),
+                        ),
+                    ],
+                );
+            }
+            // the distance covered since the end of the last lap, rather than since its first reading
+            let since = &self.readings[window[0].saturating_sub(1)..window[1]];
+            let lap = Summary::new(self, &stats, since);
+            file.message(
+                MESG_LAP,
+                &[
+                    (253, Value::U32(Some(lap.end))),
+                    (254, Value::U16(Some(laps))),
+                    (0, Value::Enum(EVENT_LAP)),
+                    (1, Value::Enum(EVENT_TYPE_STOP)),
+                    (2, Value::U32(Some(lap.start))),
+                    (7, Value::U32(Some(lap.elapsed))),
+                    (8, Value::U32(Some(lap.timer))),
+                    (9, Value::U32(lap.distance)),
+                    (11, Value::U16(lap.calories)),
+                    (13, Value::U16(lap.avg_speed)),
+                    (14, Value::U16(lap.max_speed)),
+                    (15, Value::U8(lap.avg_heart_rate)),
+                    (16, Value::U8(lap.max_heart_rate)),
+                    (17, Value::U8(lap.avg_cadence)),
+                    (18, Value::U8(lap.max_cadence)),
+                    (19, Value::U16(lap.avg_power)),
+                    (20, Value::U16(lap.max_power)),
+                    (25, Value::Enum(sport)),
+                    (33, Value::U16(lap.normalized_power)),
+                    (39, Value::Enum(sub_sport)),
+                ],
+            );
+            laps += 1;
+        }
+
+        let summary = Summary::new(self, &session, &self.readings);
+        file.message(
+            MESG_SESSION,
+            &[
+                (253, Value::U32(Some(summary.end))),
+                (0, Value::Enum(EVENT_SESSION)),
+                (1, Value::Enum(EVENT_TYPE_STOP)),
+                (2, Value::U32(Some(summary.start))),
+                (5, Value::Enum(sport)),
+                (6, Value::Enum(sub_sport)),
+                (7, Value::U32(Some(summary.elapsed))),
+                (8, Value::U32(Some(summary.timer))),
+                (9, Value::U32(summary.distance)),
+                (11, Value::U16(summary.calories)),
+                (14, Value::U16(summary.avg_speed)),
+                (15, Value::U16(summary.max_speed)),
+                (16, Value::U8(summary.avg_heart_rate)),
+                (17, Value::U8(summary.max_heart_rate)),
+                (18, Value::U8(summary.avg_cadence)),
+                (19, Value::U8(summary.max_cadence)),
+                (20, Value::U16(summary.avg_power)),
+                (21, Value::U16(summary.max_power)),
+                (25, Value::U16(Some(0))),
+                (26, Value::U16(Some(laps))),
+                (34, Value::U16(summary.normalized_power)),
+                (
+                    35,
+                    Value::U16(
+                        session
+                            .training_stress_score()
+                            .and_then(|tss| word(tss * 10.)),
+                    ),
+                ),
+                (
+                    36,
+                    Value::U16(
+                        session
+                            .intensity_factor()
+                            .and_then(|factor| word(factor * 1000.)),
+                    ),
+                ),
+                (48, Value::U32(Some((session.work() * 1000.) as u32))),
+            ],
+        );
+        file.message(
+            MESG_ACTIVITY,
+            &[
+                (253, Value::U32(Some(summary.end))),
+                (0, Value::U32(Some(summary.timer))),
+                (1, Value::U16(Some(1))),
+                (2, Value::Enum(0)),
+                (3, Value::Enum(EVENT_ACTIVITY)),
+                (4, Value::Enum(EVENT_TYPE_STOP)),
+            ],
+        );
+        file.finish()
+    }
+
+    fn first_timestamp(&self) -> Duration {
+        self.readings
+            .first()
+            .map_or(Duration::ZERO, |r| r.timestamp)
+    }
+
+    /// The FIT timestamp of a reading taken at `timestamp`
+    fn fit_time(&self, timestamp: Duration) -> u32 {
+        let time = self.start_time + timestamp.saturating_sub(self.first_timestamp());
+        let unix = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        unix.saturating_sub(FIT_EPOCH) as u32
+    }
+}
+
+impl Default for FitRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What laps and the session sum up, in FIT units
+struct Summary {
+    start: u32,
+    end: u32,
+    /// ms
+    elapsed: u32,
+    /// ms
+    timer: u32,
+    /// cm
+    distance: Option<u32>,
+    /// kcal
+    calories: Option<u16>,
+    /// mm/s
+    avg_speed: Option<u16>,
+    /// mm/s
+    max_speed: Option<u16>,
+    avg_heart_rate: Option<u8>,
+    max_heart_rate: Option<u8>,
+    avg_cadence: Option<u8>,
+    max_cadence: Option<u8>,
+    avg_power: Option<u16>,
+    max_power: Option<u16>,
+    normalized_power: Option<u16>,
+}
+
+impl Summary {
+    /// The summary of `stats`, covering the distance from the first to the last of `readings`
+    fn new(recorder: &FitRecorder, stats: &SessionStats, readings: &[Reading]) -> Self {
+        let distances = || {
+            readings
+                .iter()
+                .filter_map(|reading| FTMSData::from(reading.data.clone()).distance)
+        };
+        let distance = distances()
+            .next()
+            .zip(distances().next_back())
+            .and_then(|(first, last)| centimeters(f64::from(last - first)));
+        let end = readings.last().map_or(Duration::ZERO, |r| r.timestamp);
+        Summary {
+            start: recorder.fit_time(end.saturating_sub(stats.elapsed_time())),
+            end: recorder.fit_time(end),
+            elapsed: millis(stats.elapsed_time()),
+            timer: millis(stats.moving_time()),
+            distance,
+            calories: word(stats.calories()),
+            avg_speed: stats.average_speed().and_then(speed),
+            max_speed: stats.max_speed().and_then(speed),
+            avg_heart_rate: stats.average_heart_rate().and_then(byte),
+            max_heart_rate: stats.max_heart_rate().and_then(byte),
+            avg_cadence: stats.average_cadence().and_then(byte),
+            max_cadence: stats.max_cadence().and_then(byte),
+            avg_power: stats.average_power().and_then(word),
+            max_power: stats.max_power().and_then(word),
+            normalized_power: stats.normalized_power().and_then(word),
+        }
+    }
+}
+
+/// The FIT sport and sub sport of the machine `data` came from
+fn sport(data: &MachineData) -> (u8, u8) {
+    match data {
+        // cycling, indoor cycling
+        MachineData::Bike(_) => (2, 6),
+        // running, treadmill
+        MachineData::Treadmill(_) => (1, 1),
+        // rowing, indoor rowing
+        MachineData::Rower(_) => (15, 14),
+        // fitness equipment, elliptical
+        MachineData::CrossTrainer(_) => (4, 15),
+        // generic
+        MachineData::HeartRate(_) => (0, 0),
+        // cycling
+        MachineData::SpeedCadence(_) => (2, 0),
+    }
+}
+
+fn millis(duration: Duration) -> u32 {
+    duration.as_millis().min(u32::MAX.into()) as u32
+}
+
+/// km as cm
+fn centimeters(km: f64) -> Option<u32> {
+    (km >= 0.).then(|| (km * 100_000.).round().min(f64::from(u32::MAX - 1)) as u32)
+}
+
+/// km/h as mm/s
+fn speed(kmh: f64) -> Option<u16> {
+    word(kmh / 3.6 * 1000.)
+}
+
+/// A value that fits a FIT byte, the largest one meaning invalid
+fn byte(value: f64) -> Option<u8> {
+    (0. ..255.)
+        .contains(&value.round())
+        .then(|| value.round() as u8)
+}
+
+/// A value that fits a FIT 16 bit integer, the largest one meaning invalid
+fn word(value: f64) -> Option<u16> {
+    (0. ..65535.)
+        .contains(&value.round())
+        .then(|| value.round() as u16)
+}
+
+/// A field of a FIT message, `None` being written as the invalid value of its type
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Enum(u8),
+    U8(Option<u8>),
+    U16(Option<u16>),
+    U32(Option<u32>),
+}
+
+impl Value {
+    fn size(&self) -> u8 {
+        match self {
+            Value::Enum(_) | Value::U8(_) => 1,
+            Value::U16(_) => 2,
+            Value::U32(_) => 4,
+        }
+    }
+
+    fn base_type(&self) -> u8 {
+        match self {
+            Value::Enum(_) => 0x00,
+            Value::U8(_) => 0x02,
+            Value::U16(_) => 0x84,
+            Value::U32(_) => 0x86,
+        }
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            Value::Enum(value) => bytes.push(value),
+            Value::U8(value) => bytes.push(value.unwrap_or(u8::MAX)),
+            Value::U16(value) => bytes.extend(value.unwrap_or(u16::MAX).to_le_bytes()),
+            Value::U32(value) => bytes.extend(value.unwrap_or(u32::MAX).to_le_bytes()),
+        }
+    }
+}
+
+/// Encodes FIT messages, defining every kind of message the first time one is written
+#[derive(Debug, Default)]
+struct FitWriter {
+    data: Vec<u8>,
+    /// the global message number of every local message type, in order of definition
+    defined: Vec<u16>,
+}
+
+impl FitWriter {
+    fn message(&mut self, global: u16, fields: &[(u8, Value)]) {
+        let local = match self.defined.iter().position(|&defined| defined == global) {
+            Some(local) => local as u8,
+            None => {
+                let local = self.defined.len() as u8;
+                self.defined.push(global);
+                // definition message, little endian
+                self.data.extend([0x40 | local, 0, 0]);
+                self.data.extend(global.to_le_bytes());
+                self.data.push(fields.len() as u8);
+                for (number, value) in fields {
+                    self.data.extend([*number, value.size(), value.base_type()]);
+                }
+                local
+            }
+        };
+        self.data.push(local);
+        for (_, value) in fields {
+            value.write(&mut self.data);
+        }
+    }
+
+    /// The whole file, header, messages and checksum
+    fn finish(self) -> Vec<u8> {
+        let mut file = vec![14, PROTOCOL_VERSION];
+        file.extend(PROFILE_VERSION.to_le_bytes());
+        file.extend((self.data.len() as u32).to_le_bytes());
+        file.extend(b".FIT");
+        file.extend(crc(&file).to_le_bytes());
+        file.extend(self.data);
+        file.extend(crc(&file).to_le_bytes());
+        file
+    }
+}
+
+/// The FIT checksum of `bytes`, CRC-16/ARC
+fn crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    bytes.iter().fold(0, |crc, &byte| {
+        let crc = (crc >> 4) ^ TABLE[usize::from(crc & 0xF)] ^ TABLE[usize::from(byte & 0xF)];
+        (crc >> 4) ^ TABLE[usize::from(crc & 0xF)] ^ TABLE[usize::from(byte >> 4)]
+    })
+}
+
+/// The records of a FIT activity file, timed from the first one
+///
+/// Every record turns into bike data, whatever the sport of the activity, holding the power, cadence,
+/// heart rate, speed and distance it has and the time since the first record.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::FitRecorder;
+///
+/// let file = FitRecorder::new().to_bytes();
+/// assert!(kondis::parse_fit(&file)?.is_empty());
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_fit(bytes: &[u8]) -> Result<Vec<(Duration, MachineData)>> {
+    let invalid = |reason: &str| KondisError::InvalidData(format!("Invalid FIT file: {reason}"));
+    let header_size = usize::from(*bytes.first().ok_or_else(|| invalid("empty"))?);
+    if bytes.len() < header_size.max(12) || &bytes[8..12] != b".FIT" {
+        return Err(invalid("no FIT header"));
+    }
+    let data_size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let end = header_size + data_size;
+    if bytes.len() < end + 2 {
+        return Err(invalid("truncated"));
+    }
+    if crc(&bytes[..end + 2]) != 0 {
+        return Err(invalid("checksum mismatch"));
+    }
+
+    let mut definitions: [Option<Definition>; 16] = Default::default();
+    let mut records = Vec::new();
+    let mut first = None;
+    let mut last_timestamp = 0;
+    let mut data = &bytes[header_size..end];
+    while let Some((&header, rest)) = data.split_first() {
+        data = rest;
+        let (local, compressed) = if header & 0x80 != 0 {
+            // the five lowest bits of the timestamp, relative to the last full one
+            let offset = u32::from(header & 0x1F);
+            let mut timestamp = (last_timestamp & !0x1F) + offset;
+            if offset < last_timestamp & 0x1F {
+                timestamp += 0x20;
+            }
+            (usize::from((header >> 5) & 0x03), Some(timestamp))
+        } else if header & 0x40 != 0 {
+            let fixed = take(&mut data, 5)?;
+            let big_endian = fixed[1] == 1;
+            let global = if big_endian {
+                u16::from_be_bytes([fixed[2], fixed[3]])
+            } else {
+                u16::from_le_bytes([fixed[2], fixed[3]])
+            };
+            let mut fields = take(&mut data, 3 * usize::from(fixed[4]))?
+                .chunks(3)
+                .map(|field| (field[0], usize::from(field[1])))
+                .collect::<Vec<_>>();
+            if header & 0x20 != 0 {
+                // developer fields, only skipped over
+                let count = usize::from(take(&mut data, 1)?[0]);
+                let developer = take(&mut data, 3 * count)?;
+                fields.extend(
+                    developer
+                        .chunks(3)
+                        .map(|field| (u8::MAX, usize::from(field[1]))),
+                );
+            }
+            definitions[usize::from(header & 0x0F)] = Some(Definition {
+                global,
+                big_endian,
+                fields,
+            });
+            continue;
+        } else {
+            (usize::from(header & 0x0F), None)
+        };
+
+        let definition = definitions[local]
+            .as_ref()
+            .ok_or_else(|| invalid("data message without a definition"))?;
+        let mut values = Vec::with_capacity(definition.fields.len());
+        for &(number, size) in &definition.fields {
+            let value = take(&mut data, size)?;
+            values.push((number, unsigned(value, definition.big_endian)));
+        }
+        if definition.global != MESG_RECORD {
+            continue;
+        }
+        let field = |number: u8| {
+            values
+                .iter()
+                .find(|(field, _)| *field == number)
+                .and_then(|(_, value)| *value)
+        };
+        let Some(timestamp) = field(253).map(|timestamp| timestamp as u32).or(compressed) else {
+            continue;
+        };
+        last_timestamp = timestamp;
+        let first = *first.get_or_insert(timestamp);
+        let offset = Duration::from_secs(timestamp.saturating_sub(first).into());
+        // enhanced speed holds the same mm/s in 32 bits
+        let speed = field(73).or(field(6));
+        records.push((
+            offset,
+            MachineData::Bike(BikeData {
+                speed: speed.map(|speed| (speed as f64 * 3.6 / 1000.) as f32),
+                cadence: field(4).map(|rpm| rpm as f32),
+                distance: field(5).map(|cm| (cm as f64 / 100_000.) as f32),
+                power: field(7).map(|watts| watts.min(i16::MAX as u64) as i16),
+                heart_rate: field(3).map(|bpm| bpm as f64),
+                time: Some(offset.as_secs() as u32),
+                ..Default::default()
+            }),
+        ));
+    }
+    Ok(records)
+}
+
+/// How the data messages of a local message type are laid out
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    /// the number and size of every field, developer fields numbered `u8::MAX`
+    fields: Vec<(u8, usize)>,
+}
+
+/// The next `size` bytes of `data`, moving past them
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8]> {
+    let (taken, rest) = data
+        .split_at_checked(size)
+        .ok_or_else(|| KondisError::InvalidData("Invalid FIT file: truncated".to_string()))?;
+    *data = rest;
+    Ok(taken)
+}
+
+/// An unsigned FIT value of 1, 2, 4 or 8 bytes, `None` for the invalid value or other sizes
+fn unsigned(bytes: &[u8], big_endian: bool) -> Option<u64> {
+    if !matches!(bytes.len(), 1 | 2 | 4 | 8) || bytes.iter().all(|&byte| byte == u8::MAX) {
+        return None;
+    }
+    let fold = |value: u64, &byte: &u8| value << 8 | u64::from(byte);
+    Some(if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BikeData;
+
+    #[test]
+    fn test_parse_fit() -> Result<()> {
+        let mut recorder = FitRecorder::new();
+        for second in 0..3 {
+            recorder.record(&Reading {
+                timestamp: Duration::from_secs(second),
+                sequence: second,
+                data: MachineData::Bike(BikeData {
+                    speed: Some(36.),
+                    power: Some(150 + second as i16),
+                    heart_rate: Some(140.),
+                    ..Default::default()
+                }),
+            });
+        }
+        let records = parse_fit(&recorder.to_bytes())?;
+        assert_eq!(records.len(), 3);
+        let (offset, data) = records[2].clone();
+        assert_eq!(offset, Duration::from_secs(2));
+        let data = FTMSData::from(data);
+        assert_eq!(data.power, Some(152));
+        assert_eq!(data.speed, Some(36.));
+        assert_eq!(data.heart_rate, Some(140.));
+        assert_eq!(data.cadence, None);
+
+        let mut corrupted = recorder.to_bytes();
+        corrupted[20] ^= 0xFF;
+        assert!(parse_fit(&corrupted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let mut recorder =
+            FitRecorder::new().start_time(UNIX_EPOCH + Duration::from_secs(FIT_EPOCH));
+        for second in 0..4 {
+            recorder.record(&Reading {
+                timestamp: Duration::from_secs(100 + second),
+                sequence: second,
+                data: MachineData::Bike(BikeData {
+                    power: Some(200),
+                    distance: Some(second as f32 / 100.),
+                    ..Default::default()
+                }),
+            });
+            if second == 1 {
+                recorder.lap();
+            }
+        }
+        let file = recorder.to_bytes();
+        assert_eq!(&file[8..12], b".FIT");
+        let size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        assert_eq!(file.len(), 14 + size + 2);
+        assert_eq!(crc(&file[..12]), u16::from_le_bytes([file[12], file[13]]));
+        // the checksum of a file ending in its own checksum comes out as zero
+        assert_eq!(crc(&file), 0);
+
+        // walk the messages, counting them by global message number
+        let mut definitions = Vec::new();
+        let mut counts = std::collections::BTreeMap::new();
+        let mut data = &file[14..14 + size];
+        while let [header, rest @ ..] = data {
+            if header & 0x40 != 0 {
+                let global = u16::from_le_bytes([rest[2], rest[3]]);
+                let fields = usize::from(rest[4]);
+                let size: usize = (0..fields).map(|i| usize::from(rest[6 + 3 * i])).sum();
+                definitions.push((global, size));
+                data = &rest[5 + 3 * fields..];
+            } else {
+                let (global, size) = definitions[usize::from(*header)];
+                *counts.entry(global).or_insert(0) += 1;
+                data = &rest[size..];
+            }
+        }
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            vec![
+                (MESG_FILE_ID, 1),
+                (MESG_SESSION, 1),
+                (MESG_LAP, 2),
+                (MESG_RECORD, 4),
+                (MESG_ACTIVITY, 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use super::FTMSControlOpCode;
+
+/// Result codes of a control point response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResultCode {
+    /// The command was carried out
+    Success,
+    /// The machine doesn't know the op code of the command
+    NotSupported,
+    /// The parameter of the command is out of range or malformed
+    InvalidParameter,
+    /// The machine understood the command but couldn't carry it out
+    OperationFailed,
+    /// Control has to be requested with `RequestControl` first, or was taken by another client
+    ControlNotPermitted,
+    /// A result code the FTMS specification reserves for future use
+    Other(u8),
+}
+
+impl From<u8> for ResultCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => ResultCode::Success,
+            0x02 => ResultCode::NotSupported,
+            0x03 => ResultCode::InvalidParameter,
+            0x04 => ResultCode::OperationFailed,
+            0x05 => ResultCode::ControlNotPermitted,
+            other => ResultCode::Other(other),
+        }
+    }
+}
+
+impl From<ResultCode> for u8 {
+    fn from(code: ResultCode) -> Self {
+        match code {
+            ResultCode::Success => 0x01,
+            ResultCode::NotSupported => 0x02,
+            ResultCode::InvalidParameter => 0x03,
+            ResultCode::OperationFailed => 0x04,
+            ResultCode::ControlNotPermitted => 0x05,
+            ResultCode::Other(code) => code,
+        }
+    }
+}
+
+/// A control point command the machine refused
+///
+/// Control methods like `set_target_power` fail with `KondisError::ControlRejected` holding it, so the
+/// reason can be matched on:
+///
+/// ```
+/// use kondis::{ControlPointError, KondisError, ResultCode};
+///
+/// fn needs_control(error: &KondisError) -> bool {
+///     matches!(
+///         error,
+///         KondisError::ControlRejected(ControlPointError {
+///             result: ResultCode::ControlNotPermitted,
+///             ..
+///         })
+///     )
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlPointError {
+    /// The op code of the refused command
+    pub op_code: u8,
+    pub result: ResultCode,
+}
+
+impl fmt::Display for ControlPointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.result {
+            ResultCode::Success => "succeeded".to_string(),
+            ResultCode::NotSupported => "is not supported".to_string(),
+            ResultCode::InvalidParameter => "has an invalid parameter".to_string(),
+            ResultCode::OperationFailed => "failed".to_string(),
+            ResultCode::ControlNotPermitted => {
+                "is not permitted, control was not granted".to_string()
+            }
+            ResultCode::Other(code) => format!("failed with result code {code:#04x}"),
+        };
+        write!(f, "Control point command {:#04x} {}", self.op_code, reason)
+    }
+}
+
+impl std::error::Error for ControlPointError {}
+
+/// Parse a control point indication, if it is the response to the command with `op_code`
+///
+/// Resolves to the response parameters following the result code when the command succeeded.
+pub fn parse_control_point_response(
+    data: &[u8],
+    op_code: u8,
+) -> Option<Result<Vec<u8>, ControlPointError>> {
+    match data {
+        [response, request, result, parameters @ ..]
+            if *response == FTMSControlOpCode::Success as u8 && *request == op_code =>
+        {
+            Some(match ResultCode::from(*result) {
+                ResultCode::Success => Ok(parameters.to_vec()),
+                result => Err(ControlPointError { op_code, result }),
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_point_response() {
+        assert_eq!(
+            parse_control_point_response(&[0x80, 0x05, 0x01], 0x05),
+            Some(Ok(Vec::new()))
+        );
+        assert_eq!(
+            parse_control_point_response(&[0x80, 0x05, 0x05], 0x05),
+            Some(Err(ControlPointError {
+                op_code: 0x05,
+                result: ResultCode::ControlNotPermitted
+            }))
+        );
+        assert_eq!(
+            parse_control_point_response(&[0x80, 0x00, 0x01], 0x05),
+            None
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use super::FTMSData;
+use super::reader::Reader;
+
+use crate::Result;
+
+/// Cross trainer data structure
+/// Used to represent the data received from FTMS cross trainers and ellipticals
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrossTrainerData {
+    /// km/h
+    pub speed: Option<f32>,
+    /// km
+    pub distance: Option<f32>,
+    /// steps per minute
+    pub stride_rate: Option<f32>,
+    pub stride_count: Option<f32>,
+    /// meters
+    pub elevation_gain: Option<f32>,
+    /// percent
+    pub inclination: Option<f32>,
+    pub resistance: Option<f64>,
+    pub power: Option<i16>,
+    pub calories: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub time: Option<u32>,
+    /// whether the user is moving backwards
+    pub backwards: bool,
+}
+
+impl From<CrossTrainerData> for FTMSData {
+    fn from(data: CrossTrainerData) -> Self {
+        FTMSData {
+            speed: data.speed,
+            cadence: data.stride_rate,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+/// Flag bits of the Cross Trainer Data characteristic (0x2ACE)
+///
+/// Unlike the other machines, the flags field is 24 bits wide. `MORE_DATA` is inverted and signals the
+/// instantaneous speed when cleared, and `MOVEMENT_DIRECTION` carries no field of its own.
+mod flags {
+    pub const MORE_DATA: u32 = 1 << 0;
+    pub const AVERAGE_SPEED: u32 = 1 << 1;
+    pub const TOTAL_DISTANCE: u32 = 1 << 2;
+    pub const STEP_COUNT: u32 = 1 << 3;
+    pub const STRIDE_COUNT: u32 = 1 << 4;
+    pub const ELEVATION_GAIN: u32 = 1 << 5;
+    pub const INCLINATION: u32 = 1 << 6;
+    pub const RESISTANCE_LEVEL: u32 = 1 << 7;
+    pub const INSTANTANEOUS_POWER: u32 = 1 << 8;
+    pub const AVERAGE_POWER: u32 = 1 << 9;
+    pub const EXPENDED_ENERGY: u32 = 1 << 10;
+    pub const HEART_RATE: u32 = 1 << 11;
+    pub const METABOLIC_EQUIVALENT: u32 = 1 << 12;
+    pub const ELAPSED_TIME: u32 = 1 << 13;
+    pub const REMAINING_TIME: u32 = 1 << 14;
+    pub const MOVEMENT_DIRECTION: u32 = 1 << 15;
+}
+
+/// Parse a Cross Trainer Data notification into `CrossTrainerData`
+///
+/// Only the fields flagged as present are populated, everything else is left as `None`.
+pub fn parse_cross_trainer_data(data: &[u8]) -> Result<CrossTrainerData> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u24()?;
+    let mut parsed = CrossTrainerData {
+        backwards: flags & flags::MOVEMENT_DIRECTION != 0,
+        ..Default::default()
+    };
+
+    if flags & flags::MORE_DATA == 0 {
+        parsed.speed = Some(reader.u16()? as f32 / 100.);
+    }
+    if flags & flags::AVERAGE_SPEED != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::TOTAL_DISTANCE != 0 {
+        parsed.distance = Some(reader.u24()? as f32 / 1000.);
+    }
+    if flags & flags::STEP_COUNT != 0 {
+        parsed.stride_rate = Some(reader.u16()? as f32);
+        reader.u16()?; // average step rate
+    }
+    if flags & flags::STRIDE_COUNT != 0 {
+        parsed.stride_count = Some(reader.u16()? as f32 / 10.);
+    }
+    if flags & flags::ELEVATION_GAIN != 0 {
+        parsed.elevation_gain = Some(reader.u16()? as f32);
+        reader.u16()?; // negative elevation gain
+    }
+    if flags & flags::INCLINATION != 0 {
+        parsed.inclination = Some(reader.i16()? as f32 / 10.);
+        reader.i16()?; // ramp angle
+    }
+    if flags & flags::RESISTANCE_LEVEL != 0 {
+        parsed.resistance = Some(reader.i16()? as f64);
+    }
+    if flags & flags::INSTANTANEOUS_POWER != 0 {
+        parsed.power = Some(reader.i16()?);
+    }
+    if flags & flags::AVERAGE_POWER != 0 {
+        reader.i16()?;
+    }
+    if flags & flags::EXPENDED_ENERGY != 0 {
+        parsed.calories = Some(reader.u16()? as f64);
+        reader.u16()?; // energy per hour
+        reader.u8()?; // energy per minute
+    }
+    if flags & flags::HEART_RATE != 0 {
+        parsed.heart_rate = Some(reader.u8()? as f64);
+    }
+    if flags & flags::METABOLIC_EQUIVALENT != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::ELAPSED_TIME != 0 {
+        parsed.time = Some(reader.u16()? as u32);
+    }
+    if flags & flags::REMAINING_TIME != 0 {
+        reader.u16()?;
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stride_elevation_resistance() -> Result<()> {
+        let data = [
+            0xA8, 0x80, 0x00, // flags: steps + elevation + resistance + backwards
+            0x20, 0x03, // speed 8.00 km/h
+            0x78, 0x00, 0x70, 0x00, // 120 steps per minute, average 112
+            0x0C, 0x00, 0x00, 0x00, // 12 m up, 0 m down
+            0x08, 0x00, // resistance 8
+        ];
+        let parsed = parse_cross_trainer_data(&data)?;
+        assert_eq!(parsed.speed, Some(8.0));
+        assert_eq!(parsed.stride_rate, Some(120.0));
+        assert_eq!(parsed.elevation_gain, Some(12.0));
+        assert_eq!(parsed.resistance, Some(8.0));
+        assert!(parsed.backwards);
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use super::reader::Reader;
+
+use crate::Result;
+
+/// What an equipment can report and which targets it accepts
+///
+/// Decoded from the Fitness Machine Feature characteristic (0x2ACC) for FTMS machines.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    pub targets: TargetCapabilities,
+    pub data: DataCapabilities,
+}
+
+/// The targets an equipment accepts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TargetCapabilities {
+    pub speed: bool,
+    pub inclination: bool,
+    pub resistance: bool,
+    pub power: bool,
+    pub heart_rate: bool,
+    pub expended_energy: bool,
+    pub distance: bool,
+    pub training_time: bool,
+    pub simulation: bool,
+    pub spin_down: bool,
+    pub cadence: bool,
+}
+
+/// The data fields an equipment reports
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataCapabilities {
+    pub average_speed: bool,
+    pub cadence: bool,
+    pub distance: bool,
+    pub inclination: bool,
+    pub elevation_gain: bool,
+    pub pace: bool,
+    pub step_count: bool,
+    pub resistance: bool,
+    pub stride_count: bool,
+    pub expended_energy: bool,
+    pub heart_rate: bool,
+    pub elapsed_time: bool,
+    pub remaining_time: bool,
+    pub power: bool,
+}
+
+/// Parse the Fitness Machine Feature characteristic into `Capabilities`
+///
+/// The characteristic holds two bit fields, the fitness machine features followed by the target
+/// setting features.
+pub fn parse_fitness_machine_feature(data: &[u8]) -> Result<Capabilities> {
+    let mut reader = Reader::new(data);
+    let machine = reader.u32()?;
+    let target = reader.u32()?;
+    let bit = |field: u32, n: u32| field & (1 << n) != 0;
+
+    Ok(Capabilities {
+        targets: TargetCapabilities {
+            speed: bit(target, 0),
+            inclination: bit(target, 1),
+            resistance: bit(target, 2),
+            power: bit(target, 3),
+            heart_rate: bit(target, 4),
+            expended_energy: bit(target, 5),
+            distance: bit(target, 8),
+            training_time: bit(target, 9),
+            simulation: bit(target, 13),
+            spin_down: bit(target, 15),
+            cadence: bit(target, 16),
+        },
+        data: DataCapabilities {
+            average_speed: bit(machine, 0),
+            cadence: bit(machine, 1),
+            distance: bit(machine, 2),
+            inclination: bit(machine, 3),
+            elevation_gain: bit(machine, 4),
+            pace: bit(machine, 5),
+            step_count: bit(machine, 6),
+            resistance: bit(machine, 7),
+            stride_count: bit(machine, 8),
+            expended_energy: bit(machine, 9),
+            heart_rate: bit(machine, 10),
+            elapsed_time: bit(machine, 12),
+            remaining_time: bit(machine, 13),
+            power: bit(machine, 14) || bit(machine, 15),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smart_trainer_features() -> Result<()> {
+        // cadence + power measurement, power + simulation + spin down targets
+        let data = [0x02, 0x40, 0x00, 0x00, 0x08, 0xA0, 0x00, 0x00];
+        let capabilities = parse_fitness_machine_feature(&data)?;
+        assert!(capabilities.data.cadence);
+        assert!(capabilities.data.power);
+        assert!(!capabilities.data.heart_rate);
+        assert!(capabilities.targets.power);
+        assert!(capabilities.targets.simulation);
+        assert!(capabilities.targets.spin_down);
+        assert!(!capabilities.targets.cadence);
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use super::FTMSControlOpCode;
+
+use crate::{KondisError, Result};
+
+/// A goal the machine tracks itself, ending the session once reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrainingGoal {
+    /// kcal
+    ExpendedEnergy(u16),
+    /// meters, at most 16 777 215
+    Distance(u32),
+    /// seconds
+    TrainingTime(u16),
+}
+
+/// Encode a training goal as a control point command
+pub fn training_goal(goal: TrainingGoal) -> Result<Vec<u8>> {
+    let command = match goal {
+        TrainingGoal::ExpendedEnergy(kcal) => {
+            let value = kcal.to_le_bytes();
+            vec![
+                FTMSControlOpCode::TargetExpendedEnergy as u8,
+                value[0],
+                value[1],
+            ]
+        }
+        TrainingGoal::Distance(meters) => {
+            if meters > 0xFF_FFFF {
+                return Err(KondisError::InvalidArgument(
+                    "Distance must be at most 16777215 m".to_string(),
+                ));
+            }
+            let value = meters.to_le_bytes();
+            vec![
+                FTMSControlOpCode::TargetDistance as u8,
+                value[0],
+                value[1],
+                value[2],
+            ]
+        }
+        TrainingGoal::TrainingTime(seconds) => {
+            let value = seconds.to_le_bytes();
+            vec![
+                FTMSControlOpCode::TargetTrainingTime as u8,
+                value[0],
+                value[1],
+            ]
+        }
+    };
+    Ok(command)
+}
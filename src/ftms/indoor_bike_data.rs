@@ -0,0 +1,176 @@
+use super::FTMSData;
+use super::reader::Reader;
+
+use crate::Result;
+
+/// Indoor bike data structure
+/// Used to represent the data received from FTMS indoor bikes
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BikeData {
+    /// km/h
+    pub speed: Option<f32>,
+    /// rpm
+    pub cadence: Option<f32>,
+    /// km
+    pub distance: Option<f32>,
+    pub resistance: Option<f64>,
+    pub power: Option<i16>,
+    pub calories: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub time: Option<u32>,
+}
+
+impl From<BikeData> for FTMSData {
+    fn from(data: BikeData) -> Self {
+        FTMSData {
+            speed: data.speed,
+            cadence: data.cadence,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+/// Flag bits of the Indoor Bike Data characteristic (0x2AD2)
+///
+/// Every bit except `MORE_DATA` signals that the matching field is present. `MORE_DATA` is inverted:
+/// when it is cleared, the instantaneous speed field is present.
+pub(crate) mod flags {
+    pub const MORE_DATA: u16 = 1 << 0;
+    pub const AVERAGE_SPEED: u16 = 1 << 1;
+    pub const INSTANTANEOUS_CADENCE: u16 = 1 << 2;
+    pub const AVERAGE_CADENCE: u16 = 1 << 3;
+    pub const TOTAL_DISTANCE: u16 = 1 << 4;
+    pub const RESISTANCE_LEVEL: u16 = 1 << 5;
+    pub const INSTANTANEOUS_POWER: u16 = 1 << 6;
+    pub const AVERAGE_POWER: u16 = 1 << 7;
+    pub const EXPENDED_ENERGY: u16 = 1 << 8;
+    pub const HEART_RATE: u16 = 1 << 9;
+    pub const METABOLIC_EQUIVALENT: u16 = 1 << 10;
+    pub const ELAPSED_TIME: u16 = 1 << 11;
+    pub const REMAINING_TIME: u16 = 1 << 12;
+}
+
+/// Parse an Indoor Bike Data notification into `BikeData`
+///
+/// Only the fields flagged as present are populated, everything else is left as `None`.
+/// Averages, metabolic equivalent and remaining time are read past but not kept.
+pub fn parse_indoor_bike_data(data: &[u8]) -> Result<BikeData> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u16()?;
+    let mut parsed = BikeData::default();
+
+    if flags & flags::MORE_DATA == 0 {
+        parsed.speed = Some(reader.u16()? as f32 / 100.);
+    }
+    if flags & flags::AVERAGE_SPEED != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::INSTANTANEOUS_CADENCE != 0 {
+        parsed.cadence = Some((reader.u16()? as f32 / 2.).round());
+    }
+    if flags & flags::AVERAGE_CADENCE != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::TOTAL_DISTANCE != 0 {
+        parsed.distance = Some(reader.u24()? as f32 / 1000.);
+    }
+    if flags & flags::RESISTANCE_LEVEL != 0 {
+        parsed.resistance = Some(reader.i16()? as f64);
+    }
+    if flags & flags::INSTANTANEOUS_POWER != 0 {
+        parsed.power = Some(reader.i16()?);
+    }
+    if flags & flags::AVERAGE_POWER != 0 {
+        reader.i16()?;
+    }
+    if flags & flags::EXPENDED_ENERGY != 0 {
+        parsed.calories = Some(reader.u16()? as f64);
+        reader.u16()?; // energy per hour
+        reader.u8()?; // energy per minute
+    }
+    if flags & flags::HEART_RATE != 0 {
+        parsed.heart_rate = Some(reader.u8()? as f64);
+    }
+    if flags & flags::METABOLIC_EQUIVALENT != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::ELAPSED_TIME != 0 {
+        parsed.time = Some(reader.u16()? as u32);
+    }
+    if flags & flags::REMAINING_TIME != 0 {
+        reader.u16()?;
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_cadence_power() -> Result<()> {
+        // flags: cadence + power, speed present since MORE_DATA is cleared
+        let data = [0x44, 0x00, 0xC4, 0x09, 0xB4, 0x00, 0xC8, 0x00];
+        let parsed = parse_indoor_bike_data(&data)?;
+        assert_eq!(parsed.speed, Some(25.0));
+        assert_eq!(parsed.cadence, Some(90.0));
+        assert_eq!(parsed.power, Some(200));
+        assert_eq!(parsed.distance, None);
+        assert_eq!(parsed.time, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_more_data_skips_speed() -> Result<()> {
+        // flags: MORE_DATA + heart rate + elapsed time
+        let data = [0x01, 0x0A, 0x8C, 0x3C, 0x00];
+        let parsed = parse_indoor_bike_data(&data)?;
+        assert_eq!(parsed.speed, None);
+        assert_eq!(parsed.heart_rate, Some(140.0));
+        assert_eq!(parsed.time, Some(60));
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_fields() -> Result<()> {
+        let data = [
+            0xFE, 0x1F, // flags, every field present
+            0xE8, 0x03, // speed 10.00 km/h
+            0x00, 0x00, // average speed
+            0x78, 0x00, // cadence 60 rpm
+            0x00, 0x00, // average cadence
+            0xD0, 0x07, 0x00, // distance 2000 m
+            0x05, 0x00, // resistance 5
+            0x96, 0x00, // power 150 W
+            0x00, 0x00, // average power
+            0x2A, 0x00, 0x00, 0x00, 0x00, // 42 kcal
+            0x78, // heart rate 120 bpm
+            0x00, // metabolic equivalent
+            0x2C, 0x01, // elapsed 300 s
+            0x00, 0x00, // remaining time
+        ];
+        let parsed = parse_indoor_bike_data(&data)?;
+        assert_eq!(parsed.speed, Some(10.0));
+        assert_eq!(parsed.cadence, Some(60.0));
+        assert_eq!(parsed.distance, Some(2.0));
+        assert_eq!(parsed.resistance, Some(5.0));
+        assert_eq!(parsed.power, Some(150));
+        assert_eq!(parsed.calories, Some(42.0));
+        assert_eq!(parsed.heart_rate, Some(120.0));
+        assert_eq!(parsed.time, Some(300));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let data = [0x44, 0x00, 0xC4, 0x09, 0xB4];
+        assert!(parse_indoor_bike_data(&data).is_err());
+    }
+}
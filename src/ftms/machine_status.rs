@@ -0,0 +1,114 @@
+use super::SpinDownStatus;
+use super::reader::Reader;
+
+use crate::Result;
+
+/// A state change reported by the Fitness Machine Status characteristic (0x2ADA)
+///
+/// Most of these are the machine acknowledging a command, but the "by user" and "safety key" ones are
+/// sent when somebody presses buttons on the console itself.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MachineStatus {
+    Reset,
+    StoppedByUser,
+    PausedByUser,
+    StoppedBySafetyKey,
+    StartedOrResumedByUser,
+    /// km/h
+    TargetSpeedChanged(f32),
+    /// percent
+    TargetInclinationChanged(f32),
+    TargetResistanceLevelChanged(f32),
+    /// watts
+    TargetPowerChanged(i16),
+    /// bpm
+    TargetHeartRateChanged(u8),
+    /// kcal
+    TargetedExpendedEnergyChanged(u16),
+    /// meters
+    TargetedDistanceChanged(u32),
+    /// seconds
+    TargetedTrainingTimeChanged(u16),
+    SimulationParametersChanged {
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    },
+    SpinDown(SpinDownStatus),
+    /// rpm
+    TargetCadenceChanged(f32),
+    ControlPermissionLost,
+    /// A status this crate doesn't decode yet, kept as the raw op code
+    Other(u8),
+}
+
+/// Parse a Fitness Machine Status notification
+pub fn parse_machine_status(data: &[u8]) -> Result<MachineStatus> {
+    let mut reader = Reader::new(data);
+    let op_code = reader.u8()?;
+    let status = match op_code {
+        0x01 => MachineStatus::Reset,
+        0x02 => match reader.u8()? {
+            0x02 => MachineStatus::PausedByUser,
+            _ => MachineStatus::StoppedByUser,
+        },
+        0x03 => MachineStatus::StoppedBySafetyKey,
+        0x04 => MachineStatus::StartedOrResumedByUser,
+        0x05 => MachineStatus::TargetSpeedChanged(reader.u16()? as f32 / 100.),
+        0x06 => MachineStatus::TargetInclinationChanged(reader.i16()? as f32 / 10.),
+        0x07 => MachineStatus::TargetResistanceLevelChanged(reader.u8()? as f32 / 10.),
+        0x08 => MachineStatus::TargetPowerChanged(reader.i16()?),
+        0x09 => MachineStatus::TargetHeartRateChanged(reader.u8()?),
+        0x0A => MachineStatus::TargetedExpendedEnergyChanged(reader.u16()?),
+        0x0D => MachineStatus::TargetedDistanceChanged(reader.u24()?),
+        0x0E => MachineStatus::TargetedTrainingTimeChanged(reader.u16()?),
+        0x12 => {
+            let wind_speed = reader.i16()? as f32 / 1000.;
+            let grade = reader.i16()? as f32 / 100.;
+            let crr = reader.u8()? as f32 / 10000.;
+            let cw = reader.u8()? as f32 / 100.;
+            MachineStatus::SimulationParametersChanged {
+                grade,
+                wind_speed,
+                crr,
+                cw,
+            }
+        }
+        0x14 => MachineStatus::SpinDown(match reader.u8()? {
+            0x01 => SpinDownStatus::Requested,
+            0x02 => SpinDownStatus::Success,
+            0x04 => SpinDownStatus::StopPedaling,
+            _ => SpinDownStatus::Error,
+        }),
+        0x15 => MachineStatus::TargetCadenceChanged(reader.u16()? as f32 / 2.),
+        0xFF => MachineStatus::ControlPermissionLost,
+        other => MachineStatus::Other(other),
+    };
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_status() -> Result<()> {
+        assert_eq!(
+            parse_machine_status(&[0x02, 0x02])?,
+            MachineStatus::PausedByUser
+        );
+        assert_eq!(
+            parse_machine_status(&[0x08, 0xFA, 0x00])?,
+            MachineStatus::TargetPowerChanged(250)
+        );
+        assert_eq!(
+            parse_machine_status(&[0x14, 0x04])?,
+            MachineStatus::SpinDown(SpinDownStatus::StopPedaling)
+        );
+        assert_eq!(parse_machine_status(&[0x42])?, MachineStatus::Other(0x42));
+        assert!(parse_machine_status(&[0x08, 0xFA]).is_err());
+        Ok(())
+    }
+}
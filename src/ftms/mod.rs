@@ -1,16 +1,166 @@
+mod control_point;
+mod cross_trainer_data;
+mod feature;
+mod goal;
+pub(crate) mod indoor_bike_data;
+mod machine_status;
+pub(crate) mod reader;
+mod rower_data;
+mod spin_down;
+mod supported_range;
+mod treadmill_data;
+
+pub use control_point::{ControlPointError, ResultCode, parse_control_point_response};
+pub use cross_trainer_data::{CrossTrainerData, parse_cross_trainer_data};
+pub use feature::{
+    Capabilities, DataCapabilities, TargetCapabilities, parse_fitness_machine_feature,
+};
+pub use goal::{TrainingGoal, training_goal};
+pub use indoor_bike_data::{BikeData, parse_indoor_bike_data};
+pub use machine_status::{MachineStatus, parse_machine_status};
+pub use rower_data::{RowerData, parse_rower_data};
+pub use spin_down::{SpinDownControl, SpinDownResult, SpinDownStatus, parse_spin_down_target};
+pub use supported_range::{
+    SupportedRange, SupportedRanges, parse_supported_inclination_range,
+    parse_supported_power_range, parse_supported_resistance_level_range,
+    parse_supported_speed_range,
+};
+pub use treadmill_data::{TreadmillData, parse_treadmill_data};
+use uuid::Uuid;
+
+use crate::sensors::{HeartRateData, SpeedCadenceData};
+use crate::{KondisError, Result};
+
+/// The 128-bit UUID of a 16-bit UUID assigned by the Bluetooth SIG, on the Bluetooth base UUID
+pub(crate) const fn uuid_from_u16(short: u16) -> Uuid {
+    Uuid::from_u128(0x0000_0000_0000_1000_8000_0080_5f9b_34fb | ((short as u128) << 96))
+}
+
+/// Fitness Machine Service
+pub const FITNESS_MACHINE_SERVICE_UUID: Uuid = uuid_from_u16(0x1826);
+/// Fitness Machine Feature characteristic, read once to learn what the machine supports
+pub const FITNESS_MACHINE_FEATURE_UUID: Uuid = uuid_from_u16(0x2ACC);
+/// Treadmill Data characteristic, notified by treadmills
+pub const TREADMILL_DATA_UUID: Uuid = uuid_from_u16(0x2ACD);
+/// Cross Trainer Data characteristic, notified by cross trainers
+pub const CROSS_TRAINER_DATA_UUID: Uuid = uuid_from_u16(0x2ACE);
+/// Rower Data characteristic, notified by rowing machines
+pub const ROWER_DATA_UUID: Uuid = uuid_from_u16(0x2AD1);
+/// Indoor Bike Data characteristic, notified by bikes
+pub const INDOOR_BIKE_DATA_UUID: Uuid = uuid_from_u16(0x2AD2);
+/// Fitness Machine Control Point characteristic, written to and indicated on
+pub const CONTROL_POINT_UUID: Uuid = uuid_from_u16(0x2AD9);
+/// Supported Speed Range characteristic, read once when connecting
+pub const SUPPORTED_SPEED_RANGE_UUID: Uuid = uuid_from_u16(0x2AD4);
+/// Supported Inclination Range characteristic, read once when connecting
+pub const SUPPORTED_INCLINATION_RANGE_UUID: Uuid = uuid_from_u16(0x2AD5);
+/// Supported Resistance Level Range characteristic, read once when connecting
+pub const SUPPORTED_RESISTANCE_LEVEL_RANGE_UUID: Uuid = uuid_from_u16(0x2AD6);
+/// Supported Power Range characteristic, read once when connecting
+pub const SUPPORTED_POWER_RANGE_UUID: Uuid = uuid_from_u16(0x2AD8);
+/// Fitness Machine Status characteristic, notified when the machine changes state
+pub const FITNESS_MACHINE_STATUS_UUID: Uuid = uuid_from_u16(0x2ADA);
+
+/// Fitness Machine Type bits, advertised as service data of the Fitness Machine Service
+#[allow(dead_code)]
+pub mod machine_type {
+    pub const TREADMILL: u16 = 1 << 0;
+    pub const CROSS_TRAINER: u16 = 1 << 1;
+    pub const STEP_CLIMBER: u16 = 1 << 2;
+    pub const STAIR_CLIMBER: u16 = 1 << 3;
+    pub const ROWER: u16 = 1 << 4;
+    pub const INDOOR_BIKE: u16 = 1 << 5;
+}
+
 /// FTMS data structure
 /// Used to represent the data received from FTMS devices
+///
+/// Fields the machine doesn't report are `None`, rather than indistinguishable from a reported zero.
 #[allow(dead_code)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FTMSData {
-    pub speed: f32,
-    pub cadence: f32,
-    pub distance: f32,
-    pub resistance: f64,
-    pub power: u8,
-    pub calories: f64,
-    pub heart_rate: f64,
-    pub time: u16,
+    /// km/h
+    pub speed: Option<f32>,
+    /// rpm, or strokes or steps per minute
+    pub cadence: Option<f32>,
+    /// km
+    pub distance: Option<f32>,
+    pub resistance: Option<f64>,
+    /// watts, negative when the machine is driving the user
+    pub power: Option<i16>,
+    /// kcal
+    pub calories: Option<f64>,
+    /// bpm
+    pub heart_rate: Option<f64>,
+    /// elapsed seconds
+    pub time: Option<u32>,
+}
+
+/// Data received from a machine, in the shape of its kind of machine
+///
+/// What every kind of machine has in common, like power and heart rate, is available through
+/// `FTMSData::from`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MachineData {
+    Bike(BikeData),
+    Treadmill(TreadmillData),
+    Rower(RowerData),
+    CrossTrainer(CrossTrainerData),
+    /// From a heart rate monitor, rather than a machine
+    HeartRate(HeartRateData),
+    /// From a speed and cadence sensor, rather than a machine
+    SpeedCadence(SpeedCadenceData),
+}
+
+impl From<BikeData> for MachineData {
+    fn from(data: BikeData) -> Self {
+        MachineData::Bike(data)
+    }
+}
+
+impl From<TreadmillData> for MachineData {
+    fn from(data: TreadmillData) -> Self {
+        MachineData::Treadmill(data)
+    }
+}
+
+impl From<RowerData> for MachineData {
+    fn from(data: RowerData) -> Self {
+        MachineData::Rower(data)
+    }
+}
+
+impl From<CrossTrainerData> for MachineData {
+    fn from(data: CrossTrainerData) -> Self {
+        MachineData::CrossTrainer(data)
+    }
+}
+
+impl From<HeartRateData> for MachineData {
+    fn from(data: HeartRateData) -> Self {
+        MachineData::HeartRate(data)
+    }
+}
+
+impl From<SpeedCadenceData> for MachineData {
+    fn from(data: SpeedCadenceData) -> Self {
+        MachineData::SpeedCadence(data)
+    }
+}
+
+impl From<MachineData> for FTMSData {
+    fn from(data: MachineData) -> Self {
+        match data {
+            MachineData::Bike(data) => data.into(),
+            MachineData::Treadmill(data) => data.into(),
+            MachineData::Rower(data) => data.into(),
+            MachineData::CrossTrainer(data) => data.into(),
+            MachineData::HeartRate(data) => data.into(),
+            MachineData::SpeedCadence(data) => data.into(),
+        }
+    }
 }
 
 /// FTMS control operation codes
@@ -19,9 +169,18 @@ pub struct FTMSData {
 #[allow(dead_code)]
 pub enum FTMSControlOpCode {
     RequestControl = 0x00,
+    Reset = 0x01,
+    TargetSpeed = 0x02,
+    TargetInclination = 0x03,
+    TargetResistanceLevel = 0x04,
     TargetPower = 0x05,
+    TargetHeartRate = 0x06,
     Start = 0x07,
     Stop = 0x08,
+    TargetExpendedEnergy = 0x09,
+    TargetDistance = 0x0C,
+    TargetTrainingTime = 0x0D,
+    SimulationParameters = 0x11,
     SpinDownControl = 0x13,
     TargetCadence = 0x14,
     Success = 0x80,
@@ -35,3 +194,206 @@ pub enum StopCode {
     Stop = 0x01,
     Pause = 0x02,
 }
+
+/// Encode an indoor bike simulation parameters command
+///
+/// `grade` is in percent, `wind_speed` in m/s, `crr` is the rolling resistance coefficient and
+/// `cw` the wind resistance coefficient in kg/m.
+pub fn simulation_parameters(grade: f32, wind_speed: f32, crr: f32, cw: f32) -> Result<[u8; 7]> {
+    if !(-100.0..=100.0).contains(&grade) {
+        return Err(KondisError::InvalidArgument(
+            "Grade must be between -100 and 100 %".to_string(),
+        ));
+    }
+    if !(-32.0..=32.0).contains(&wind_speed) {
+        return Err(KondisError::InvalidArgument(
+            "Wind speed must be between -32 and 32 m/s".to_string(),
+        ));
+    }
+    if !(0.0..=0.0255).contains(&crr) {
+        return Err(KondisError::InvalidArgument(
+            "Crr must be between 0 and 0.0255".to_string(),
+        ));
+    }
+    if !(0.0..=2.55).contains(&cw) {
+        return Err(KondisError::InvalidArgument(
+            "Cw must be between 0 and 2.55 kg/m".to_string(),
+        ));
+    }
+    let wind_speed = ((wind_speed * 1000.).round() as i16).to_le_bytes();
+    let grade = ((grade * 100.).round() as i16).to_le_bytes();
+    Ok([
+        FTMSControlOpCode::SimulationParameters as u8,
+        wind_speed[0],
+        wind_speed[1],
+        grade[0],
+        grade[1],
+        (crr * 10000.).round() as u8,
+        (cw * 100.).round() as u8,
+    ])
+}
+
+/// Encode a target power command, in watts
+pub fn target_power(watts: i16) -> [u8; 3] {
+    let value = watts.to_le_bytes();
+    [FTMSControlOpCode::TargetPower as u8, value[0], value[1]]
+}
+
+/// Encode a target heart rate command, in bpm
+pub fn target_heart_rate(bpm: u8) -> Result<[u8; 2]> {
+    if bpm == 0 {
+        return Err(KondisError::InvalidArgument(
+            "Heart rate must be between 1 and 255".to_string(),
+        ));
+    }
+    Ok([FTMSControlOpCode::TargetHeartRate as u8, bpm])
+}
+
+/// The highest cadence equipment gets asked to target, in rpm, well above what anyone pedals
+pub const MAX_CADENCE: i16 = 200;
+
+/// Encode a target cadence command, the targeted cadence having a resolution of 0.5 rpm
+pub fn target_cadence(rpm: i16) -> Result<[u8; 3]> {
+    let Some(value) = rpm.checked_mul(2) else {
+        return Err(KondisError::InvalidArgument(format!(
+            "RPM must be at most {}",
+            i16::MAX / 2
+        )));
+    };
+    let value = value.to_le_bytes();
+    Ok([FTMSControlOpCode::TargetCadence as u8, value[0], value[1]])
+}
+
+/// Encode a target resistance level command, the targeted level having a resolution of 0.1
+pub fn target_resistance_level(level: i16) -> Result<[u8; 3]> {
+    let Some(value) = level.checked_mul(10) else {
+        return Err(KondisError::InvalidArgument(format!(
+            "Resistance level must be between {} and {}",
+            i16::MIN / 10,
+            i16::MAX / 10
+        )));
+    };
+    let value = value.to_le_bytes();
+    Ok([
+        FTMSControlOpCode::TargetResistanceLevel as u8,
+        value[0],
+        value[1],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ftms_data() -> Result<()> {
+        // speed, a negative power and 4000 seconds of elapsed time
+        let data = parse_indoor_bike_data(&[0x40, 0x08, 0xc4, 0x09, 0xce, 0xff, 0xa0, 0x0f])?;
+        assert_eq!(
+            FTMSData::from(MachineData::from(data)),
+            FTMSData {
+                speed: Some(25.),
+                power: Some(-50),
+                time: Some(4000),
+                ..FTMSData::default()
+            }
+        );
+        let heart_rate = FTMSData::from(MachineData::from(HeartRateData {
+            heart_rate: 140.,
+            sensor_contact: None,
+            energy_expended: None,
+            rr_intervals: Vec::new(),
+        }));
+        assert_eq!(heart_rate.heart_rate, Some(140.));
+        assert_eq!((heart_rate.power, heart_rate.speed), (None, None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_power() {
+        assert_eq!(target_power(250), [0x05, 0xFA, 0x00]);
+        assert_eq!(target_power(1000), [0x05, 0xE8, 0x03]);
+        assert_eq!(target_power(-1), [0x05, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_target_heart_rate() -> Result<()> {
+        assert_eq!(target_heart_rate(140)?, [0x06, 140]);
+        assert_eq!(target_heart_rate(u8::MAX)?, [0x06, 0xFF]);
+        assert!(matches!(
+            target_heart_rate(0),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_cadence() -> Result<()> {
+        assert_eq!(target_cadence(90)?, [0x14, 180, 0]);
+        assert_eq!(target_cadence(i16::MAX / 2)?, [0x14, 0xFE, 0x7F]);
+        assert!(matches!(
+            target_cadence(i16::MAX / 2 + 1),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_resistance_level() -> Result<()> {
+        assert_eq!(target_resistance_level(12)?, [0x04, 120, 0]);
+        assert_eq!(target_resistance_level(-5)?, [0x04, 0xCE, 0xFF]);
+        assert!(matches!(
+            target_resistance_level(i16::MAX / 10 + 1),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() -> serde_json::Result<()> {
+        fn round_trip<T>(value: T) -> serde_json::Result<T>
+        where
+            T: serde::Serialize + serde::de::DeserializeOwned,
+        {
+            serde_json::from_str(&serde_json::to_string(&value)?)
+        }
+
+        let data = MachineData::Bike(BikeData {
+            power: Some(250),
+            cadence: Some(90.5),
+            ..Default::default()
+        });
+        assert_eq!(round_trip(data.clone())?, data);
+        let status = MachineStatus::TargetPowerChanged(200);
+        assert_eq!(round_trip(status.clone())?, status);
+        let ranges = SupportedRanges {
+            power: parse_supported_power_range(&[0, 0, 0xe8, 0x03, 1, 0]).ok(),
+            ..SupportedRanges::default()
+        };
+        assert_eq!(round_trip(ranges)?, ranges);
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulation_parameters() -> Result<()> {
+        let command = simulation_parameters(-1.5, 2.0, 0.004, 0.51)?;
+        assert_eq!(command, [0x11, 0xD0, 0x07, 0x6A, 0xFF, 40, 51]);
+        assert!(simulation_parameters(0.0, 0.0, 0.1, 0.51).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_training_goal() -> Result<()> {
+        assert_eq!(
+            training_goal(TrainingGoal::Distance(20_000))?,
+            [0x0C, 0x20, 0x4E, 0x00]
+        );
+        assert_eq!(
+            training_goal(TrainingGoal::TrainingTime(3600))?,
+            [0x0D, 0x10, 0x0E]
+        );
+        assert!(training_goal(TrainingGoal::Distance(0x100_0000)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use super::FTMSData;
+use super::reader::Reader;
+
+use crate::Result;
+
+/// Rower data structure
+/// Used to represent the data received from FTMS rowing machines
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RowerData {
+    /// strokes per minute
+    pub stroke_rate: Option<f32>,
+    pub stroke_count: Option<u16>,
+    /// km
+    pub distance: Option<f32>,
+    /// split pace, seconds per 500 m
+    pub pace: Option<u16>,
+    pub power: Option<i16>,
+    pub resistance: Option<f64>,
+    pub calories: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub time: Option<u32>,
+}
+
+impl From<RowerData> for FTMSData {
+    fn from(data: RowerData) -> Self {
+        FTMSData {
+            // 500 m per `pace` seconds, in km/h
+            speed: data
+                .pace
+                .filter(|&pace| pace != 0)
+                .map(|pace| 1800. / pace as f32),
+            cadence: data.stroke_rate,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+/// Flag bits of the Rower Data characteristic (0x2AD1)
+///
+/// `MORE_DATA` is inverted and signals the stroke rate and stroke count when cleared.
+mod flags {
+    pub const MORE_DATA: u16 = 1 << 0;
+    pub const AVERAGE_STROKE_RATE: u16 = 1 << 1;
+    pub const TOTAL_DISTANCE: u16 = 1 << 2;
+    pub const INSTANTANEOUS_PACE: u16 = 1 << 3;
+    pub const AVERAGE_PACE: u16 = 1 << 4;
+    pub const INSTANTANEOUS_POWER: u16 = 1 << 5;
+    pub const AVERAGE_POWER: u16 = 1 << 6;
+    pub const RESISTANCE_LEVEL: u16 = 1 << 7;
+    pub const EXPENDED_ENERGY: u16 = 1 << 8;
+    pub const HEART_RATE: u16 = 1 << 9;
+    pub const METABOLIC_EQUIVALENT: u16 = 1 << 10;
+    pub const ELAPSED_TIME: u16 = 1 << 11;
+    pub const REMAINING_TIME: u16 = 1 << 12;
+}
+
+/// Parse a Rower Data notification into `RowerData`
+///
+/// Only the fields flagged as present are populated, everything else is left as `None`.
+pub fn parse_rower_data(data: &[u8]) -> Result<RowerData> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u16()?;
+    let mut parsed = RowerData::default();
+
+    if flags & flags::MORE_DATA == 0 {
+        parsed.stroke_rate = Some(reader.u8()? as f32 / 2.);
+        parsed.stroke_count = Some(reader.u16()?);
+    }
+    if flags & flags::AVERAGE_STROKE_RATE != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::TOTAL_DISTANCE != 0 {
+        parsed.distance = Some(reader.u24()? as f32 / 1000.);
+    }
+    if flags & flags::INSTANTANEOUS_PACE != 0 {
+        parsed.pace = Some(reader.u16()?);
+    }
+    if flags & flags::AVERAGE_PACE != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::INSTANTANEOUS_POWER != 0 {
+        parsed.power = Some(reader.i16()?);
+    }
+    if flags & flags::AVERAGE_POWER != 0 {
+        reader.i16()?;
+    }
+    if flags & flags::RESISTANCE_LEVEL != 0 {
+        parsed.resistance = Some(reader.i16()? as f64);
+    }
+    if flags & flags::EXPENDED_ENERGY != 0 {
+        parsed.calories = Some(reader.u16()? as f64);
+        reader.u16()?; // energy per hour
+        reader.u8()?; // energy per minute
+    }
+    if flags & flags::HEART_RATE != 0 {
+        parsed.heart_rate = Some(reader.u8()? as f64);
+    }
+    if flags & flags::METABOLIC_EQUIVALENT != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::ELAPSED_TIME != 0 {
+        parsed.time = Some(reader.u16()? as u32);
+    }
+    if flags & flags::REMAINING_TIME != 0 {
+        reader.u16()?;
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stroke_metrics() -> Result<()> {
+        let data = [
+            0x2C, 0x00, // flags: distance + pace + power
+            0x30, // stroke rate 24 spm
+            0x2A, 0x00, // 42 strokes
+            0x20, 0x03, 0x00, // distance 800 m
+            0x78, 0x00, // split 2:00 /500 m
+            0xB4, 0x00, // power 180 W
+        ];
+        let parsed = parse_rower_data(&data)?;
+        assert_eq!(parsed.stroke_rate, Some(24.0));
+        assert_eq!(parsed.stroke_count, Some(42));
+        assert_eq!(parsed.distance, Some(0.8));
+        assert_eq!(parsed.pace, Some(120));
+        assert_eq!(parsed.power, Some(180));
+
+        let data = FTMSData::from(parsed);
+        assert_eq!(data.speed, Some(15.0));
+        assert_eq!(data.cadence, Some(24.0));
+        Ok(())
+    }
+}
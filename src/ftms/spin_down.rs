@@ -0,0 +1,65 @@
+use super::reader::Reader;
+
+use crate::Result;
+
+/// Spin Down Control parameters
+#[allow(dead_code)]
+pub enum SpinDownControl {
+    Start = 0x01,
+    Ignore = 0x02,
+}
+
+/// Progress of a spin down calibration, as reported by the machine
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SpinDownStatus {
+    /// The speed range in km/h the user has to reach before coasting, sent once the machine accepts the request
+    TargetSpeed { low: f32, high: f32 },
+    /// The machine asks the user to speed up to the target speed
+    Requested,
+    /// The target speed is reached, the user should stop pedaling and let the machine coast
+    StopPedaling,
+    /// The calibration succeeded
+    Success,
+    /// The calibration failed
+    Error,
+}
+
+/// The outcome of a spin down calibration
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpinDownResult {
+    pub success: bool,
+    /// km/h
+    pub target_speed_low: f32,
+    /// km/h
+    pub target_speed_high: f32,
+}
+
+/// Parse the parameters of an accepted spin down request into the target speed status
+pub fn parse_spin_down_target(parameters: &[u8]) -> Result<SpinDownStatus> {
+    let mut reader = Reader::new(parameters);
+    Ok(SpinDownStatus::TargetSpeed {
+        low: reader.u16()? as f32 / 100.,
+        high: reader.u16()? as f32 / 100.,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spin_down_target() -> Result<()> {
+        let status = parse_spin_down_target(&[0xD0, 0x07, 0xB8, 0x0B])?;
+        assert_eq!(
+            status,
+            SpinDownStatus::TargetSpeed {
+                low: 20.0,
+                high: 30.0
+            }
+        );
+        assert!(parse_spin_down_target(&[0xD0]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use super::reader::Reader;
+
+use crate::Result;
+
+/// A range of target values advertised by the machine, in the unit of the target
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SupportedRange {
+    pub min: f32,
+    pub max: f32,
+    /// The smallest step the machine can be adjusted by
+    pub increment: f32,
+}
+
+impl SupportedRange {
+    /// Clamp a value into the range, rounded to the nearest increment
+    pub fn clamp(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min, self.max);
+        if self.increment <= 0.0 {
+            return value;
+        }
+        let steps = ((value - self.min) / self.increment).round();
+        (self.min + steps * self.increment).min(self.max)
+    }
+}
+
+/// Every range of target values a machine advertises, for sliders going from `min` to `max` by
+/// `increment`, see `Equipment::supported_ranges`
+///
+/// Ranges the machine doesn't advertise are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SupportedRanges {
+    /// W
+    pub power: Option<SupportedRange>,
+    pub resistance: Option<SupportedRange>,
+    /// km/h
+    pub speed: Option<SupportedRange>,
+    /// %
+    pub inclination: Option<SupportedRange>,
+}
+
+/// Parse the Supported Power Range characteristic (0x2AD8), in watts
+pub fn parse_supported_power_range(data: &[u8]) -> Result<SupportedRange> {
+    let mut reader = Reader::new(data);
+    Ok(SupportedRange {
+        min: reader.i16()? as f32,
+        max: reader.i16()? as f32,
+        increment: reader.u16()? as f32,
+    })
+}
+
+/// Parse the Supported Resistance Level Range characteristic (0x2AD6)
+pub fn parse_supported_resistance_level_range(data: &[u8]) -> Result<SupportedRange> {
+    let mut reader = Reader::new(data);
+    Ok(SupportedRange {
+        min: reader.i16()? as f32 / 10.,
+        max: reader.i16()? as f32 / 10.,
+        increment: reader.u16()? as f32 / 10.,
+    })
+}
+
+/// Parse the Supported Speed Range characteristic (0x2AD4), in km/h
+pub fn parse_supported_speed_range(data: &[u8]) -> Result<SupportedRange> {
+    let mut reader = Reader::new(data);
+    Ok(SupportedRange {
+        min: reader.u16()? as f32 / 100.,
+        max: reader.u16()? as f32 / 100.,
+        increment: reader.u16()? as f32 / 100.,
+    })
+}
+
+/// Parse the Supported Inclination Range characteristic (0x2AD5), in percent
+pub fn parse_supported_inclination_range(data: &[u8]) -> Result<SupportedRange> {
+    let mut reader = Reader::new(data);
+    Ok(SupportedRange {
+        min: reader.i16()? as f32 / 10.,
+        max: reader.i16()? as f32 / 10.,
+        increment: reader.u16()? as f32 / 10.,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_range_clamp() -> Result<()> {
+        let range = parse_supported_power_range(&[0x19, 0x00, 0xD0, 0x07, 0x05, 0x00])?;
+        assert_eq!(range.min, 25.0);
+        assert_eq!(range.max, 2000.0);
+        assert_eq!(range.clamp(10.0), 25.0);
+        assert_eq!(range.clamp(3000.0), 2000.0);
+        assert_eq!(range.clamp(203.0), 205.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resistance_range() -> Result<()> {
+        let range = parse_supported_resistance_level_range(&[0x0A, 0x00, 0xC8, 0x00, 0x0A, 0x00])?;
+        assert_eq!(range.min, 1.0);
+        assert_eq!(range.max, 20.0);
+        assert_eq!(range.increment, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_treadmill_ranges() -> Result<()> {
+        let speed = parse_supported_speed_range(&[0x64, 0x00, 0x60, 0x09, 0x0A, 0x00])?;
+        assert_eq!((speed.min, speed.max, speed.increment), (1.0, 24.0, 0.1));
+        let inclination = parse_supported_inclination_range(&[0xEC, 0xFF, 0x96, 0x00, 0x05, 0x00])?;
+        assert_eq!(
+            (inclination.min, inclination.max, inclination.increment),
+            (-2.0, 15.0, 0.5)
+        );
+        assert!(parse_supported_speed_range(&[0x64, 0x00]).is_err());
+        Ok(())
+    }
+}
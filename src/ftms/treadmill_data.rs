@@ -0,0 +1,148 @@
+use super::FTMSData;
+use super::reader::Reader;
+
+use crate::Result;
+
+/// Treadmill data structure
+/// Used to represent the data received from FTMS treadmills
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreadmillData {
+    /// km/h
+    pub speed: Option<f32>,
+    /// km
+    pub distance: Option<f32>,
+    /// percent
+    pub inclination: Option<f32>,
+    /// degrees
+    pub ramp_angle: Option<f32>,
+    /// meters
+    pub elevation_gain: Option<f32>,
+    /// km/min
+    pub pace: Option<f32>,
+    pub power: Option<i16>,
+    pub calories: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub time: Option<u32>,
+}
+
+impl From<TreadmillData> for FTMSData {
+    fn from(data: TreadmillData) -> Self {
+        FTMSData {
+            speed: data.speed,
+            distance: data.distance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+            ..Default::default()
+        }
+    }
+}
+
+/// Flag bits of the Treadmill Data characteristic (0x2ACD)
+///
+/// As with indoor bike data, `MORE_DATA` is inverted and signals the instantaneous speed when cleared.
+mod flags {
+    pub const MORE_DATA: u16 = 1 << 0;
+    pub const AVERAGE_SPEED: u16 = 1 << 1;
+    pub const TOTAL_DISTANCE: u16 = 1 << 2;
+    pub const INCLINATION: u16 = 1 << 3;
+    pub const ELEVATION_GAIN: u16 = 1 << 4;
+    pub const INSTANTANEOUS_PACE: u16 = 1 << 5;
+    pub const AVERAGE_PACE: u16 = 1 << 6;
+    pub const EXPENDED_ENERGY: u16 = 1 << 7;
+    pub const HEART_RATE: u16 = 1 << 8;
+    pub const METABOLIC_EQUIVALENT: u16 = 1 << 9;
+    pub const ELAPSED_TIME: u16 = 1 << 10;
+    pub const REMAINING_TIME: u16 = 1 << 11;
+    pub const FORCE_AND_POWER: u16 = 1 << 12;
+}
+
+/// Parse a Treadmill Data notification into `TreadmillData`
+///
+/// Only the fields flagged as present are populated, everything else is left as `None`.
+pub fn parse_treadmill_data(data: &[u8]) -> Result<TreadmillData> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u16()?;
+    let mut parsed = TreadmillData::default();
+
+    if flags & flags::MORE_DATA == 0 {
+        parsed.speed = Some(reader.u16()? as f32 / 100.);
+    }
+    if flags & flags::AVERAGE_SPEED != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::TOTAL_DISTANCE != 0 {
+        parsed.distance = Some(reader.u24()? as f32 / 1000.);
+    }
+    if flags & flags::INCLINATION != 0 {
+        parsed.inclination = Some(reader.i16()? as f32 / 10.);
+        parsed.ramp_angle = Some(reader.i16()? as f32 / 10.);
+    }
+    if flags & flags::ELEVATION_GAIN != 0 {
+        parsed.elevation_gain = Some(reader.u16()? as f32 / 10.);
+        reader.u16()?; // negative elevation gain
+    }
+    if flags & flags::INSTANTANEOUS_PACE != 0 {
+        parsed.pace = Some(reader.u8()? as f32 / 10.);
+    }
+    if flags & flags::AVERAGE_PACE != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::EXPENDED_ENERGY != 0 {
+        parsed.calories = Some(reader.u16()? as f64);
+        reader.u16()?; // energy per hour
+        reader.u8()?; // energy per minute
+    }
+    if flags & flags::HEART_RATE != 0 {
+        parsed.heart_rate = Some(reader.u8()? as f64);
+    }
+    if flags & flags::METABOLIC_EQUIVALENT != 0 {
+        reader.u8()?;
+    }
+    if flags & flags::ELAPSED_TIME != 0 {
+        parsed.time = Some(reader.u16()? as u32);
+    }
+    if flags & flags::REMAINING_TIME != 0 {
+        reader.u16()?;
+    }
+    if flags & flags::FORCE_AND_POWER != 0 {
+        reader.i16()?; // force on belt
+        parsed.power = Some(reader.i16()?);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_distance_inclination() -> Result<()> {
+        let data = [
+            0x0C, 0x00, // flags: distance + inclination
+            0x58, 0x02, // speed 6.00 km/h
+            0xF4, 0x01, 0x00, // distance 500 m
+            0x19, 0x00, // inclination 2.5 %
+            0x0E, 0x00, // ramp angle 1.4 degrees
+        ];
+        let parsed = parse_treadmill_data(&data)?;
+        assert_eq!(parsed.speed, Some(6.0));
+        assert_eq!(parsed.distance, Some(0.5));
+        assert_eq!(parsed.inclination, Some(2.5));
+        assert_eq!(parsed.ramp_angle, Some(1.4));
+        assert_eq!(parsed.time, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_inclination() -> Result<()> {
+        let data = [0x09, 0x00, 0xEC, 0xFF, 0x00, 0x00];
+        let parsed = parse_treadmill_data(&data)?;
+        assert_eq!(parsed.speed, None);
+        assert_eq!(parsed.inclination, Some(-2.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::ControllerButton;
+use crate::physics::RideModel;
+use crate::{DeviceEvent, Equipment, FTMSData, KondisError, Result};
+
+/// Teeth of a compact road crankset
+const DEFAULT_CHAINRINGS: [u8; 2] = [34, 50];
+/// Teeth of an 11-28 road cassette
+const DEFAULT_CASSETTE: [u8; 11] = [28, 25, 23, 21, 19, 17, 15, 14, 13, 12, 11];
+/// mm, of a 700x25c road tyre
+const DEFAULT_WHEEL_CIRCUMFERENCE: f64 = 2105.;
+
+/// A combination of a chainring and a cog of a `VirtualDrivetrain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gear {
+    /// teeth of the chainring
+    pub chainring: u8,
+    /// teeth of the cog
+    pub cog: u8,
+}
+
+impl Gear {
+    /// Turns of the wheel for every turn of the cranks
+    pub fn ratio(&self) -> f64 {
+        f64::from(self.chainring) / f64::from(self.cog)
+    }
+}
+
+/// Virtual shifting, for equipment without gears of its own
+///
+/// Works out the speed the cadence the equipment reports rides at in the selected gear, and sets the
+/// target power riding the current grade at that speed takes, like `GradeSimulator` does with the speed
+/// the equipment reports. Gears are every combination of the chainrings and the cassette, from the
+/// easiest to the hardest, shifted through one by one with `shift_up` and `shift_down`, or with the
+/// buttons of a controller, see `follow`.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{
+///     devices::NonBluetoothDevice, CancellationToken, Equipment, RideModel, UserProfile,
+///     VirtualDrivetrain,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let drivetrain = VirtualDrivetrain::new(RideModel::new(&UserProfile::default()))
+///         .chainrings(&[39, 53])
+///         .cassette(&[25, 23, 21, 19, 17, 16, 15, 14, 13, 12, 11]);
+///     drivetrain.shift_up();
+///     println!("{:.1} km/h at 90 rpm", drivetrain.speed(90.));
+///     shutdown.cancel();
+///     drivetrain.run(&device, &shutdown).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct VirtualDrivetrain {
+    model: RideModel,
+    /// from the easiest to the hardest
+    gears: Vec<Gear>,
+    /// mm
+    wheel_circumference: f64,
+    /// index into `gears`
+    gear_tx: watch::Sender<usize>,
+    /// percent
+    grade_tx: watch::Sender<f64>,
+}
+
+impl VirtualDrivetrain {
+    /// A compact crankset and an 11-28 cassette ridden with `model`, in the middle gear on the flat
+    pub fn new(model: RideModel) -> Self {
+        VirtualDrivetrain {
+            model,
+            gears: gears(&DEFAULT_CHAINRINGS, &DEFAULT_CASSETTE),
+            wheel_circumference: DEFAULT_WHEEL_CIRCUMFERENCE,
+            gear_tx: watch::Sender::new(0),
+            grade_tx: watch::Sender::new(0.),
+        }
+        .in_middle_gear()
+    }
+
+    /// The teeth of the chainrings, a 50/34 compact by default, shifting to the middle gear
+    pub fn chainrings(mut self, teeth: &[u8]) -> Self {
+        let (chainrings, cassette) = self.drivetrain();
+        self.gears = gears(teeth, &cassette);
+        if self.gears.is_empty() {
+            self.gears = gears(&chainrings, &cassette);
+        }
+        self.in_middle_gear()
+    }
+
+    /// The teeth of the cogs of the cassette, an 11-28 by default, shifting to the middle gear
+    pub fn cassette(mut self, teeth: &[u8]) -> Self {
+        let (chainrings, cassette) = self.drivetrain();
+        self.gears = gears(&chainrings, teeth);
+        if self.gears.is_empty() {
+            self.gears = gears(&chainrings, &cassette);
+        }
+        self.in_middle_gear()
+    }
+
+    /// The circumference of the virtual wheel in millimeters, 2105 mm by default
+    pub fn wheel_circumference(mut self, mm: f64) -> Self {
+        self.wheel_circumference = mm;
+        self
+    }
+
+    /// Every gear, from the easiest to the hardest
+    pub fn gears(&self) -> &[Gear] {
+        &self.gears
+    }
+
+    /// The selected gear
+    pub fn gear(&self) -> Gear {
+        self.gears[*self.gear_tx.borrow()]
+    }
+
+    /// Select the gear at `index` of `gears`
+    pub fn set_gear(&self, index: usize) -> Result<()> {
+        if index >= self.gears.len() {
+            return Err(KondisError::InvalidArgument(format!(
+                "Gear must be below {}",
+                self.gears.len()
+            )));
+        }
+        self.gear_tx.send_replace(index);
+        Ok(())
+    }
+
+    /// Shift to the next harder gear, staying in the hardest one
+    pub fn shift_up(&self) {
+        let hardest = self.gears.len() - 1;
+        self.gear_tx
+            .send_modify(|gear| *gear = (*gear + 1).min(hardest));
+    }
+
+    /// Shift to the next easier gear, staying in the easiest one
+    pub fn shift_down(&self) {
+        self.gear_tx
+            .send_modify(|gear| *gear = gear.saturating_sub(1));
+    }
+
+    /// Change the grade being ridden, in percent
+    pub fn set_grade(&self, grade: f64) {
+        self.grade_tx.send_replace(grade);
+    }
+
+    /// The grade being ridden, in percent
+    pub fn grade(&self) -> f64 {
+        *self.grade_tx.borrow()
+    }
+
+    /// The speed in km/h pedalling at `cadence` rpm rides at in the selected gear
+    pub fn speed(&self, cadence: f64) -> f64 {
+        cadence / 60. * self.gear().ratio() * self.wheel_circumference / 1000. * 3.6
+    }
+
+    /// Keep setting the target power of `equipment`, until `shutdown` gets cancelled or the equipment
+    /// stops sending events
+    ///
+    /// Fails as soon as the equipment refuses a target power.
+    pub async fn run<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut events = equipment.events();
+        let mut gear_rx = self.gear_tx.subscribe();
+        let mut grade_rx = self.grade_tx.subscribe();
+        let mut cadence = None;
+        let mut watts = None;
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = gear_rx.changed() => {}
+                _ = grade_rx.changed() => {}
+                event = events.recv() => match event {
+                    Ok(DeviceEvent::Data(reading)) => {
+                        cadence = FTMSData::from(reading.data).cadence.map(f64::from).or(cadence);
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+            let Some(cadence) = cadence else {
+                continue;
+            };
+            let grade = *grade_rx.borrow_and_update();
+            gear_rx.mark_unchanged();
+            // equipment takes no less than 1 W, even when coasting downhill
+            let next = self
+                .model
+                .power(self.speed(cadence), grade)
+                .round()
+                .clamp(1., i16::MAX.into()) as i16;
+            if watts != Some(next) {
+                equipment.set_target_power(next).await?;
+                watts = Some(next);
+            }
+        }
+    }
+
+    /// Shift up and down with the buttons of `controller`, like a `devices::ZwiftController`, until
+    /// `shutdown` gets cancelled or the controller stops sending events
+    pub async fn follow<C: Equipment + ?Sized>(
+        &self,
+        controller: &C,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut events = controller.events();
+        loop {
+            let event = tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                event = events.recv() => event,
+            };
+            match event {
+                Ok(DeviceEvent::ButtonPressed(ControllerButton::ShiftUp)) => self.shift_up(),
+                Ok(DeviceEvent::ButtonPressed(ControllerButton::ShiftDown)) => self.shift_down(),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    /// The chainrings and the cassette the gears are combinations of
+    fn drivetrain(&self) -> (Vec<u8>, Vec<u8>) {
+        let mut chainrings: Vec<u8> = self.gears.iter().map(|gear| gear.chainring).collect();
+        let mut cassette: Vec<u8> = self.gears.iter().map(|gear| gear.cog).collect();
+        chainrings.sort_unstable();
+        chainrings.dedup();
+        cassette.sort_unstable();
+        cassette.dedup();
+        (chainrings, cassette)
+    }
+
+    fn in_middle_gear(self) -> Self {
+        self.gear_tx.send_replace(self.gears.len() / 2);
+        self
+    }
+}
+
+/// Every combination of `chainrings` and `cassette`, from the easiest to the hardest, leaving out
+/// sprockets without teeth
+fn gears(chainrings: &[u8], cassette: &[u8]) -> Vec<Gear> {
+    let mut gears: Vec<Gear> = chainrings
+        .iter()
+        .filter(|&&chainring| chainring > 0)
+        .flat_map(|&chainring| {
+            cassette
+                .iter()
+                .filter(|&&cog| cog > 0)
+                .map(move |&cog| Gear { chainring, cog })
+        })
+        .collect();
+    gears.sort_by(|a, b| a.ratio().total_cmp(&b.ratio()));
+    gears
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserProfile;
+    use crate::devices::NonBluetoothDevice;
+
+    #[test]
+    fn test_gears() -> Result<()> {
+        let drivetrain = VirtualDrivetrain::new(RideModel::new(&UserProfile::default()))
+            .chainrings(&[36, 52])
+            .cassette(&[11, 14, 0, 17]);
+        assert_eq!(drivetrain.gears().len(), 6);
+        assert_eq!(
+            drivetrain.gears()[0],
+            Gear {
+                chainring: 36,
+                cog: 17
+            }
+        );
+        assert_eq!(
+            drivetrain.gears()[5],
+            Gear {
+                chainring: 52,
+                cog: 11
+            }
+        );
+        assert_eq!(drivetrain.gear(), drivetrain.gears()[3]);
+
+        let speed = drivetrain.speed(90.);
+        drivetrain.shift_up();
+        assert!(drivetrain.speed(90.) > speed);
+        drivetrain.shift_up();
+        drivetrain.shift_up();
+        assert_eq!(
+            drivetrain.gear(),
+            Gear {
+                chainring: 52,
+                cog: 11
+            }
+        );
+        drivetrain.set_gear(0)?;
+        drivetrain.shift_down();
+        assert_eq!(
+            drivetrain.gear(),
+            Gear {
+                chainring: 36,
+                cog: 17
+            }
+        );
+        assert!(drivetrain.set_gear(6).is_err());
+        // 52x11 at 90 rpm
+        drivetrain.set_gear(5)?;
+        assert!((53. ..55.).contains(&drivetrain.speed(90.)));
+        // without any teeth, the drivetrain stays as it was
+        let drivetrain = drivetrain.chainrings(&[]);
+        assert_eq!(drivetrain.gears().len(), 6);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let controller = NonBluetoothDevice::new(0, &shutdown).await?;
+        let drivetrain = VirtualDrivetrain::new(RideModel::new(&UserProfile::default()));
+        let middle = drivetrain.gear();
+        shutdown.cancel();
+        drivetrain.follow(&controller, &shutdown).await?;
+        assert_eq!(drivetrain.gear(), middle);
+        Ok(())
+    }
+}
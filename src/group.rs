@@ -0,0 +1,308 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::{DeviceEvent, DynEquipment, FTMSData, Result};
+
+/// A stream of the data of a whole group, see `DeviceGroup::data_stream`
+pub type GroupDataStream = Pin<Box<dyn Stream<Item = FTMSData> + Send>>;
+
+/// A stream of the events of every device of a group, see `DeviceGroup::events`
+pub type GroupEventStream = Pin<Box<dyn Stream<Item = (usize, DeviceEvent)> + Send>>;
+
+/// A field of `FTMSData`, to pick the device it comes from, see `DeviceGroup::prefer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DataField {
+    Speed,
+    Cadence,
+    Distance,
+    Resistance,
+    Power,
+    Calories,
+    HeartRate,
+    Time,
+}
+
+/// Several pieces of equipment used together as one, like a bike with a heart rate monitor and a power
+/// meter
+///
+/// A group connects to and disconnects from all of its devices at once, and merges what they report
+/// into a single `FTMSData`. Every field comes from the first device in the group reporting it, so
+/// devices added earlier win where they overlap, unless another device is preferred for the field, see
+/// `DeviceGroup::prefer`. Commands go to the devices themselves, see
+/// `DeviceGroup::devices`.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::devices::{NonBluetoothDevice, SimulatorBike};
+/// use kondis::{CancellationToken, DataField, DeviceGroup, Equipment};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut group = DeviceGroup::new(&shutdown)
+///         .device(Box::new(SimulatorBike::new(32, &shutdown).await?))
+///         .device(Box::new(NonBluetoothDevice::new(32, &shutdown).await?))
+///         // the cadence of the second device, rather than that of the simulator
+///         .prefer(DataField::Cadence, 1);
+///     group.connect().await?;
+///     let mut data = group.data_stream().await?.take(2);
+///     while let Some(data) = data.next().await {
+///         println!("{:?} W at {:?} bpm", data.power, data.heart_rate);
+///     }
+///     group.disconnect().await?;
+///     Ok(())
+/// }
+/// ```
+pub struct DeviceGroup {
+    devices: Vec<Box<dyn DynEquipment>>,
+    /// The device every field comes from first, by its index in `devices`
+    preferred: Vec<(DataField, usize)>,
+    shutdown: CancellationToken,
+}
+
+impl DeviceGroup {
+    /// An empty group, its streams ending once `shutdown` is cancelled
+    pub fn new(shutdown: &CancellationToken) -> Self {
+        DeviceGroup {
+            devices: Vec::new(),
+            preferred: Vec::new(),
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Add `device` to the group, after every device added before
+    pub fn device(mut self, device: Box<dyn DynEquipment>) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    /// Take `field` from the device at index `device` of `devices` whenever it reports it, like the power
+    /// of pedals over that of a trainer, falling back to the order of the group when it doesn't
+    pub fn prefer(mut self, field: DataField, device: usize) -> Self {
+        self.preferred.retain(|(preferred, _)| *preferred != field);
+        self.preferred.push((field, device));
+        self
+    }
+
+    /// The devices of the group, in the order they were added, to send commands to
+    pub fn devices(&self) -> &[Box<dyn DynEquipment>] {
+        &self.devices
+    }
+
+    /// Connect to every device at once
+    ///
+    /// When any of them fails to connect, the devices that did connect get disconnected from again, and
+    /// the first error is returned.
+    pub async fn connect(&mut self) -> Result<()> {
+        let results =
+            futures::future::join_all(self.devices.iter_mut().map(|device| device.connect())).await;
+        if results.iter().all(Result::is_ok) {
+            return Ok(());
+        }
+        let connected = self
+            .devices
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(device, _)| device.disconnect());
+        futures::future::join_all(connected).await;
+        results
+            .into_iter()
+            .find_map(Result::err)
+            .map_or(Ok(()), Err)
+    }
+
+    /// Disconnect from every device at once, returning the first error once all of them have been tried
+    pub async fn disconnect(&self) -> Result<()> {
+        let results =
+            futures::future::join_all(self.devices.iter().map(|device| device.disconnect())).await;
+        results.into_iter().collect()
+    }
+
+    /// The latest data of every device, merged
+    pub async fn read(&self) -> Result<FTMSData> {
+        let mut latest = Vec::with_capacity(self.devices.len());
+        for device in &self.devices {
+            latest.push(device.read().await?.map(FTMSData::from));
+        }
+        Ok(merge(&latest, &self.preferred))
+    }
+
+    /// The merged data of the group from now on, every time any of its devices reports new data
+    ///
+    /// The data of a device lasts until it reports again. Once its stream ends, like when it gets
+    /// disconnected from, it no longer adds to the merged data. The stream ends with the last device.
+    pub async fn data_stream(&self) -> Result<GroupDataStream> {
+        let mut streams = Vec::with_capacity(self.devices.len());
+        for (index, device) in self.devices.iter().enumerate() {
+            let readings = device.data_stream().await?;
+            let ended = futures::stream::once(async move { (index, None) });
+            streams.push(
+                readings
+                    .map(move |reading| (index, Some(FTMSData::from(reading.data))))
+                    .chain(ended)
+                    .boxed(),
+            );
+        }
+        let latest = vec![None; streams.len()];
+        let preferred = self.preferred.clone();
+        Ok(Box::pin(
+            futures::stream::select_all(streams)
+                .scan(latest, move |latest, (index, data)| {
+                    latest[index] = data;
+                    futures::future::ready(Some(merge(latest, &preferred)))
+                })
+                .take_until(self.shutdown.clone().cancelled_owned()),
+        ))
+    }
+
+    /// The events of every device, along with the index of the device in `devices`
+    ///
+    /// Like `Equipment::events`, events are only received from the moment of subscribing. Events a slow
+    /// stream fell too far behind on are skipped.
+    pub fn events(&self) -> GroupEventStream {
+        let streams = self.devices.iter().enumerate().map(|(index, device)| {
+            futures::stream::unfold(device.events(), move |mut events| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) => return Some(((index, event), events)),
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .boxed()
+        });
+        Box::pin(
+            futures::stream::select_all(streams)
+                .take_until(self.shutdown.clone().cancelled_owned()),
+        )
+    }
+}
+
+/// Every field of the preferred data, if it holds it, or else of the first data holding it
+fn merge(latest: &[Option<FTMSData>], preferred: &[(DataField, usize)]) -> FTMSData {
+    let merged = Merge { latest, preferred };
+    FTMSData {
+        speed: merged.pick(DataField::Speed, |data| data.speed),
+        cadence: merged.pick(DataField::Cadence, |data| data.cadence),
+        distance: merged.pick(DataField::Distance, |data| data.distance),
+        resistance: merged.pick(DataField::Resistance, |data| data.resistance),
+        power: merged.pick(DataField::Power, |data| data.power),
+        calories: merged.pick(DataField::Calories, |data| data.calories),
+        heart_rate: merged.pick(DataField::HeartRate, |data| data.heart_rate),
+        time: merged.pick(DataField::Time, |data| data.time),
+    }
+}
+
+struct Merge<'a> {
+    latest: &'a [Option<FTMSData>],
+    preferred: &'a [(DataField, usize)],
+}
+
+impl Merge<'_> {
+    fn pick<T>(&self, field: DataField, value: impl Fn(&FTMSData) -> Option<T>) -> Option<T> {
+        let preferred = self
+            .preferred
+            .iter()
+            .find(|(preferred, _)| *preferred == field)
+            .and_then(|(_, device)| self.latest.get(*device)?.as_ref());
+        preferred
+            .into_iter()
+            .chain(self.latest.iter().flatten())
+            .find_map(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::devices::ReplayDevice;
+    use crate::{BikeData, ConnectionState, HeartRateData, MachineData};
+
+    #[tokio::test]
+    async fn test_device_group() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let bike = |millis, power| {
+            let data = BikeData {
+                power: Some(power),
+                heart_rate: Some(120.),
+                ..Default::default()
+            };
+            (Duration::from_millis(millis), MachineData::from(data))
+        };
+        let monitor = |millis| {
+            let data = HeartRateData {
+                heart_rate: 140.,
+                sensor_contact: None,
+                energy_expended: None,
+                rr_intervals: Vec::new(),
+            };
+            (Duration::from_millis(millis), MachineData::from(data))
+        };
+        let bike = [bike(50, 200), bike(200, 210)];
+        let monitor = [monitor(100), monitor(300)];
+        let mut group = DeviceGroup::new(&shutdown)
+            .device(Box::new(ReplayDevice::from_activity(&bike, &shutdown)))
+            .device(Box::new(ReplayDevice::from_activity(&monitor, &shutdown)));
+        let mut events = group.events();
+        group.connect().await?;
+        assert!(matches!(
+            events.next().await,
+            Some((_, DeviceEvent::StateChanged(ConnectionState::Connecting)))
+        ));
+
+        let data: Vec<_> = group
+            .data_stream()
+            .await?
+            .map(|data| (data.power, data.heart_rate))
+            .collect()
+            .await;
+        // the heart rate of the bike wins, until its playback ends
+        assert_eq!(
+            data,
+            [
+                (Some(200), Some(120.)),
+                (Some(200), Some(120.)),
+                (Some(210), Some(120.)),
+                (None, Some(140.)),
+                (None, Some(140.)),
+                (None, None),
+            ]
+        );
+        group.disconnect().await
+    }
+
+    #[test]
+    fn test_prefer() {
+        let trainer = FTMSData {
+            power: Some(200),
+            cadence: Some(90.),
+            speed: Some(30.),
+            ..Default::default()
+        };
+        let pedals = FTMSData {
+            power: Some(190),
+            cadence: Some(88.),
+            ..Default::default()
+        };
+        let preferred = [(DataField::Power, 1), (DataField::Speed, 1)];
+        let merged = merge(&[Some(trainer), Some(pedals)], &preferred);
+        assert_eq!(merged.power, Some(190));
+        assert_eq!(merged.cadence, Some(90.));
+        // the pedals don't report any speed
+        assert_eq!(merged.speed, Some(30.));
+
+        // nor does a device that hasn't reported yet
+        let merged = merge(&[None, Some(FTMSData::default())], &[(DataField::Power, 0)]);
+        assert_eq!(merged, FTMSData::default());
+    }
+}
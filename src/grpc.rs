@@ -0,0 +1,442 @@
+//! Controlling equipment over gRPC, with the `grpc` feature
+//!
+//! `GrpcServer` serves the `kondis.Equipment` service of `proto/kondis.proto`, mirroring `Equipment`,
+//! so frontends written in any language can connect to equipment, stream its data, set its targets
+//! and ride workouts on it. Clients are generated from the same file, and Rust clients can use the one
+//! in `proto`.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use crate::remote::capability_flags;
+use crate::workout::{self, WorkoutEvent, WorkoutPlayer};
+use crate::{DeviceEvent, DynEquipment, FTMSData, KondisError, Registry, ScanConfig};
+
+/// The messages and service of `proto/kondis.proto`, generated by tonic
+pub mod proto {
+    tonic::include_proto!("kondis");
+}
+
+use proto::equipment_server::EquipmentServer;
+use proto::{
+    ConnectReply, ConnectRequest, Empty, Progress, Reading, RunWorkoutRequest,
+    SimulationParameters, Target, workout_event,
+};
+
+type GrpcStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Serves equipment to gRPC clients, one piece of equipment at a time
+///
+/// Clients connect to equipment by the name it's registered under in the registry the server was
+/// given, and every client then shares it, like the clients of `RemoteServer`. Connecting again
+/// replaces the equipment, disconnecting from the one before. Failures of the equipment are answered
+/// with the status matching the `KondisError`, like `INVALID_ARGUMENT` for targets out of range.
+///
+/// There is no authentication nor encryption, so only serve on networks you trust.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{grpc::GrpcServer, CancellationToken, Registry};
+/// use tokio::net::TcpListener;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let listener = TcpListener::bind("0.0.0.0:50051").await?;
+///     GrpcServer::new(Registry::default(), &shutdown)
+///         .serve(listener)
+///         .await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GrpcServer {
+    registry: Arc<Registry>,
+    equipment: Arc<Mutex<Option<Arc<dyn DynEquipment>>>>,
+    shutdown: CancellationToken,
+}
+
+impl GrpcServer {
+    /// A server creating equipment from `registry`, serving until `shutdown` is cancelled
+    pub fn new(registry: Registry, shutdown: &CancellationToken) -> Self {
+        GrpcServer {
+            registry: Arc::new(registry),
+            equipment: Arc::default(),
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// The service, for serving along with services of your own
+    pub fn service(self) -> EquipmentServer<Self> {
+        EquipmentServer::new(self)
+    }
+
+    /// Serve every client connecting to `listener`, until shut down, disconnecting from the equipment
+    /// once done
+    pub async fn serve(self, listener: TcpListener) -> crate::Result<()> {
+        let shutdown = self.shutdown.clone();
+        let equipment = self.equipment.clone();
+        let served = tonic::transport::Server::builder()
+            .add_service(self.service())
+            .serve_with_incoming_shutdown(TcpIncoming::from(listener), shutdown.cancelled())
+            .await;
+        let connected = equipment.lock().unwrap().take();
+        if let Some(equipment) = connected {
+            equipment.close().await?;
+        }
+        served.map_err(|e| KondisError::Remote(e.to_string()))
+    }
+
+    /// The equipment clients connected to, failing when there is none
+    fn equipment(&self) -> Result<Arc<dyn DynEquipment>, Status> {
+        self.equipment
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Status::failed_precondition("Not connected to any equipment"))
+    }
+}
+
+impl std::fmt::Debug for GrpcServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GrpcServer")
+            .field("registry", &self.registry.names())
+            .finish_non_exhaustive()
+    }
+}
+
+#[tonic::async_trait]
+impl proto::equipment_server::Equipment for GrpcServer {
+    async fn connect_equipment(
+        &self,
+        request: Request<ConnectRequest>,
+    ) -> Result<Response<ConnectReply>, Status> {
+        let request = request.into_inner();
+        let mut config = ScanConfig::new();
+        if let Some(pattern) = request.name_pattern {
+            config = config.name_pattern(pattern);
+        }
+        if let Some(address) = request.address {
+            config = config.address(address);
+        }
+        if let Some(port) = request.port {
+            config = config.port(port);
+        }
+        if let Some(timeout) = request.timeout {
+            let timeout = Duration::try_from_secs_f64(timeout)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            config = config.timeout(timeout);
+        }
+        let max_level = i16::try_from(request.max_level)
+            .map_err(|_| Status::invalid_argument("The max level is out of range"))?;
+        let mut equipment = self
+            .registry
+            .create(&request.equipment, max_level, config, &self.shutdown)
+            .await
+            .map_err(status)?;
+        if !equipment.connect().await.map_err(status)? {
+            return Err(Status::unavailable("Could not connect to the equipment"));
+        }
+        let mut capabilities = equipment.capabilities().unwrap_or_default();
+        let [targets, data] = capability_flags(&mut capabilities).map(|flags| {
+            flags
+                .into_iter()
+                .filter(|(_, supported)| **supported)
+                .map(|(name, _)| name.to_string())
+                .collect()
+        });
+        let previous = self.equipment.lock().unwrap().replace(equipment.into());
+        if let Some(previous) = previous {
+            // the new equipment is connected either way
+            let _ = previous.close().await;
+        }
+        Ok(Response::new(ConnectReply { targets, data }))
+    }
+
+    async fn disconnect_equipment(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        let equipment = self.equipment.lock().unwrap().take();
+        if let Some(equipment) = equipment {
+            equipment.close().await.map_err(status)?;
+        }
+        Ok(Response::new(Empty {}))
+    }
+
+    type StreamDataStream = GrpcStream<Reading>;
+
+    async fn stream_data(
+        &self,
+        _: Request<Empty>,
+    ) -> Result<Response<Self::StreamDataStream>, Status> {
+        let events = self.equipment()?.events();
+        let readings = futures::stream::unfold(events, |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(DeviceEvent::Data(reading)) => return Some((Ok(reading.into()), events)),
+                    Ok(DeviceEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                }
+            }
+        })
+        .take_until(self.shutdown.clone().cancelled_owned());
+        Ok(Response::new(Box::pin(readings)))
+    }
+
+    async fn set_target_power(&self, request: Request<Target>) -> Result<Response<Empty>, Status> {
+        let watts = target(request)?;
+        done(self.equipment()?.set_target_power(watts).await)
+    }
+
+    async fn set_target_cadence(
+        &self,
+        request: Request<Target>,
+    ) -> Result<Response<Empty>, Status> {
+        let rpm = target(request)?;
+        done(self.equipment()?.set_target_cadence(rpm).await)
+    }
+
+    async fn set_target_resistance_level(
+        &self,
+        request: Request<Target>,
+    ) -> Result<Response<Empty>, Status> {
+        let level = target(request)?;
+        done(self.equipment()?.set_target_resistance_level(level).await)
+    }
+
+    async fn set_target_heart_rate(
+        &self,
+        request: Request<Target>,
+    ) -> Result<Response<Empty>, Status> {
+        let bpm = target(request)?;
+        done(self.equipment()?.set_target_heart_rate(bpm).await)
+    }
+
+    async fn set_simulation_parameters(
+        &self,
+        request: Request<SimulationParameters>,
+    ) -> Result<Response<Empty>, Status> {
+        let SimulationParameters {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        } = request.into_inner();
+        done(
+            self.equipment()?
+                .set_simulation_parameters(grade, wind_speed, crr, cw)
+                .await,
+        )
+    }
+
+    async fn start(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        done(self.equipment()?.start().await)
+    }
+
+    async fn stop(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        done(self.equipment()?.stop().await)
+    }
+
+    async fn pause(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        done(self.equipment()?.pause().await)
+    }
+
+    async fn resume(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        done(self.equipment()?.resume().await)
+    }
+
+    async fn reset(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+        done(self.equipment()?.reset().await)
+    }
+
+    type RunWorkoutStream = GrpcStream<proto::WorkoutEvent>;
+
+    async fn run_workout(
+        &self,
+        request: Request<RunWorkoutRequest>,
+    ) -> Result<Response<Self::RunWorkoutStream>, Status> {
+        let request = request.into_inner();
+        let equipment = self.equipment()?;
+        let mut player = WorkoutPlayer::new(workout::parse_erg(&request.erg).map_err(status)?);
+        if let Some(ftp) = request.ftp {
+            player = player.ftp(ftp);
+        }
+        let events = player.events();
+        // the workout stops once the client stops listening
+        let riding = self.shutdown.child_token();
+        let stop = riding.clone().drop_guard();
+        let ride = tokio::spawn(async move { player.run(&*equipment, &riding).await });
+        let events = futures::stream::unfold(
+            (events, Some(ride), stop),
+            |(mut events, ride, stop)| async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) => {
+                            let finished = event == WorkoutEvent::Finished;
+                            let event = Ok(event.into());
+                            // the ride having ended is checked for with the next event
+                            return Some((event, (events, ride.filter(|_| !finished), stop)));
+                        }
+                        Err(RecvError::Lagged(_)) => {}
+                        // the player is gone, so the ride ended, maybe failing
+                        Err(RecvError::Closed) => {
+                            return match ride?.await {
+                                Ok(Err(e)) => Some((Err(status(e)), (events, None, stop))),
+                                _ => None,
+                            };
+                        }
+                    }
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+impl From<crate::Reading> for Reading {
+    fn from(reading: crate::Reading) -> Self {
+        let data = FTMSData::from(reading.data);
+        Reading {
+            timestamp: reading.timestamp.as_secs_f64(),
+            sequence: reading.sequence,
+            speed: data.speed,
+            cadence: data.cadence,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power.map(i32::from),
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+impl From<WorkoutEvent> for proto::WorkoutEvent {
+    fn from(event: WorkoutEvent) -> Self {
+        let event = match event {
+            WorkoutEvent::BlockStarted { index } => {
+                workout_event::Event::BlockStarted(index as u32)
+            }
+            WorkoutEvent::Message(text) => workout_event::Event::Message(text),
+            WorkoutEvent::Progress {
+                elapsed,
+                remaining,
+                target,
+            } => workout_event::Event::Progress(Progress {
+                elapsed: elapsed.as_secs_f64(),
+                remaining: remaining.as_secs_f64(),
+                block: target.block as u32,
+                power: target.power,
+                cadence: target.cadence,
+            }),
+            WorkoutEvent::Paused => workout_event::Event::Paused(Empty {}),
+            WorkoutEvent::Resumed => workout_event::Event::Resumed(Empty {}),
+            WorkoutEvent::Finished => workout_event::Event::Finished(Empty {}),
+        };
+        proto::WorkoutEvent { event: Some(event) }
+    }
+}
+
+/// The value of a target request, failing when it's out of range for `T`
+fn target<T: TryFrom<i32>>(request: Request<Target>) -> Result<T, Status> {
+    let value = request.into_inner().value;
+    T::try_from(value).map_err(|_| Status::invalid_argument(format!("{value} is out of range")))
+}
+
+fn done(result: crate::Result<()>) -> Result<Response<Empty>, Status> {
+    result.map(|()| Response::new(Empty {})).map_err(status)
+}
+
+/// The status telling clients about `error`
+fn status(error: KondisError) -> Status {
+    let message = error.to_string();
+    match error {
+        KondisError::InvalidArgument(_) => Status::invalid_argument(message),
+        KondisError::Unsupported(_) => Status::unimplemented(message),
+        KondisError::ControlRejected(_) => Status::failed_precondition(message),
+        KondisError::DeviceNotFound | KondisError::UnknownEquipment(_) => {
+            Status::not_found(message)
+        }
+        KondisError::ScanTimeout(_)
+        | KondisError::ConnectTimeout(_)
+        | KondisError::DiscoveryTimeout(_)
+        | KondisError::NotificationTimeout(_)
+        | KondisError::Timeout(_) => Status::deadline_exceeded(message),
+        KondisError::Disconnected(_) | KondisError::BluetoothUnavailable(_) => {
+            Status::unavailable(message)
+        }
+        KondisError::PermissionDenied(_) => Status::permission_denied(message),
+        KondisError::InvalidData(_) => Status::data_loss(message),
+        KondisError::Shutdown => Status::cancelled(message),
+        _ => Status::internal(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::equipment_client::EquipmentClient;
+
+    #[tokio::test]
+    async fn test_grpc() -> anyhow::Result<()> {
+        let shutdown = CancellationToken::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(GrpcServer::new(Registry::default(), &shutdown).serve(listener));
+        let mut client = EquipmentClient::connect(format!("http://{address}")).await?;
+
+        let not_connected = client.start(Empty {}).await.unwrap_err();
+        assert_eq!(not_connected.code(), tonic::Code::FailedPrecondition);
+        let unknown = ConnectRequest {
+            equipment: "treadmill-of-theseus".to_string(),
+            ..Default::default()
+        };
+        let unknown = client.connect_equipment(unknown).await.unwrap_err();
+        assert_eq!(unknown.code(), tonic::Code::NotFound);
+
+        let connected = client
+            .connect_equipment(ConnectRequest {
+                equipment: "non-bluetooth-device".to_string(),
+                max_level: 300,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        assert!(connected.targets.contains(&"power".to_string()));
+
+        client.set_target_power(Target { value: 150 }).await?;
+        let out_of_range = client.set_target_power(Target { value: 400 }).await;
+        assert_eq!(
+            out_of_range.unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+        let mut data = client.stream_data(Empty {}).await?.into_inner();
+        let reading = data.message().await?.expect("no reading");
+        assert!(reading.time.is_some());
+
+        let workout = RunWorkoutRequest {
+            erg: "[COURSE HEADER]\nMINUTES WATTS\n[END COURSE HEADER]\n\
+                  [COURSE DATA]\n0 150\n0.02 150\n[END COURSE DATA]\n"
+                .to_string(),
+            ftp: None,
+        };
+        let mut events = client.run_workout(workout).await?.into_inner();
+        let mut finished = false;
+        while let Some(event) = events.message().await? {
+            finished = matches!(event.event, Some(workout_event::Event::Finished(_)));
+        }
+        assert!(finished);
+
+        client.disconnect_equipment(Empty {}).await?;
+        shutdown.cancel();
+        server.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::remote::{self, Request};
+use crate::{
+    Capabilities, DeviceEvent, Equipment, KondisError, Reading, Result, SupportedRanges,
+    TrainingGoal,
+};
+
+/// How many commands may wait for the equipment before sending more waits as well
+const COMMAND_BUFFER: usize = 16;
+/// How many events subscribers may lag behind before missing some, like `Equipment::events`
+const EVENT_BUFFER: usize = 64;
+
+enum Message {
+    Request(Request, oneshot::Sender<Result<()>>),
+    Close(oneshot::Sender<Result<()>>),
+}
+
+impl std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Request(request, _) => f.debug_tuple("Request").field(request).finish(),
+            Message::Close(_) => f.write_str("Close"),
+        }
+    }
+}
+
+/// Connected equipment owned by a task of its own, commanded and watched through clones of the handle
+///
+/// Every clone sends its commands to the same task, which runs them on the equipment one at a time,
+/// and keeps the latest reading around for anyone to look at. The task closes the equipment once
+/// `close` is called, `shutdown` gets cancelled or every handle is dropped, after which commands fail
+/// with `KondisError::Disconnected`.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, EquipmentHandle};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     let handle = EquipmentHandle::connect(Box::new(device), &shutdown).await?;
+///
+///     let workout = handle.clone();
+///     tokio::spawn(async move { workout.set_target_power(150).await });
+///     if let Some(reading) = handle.latest() {
+///         println!("{:?}", reading.data);
+///     }
+///     handle.close().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EquipmentHandle {
+    messages: mpsc::Sender<Message>,
+    latest: watch::Receiver<Option<Reading>>,
+    events: broadcast::Sender<DeviceEvent>,
+    capabilities: Option<Capabilities>,
+    supported_ranges: Option<SupportedRanges>,
+}
+
+impl EquipmentHandle {
+    /// Connect to `equipment` and hand it over to a task of its own
+    ///
+    /// Concrete equipment gets boxed, and `Box<dyn DynEquipment>`, like from `Registry`, goes as it is.
+    pub async fn connect<E: Equipment + ?Sized + 'static>(
+        mut equipment: Box<E>,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        if !equipment.connect().await? {
+            return Err(KondisError::Disconnected(
+                "Could not connect to the equipment".to_string(),
+            ));
+        }
+        let (messages, messages_rx) = mpsc::channel(COMMAND_BUFFER);
+        let (latest_tx, latest) = watch::channel(None);
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let capabilities = equipment.capabilities();
+        let supported_ranges = equipment.supported_ranges();
+        let equipment_events = equipment.events();
+        crate::runtime::spawn(own(
+            equipment,
+            messages_rx,
+            equipment_events,
+            latest_tx,
+            events.clone(),
+            shutdown.clone(),
+        ));
+        Ok(EquipmentHandle {
+            messages,
+            latest,
+            events,
+            capabilities,
+            supported_ranges,
+        })
+    }
+
+    /// The latest reading of the equipment, if it reported any yet
+    pub fn latest(&self) -> Option<Reading> {
+        self.latest.borrow().clone()
+    }
+
+    /// Wait for the next reading of the equipment, resolving to `None` once the task is gone
+    pub async fn next_reading(&self) -> Option<Reading> {
+        let mut latest = self.latest.clone();
+        latest.borrow_and_update();
+        latest.changed().await.ok()?;
+        latest.borrow().clone()
+    }
+
+    /// Every event of the equipment from now on, see `Equipment::events`
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.events.subscribe()
+    }
+
+    /// The capabilities of the equipment, as discovered while connecting
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// The target ranges of the equipment, as read while connecting, see `Equipment::supported_ranges`
+    pub fn supported_ranges(&self) -> Option<SupportedRanges> {
+        self.supported_ranges
+    }
+
+    /// Whether the task let go of the equipment, after which every command fails
+    pub fn is_closed(&self) -> bool {
+        self.messages.is_closed()
+    }
+
+    /// See `Equipment::set_target_cadence`
+    pub async fn set_target_cadence(&self, rpm: i16) -> Result<()> {
+        self.request(Request::Cadence(rpm)).await
+    }
+
+    /// See `Equipment::set_target_power`
+    pub async fn set_target_power(&self, watts: i16) -> Result<()> {
+        self.request(Request::Power(watts)).await
+    }
+
+    /// See `Equipment::set_target_resistance_level`
+    pub async fn set_target_resistance_level(&self, level: i16) -> Result<()> {
+        self.request(Request::Resistance(level)).await
+    }
+
+    /// See `Equipment::set_target_heart_rate`
+    pub async fn set_target_heart_rate(&self, bpm: u8) -> Result<()> {
+        self.request(Request::HeartRate(bpm)).await
+    }
+
+    /// See `Equipment::set_goal`
+    pub async fn set_goal(&self, goal: TrainingGoal) -> Result<()> {
+        self.request(Request::Goal(goal)).await
+    }
+
+    /// See `Equipment::set_simulation_parameters`
+    pub async fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> Result<()> {
+        self.request(Request::Simulation {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        })
+        .await
+    }
+
+    /// See `Equipment::start`
+    pub async fn start(&self) -> Result<()> {
+        self.request(Request::Start).await
+    }
+
+    /// See `Equipment::stop`
+    pub async fn stop(&self) -> Result<()> {
+        self.request(Request::Stop).await
+    }
+
+    /// See `Equipment::pause`
+    pub async fn pause(&self) -> Result<()> {
+        self.request(Request::Pause).await
+    }
+
+    /// See `Equipment::resume`
+    pub async fn resume(&self) -> Result<()> {
+        self.request(Request::Resume).await
+    }
+
+    /// See `Equipment::reset`
+    pub async fn reset(&self) -> Result<()> {
+        self.request(Request::Reset).await
+    }
+
+    /// Stop the equipment and disconnect from it, see `Equipment::close`, ending the task for every
+    /// clone of the handle
+    pub async fn close(&self) -> Result<()> {
+        let (reply_tx, reply) = oneshot::channel();
+        self.send(Message::Close(reply_tx)).await?;
+        reply.await.map_err(|_| gone())?
+    }
+
+    async fn request(&self, request: Request) -> Result<()> {
+        let (reply_tx, reply) = oneshot::channel();
+        self.send(Message::Request(request, reply_tx)).await?;
+        reply.await.map_err(|_| gone())?
+    }
+
+    async fn send(&self, message: Message) -> Result<()> {
+        self.messages.send(message).await.map_err(|_| gone())
+    }
+}
+
+fn gone() -> KondisError {
+    KondisError::Disconnected("The equipment task has ended".to_string())
+}
+
+/// Run the commands of every handle on `equipment`, keeping track of its readings, until told to
+/// close it
+async fn own<E: Equipment + ?Sized>(
+    equipment: Box<E>,
+    mut messages: mpsc::Receiver<Message>,
+    mut equipment_events: broadcast::Receiver<DeviceEvent>,
+    latest: watch::Sender<Option<Reading>>,
+    events: broadcast::Sender<DeviceEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut closing = None;
+    let mut listening = true;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            message = messages.recv() => match message {
+                Some(Message::Request(request, reply)) => {
+                    let _ = reply.send(remote::run(&*equipment, request).await);
+                }
+                Some(Message::Close(reply)) => {
+                    closing = Some(reply);
+                    break;
+                }
+                // every handle is gone, so nobody is left to tell it's closed
+                None => break,
+            },
+            event = equipment_events.recv(), if listening => match event {
+                Ok(event) => {
+                    if let DeviceEvent::Data(reading) = &event {
+                        latest.send_replace(Some(reading.clone()));
+                    }
+                    let _ = events.send(event);
+                }
+                Err(RecvError::Lagged(_)) => {}
+                // commands still get answered, by the equipment failing them
+                Err(RecvError::Closed) => listening = false,
+            },
+        }
+    }
+    messages.close();
+    let result = equipment.close().await;
+    if let Some(reply) = closing {
+        let _ = reply.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MachineData;
+    use crate::devices::NonBluetoothDevice;
+
+    #[tokio::test]
+    async fn test_handle() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let device = NonBluetoothDevice::new(10, &shutdown).await?;
+        let handle = EquipmentHandle::connect(Box::new(device), &shutdown).await?;
+        let mut events = handle.subscribe();
+
+        let clone = handle.clone();
+        tokio::spawn(async move { clone.set_target_power(5).await })
+            .await
+            .expect("command panicked")?;
+        assert!(handle.set_target_power(20).await.is_err());
+        handle.start().await?;
+
+        // the first reading may have arrived before subscribing
+        let reading = match handle.latest() {
+            Some(reading) => reading,
+            None => handle.next_reading().await.expect("no reading"),
+        };
+        assert!(matches!(reading.data, MachineData::Bike(_)));
+
+        handle.close().await?;
+        assert!(handle.is_closed());
+        assert!(handle.set_target_power(5).await.is_err());
+        assert!(handle.close().await.is_err());
+        let mut forwarded = std::iter::from_fn(|| events.try_recv().ok());
+        assert!(forwarded.any(|event| matches!(event, DeviceEvent::Data(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_executor() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let shutdown = CancellationToken::new();
+        let handle = runtime.block_on(async {
+            let device = NonBluetoothDevice::new(10, &shutdown).await?;
+            EquipmentHandle::connect(Box::new(device), &shutdown).await
+        })?;
+        futures::executor::block_on(async {
+            handle.set_target_power(5).await?;
+            assert!(handle.next_reading().await.is_some());
+            handle.close().await
+        })
+    }
+}
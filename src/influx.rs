@@ -0,0 +1,418 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, StatusCode, Url};
+use tokio::io::AsyncWriteExt;
+
+use crate::{FTMSData, KondisError, Reading, Result};
+
+/// Samples written at once, unless told otherwise
+const DEFAULT_BATCH_SIZE: usize = 10;
+/// Attempts after a failed write, unless told otherwise
+const DEFAULT_RETRIES: u32 = 3;
+/// The wait before the first retry, doubling with every retry after it
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// How long writing a batch to the server may take
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Batches kept for later while the server is unreachable, before the oldest samples get dropped
+const MAX_PENDING_BATCHES: usize = 100;
+
+/// Where `InfluxSink` writes to
+#[derive(Debug, Clone)]
+enum Destination {
+    Http {
+        client: Client,
+        /// the write endpoint
+        url: Url,
+        token: Option<String>,
+    },
+    File(PathBuf),
+}
+
+/// Writes every reading as InfluxDB line protocol, to an InfluxDB server or to a file
+///
+/// Samples are points of the `kondis` measurement, unless told otherwise, at the unix time they got
+/// recorded, holding the fields of `FTMSData` the equipment reported and the tags added with `tag`.
+/// They are written in batches of 10, unless told otherwise, and whatever is left in the batch gets
+/// written by `flush`, which is worth calling before dropping the sink.
+///
+/// A batch the server doesn't take gets retried a few times, waiting longer between every attempt.
+/// When the server stays unreachable, the batch is kept to be written along with the next one, until
+/// there are too many kept and the oldest samples are dropped. Batches the server refuses as invalid
+/// are dropped right away.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, InfluxSink};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///     let mut sink = InfluxSink::http("http://localhost:8086/api/v2/write?org=home&bucket=rides")?
+///         .token("my-token")
+///         .tag("bike", "garage");
+///     let mut data = device.data_stream().await?.take(20);
+///     while let Some(reading) = data.next().await {
+///         sink.record(&reading).await?;
+///     }
+///     sink.flush().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct InfluxSink {
+    destination: Destination,
+    measurement: String,
+    tags: Vec<(String, String)>,
+    batch_size: usize,
+    retries: u32,
+    /// lines not written yet
+    pending: Vec<String>,
+}
+
+impl InfluxSink {
+    /// Write to the write endpoint of an InfluxDB server, like
+    /// `http://localhost:8086/api/v2/write?org=home&bucket=rides`,
+    /// `https://eu-central-1-1.aws.cloud2.influxdata.com/api/v2/write?org=home&bucket=rides` for
+    /// InfluxDB Cloud or `http://localhost:8086/write?db=rides` for InfluxDB 1
+    pub fn http(url: &str) -> Result<Self> {
+        let invalid = |reason: &str| KondisError::InvalidArgument(format!("{reason}: {url}"));
+        let url = Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid("Only http:// and https:// URLs are supported"));
+        }
+        if url.host().is_none() {
+            return Err(invalid("No host in the URL"));
+        }
+        let client = Client::builder()
+            .timeout(WRITE_TIMEOUT)
+            .build()
+            .map_err(|e| KondisError::Export(e.to_string()))?;
+        Ok(Self::new(Destination::Http {
+            client,
+            url,
+            token: None,
+        }))
+    }
+
+    /// Append to the file at `path`, for importing later with `influx write`
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self::new(Destination::File(path.into()))
+    }
+
+    fn new(destination: Destination) -> Self {
+        InfluxSink {
+            destination,
+            measurement: "kondis".to_string(),
+            tags: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            retries: DEFAULT_RETRIES,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The API token of the server, sent as `Authorization: Token <token>`
+    ///
+    /// Tokens are sent as they are over plain HTTP, so use HTTPS for servers beyond the local network.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        if let Destination::Http { token: current, .. } = &mut self.destination {
+            *current = Some(token.into());
+        }
+        self
+    }
+
+    /// The measurement samples are points of, `kondis` by default
+    pub fn measurement(mut self, measurement: impl Into<String>) -> Self {
+        self.measurement = measurement.into();
+        self
+    }
+
+    /// Tag every sample with `key` being `value`, like the name of the equipment
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Write samples `size` at a time, 10 by default, 1 writing every sample right away
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    /// Attempt failed writes `retries` more times, 3 by default
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Add `reading` to the batch, writing the batch once it's full
+    ///
+    /// Readings without any field the equipment reported are skipped, as points need at least one.
+    pub async fn record(&mut self, reading: &Reading) -> Result<()> {
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Some(line) = self.line(&FTMSData::from(reading.data.clone()), unix_time) {
+            self.pending.push(line);
+        }
+        if self.pending.len() >= self.batch_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write every sample not written yet
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut body = self.pending.join("\n");
+        body.push('\n');
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+        let result = loop {
+            match self.write(&body).await {
+                Err(Failure::Retry(_)) if attempt < self.retries => {
+                    attempt += 1;
+                    crate::runtime::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.pending.clear();
+                Ok(())
+            }
+            Err(Failure::Refused(e)) => {
+                self.pending.clear();
+                Err(e)
+            }
+            Err(Failure::Retry(e)) => {
+                let max = self.batch_size * MAX_PENDING_BATCHES;
+                if self.pending.len() > max {
+                    self.pending.drain(..self.pending.len() - max);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// `data` as a line of line protocol, at `unix_time`
+    fn line(&self, data: &FTMSData, unix_time: Duration) -> Option<String> {
+        let fields = [
+            field("speed", data.speed),
+            field("cadence", data.cadence),
+            field("distance", data.distance),
+            field("resistance", data.resistance),
+            data.power.map(|power| format!("power={power}i")),
+            field("calories", data.calories),
+            field("heart_rate", data.heart_rate),
+            data.time.map(|time| format!("time={time}i")),
+        ];
+        let fields: Vec<String> = fields.into_iter().flatten().collect();
+        if fields.is_empty() {
+            return None;
+        }
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (key, value) in &self.tags {
+            let special = [',', '=', ' '];
+            line.push_str(&format!(
+                ",{}={}",
+                escape(key, &special),
+                escape(value, &special)
+            ));
+        }
+        Some(format!(
+            "{line} {} {}",
+            fields.join(","),
+            unix_time.as_nanos()
+        ))
+    }
+
+    async fn write(&self, body: &str) -> std::result::Result<(), Failure> {
+        match &self.destination {
+            Destination::File(path) => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| Failure::Refused(e.into()))?;
+                file.write_all(body.as_bytes())
+                    .await
+                    .map_err(|e| Failure::Retry(e.into()))
+            }
+            Destination::Http { client, url, token } => {
+                let mut request = client
+                    .post(url.clone())
+                    .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                    .body(body.to_string());
+                if let Some(token) = token {
+                    request = request.header(AUTHORIZATION, format!("Token {token}"));
+                }
+                let response = request.send().await.map_err(|e| {
+                    let error = if e.is_timeout() {
+                        KondisError::Timeout(format!(
+                            "Writing to InfluxDB took longer than {WRITE_TIMEOUT:?}"
+                        ))
+                    } else {
+                        KondisError::Export(e.to_string())
+                    };
+                    Failure::Retry(error)
+                })?;
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+                let error = KondisError::Export(format!("InfluxDB answered {status}"));
+                // too many requests, or the server having trouble
+                if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                    Err(Failure::Retry(error))
+                } else {
+                    Err(Failure::Refused(error))
+                }
+            }
+        }
+    }
+}
+
+/// Why a batch didn't get written
+enum Failure {
+    /// Worth trying again, like the server being unreachable
+    Retry(KondisError),
+    /// Never going to be taken, like invalid lines
+    Refused(KondisError),
+}
+
+fn field(key: &str, value: Option<impl Display>) -> Option<String> {
+    value.map(|value| format!("{key}={value}"))
+}
+
+/// `text` with every character of `special` escaped by a backslash
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BikeData, MachineData};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn reading() -> Reading {
+        Reading {
+            timestamp: Duration::from_millis(1500),
+            sequence: 0,
+            data: MachineData::Bike(BikeData {
+                power: Some(180),
+                cadence: Some(90.5),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_line() -> Result<()> {
+        let sink = InfluxSink::http("http://localhost:8086/write?db=rides")?
+            .measurement("my rides")
+            .tag("bike", "garage trainer");
+        let data = FTMSData::from(reading().data);
+        assert_eq!(
+            sink.line(&data, Duration::from_secs(1)).as_deref(),
+            Some(r"my\ rides,bike=garage\ trainer cadence=90.5,power=180i 1000000000")
+        );
+        assert_eq!(sink.line(&FTMSData::default(), Duration::ZERO), None);
+        assert!(InfluxSink::http("https://rides.example.com/api/v2/write").is_ok());
+        assert!(InfluxSink::http("ftp://localhost:8086/write").is_err());
+        assert!(InfluxSink::http("localhost:8086/write").is_err());
+        Ok(())
+    }
+
+    /// Read a request with its body, going by its content length
+    async fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).to_lowercase();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .and_then(|length| length.trim().parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        Ok(String::from_utf8_lossy(&request).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_http() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in [
+                "503 Service Unavailable",
+                "204 No Content",
+                "400 Bad Request",
+            ] {
+                let (mut stream, _) = listener.accept().await?;
+                requests.push(read_request(&mut stream).await?);
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await?;
+            }
+            Ok::<_, std::io::Error>(requests)
+        });
+
+        let url = format!("http://{address}/api/v2/write?bucket=rides");
+        let mut sink = InfluxSink::http(&url)?.token("secret").batch_size(2);
+        sink.record(&reading()).await?;
+        // retried after the server being unavailable
+        sink.record(&reading()).await?;
+        assert!(sink.pending.is_empty());
+        // refused, and dropped
+        sink.record(&reading()).await?;
+        assert!(sink.flush().await.is_err());
+        assert!(sink.pending.is_empty());
+
+        let requests = server.await.expect("server panicked")?;
+        assert!(requests[1].starts_with("POST /api/v2/write?bucket=rides HTTP/1.1\r\n"));
+        assert!(
+            requests[1]
+                .to_lowercase()
+                .contains("authorization: token secret\r\n")
+        );
+        assert_eq!(
+            requests[1]
+                .matches("kondis cadence=90.5,power=180i")
+                .count(),
+            2
+        );
+        Ok(())
+    }
+}
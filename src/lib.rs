@@ -1,13 +1,142 @@
 #![doc = include_str!("../README.md")]
-use std::sync::mpsc::Receiver;
+// the decoding and encoding for equipment the browser can't reach goes unused there
+#![cfg_attr(target_arch = "wasm32", allow(unused))]
+use std::pin::Pin;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
-use async_trait::async_trait;
+use futures::Stream;
+pub use tokio_util::sync::CancellationToken;
 
-mod bluetooth;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+#[cfg(not(feature = "tracing"))]
+#[macro_use]
+mod logging;
+
+mod ant;
+/// Discovering and talking to Bluetooth peripherals, for implementing `Equipment` outside of this crate
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bluetooth;
+/// What discovering Bluetooth peripherals is configured with, peripherals being picked in the browser
+#[cfg(target_arch = "wasm32")]
+pub mod bluetooth {
+    mod device_info;
+    mod scan_config;
+    mod timeouts;
+
+    pub use device_info::DeviceInfo;
+    pub use scan_config::ScanConfig;
+    pub(crate) use timeouts::{Timeouts, within};
+}
+#[cfg(all(feature = "bridge", target_os = "linux"))]
+pub mod bridge;
+mod capture;
+mod csafe;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod devices;
+/// Equipment in the browser, through Web Bluetooth
+#[cfg(target_arch = "wasm32")]
+pub mod devices {
+    mod controllers;
+    mod event_sender;
+    mod sensors;
+    mod shutdown;
+    mod web;
+
+    pub use controllers::ControllerButton;
+    pub use sensors::SteeringEvent;
+    pub use web::WebFtmsBike;
+}
+mod dyn_equipment;
+mod erg;
+mod error;
+mod fec;
+#[cfg(feature = "uniffi")]
+pub mod ffi;
+mod fit;
 mod ftms;
+mod gearing;
+mod group;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(not(target_arch = "wasm32"))]
+mod handle;
+#[cfg(feature = "influx")]
+mod influx;
+mod physics;
+mod power_comparison;
+mod power_curve;
+mod profile;
+#[cfg(feature = "python")]
+mod python;
+mod quirks;
+mod reading;
+mod recorder;
+#[cfg(not(target_arch = "wasm32"))]
+mod registry;
+#[cfg(not(target_arch = "wasm32"))]
+mod remote;
+pub mod route;
+mod runtime;
+mod sensors;
+mod sink;
+mod stats;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+#[cfg(feature = "tcx")]
+mod tcx;
+pub mod workout;
+mod zones;
 
-use devices::{DebugBike, Iconsole0028Bike, NonBluetoothDevice};
+pub use bluetooth::{DeviceInfo, ScanConfig};
+#[cfg(not(target_arch = "wasm32"))]
+pub use bluetooth::{DiscoveredDevice, scan};
+pub use capture::{CapturedNotification, parse_capture};
+#[cfg(not(target_arch = "wasm32"))]
+use devices::{
+    CsafeRower, DebugBike, EchelonBike, GenericFtmsBike, GenericFtmsCrossTrainer, GenericFtmsRower,
+    GenericFtmsTreadmill, HeartRateMonitor, Iconsole0010Bike, Iconsole0028Bike, Iconsole0051Bike,
+    KeiserM3iBike, KettlerBike, KickrBike, NonBluetoothDevice, Pm5Rower, SimulatorBike,
+    SpeedCadenceSensor, SterzoSteering, TacxFecBike, ZwiftController,
+};
+pub use dyn_equipment::DynEquipment;
+pub use erg::ErgController;
+pub use error::{KondisError, Result};
+pub use fit::{FitRecorder, parse_fit};
+pub use ftms::{
+    BikeData, Capabilities, ControlPointError, CrossTrainerData, DataCapabilities, FTMSData,
+    MachineData, MachineStatus, ResultCode, RowerData, SpinDownResult, SpinDownStatus,
+    SupportedRange, SupportedRanges, TargetCapabilities, TrainingGoal, TreadmillData,
+};
+pub use gearing::{Gear, VirtualDrivetrain};
+pub use group::{DataField, DeviceGroup, GroupDataStream, GroupEventStream};
+#[cfg(not(target_arch = "wasm32"))]
+pub use handle::EquipmentHandle;
+#[cfg(feature = "influx")]
+pub use influx::InfluxSink;
+pub use physics::{GradeSimulator, RideModel};
+pub use power_comparison::PowerComparison;
+pub use power_curve::PowerCurve;
+pub use profile::UserProfile;
+#[cfg(feature = "quirks")]
+pub use quirks::parse_quirks;
+pub use quirks::{CommandQuirks, DeviceQuirks, FieldQuirk, FieldType, Metric};
+pub use reading::Reading;
+pub use recorder::{RecordFormat, SampleRecorder};
+#[cfg(not(target_arch = "wasm32"))]
+pub use registry::{EquipmentFactory, Registry};
+#[cfg(not(target_arch = "wasm32"))]
+pub use remote::RemoteServer;
+pub use sensors::{HeartRateData, SpeedCadenceData};
+pub use sink::{DataSink, DynDataSink, Session};
+pub use stats::SessionStats;
+#[cfg(feature = "tcx")]
+pub use tcx::parse_tcx;
+pub use zones::{HeartRateZones, PowerZones};
 
 /// Equipment types supported
 ///
@@ -15,169 +144,1056 @@ use devices::{DebugBike, Iconsole0028Bike, NonBluetoothDevice};
 ///
 /// If you are contributing a new type of equipment, please add it here as well.
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum EquipmentType {
     /// iConsole+0028 bike
     Iconsole0028Bike,
-    /// debug bike, any bluetooth bike containing "Console" in its name
+    /// iConsole+0010 bike
+    Iconsole0010Bike,
+    /// iConsole+0051 bike
+    Iconsole0051Bike,
+    /// debug bike, any bluetooth device matching the name pattern or service of the `ScanConfig`, or
+    /// containing "Console" in its name without either
     DebugBike,
+    /// any bike advertising the standard Fitness Machine Service
+    GenericFtmsBike,
+    /// any treadmill advertising the standard Fitness Machine Service
+    GenericFtmsTreadmill,
+    /// any rowing machine advertising the standard Fitness Machine Service
+    GenericFtmsRower,
+    /// any cross trainer or elliptical advertising the standard Fitness Machine Service
+    GenericFtmsCrossTrainer,
     /// a bogus device, implemented without any connection, printing states when functions are called
     NonBluetoothDevice,
+    /// a simulated bike with a simulated rider on it, riding at realistic power, cadence and speed
+    SimulatorBike,
+    /// any heart rate monitor advertising the standard Heart Rate Service
+    HeartRateMonitor,
+    /// any speed, cadence or combined sensor advertising the standard Cycling Speed and Cadence Service
+    SpeedCadenceSensor,
+    /// Keiser M3i bike, broadcasting its data in its advertisements
+    KeiserM3iBike,
+    /// Echelon Connect bike, speaking the proprietary protocol of Echelon
+    EchelonBike,
+    /// Wahoo KICKR smart trainer, controlled through the trainer control characteristic of Wahoo
+    KickrBike,
+    /// Tacx trainer tunnelling ANT+ FE-C over BLE
+    TacxFecBike,
+    /// Concept2 rower, ski erg or bike erg with a PM5 monitor, speaking the proprietary services of Concept2
+    Concept2Pm5Rower,
+    /// rower or other equipment speaking CSAFE over a serial port, or a Concept2 monitor over USB
+    CsafeRower,
+    /// older Kettler ergometer, speaking the text protocol of Kettler over a serial port
+    KettlerBike,
+    /// Elite Sterzo steering plate, measuring the steering angle of the rider
+    SterzoSteering,
+    /// Zwift Click or Zwift Play handlebar controller, reporting its buttons as events
+    ZwiftController,
+    /// trainer speaking ANT+ FE-C through an ANT USB stick, with the `ant` feature
+    #[cfg(feature = "ant")]
+    AntFecBike,
+    /// heart rate monitor broadcasting ANT+, through an ANT USB stick, with the `ant` feature
+    #[cfg(feature = "ant")]
+    AntHeartRateMonitor,
+    /// bicycle power meter broadcasting ANT+, through an ANT USB stick, with the `ant` feature
+    #[cfg(feature = "ant")]
+    AntPowerMeter,
+}
+
+/// A stream of machine status changes, see `Equipment::machine_status`
+pub type MachineStatusStream = Pin<Box<dyn Stream<Item = MachineStatus> + Send>>;
+
+/// A stream of steering angles, see `devices::SterzoSteering::steering`
+pub type SteeringStream = Pin<Box<dyn Stream<Item = devices::SteeringEvent> + Send>>;
+
+/// A stream of data notifications, see `Equipment::data_stream`
+pub type DataStream = Pin<Box<dyn Stream<Item = Reading> + Send>>;
+
+/// A stream of the raw values of a characteristic, see `BluetoothEquipment::subscribe_raw`
+pub type RawStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+/// Where the connection to a piece of equipment is at, see `Equipment::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConnectionState {
+    /// Found, but never connected to
+    Idle,
+    /// Looking for the device to connect to, for equipment scanning when connecting rather than when
+    /// created
+    Scanning,
+    /// Connecting, discovering services and subscribing to notifications
+    Connecting,
+    /// Connected and ready to be controlled, but no data has arrived yet
+    Connected,
+    /// Connected, with data arriving
+    Streaming,
+    /// The connection got lost, and is being connected again
+    Reconnecting,
+    /// Disconnected from, or failed to connect
+    Disconnected,
+}
+
+/// Something that happened to a piece of equipment, see `Equipment::events`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeviceEvent {
+    /// The equipment got connected to and is ready to be controlled
+    Connected,
+    /// The equipment got disconnected from
+    Disconnected,
+    /// The equipment reported new data
+    Data(Reading),
+    /// The machine status changed, like the user pressing start or stop on the console itself
+    MachineStatus(MachineStatus),
+    /// The battery level changed, in percent, see `Equipment::battery_level`
+    BatteryLevel(u8),
+    /// The battery dropped below the low level of `ScanConfig::low_battery`, holding the level in percent
+    LowBattery(u8),
+    /// The signal strength of the connection in dBm, sampled every few seconds while connected
+    ///
+    /// Connections start dropping out somewhere below -85 dBm or so, worth warning the user about.
+    Rssi(i16),
+    /// No data arrived for the interval of `ScanConfig::stale_after`, while connected
+    DataStale,
+    /// A button of a handlebar controller got pressed, see `devices::ZwiftController`
+    ButtonPressed(devices::ControllerButton),
+    /// A button of a handlebar controller got released
+    ButtonReleased(devices::ControllerButton),
+    /// The connection moved to another state, see `Equipment::state`
+    StateChanged(ConnectionState),
+    /// Something went wrong outside of any call, like a notification that couldn't be decoded
+    Error(String),
 }
 
+/// How long `Equipment::close` waits for the equipment to stop and disconnect
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Equipment trait for all equipment types
-#[async_trait]
-pub trait Equipment {
+///
+/// The futures of its methods aren't boxed, which keeps them cheap but the trait out of reach of
+/// trait objects. Every equipment is a `DynEquipment` as well, which boxes them instead, for
+/// `Box<dyn DynEquipment>` holding any equipment picked at runtime.
+///
+/// Equipment is `Send` and `Sync`, and every method but `connect` only borrows it, so once connected it
+/// can be shared between tasks through an `Arc`, like one task reading while another rides a workout.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, ErgController};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///     let device = Arc::new(device);
+///
+///     let erg = tokio::spawn({
+///         let (device, shutdown) = (device.clone(), shutdown.clone());
+///         async move {
+///             let erg = ErgController::new(25.);
+///             erg.set_target_power(150);
+///             erg.run(&*device, &shutdown).await
+///         }
+///     });
+///     let reading = device.read().await?;
+///     println!("{reading:?}");
+///     shutdown.cancel();
+///     erg.await??;
+///     Ok(())
+/// }
+/// ```
+pub trait Equipment: Send + Sync {
     /// Create a new instance of the equipment.
-    /// `max_level` is used to prevent the equipment from being set to a level higher than its capabilities
-    /// `shutdown_rx`` is used in the discovery/scanning phase of connecting to the equipment, allowing it to be shut down gracefully
+    /// `max_level` is used to prevent the equipment from being set to a level higher than its capabilities.
+    /// Equipment advertising its supported power and resistance ranges is clamped to those once connected instead.
+    /// `shutdown` is held on to by the equipment, and cancelling it stops discovery, connecting, reading and writing alike,
+    /// allowing it to be shut down gracefully. `disconnect` still stops the equipment when it has been cancelled.
     ///
     /// # Examples
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    fn new(
+        max_level: i16,
+        shutdown: &CancellationToken,
+    ) -> impl Future<Output = Result<Self>> + Send
+    where
+        Self: Sized,
+    {
+        Self::with_config(max_level, ScanConfig::default(), shutdown)
+    }
+    /// Create a new instance of the equipment, discovering it as described by `config`
+    ///
+    /// Behaves like `new` otherwise, which uses the default `ScanConfig`.
     ///
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use kondis::{devices::GenericFtmsBike, CancellationToken, Equipment, ScanConfig};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let config = ScanConfig::new()
+    ///         .timeout(Duration::from_secs(30))
+    ///         .address("C0:FF:EE:00:00:01");
+    ///     let mut bike = GenericFtmsBike::with_config(400, config, &shutdown).await?;
+    ///     bike.connect().await?;
     ///     Ok(())
     /// }
     /// ```
-    async fn new(max_level: i16, shutdown_rx: &mut Receiver<()>) -> anyhow::Result<Self>
+    fn with_config(
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> impl Future<Output = Result<Self>> + Send
     where
         Self: Sized;
+    /// Create a new instance of the equipment with the given address and connect to it
+    ///
+    /// Scanning is skipped entirely when an adapter already knows about the device, like after having
+    /// connected to it before, which makes reconnecting much faster. Otherwise the device is scanned for
+    /// by its address alone. Platforms which don't expose device addresses, like macOS, always scan.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsBike, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let bike = GenericFtmsBike::connect_by_address(400, "C0:FF:EE:00:00:01", &shutdown).await?;
+    ///     bike.start().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn connect_by_address(
+        max_level: i16,
+        address: &str,
+        shutdown: &CancellationToken,
+    ) -> impl Future<Output = Result<Self>> + Send
+    where
+        Self: Sized,
+    {
+        async move {
+            let config = ScanConfig::new().address(address);
+            let mut equipment = Self::with_config(max_level, config, shutdown).await?;
+            if !equipment.connect().await? {
+                return Err(KondisError::Disconnected(format!(
+                    "Could not connect to {address}"
+                )));
+            }
+            Ok(equipment)
+        }
+    }
     /// Connect to the equipment, discover its capabilities for reading and writing
     ///
     /// # Examples
     ///
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let mut device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
     ///     device.connect().await?;
     ///     Ok(())
     /// }
     /// ```
-    async fn connect(&mut self) -> anyhow::Result<bool>;
+    fn connect(&mut self) -> impl Future<Output = Result<bool>> + Send;
     /// Disconnect from the equipment, disconnecting from any subscriptions, and sending any stop signals if required
     ///
     /// # Examples
     ///
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let mut device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
     ///     device.connect().await?;
     ///     device.disconnect().await?;
     ///     Ok(())
     /// }
-    async fn disconnect(&self) -> anyhow::Result<()>;
+    fn disconnect(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Stop the session on the equipment and disconnect from it, giving up after 10 seconds
+    ///
+    /// The equipment gets told to stop as far as it lets itself be, even when `shutdown` has been
+    /// cancelled, so it lets go of any target it was holding, and unlike `disconnect`, closing doesn't
+    /// hang on equipment that stopped responding. FTMS equipment dropped while still connected gets
+    /// stopped and disconnected from in the background as well, which may not get to happen when the
+    /// app exits right after, so close equipment before exiting. Other equipment, like `IconsoleBike`
+    /// and `QuirkBike`, is left as it was when dropped, holding its last target until closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.set_target_power(300).await?;
+    ///     shutdown.cancel();
+    ///     device.close().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn close(&self) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let closing = async {
+                if let Err(e) = self.stop().await {
+                    warn!("Stopping before disconnecting failed: {e}");
+                }
+                self.disconnect().await
+            };
+            crate::runtime::timeout(CLOSE_TIMEOUT, closing)
+                .await
+                .map_err(|_| {
+                    KondisError::Timeout(format!("Closing took longer than {CLOSE_TIMEOUT:?}"))
+                })?
+        }
+    }
     /// Set the equipment target cadence
     ///
     /// # Examples
     ///
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let mut device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
     ///     device.connect().await?;
     ///     device.set_target_cadence(32).await?;
     ///     Ok(())
     /// }
-    async fn set_target_cadence(&self, rpm: i16) -> anyhow::Result<()>;
+    fn set_target_cadence(&self, rpm: i16) -> impl Future<Output = Result<()>> + Send;
     /// Set the equipment target power
     ///
+    /// FTMS equipment confirms every command, so refused targets fail with `KondisError::ControlRejected`.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let mut device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
     ///     device.connect().await?;
     ///     device.set_target_power(32).await?;
     ///     Ok(())
     /// }
-    async fn set_target_power(&self, watts: i16) -> anyhow::Result<()>;
-    /// Read the latest notification received and process it to an easy to use FTMS format
+    fn set_target_power(&self, watts: i16) -> impl Future<Output = Result<()>> + Send;
+    /// Set the equipment target resistance level
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.set_target_resistance_level(12).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_target_resistance_level(&self, level: i16) -> impl Future<Output = Result<()>> + Send;
+    /// Set the equipment target heart rate, letting it adjust the load to keep the user at `bpm`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.set_target_heart_rate(140).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_target_heart_rate(&self, bpm: u8) -> impl Future<Output = Result<()>> + Send;
+    /// Set a goal for the session, which the equipment tracks and ends the session on once reached
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, TrainingGoal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.set_goal(TrainingGoal::TrainingTime(45 * 60)).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_goal(&self, goal: ftms::TrainingGoal) -> impl Future<Output = Result<()>> + Send;
+    /// Set the equipment simulation parameters, letting it pick the resistance for a virtual route
+    ///
+    /// `grade` is in percent, `wind_speed` in m/s, `crr` is the rolling resistance coefficient and
+    /// `cw` the wind resistance coefficient in kg/m.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.set_simulation_parameters(4.5, 0.0, 0.004, 0.51).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_simulation_parameters(
+        &self,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Start a session on the equipment
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.start().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn start(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Stop the session on the equipment
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.start().await?;
+    ///     device.stop().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn stop(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Pause the session on the equipment, keeping its progress
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.start().await?;
+    ///     device.pause().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn pause(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Resume a paused session on the equipment
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.pause().await?;
+    ///     device.resume().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn resume(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Reset the equipment, clearing its session data and any targets set
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     device.reset().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn reset(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Calibrate the equipment with a spin down
+    ///
+    /// Every status update is sent on `status_tx`, telling the user when to speed up and when to stop
+    /// pedaling. Resolves with the outcome once the equipment reports the calibration as done.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let (status_tx, status_rx) = std::sync::mpsc::channel();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     let result = device.spin_down(&status_tx).await?;
+    ///     for status in status_rx.try_iter() {
+    ///         println!("{status:?}");
+    ///     }
+    ///     assert!(result.success);
+    ///     Ok(())
+    /// }
+    /// ```
+    fn spin_down<'a>(
+        &'a self,
+        status_tx: &'a Sender<SpinDownStatus>,
+    ) -> impl Future<Output = Result<SpinDownResult>> + Send;
+    /// Listen for machine status changes, like the user pressing start or stop on the console itself
+    ///
+    /// The stream yields every status change from the moment it is created.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::StreamExt;
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, MachineStatus};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     let mut status = device.machine_status().await?;
+    ///     while let Some(status) = status.next().await {
+    ///         if status == MachineStatus::StoppedByUser {
+    ///             break;
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn machine_status(&self) -> impl Future<Output = Result<MachineStatusStream>> + Send;
+    /// The targets the equipment accepts and the data fields it reports
+    ///
+    /// Learned when connecting, so this is `None` before `connect` or when the equipment doesn't say.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     if let Some(capabilities) = device.capabilities() {
+    ///         println!("supports target power: {}", capabilities.targets.power);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn capabilities(&self) -> Option<ftms::Capabilities>;
+    /// The battery level of the equipment in percent, or `None` if it doesn't report one through the
+    /// Battery Service
+    ///
+    /// Read when connecting, and kept up to date for equipment notifying changes of the level, which get
+    /// reported as `DeviceEvent::BatteryLevel` as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     if let Some(level) = device.battery_level() {
+    ///         println!("{level}% battery left");
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn battery_level(&self) -> Option<u8> {
+        None
+    }
+    /// The manufacturer, model, serial number and firmware revision of the equipment, or `None` if it
+    /// doesn't tell through the Device Information Service
+    ///
+    /// Read when connecting, handy for working around the quirks of particular models and for support
+    /// reports.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     if let Some(info) = device.device_info() {
+    ///         println!("{:?} firmware {:?}", info.model_number, info.firmware_revision);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn device_info(&self) -> Option<DeviceInfo> {
+        None
+    }
+    /// The ranges of target power, resistance level, speed and inclination the equipment advertises,
+    /// so a UI can render sliders with the right bounds and steps rather than guessing them
+    ///
+    /// Read when connecting, so this is `None` before `connect` or when the equipment doesn't say.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsBike, CancellationToken, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut bike = GenericFtmsBike::new(400, &shutdown).await?;
+    ///     bike.connect().await?;
+    ///     if let Some(power) = bike.supported_ranges().and_then(|ranges| ranges.power) {
+    ///         println!("{} to {} W by {} W", power.min, power.max, power.increment);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn supported_ranges(&self) -> Option<SupportedRanges> {
+        None
+    }
+    /// Subscribe to everything happening to the equipment, so a UI can be driven without polling `read`
+    ///
+    /// The receiver gets every event from the moment it subscribes. A receiver falling too far behind
+    /// misses the oldest events, and learns about it through `RecvError::Lagged`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, DeviceEvent, Equipment, FTMSData};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     let mut events = device.events();
+    ///     device.connect().await?;
+    ///     while let Ok(event) = events.recv().await {
+    ///         if let DeviceEvent::Data(reading) = event {
+    ///             println!("{:?} W", FTMSData::from(reading.data).power);
+    ///             break;
+    ///         }
+    ///     }
+    ///     device.disconnect().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn events(&self) -> tokio::sync::broadcast::Receiver<DeviceEvent>;
+    /// Where the connection to the equipment is at, changes of which are reported as
+    /// `DeviceEvent::StateChanged` as well
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, ConnectionState, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     assert_eq!(device.state(), ConnectionState::Idle);
+    ///     device.connect().await?;
+    ///     // streaming once the first data arrived
+    ///     println!("{:?}", device.state());
+    ///     device.disconnect().await?;
+    ///     assert_eq!(device.state(), ConnectionState::Disconnected);
+    ///     Ok(())
+    /// }
+    /// ```
+    fn state(&self) -> ConnectionState;
+    /// Read the latest notification received and decode it to the data of this kind of machine
     ///
     /// # Examples
     ///
     /// ```
-    /// use kondis::{devices::NonBluetoothDevice, Equipment};
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment};
     ///
     /// #[tokio::main]
     /// async fn main() -> anyhow::Result<()> {
-    ///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-    ///     let mut device = NonBluetoothDevice::new(32, &mut shutdown_rx).await?;
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
     ///     device.connect().await?;
     ///     println!("{:?}", device.read().await?);
     ///     Ok(())
     /// }
     /// ```
-    async fn read(&self) -> anyhow::Result<Option<ftms::FTMSData>>;
+    fn read(&self) -> impl Future<Output = Result<Option<MachineData>>> + Send;
+    /// Every data notification received from now on, processed to the same format as `read`
+    ///
+    /// Unlike calling `read` in a loop, each notification is yielded exactly once and none get missed
+    /// in between. Notifications that can't be decoded are skipped, leaving a gap in the sequence
+    /// numbers of the readings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::StreamExt;
+    /// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, FTMSData};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+    ///     device.connect().await?;
+    ///     let mut data = device.data_stream().await?.take(1);
+    ///     while let Some(reading) = data.next().await {
+    ///         println!("#{} {:?} rpm", reading.sequence, FTMSData::from(reading.data).cadence);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn data_stream(&self) -> impl Future<Output = Result<DataStream>> + Send;
+}
+
+/// Equipment talked to over Bluetooth, giving raw access to the characteristics of its peripheral
+///
+/// An escape hatch for proprietary characteristics this crate doesn't know, without forking it. Raw
+/// writes go to the peripheral beside the commands of the equipment itself, which won't know about
+/// them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use kondis::{devices::GenericFtmsBike, BluetoothEquipment, CancellationToken, Equipment};
+/// use uuid::uuid;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut bike = GenericFtmsBike::new(400, &shutdown).await?;
+///     bike.connect().await?;
+///     let vendor = uuid!("0000fff1-0000-1000-8000-00805f9b34fb");
+///     let mut values = bike.subscribe_raw(vendor).await?;
+///     bike.write_raw(vendor, &[0x01, 0x02]).await?;
+///     if let Some(value) = values.next().await {
+///         println!("{value:02x?}");
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub trait BluetoothEquipment: Equipment {
+    /// The peripheral of the equipment, for the functions of `bluetooth`
+    fn peripheral(&self) -> &btleplug::platform::Peripheral;
+
+    /// Write `bytes` as they are to the characteristic `uuid` of the connected peripheral
+    ///
+    /// Characteristics that can't be written with a response get written without one.
+    fn write_raw(&self, uuid: uuid::Uuid, bytes: &[u8]) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            use btleplug::api::Peripheral as _;
+
+            let peripheral = self.peripheral();
+            let Some(characteristic) = bluetooth::find_characteristic(peripheral, uuid) else {
+                return Err(KondisError::CharacteristicMissing(uuid.to_string()));
+            };
+            trace!(uuid = %uuid, "Writing raw {bytes:02x?}");
+            let write_type = bluetooth::write_type(&characteristic);
+            Ok(peripheral.write(&characteristic, bytes, write_type).await?)
+        }
+    }
+
+    /// Subscribe to the characteristic `uuid` of the connected peripheral, streaming its values as they
+    /// are
+    ///
+    /// The characteristic stays subscribed to, and the stream goes on, until the peripheral
+    /// disconnects.
+    fn subscribe_raw(&self, uuid: uuid::Uuid) -> impl Future<Output = Result<RawStream>> + Send {
+        async move {
+            // listening first, so nothing notified right after subscribing gets missed
+            let values = bluetooth::notifications(self.peripheral(), uuid).await?;
+            bluetooth::subscribe(self.peripheral(), uuid).await?;
+            Ok(values)
+        }
+    }
+}
+
+/// Treadmill trait for equipment driven by speed and inclination rather than cadence and power
+///
+/// `max_level` passed to `Equipment::new` is the highest speed in km/h the treadmill may be set to.
+pub trait Treadmill: Equipment {
+    /// Set the treadmill target speed in km/h
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsTreadmill, CancellationToken, Equipment, Treadmill};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut treadmill = GenericFtmsTreadmill::new(16, &shutdown).await?;
+    ///     treadmill.connect().await?;
+    ///     treadmill.set_target_speed(8.5).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_target_speed(&self, kmh: f32) -> impl Future<Output = Result<()>> + Send;
+    /// Set the treadmill target inclination in percent
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsTreadmill, CancellationToken, Equipment, Treadmill};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut treadmill = GenericFtmsTreadmill::new(16, &shutdown).await?;
+    ///     treadmill.connect().await?;
+    ///     treadmill.set_target_inclination(2.5).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn set_target_inclination(&self, percent: f32) -> impl Future<Output = Result<()>> + Send;
+    /// Read the latest notification received, keeping treadmill specific fields like inclination and pace
+    fn read_treadmill(&self) -> impl Future<Output = Result<Option<ftms::TreadmillData>>> + Send;
+}
+
+/// Rower trait for rowing machines, which report strokes rather than pedal revolutions
+pub trait Rower: Equipment {
+    /// Read the latest notification received, keeping rower specific fields like stroke count and split pace
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsRower, CancellationToken, Equipment, Rower};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut rower = GenericFtmsRower::new(400, &shutdown).await?;
+    ///     rower.connect().await?;
+    ///     if let Some(data) = rower.read_rower().await? {
+    ///         println!("{:?} strokes at {:?} spm", data.stroke_count, data.stroke_rate);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn read_rower(&self) -> impl Future<Output = Result<Option<ftms::RowerData>>> + Send;
+}
+
+/// Cross trainer trait for ellipticals, which report strides rather than pedal revolutions
+pub trait CrossTrainer: Equipment {
+    /// Read the latest notification received, keeping cross trainer specific fields like stride rate and elevation gain
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kondis::{devices::GenericFtmsCrossTrainer, CancellationToken, CrossTrainer, Equipment};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let shutdown = CancellationToken::new();
+    ///     let mut elliptical = GenericFtmsCrossTrainer::new(300, &shutdown).await?;
+    ///     elliptical.connect().await?;
+    ///     if let Some(data) = elliptical.read_cross_trainer().await? {
+    ///         println!("{:?} spm, {:?} m climbed", data.stride_rate, data.elevation_gain);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn read_cross_trainer(
+        &self,
+    ) -> impl Future<Output = Result<Option<ftms::CrossTrainerData>>> + Send;
 }
 
 /// Convert an equipment type to an instance of an equipment
 ///
-/// This function takes an `EquipmentType`, a maximum resistance level, and a shutdown receiver,
-/// and returns an instance of the corresponding equipment type.
+/// This function takes an `EquipmentType`, a maximum resistance level, and a shutdown token,
+/// and returns an instance of the corresponding equipment type, or the reason it couldn't be created,
+/// like `KondisError::DeviceNotFound` when scanning got shut down before finding it.
 ///
 /// If you are contributing a new type of equipment, remember to add it here as well.
 ///
 /// # Examples
 ///
 /// ```
-/// use kondis::{EquipmentType, equipment_type_to_equipment, Equipment};
+/// use kondis::{equipment_type_to_equipment, CancellationToken, Equipment, EquipmentType};
 ///
 /// #[tokio::main]
 /// async fn main() -> anyhow::Result<()> {
-///     let (_, mut shutdown_rx) = std::sync::mpsc::channel();
-///     let equipment = equipment_type_to_equipment(EquipmentType::NonBluetoothDevice, 10, &mut shutdown_rx).await;
-///     if let Some(mut equip) = equipment {
-///         equip.connect().await?;
-///         equip.read().await?;
-///     }
+///     let shutdown = CancellationToken::new();
+///     let mut equip = equipment_type_to_equipment(EquipmentType::NonBluetoothDevice, 10, &shutdown).await?;
+///     equip.connect().await?;
+///     equip.read().await?;
 ///     Ok(())
 /// }
 /// ```
+#[cfg(not(target_arch = "wasm32"))]
 pub async fn equipment_type_to_equipment(
     equipment_type: EquipmentType,
     max_level: i16,
-    shutdown_rx: &mut Receiver<()>,
-) -> Option<Box<dyn Equipment>> {
+    shutdown: &CancellationToken,
+) -> Result<Box<dyn DynEquipment>> {
     match equipment_type {
         EquipmentType::Iconsole0028Bike => {
-            let equip = Iconsole0028Bike::new(max_level, shutdown_rx).await;
-            if equip.is_err() {
-                return None;
-            }
-            Some(Box::new(equip.unwrap()))
+            let equip = Iconsole0028Bike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::Iconsole0010Bike => {
+            let equip = Iconsole0010Bike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::Iconsole0051Bike => {
+            let equip = Iconsole0051Bike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
         }
         EquipmentType::DebugBike => {
-            let equip = DebugBike::new(max_level, shutdown_rx).await;
-            if equip.is_err() {
-                return None;
-            }
-            Some(Box::new(equip.unwrap()))
+            let equip = DebugBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::GenericFtmsBike => {
+            let equip = GenericFtmsBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::GenericFtmsTreadmill => {
+            let equip = GenericFtmsTreadmill::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::GenericFtmsRower => {
+            let equip = GenericFtmsRower::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::GenericFtmsCrossTrainer => {
+            let equip = GenericFtmsCrossTrainer::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
         }
         EquipmentType::NonBluetoothDevice => {
-            let equip = NonBluetoothDevice::new(max_level, shutdown_rx).await;
-            if equip.is_err() {
-                return None;
-            }
-            Some(Box::new(equip.unwrap()))
+            let equip = NonBluetoothDevice::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::SimulatorBike => {
+            let equip = SimulatorBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::HeartRateMonitor => {
+            let equip = HeartRateMonitor::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::SpeedCadenceSensor => {
+            let equip = SpeedCadenceSensor::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::KeiserM3iBike => {
+            let equip = KeiserM3iBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::EchelonBike => {
+            let equip = EchelonBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::KickrBike => {
+            let equip = KickrBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::TacxFecBike => {
+            let equip = TacxFecBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::Concept2Pm5Rower => {
+            let equip = Pm5Rower::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::CsafeRower => {
+            let equip = CsafeRower::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::KettlerBike => {
+            let equip = KettlerBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::SterzoSteering => {
+            let equip = SterzoSteering::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        EquipmentType::ZwiftController => {
+            let equip = ZwiftController::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        #[cfg(feature = "ant")]
+        EquipmentType::AntFecBike => {
+            let equip = devices::AntFecBike::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        #[cfg(feature = "ant")]
+        EquipmentType::AntHeartRateMonitor => {
+            let equip = devices::AntHeartRateMonitor::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
+        }
+        #[cfg(feature = "ant")]
+        EquipmentType::AntPowerMeter => {
+            let equip = devices::AntPowerMeter::new(max_level, shutdown).await?;
+            Ok(Box::new(equip))
         }
     }
 }
@@ -185,43 +1201,109 @@ pub async fn equipment_type_to_equipment(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::devices::RecordingEquipment;
+    use crate::remote::Request;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close() {
+        let equipment = RecordingEquipment::new();
+        let mut events = Equipment::events(&equipment);
+        assert!(Equipment::close(&equipment).await.is_ok());
+        // stopped before disconnecting
+        assert_eq!(equipment.requests(), [(Duration::ZERO, Request::Stop)]);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(DeviceEvent::StateChanged(ConnectionState::Disconnected))
+        ));
+        assert!(matches!(events.try_recv(), Ok(DeviceEvent::Disconnected)));
+
+        // equipment that stopped responding is given up on
+        equipment.stop_responding();
+        let start = crate::runtime::Instant::now();
+        assert!(matches!(
+            Equipment::close(&equipment).await,
+            Err(KondisError::Timeout(_))
+        ));
+        assert_eq!(start.elapsed(), CLOSE_TIMEOUT);
+        assert_eq!(equipment.requests().len(), 2);
+    }
 
     #[tokio::test]
-    async fn test_equipment_type_to_equipment() -> anyhow::Result<()> {
-        let (_, mut shutdown_rx) = std::sync::mpsc::channel();
+    async fn test_equipment_type_to_equipment() -> Result<()> {
+        let shutdown = CancellationToken::new();
         let equipment =
-            equipment_type_to_equipment(EquipmentType::NonBluetoothDevice, 10, &mut shutdown_rx)
-                .await;
-        assert!(equipment.is_some());
-        let mut equipment = equipment.unwrap();
+            equipment_type_to_equipment(EquipmentType::NonBluetoothDevice, 10, &shutdown).await;
+        assert!(equipment.is_ok());
+        let mut equipment = equipment?;
         assert!(equipment.connect().await?);
         assert!(equipment.read().await?.is_some());
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_shutdown_while_scanning() -> anyhow::Result<()> {
-        let (shutdown_tx, mut shutdown_rx) = std::sync::mpsc::channel();
+    async fn test_equipment_type_to_equipment_failure() {
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        let failed =
+            equipment_type_to_equipment(EquipmentType::GenericFtmsBike, 10, &shutdown).await;
+        // shut down before scanning, unless there's no Bluetooth to scan with in the first place
+        assert!(matches!(
+            failed,
+            Err(KondisError::DeviceNotFound
+                | KondisError::BluetoothUnavailable(_)
+                | KondisError::AdapterPoweredOff(_)
+                | KondisError::PermissionDenied(_))
+        ));
+    }
 
-        let _ = shutdown_tx.send(());
+    #[tokio::test]
+    async fn test_shared_between_tasks() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut equipment =
+            equipment_type_to_equipment(EquipmentType::NonBluetoothDevice, 10, &shutdown).await?;
+        assert!(equipment.connect().await?);
+        let equipment: std::sync::Arc<dyn DynEquipment> = equipment.into();
 
-        let equipment = Iconsole0028Bike::new(10, &mut shutdown_rx).await;
+        let reader = tokio::spawn({
+            let equipment = equipment.clone();
+            async move { equipment.read().await }
+        });
+        let erg = tokio::spawn({
+            let (equipment, shutdown) = (equipment.clone(), shutdown.clone());
+            async move {
+                let erg = ErgController::new(25.);
+                erg.set_target_power(5);
+                erg.run(&*equipment, &shutdown).await
+            }
+        });
+        assert!(reader.await.expect("reader panicked")?.is_some());
+        shutdown.cancel();
+        erg.await.expect("controller panicked")?;
+        equipment.disconnect().await
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_while_scanning() -> Result<()> {
+        let shutdown = CancellationToken::new();
+
+        shutdown.cancel();
+
+        let equipment = Iconsole0028Bike::new(10, &shutdown).await;
 
         assert!(equipment.is_err());
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_shutdown_while_scanning_from_equipment_type() -> anyhow::Result<()> {
-        let (shutdown_tx, mut shutdown_rx) = std::sync::mpsc::channel();
+    async fn test_shutdown_while_scanning_from_equipment_type() -> Result<()> {
+        let shutdown = CancellationToken::new();
 
-        let _ = shutdown_tx.send(());
+        shutdown.cancel();
 
         let equipment =
-            equipment_type_to_equipment(EquipmentType::Iconsole0028Bike, 10, &mut shutdown_rx)
-                .await;
+            equipment_type_to_equipment(EquipmentType::Iconsole0028Bike, 10, &shutdown).await;
 
-        assert!(equipment.is_none());
+        assert!(equipment.is_err());
         Ok(())
     }
 }
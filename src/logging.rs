@@ -0,0 +1,82 @@
+//! Stand-ins for the `tracing` macros, for builds without the `tracing` feature
+//!
+//! With the feature, events and spans come straight from `tracing`. Without it nothing gets
+//! recorded, but the fields and messages still get type checked, so the events can't rot. Only the
+//! `key = value`, `key = %value` and `key = ?value` fields are understood, followed by the message.
+
+/// Type check the fields and message of an event, without recording it
+macro_rules! event {
+    ($key:ident = %$value:expr, $($rest:tt)+) => {{
+        let _ = &$value;
+        event!($($rest)+);
+    }};
+    ($key:ident = ?$value:expr, $($rest:tt)+) => {{
+        let _ = &$value;
+        event!($($rest)+);
+    }};
+    ($key:ident = $value:expr, $($rest:tt)+) => {{
+        let _ = &$value;
+        event!($($rest)+);
+    }};
+    ($($arg:tt)+) => {
+        let _ = format_args!($($arg)+);
+    };
+}
+
+/// Every notification and advertisement
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        if false {
+            event!($($arg)+);
+        }
+    };
+}
+
+/// Every write, and what scanning and connecting go through
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if false {
+            event!($($arg)+);
+        }
+    };
+}
+
+/// Equipment found, connected or served
+macro_rules! info {
+    ($($arg:tt)+) => {
+        if false {
+            event!($($arg)+);
+        }
+    };
+}
+
+/// What goes wrong without failing anything, like reconnecting
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        if false {
+            event!($($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    #[test]
+    fn test_stand_ins() {
+        let evaluated = Cell::new(0);
+        let count = || {
+            evaluated.set(evaluated.get() + 1);
+            evaluated.get()
+        };
+        let name = String::from("bike");
+        trace!(count = count(), "Traced {}", count());
+        debug!(name = %name, "Debugged");
+        info!(name = ?name, count = count(), "Found {name}");
+        warn!("Warned {}", count());
+        // nothing gets evaluated, or moved
+        assert_eq!(evaluated.get(), 0);
+        assert_eq!(name, "bike");
+    }
+}
@@ -0,0 +1,192 @@
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{DeviceEvent, Equipment, FTMSData, Result, UserProfile};
+
+/// m/s²
+const GRAVITY: f64 = 9.80665;
+/// kg/m³, at sea level and 15 °C
+pub(crate) const AIR_DENSITY: f64 = 1.225;
+/// The share of power lost between the pedals and the rear wheel
+const DRIVETRAIN_LOSS: f64 = 0.025;
+
+/// The forces on a rider riding outside, to work out the power riding at a speed takes
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{RideModel, UserProfile};
+///
+/// let model = RideModel::new(&UserProfile::default()).cda(0.25);
+/// println!("{:.0} W at 30 km/h up 5 %", model.power(30., 5.));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RideModel {
+    /// kg, the rider and the bike
+    mass: f64,
+    /// m², drag coefficient times frontal area
+    cda: f64,
+    /// rolling resistance coefficient
+    crr: f64,
+    /// m/s, headwind when positive
+    wind_speed: f64,
+}
+
+impl RideModel {
+    /// A rider and bike weighing what `profile` says, riding on the hoods on a road bike without wind
+    pub fn new(profile: &UserProfile) -> Self {
+        RideModel {
+            mass: profile.weight_kg + profile.bike_weight_kg,
+            cda: 0.32,
+            crr: 0.004,
+            wind_speed: 0.,
+        }
+    }
+
+    /// Drag coefficient times frontal area in m², 0.32 by default and about 0.25 in the drops
+    pub fn cda(mut self, cda: f64) -> Self {
+        self.cda = cda;
+        self
+    }
+
+    /// Rolling resistance coefficient, 0.004 by default for road tyres on asphalt
+    pub fn crr(mut self, crr: f64) -> Self {
+        self.crr = crr;
+        self
+    }
+
+    /// Wind speed in m/s, headwind when positive and tailwind when negative
+    pub fn wind_speed(mut self, wind_speed: f64) -> Self {
+        self.wind_speed = wind_speed;
+        self
+    }
+
+    /// The power in watts riding at `speed` km/h up a `grade` percent slope takes
+    ///
+    /// Negative when coasting would be faster, like downhill.
+    pub fn power(&self, speed: f64, grade: f64) -> f64 {
+        let speed = speed / 3.6;
+        let angle = (grade / 100.).atan();
+        let gravity = self.mass * GRAVITY * angle.sin();
+        let rolling = self.mass * GRAVITY * self.crr * angle.cos();
+        let air_speed = speed + self.wind_speed;
+        let drag = 0.5 * AIR_DENSITY * self.cda * air_speed * air_speed.abs();
+        (gravity + rolling + drag) * speed / (1. - DRIVETRAIN_LOSS)
+    }
+}
+
+/// Route simulation for equipment without simulation parameters of its own
+///
+/// Sets the target power riding the current grade takes at the speed the equipment reports, every time
+/// it reports one. Equipment only taking resistance levels gets the power through its own mapping of
+/// power to level.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{
+///     devices::NonBluetoothDevice, CancellationToken, Equipment, GradeSimulator, RideModel,
+///     UserProfile,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let simulator = GradeSimulator::new(RideModel::new(&UserProfile::default()));
+///     simulator.set_grade(4.5);
+///     shutdown.cancel();
+///     simulator.run(&device, &shutdown).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct GradeSimulator {
+    model: RideModel,
+    /// percent
+    grade_tx: watch::Sender<f64>,
+}
+
+impl GradeSimulator {
+    /// A simulator of riding with `model`, on the flat until told otherwise
+    pub fn new(model: RideModel) -> Self {
+        GradeSimulator {
+            model,
+            grade_tx: watch::Sender::new(0.),
+        }
+    }
+
+    /// Change the grade being ridden, in percent
+    pub fn set_grade(&self, grade: f64) {
+        self.grade_tx.send_replace(grade);
+    }
+
+    /// The grade being ridden, in percent
+    pub fn grade(&self) -> f64 {
+        *self.grade_tx.borrow()
+    }
+
+    /// Keep setting the target power of `equipment`, until `shutdown` gets cancelled or the equipment
+    /// stops sending events
+    ///
+    /// Fails as soon as the equipment refuses a target power.
+    pub async fn run<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut events = equipment.events();
+        let mut grade_rx = self.grade_tx.subscribe();
+        let mut speed = None;
+        let mut watts = None;
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = grade_rx.changed() => {}
+                event = events.recv() => match event {
+                    Ok(DeviceEvent::Data(reading)) => {
+                        speed = FTMSData::from(reading.data).speed.map(f64::from).or(speed);
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+            let Some(speed) = speed else {
+                continue;
+            };
+            let grade = *grade_rx.borrow_and_update();
+            // equipment takes no less than 1 W, even when coasting downhill
+            let next = self
+                .model
+                .power(speed, grade)
+                .round()
+                .clamp(1., i16::MAX.into()) as i16;
+            if watts != Some(next) {
+                equipment.set_target_power(next).await?;
+                watts = Some(next);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ride_model() {
+        let model = RideModel::new(&UserProfile::default());
+        assert_eq!(model.power(0., 5.), 0.);
+        let flat = model.power(30., 0.);
+        assert!((140. ..150.).contains(&flat), "{flat} W");
+        let climb = model.power(30., 5.);
+        assert!((490. ..510.).contains(&climb), "{climb} W");
+        assert!(model.power(30., -5.) < 0.);
+        assert!(model.wind_speed(5.).power(30., 0.) > flat);
+    }
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::{Equipment, FTMSData, Reading, Result};
+
+/// Readings further apart than this aren't compared to each other
+const MAX_SKEW: Duration = Duration::from_secs(2);
+
+/// The power a trainer reports compared to that of a power meter, like pedals or a crank, riding it
+///
+/// Every reading of either is compared to the latest reading of the other, unless that one is older than
+/// a couple of seconds. How far apart they are is summed up as an offset, a scale and a drift over time,
+/// so a trainer reading steadily high can be told apart from one warming up or slipping.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::devices::{NonBluetoothDevice, SimulatorBike};
+/// use kondis::{CancellationToken, Equipment, PowerComparison};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut trainer = SimulatorBike::new(32, &shutdown).await?;
+///     let mut meter = NonBluetoothDevice::new(32, &shutdown).await?;
+///     trainer.connect().await?;
+///     meter.connect().await?;
+///
+///     let stop = shutdown.clone();
+///     tokio::spawn(async move {
+///         tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+///         stop.cancel();
+///     });
+///     let mut comparison = PowerComparison::new();
+///     comparison.run(&trainer, &meter, &shutdown).await?;
+///     println!("trainer off by {:?} W", comparison.mean_offset());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PowerComparison {
+    /// The latest power of either, with when it arrived
+    trainer: Option<(Duration, f64)>,
+    meter: Option<(Duration, f64)>,
+    first: Option<Duration>,
+    sums: Sums,
+}
+
+/// Running sums over every compared pair, of the offset `d` at `t` seconds since the first pair
+#[derive(Debug, Clone, Default)]
+struct Sums {
+    count: f64,
+    trainer: f64,
+    meter: f64,
+    t: f64,
+    tt: f64,
+    d: f64,
+    dd: f64,
+    td: f64,
+}
+
+impl PowerComparison {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a reading of the trainer, left out when it doesn't hold any power
+    pub fn record_trainer(&mut self, reading: &Reading) {
+        if let Some(power) = power(reading) {
+            self.trainer = Some((reading.timestamp, power));
+            if let Some(meter) = self.meter {
+                self.compare(reading.timestamp, power, meter);
+            }
+        }
+    }
+
+    /// Add a reading of the power meter, left out when it doesn't hold any power
+    pub fn record_meter(&mut self, reading: &Reading) {
+        if let Some(power) = power(reading) {
+            self.meter = Some((reading.timestamp, power));
+            if let Some((at, trainer)) = self.trainer {
+                self.compare(at, trainer, (reading.timestamp, power));
+            }
+        }
+    }
+
+    /// Record the readings of `trainer` and `meter`, until `shutdown` gets cancelled or both stop
+    /// reporting
+    pub async fn run<T: Equipment + ?Sized, M: Equipment + ?Sized>(
+        &mut self,
+        trainer: &T,
+        meter: &M,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut trainer = trainer.data_stream().await?.fuse();
+        let mut meter = meter.data_stream().await?.fuse();
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                Some(reading) = trainer.next() => self.record_trainer(&reading),
+                Some(reading) = meter.next() => self.record_meter(&reading),
+                else => return Ok(()),
+            }
+        }
+    }
+
+    /// How many pairs of readings got compared
+    pub fn count(&self) -> usize {
+        self.sums.count as usize
+    }
+
+    /// W the trainer reports above the power meter on average, negative when it reports below
+    pub fn mean_offset(&self) -> Option<f64> {
+        let sums = &self.sums;
+        (sums.count > 0.).then(|| sums.d / sums.count)
+    }
+
+    /// W the offset spreads by, as a standard deviation
+    pub fn offset_std_dev(&self) -> Option<f64> {
+        let sums = &self.sums;
+        let mean = self.mean_offset()?;
+        Some((sums.dd / sums.count - mean * mean).max(0.).sqrt())
+    }
+
+    /// The power of the trainer over that of the power meter, like 1.03 for a trainer reading 3% high
+    pub fn scale(&self) -> Option<f64> {
+        let sums = &self.sums;
+        (sums.meter > 0.).then(|| sums.trainer / sums.meter)
+    }
+
+    /// W per hour the offset changes by over the session, fitted as a line through every pair
+    ///
+    /// Needs pairs compared at different times.
+    pub fn drift(&self) -> Option<f64> {
+        let sums = &self.sums;
+        let variance = sums.count * sums.tt - sums.t * sums.t;
+        (sums.count >= 2. && variance > 0.)
+            .then(|| (sums.count * sums.td - sums.t * sums.d) / variance * 3600.)
+    }
+
+    fn compare(&mut self, at: Duration, trainer: f64, (meter_at, meter): (Duration, f64)) {
+        if at.abs_diff(meter_at) > MAX_SKEW {
+            return;
+        }
+        let at = at.max(meter_at);
+        let t = at
+            .saturating_sub(*self.first.get_or_insert(at))
+            .as_secs_f64();
+        let d = trainer - meter;
+        let sums = &mut self.sums;
+        sums.count += 1.;
+        sums.trainer += trainer;
+        sums.meter += meter;
+        sums.t += t;
+        sums.tt += t * t;
+        sums.d += d;
+        sums.dd += d * d;
+        sums.td += t * d;
+    }
+}
+
+fn power(reading: &Reading) -> Option<f64> {
+    FTMSData::from(reading.data.clone()).power.map(f64::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BikeData;
+
+    fn reading(seconds: u64, power: i16) -> Reading {
+        Reading {
+            timestamp: Duration::from_secs(seconds),
+            sequence: 0,
+            data: BikeData {
+                power: Some(power),
+                ..Default::default()
+            }
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_power_comparison() {
+        let mut comparison = PowerComparison::new();
+        assert_eq!(comparison.mean_offset(), None);
+        comparison.record_trainer(&reading(0, 210));
+        comparison.record_meter(&reading(1, 200));
+        // the offset grows by 10 W every 30 minutes
+        comparison.record_trainer(&reading(1800, 220));
+        comparison.record_meter(&reading(1801, 200));
+        comparison.record_trainer(&reading(3600, 230));
+        comparison.record_meter(&reading(3600, 200));
+        // too long after the meter reported to be compared
+        comparison.record_trainer(&reading(3700, 500));
+
+        assert_eq!(comparison.count(), 3);
+        assert_eq!(comparison.mean_offset(), Some(20.));
+        assert!((comparison.offset_std_dev().unwrap() - 8.165).abs() < 0.001);
+        assert_eq!(comparison.scale(), Some(1.1));
+        assert!((comparison.drift().unwrap() - 20.).abs() < 0.1);
+    }
+}
@@ -0,0 +1,114 @@
+/// How much of the remaining power error is corrected with every reading
+const CORRECTION_GAIN: f64 = 0.5;
+
+/// How much power a resistance level takes at a speed, for equipment only setting resistance levels
+///
+/// Modelled as growing linearly with both the level and the speed,
+/// `(base + per_level * level) * speed` watts at `speed` km/h, as eddy current brakes do at the speeds
+/// people ride at.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::PowerCurve;
+///
+/// let curve = PowerCurve::new(2.0, 0.44);
+/// assert_eq!(curve.level(200., 25.).round(), 14.);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerCurve {
+    /// W per km/h, at level 0
+    base: f64,
+    /// W per km/h, added by each level
+    per_level: f64,
+}
+
+impl PowerCurve {
+    /// A rough fit of the iConsole+0028, about 60 W at level 1 and 400 W at level 32, both at 25 km/h
+    pub const ICONSOLE_0028: PowerCurve = PowerCurve {
+        base: 2.0,
+        per_level: 0.44,
+    };
+
+    pub fn new(base: f64, per_level: f64) -> Self {
+        PowerCurve { base, per_level }
+    }
+
+    /// The power in watts of `level` at `speed` km/h
+    pub fn watts(&self, level: f64, speed: f64) -> f64 {
+        (self.base + self.per_level * level) * speed
+    }
+
+    /// The level taking `watts` at `speed` km/h, unrounded and unbounded
+    pub fn level(&self, watts: f64, speed: f64) -> f64 {
+        if speed <= 0. || self.per_level <= 0. {
+            return 0.;
+        }
+        (watts / speed - self.base) / self.per_level
+    }
+}
+
+/// Converges on a target power by setting resistance levels, correcting the curve by the power measured
+#[derive(Debug, Clone)]
+pub(crate) struct PowerController {
+    curve: PowerCurve,
+    max_level: i16,
+    /// levels the curve is off by
+    correction: f64,
+}
+
+impl PowerController {
+    pub fn new(curve: PowerCurve, max_level: i16) -> Self {
+        PowerController {
+            curve,
+            max_level,
+            correction: 0.,
+        }
+    }
+
+    /// The level to set for `target` watts, at the `speed` and `power` the equipment reported
+    pub fn update(&mut self, target: f64, speed: f64, power: Option<f64>) -> i16 {
+        let watts_per_level = self.curve.per_level * speed;
+        if let Some(power) = power
+            && watts_per_level > 0.
+        {
+            let max_correction = f64::from(self.max_level);
+            self.correction = (self.correction
+                + CORRECTION_GAIN * (target - power) / watts_per_level)
+                .clamp(-max_correction, max_correction);
+        }
+        let level = self.curve.level(target, speed) + self.correction;
+        (level.round() as i16).clamp(1, self.max_level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_curve() {
+        let curve = PowerCurve::ICONSOLE_0028;
+        assert!((curve.watts(10., 20.) - 128.).abs() < 1e-9);
+        assert!((curve.level(128., 20.) - 10.).abs() < 1e-9);
+        assert_eq!(curve.level(128., 0.), 0.);
+    }
+
+    #[test]
+    fn test_power_controller_converges() {
+        // the bike takes 20 % more power per level than the curve assumes
+        let bike = PowerCurve::new(2.0, 0.528);
+        let mut controller = PowerController::new(PowerCurve::ICONSOLE_0028, 32);
+        let mut power = None;
+        for _ in 0..20 {
+            let level = controller.update(250., 25., power);
+            power = Some(bike.watts(level.into(), 25.));
+        }
+        let error = (power.unwrap() - 250.).abs();
+        assert!(error <= bike.per_level * 25., "{error} W off");
+
+        // unreachable targets stay within the levels of the bike
+        assert_eq!(controller.update(2000., 25., Some(500.)), 32);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{HeartRateZones, PowerZones};
+
+/// The rider, for calculations to use their own numbers rather than those of an average rider
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{SessionStats, UserProfile};
+///
+/// let profile = UserProfile {
+///     weight_kg: 68.,
+///     ftp: Some(260.),
+///     max_heart_rate: Some(188.),
+///     ..Default::default()
+/// };
+/// let stats = SessionStats::new().profile(profile);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserProfile {
+    /// The weight of the rider alone, 75 kg by default
+    pub weight_kg: f64,
+    /// The weight of the bike being simulated, 10 kg by default
+    pub bike_weight_kg: f64,
+    /// Functional threshold power in watts
+    pub ftp: Option<f64>,
+    /// bpm
+    pub max_heart_rate: Option<f64>,
+    /// Lactate threshold heart rate in bpm, preferred over `max_heart_rate` for heart rate zones
+    pub threshold_heart_rate: Option<f64>,
+}
+
+impl UserProfile {
+    /// The power zones of the rider, if their functional threshold power is known
+    pub fn power_zones(&self) -> Option<PowerZones> {
+        self.ftp.map(PowerZones::new)
+    }
+
+    /// The heart rate zones of the rider, if their threshold or maximum heart rate is known
+    pub fn heart_rate_zones(&self) -> Option<HeartRateZones> {
+        self.threshold_heart_rate
+            .map(HeartRateZones::from_threshold_heart_rate)
+            .or_else(|| self.max_heart_rate.map(HeartRateZones::from_max_heart_rate))
+    }
+}
+
+impl Default for UserProfile {
+    fn default() -> Self {
+        UserProfile {
+            weight_kg: 75.,
+            bike_weight_kg: 10.,
+            ftp: None,
+            max_heart_rate: None,
+            threshold_heart_rate: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zones() {
+        let profile = UserProfile::default();
+        assert_eq!(profile.weight_kg, 75.);
+        assert_eq!(profile.bike_weight_kg, 10.);
+        assert_eq!(profile.power_zones(), None);
+        assert_eq!(profile.heart_rate_zones(), None);
+
+        let profile = UserProfile {
+            ftp: Some(250.),
+            max_heart_rate: Some(190.),
+            ..Default::default()
+        };
+        assert_eq!(profile.power_zones(), Some(PowerZones::new(250.)));
+        assert_eq!(
+            profile.heart_rate_zones(),
+            Some(HeartRateZones::from_max_heart_rate(190.))
+        );
+
+        let profile = UserProfile {
+            threshold_heart_rate: Some(170.),
+            ..profile
+        };
+        assert_eq!(
+            profile.heart_rate_zones(),
+            Some(HeartRateZones::from_threshold_heart_rate(170.))
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! A Python module for scripting equipment, with the `python` feature
+//!
+//! The module is the library built as a Python extension, see the README. Its functions block until
+//! done, letting go of the GIL meanwhile, on a tokio runtime of the module's own, so scripts and
+//! notebooks use equipment without any `asyncio`:
+//!
+//! ```python
+//! import kondis
+//!
+//! with kondis.Trainer("generic-ftms-bike") as trainer:
+//!     trainer.set_target_power(150)
+//!     for reading in trainer:
+//!         print(reading.power, reading.cadence)
+//! ```
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use pyo3::exceptions::{
+    PyConnectionError, PyNotImplementedError, PyPermissionError, PyRuntimeError, PyTimeoutError,
+    PyValueError,
+};
+use pyo3::prelude::*;
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+use crate::{EquipmentHandle, FTMSData, KondisError, Registry, ScanConfig};
+
+/// How often a blocked read looks up from waiting, for Python to raise `KeyboardInterrupt`
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(100);
+
+static RUNTIME: LazyLock<Runtime> =
+    LazyLock::new(|| Runtime::new().expect("The runtime of the kondis module failed to start"));
+
+impl From<KondisError> for PyErr {
+    fn from(error: KondisError) -> Self {
+        let message = error.to_string();
+        match error {
+            KondisError::ScanTimeout(_)
+            | KondisError::ConnectTimeout(_)
+            | KondisError::DiscoveryTimeout(_)
+            | KondisError::NotificationTimeout(_)
+            | KondisError::Timeout(_) => PyTimeoutError::new_err(message),
+            KondisError::DeviceNotFound | KondisError::Disconnected(_) => {
+                PyConnectionError::new_err(message)
+            }
+            KondisError::PermissionDenied(_) => PyPermissionError::new_err(message),
+            KondisError::InvalidArgument(_) | KondisError::UnknownEquipment(_) => {
+                PyValueError::new_err(message)
+            }
+            KondisError::Unsupported(_) => PyNotImplementedError::new_err(message),
+            _ => PyRuntimeError::new_err(message),
+        }
+    }
+}
+
+/// Run `future` on the runtime of the module, without holding the GIL
+fn block<T: Send>(
+    py: Python<'_>,
+    future: impl Future<Output = crate::Result<T>> + Send,
+) -> PyResult<T> {
+    Ok(py.detach(|| RUNTIME.block_on(future))?)
+}
+
+fn seconds(seconds: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// A device found by `scan`
+#[pyclass(module = "kondis", frozen, get_all, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The advertised name of the device, if it has one
+    name: Option<String>,
+    /// The address of the device, to connect to with `Trainer(address=...)`
+    address: String,
+    /// The signal strength of the last advertisement received, in dBm
+    rssi: Option<i16>,
+    /// The UUIDs of the services the device advertises
+    services: Vec<String>,
+    /// The equipment type the device most likely is, going by its advertisement
+    probable_type: Option<String>,
+}
+
+#[pymethods]
+impl Device {
+    fn __repr__(&self) -> String {
+        format!(
+            "Device(name={:?}, address={:?}, rssi={:?})",
+            self.name, self.address, self.rssi
+        )
+    }
+}
+
+/// A reading of the equipment, with `None` for whatever the equipment doesn't report
+#[pyclass(module = "kondis", frozen, get_all, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub struct Reading {
+    /// Seconds since the first reading of the process
+    timestamp: f64,
+    sequence: u64,
+    /// km/h
+    speed: Option<f32>,
+    /// rpm, or strokes or steps per minute
+    cadence: Option<f32>,
+    /// km
+    distance: Option<f32>,
+    resistance: Option<f64>,
+    /// watts
+    power: Option<i16>,
+    /// kcal
+    calories: Option<f64>,
+    /// bpm
+    heart_rate: Option<f64>,
+    /// seconds
+    time: Option<u32>,
+}
+
+#[pymethods]
+impl Reading {
+    fn __repr__(&self) -> String {
+        format!(
+            "Reading(sequence={}, power={:?}, cadence={:?}, speed={:?}, heart_rate={:?})",
+            self.sequence, self.power, self.cadence, self.speed, self.heart_rate
+        )
+    }
+}
+
+impl From<crate::Reading> for Reading {
+    fn from(reading: crate::Reading) -> Self {
+        let data = FTMSData::from(reading.data);
+        Reading {
+            timestamp: reading.timestamp.as_secs_f64(),
+            sequence: reading.sequence,
+            speed: data.speed,
+            cadence: data.cadence,
+            distance: data.distance,
+            resistance: data.resistance,
+            power: data.power,
+            calories: data.calories,
+            heart_rate: data.heart_rate,
+            time: data.time,
+        }
+    }
+}
+
+/// Scan for nearby devices for `timeout` seconds, or the default of `bluetooth::scan`
+#[pyfunction]
+#[pyo3(signature = (timeout = None))]
+fn scan(py: Python<'_>, timeout: Option<f64>) -> PyResult<Vec<Device>> {
+    let mut config = ScanConfig::new();
+    if let Some(timeout) = timeout {
+        config = config.timeout(seconds(timeout)?);
+    }
+    let devices = block(py, crate::scan(config))?;
+    Ok(devices
+        .into_iter()
+        .map(|device| Device {
+            name: device.name,
+            address: device.address,
+            rssi: device.rssi,
+            services: device.services.iter().map(ToString::to_string).collect(),
+            probable_type: device.probable_type.map(|kind| format!("{kind:?}")),
+        })
+        .collect())
+}
+
+/// The names `Trainer` takes, see `Registry::names`
+#[pyfunction]
+fn equipment_names() -> Vec<String> {
+    Registry::default()
+        .names()
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Connected equipment, closed once `close` is called, the `with` block ends or it's garbage collected
+///
+/// Iterating over it reads one reading after the other, until the equipment disconnects.
+#[pyclass(module = "kondis", frozen)]
+pub struct Trainer {
+    handle: EquipmentHandle,
+    shutdown: CancellationToken,
+}
+
+#[pymethods]
+impl Trainer {
+    /// Find and connect to `equipment`, one of `equipment_names()`, narrowed down like `ScanConfig`
+    #[new]
+    #[pyo3(signature = (equipment = "generic-ftms-bike", max_level = 400, name_pattern = None, address = None, timeout = None))]
+    fn new(
+        py: Python<'_>,
+        equipment: &str,
+        max_level: i16,
+        name_pattern: Option<String>,
+        address: Option<String>,
+        timeout: Option<f64>,
+    ) -> PyResult<Self> {
+        let mut config = ScanConfig::new();
+        if let Some(pattern) = name_pattern {
+            config = config.name_pattern(pattern);
+        }
+        if let Some(address) = address {
+            config = config.address(address);
+        }
+        if let Some(timeout) = timeout {
+            config = config.timeout(seconds(timeout)?);
+        }
+        let shutdown = CancellationToken::new();
+        let handle = block(py, async {
+            let equipment = Registry::default()
+                .create(equipment, max_level, config, &shutdown)
+                .await?;
+            EquipmentHandle::connect(equipment, &shutdown).await
+        })?;
+        Ok(Trainer { handle, shutdown })
+    }
+
+    /// The latest reading, if the equipment reported any yet
+    fn latest(&self) -> Option<Reading> {
+        self.handle.latest().map(Reading::from)
+    }
+
+    /// Wait for the next reading, or `None` once the equipment is closed
+    fn read(&self, py: Python<'_>) -> PyResult<Option<Reading>> {
+        loop {
+            let next = block(py, async {
+                Ok(crate::runtime::timeout(SIGNAL_INTERVAL, self.handle.next_reading()).await)
+            })?;
+            if let Ok(reading) = next {
+                return Ok(reading.map(Reading::from));
+            }
+            py.check_signals()?;
+        }
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<Reading>> {
+        self.read(py)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_exception))]
+    fn __exit__(&self, py: Python<'_>, _exception: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.close(py)
+    }
+
+    /// Whether the equipment got closed, after which every command fails
+    fn is_closed(&self) -> bool {
+        self.handle.is_closed()
+    }
+
+    /// See `Equipment::set_target_power`
+    fn set_target_power(&self, py: Python<'_>, watts: i16) -> PyResult<()> {
+        block(py, self.handle.set_target_power(watts))
+    }
+
+    /// See `Equipment::set_target_cadence`
+    fn set_target_cadence(&self, py: Python<'_>, rpm: i16) -> PyResult<()> {
+        block(py, self.handle.set_target_cadence(rpm))
+    }
+
+    /// See `Equipment::set_target_resistance_level`
+    fn set_target_resistance_level(&self, py: Python<'_>, level: i16) -> PyResult<()> {
+        block(py, self.handle.set_target_resistance_level(level))
+    }
+
+    /// See `Equipment::set_target_heart_rate`
+    fn set_target_heart_rate(&self, py: Python<'_>, bpm: u8) -> PyResult<()> {
+        block(py, self.handle.set_target_heart_rate(bpm))
+    }
+
+    /// See `Equipment::set_simulation_parameters`
+    #[pyo3(signature = (grade, wind_speed = 0.0, crr = 0.004, cw = 0.51))]
+    fn set_simulation_parameters(
+        &self,
+        py: Python<'_>,
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    ) -> PyResult<()> {
+        block(
+            py,
+            self.handle
+                .set_simulation_parameters(grade, wind_speed, crr, cw),
+        )
+    }
+
+    /// See `Equipment::start`
+    fn start(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.start())
+    }
+
+    /// See `Equipment::stop`
+    fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.stop())
+    }
+
+    /// See `Equipment::pause`
+    fn pause(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.pause())
+    }
+
+    /// See `Equipment::resume`
+    fn resume(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.resume())
+    }
+
+    /// See `Equipment::reset`
+    fn reset(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.reset())
+    }
+
+    /// Stop and disconnect from the equipment, see `Equipment::close`
+    fn close(&self, py: Python<'_>) -> PyResult<()> {
+        block(py, self.handle.close())
+    }
+}
+
+impl Drop for Trainer {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "kondis")]
+fn kondis(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(scan, module)?)?;
+    module.add_function(wrap_pyfunction!(equipment_names, module)?)?;
+    module.add_class::<Device>()?;
+    module.add_class::<Reading>()?;
+    module.add_class::<Trainer>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trainer() -> PyResult<()> {
+        Python::initialize();
+        Python::attach(|py| {
+            assert!(equipment_names().contains(&"non-bluetooth-device".to_string()));
+            let trainer = Trainer::new(py, "non-bluetooth-device", 400, None, None, None)?;
+            let reading = trainer.read(py)?.unwrap();
+            assert!(reading.time.is_some());
+            trainer.set_target_power(py, 150)?;
+            trainer.close(py)?;
+            assert!(trainer.set_target_power(py, 150).is_err());
+
+            let unknown = Trainer::new(py, "unknown", 400, None, None, None);
+            assert!(unknown.is_err_and(|e| e.is_instance_of::<PyValueError>(py)));
+            let negative = Trainer::new(py, "non-bluetooth-device", 400, None, None, Some(-1.0));
+            assert!(negative.is_err_and(|e| e.is_instance_of::<PyValueError>(py)));
+            Ok(())
+        })
+    }
+}
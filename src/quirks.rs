@@ -0,0 +1,460 @@
+use uuid::Uuid;
+
+use crate::ftms::{BikeData, Capabilities, DataCapabilities, TargetCapabilities};
+use crate::{KondisError, Result};
+
+/// How a bike speaking a protocol of its own, like a clone of a better known bike, gets talked to
+///
+/// Usually loaded from a quirk file with `parse_quirks` of the `quirks` feature, or deserialized from
+/// JSON or the like with the `serde` feature, so bikes slightly different from the ones this crate
+/// knows can be used without recompiling it, through `devices::QuirkBike`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceQuirks {
+    /// Part of the name the bike advertises, scanned for
+    pub name: String,
+    /// The service the bike advertises, scanned for along with the name
+    pub service: Option<Uuid>,
+    /// The characteristic the bike notifies its data frames on
+    pub data: Uuid,
+    /// The characteristic commands get written to, if the bike takes any
+    pub control: Option<Uuid>,
+    /// The highest resistance level of the bike
+    pub max_resistance: i16,
+    /// Where the metrics sit in the data frames
+    pub fields: Vec<FieldQuirk>,
+    /// The commands the bike takes
+    pub commands: CommandQuirks,
+}
+
+/// Where a metric sits in the data frames, and how to scale it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldQuirk {
+    pub metric: Metric,
+    /// Bytes from the start of the frame
+    pub offset: usize,
+    pub kind: FieldType,
+    /// What the raw value gets multiplied with, into the unit of the metric
+    pub scale: f64,
+}
+
+/// A metric of `BikeData`, in its units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Metric {
+    /// km/h
+    Speed,
+    /// rpm
+    Cadence,
+    /// km
+    Distance,
+    Resistance,
+    /// W
+    Power,
+    /// kcal
+    Calories,
+    /// bpm
+    HeartRate,
+}
+
+/// A little-endian integer of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FieldType {
+    U8,
+    U16,
+    I16,
+    U24,
+}
+
+/// The byte sequences written to the control characteristic
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandQuirks {
+    /// Written once connected, in order, like to make the bike start notifying
+    pub init: Vec<Vec<u8>>,
+    pub start: Option<Vec<u8>>,
+    pub stop: Option<Vec<u8>>,
+    /// Followed by the resistance level, scaled by `resistance_scale`, as a `resistance_type`
+    pub resistance: Option<Vec<u8>>,
+    pub resistance_type: FieldType,
+    pub resistance_scale: f64,
+    /// Whether every command ends in a checksum byte, the sum of the bytes before it
+    pub checksum: bool,
+}
+
+impl Default for CommandQuirks {
+    fn default() -> Self {
+        CommandQuirks {
+            init: Vec::new(),
+            start: None,
+            stop: None,
+            resistance: None,
+            resistance_type: FieldType::U8,
+            resistance_scale: 1.,
+            checksum: false,
+        }
+    }
+}
+
+impl DeviceQuirks {
+    /// Decode a data frame, failing when it's too short to hold every field
+    pub fn decode(&self, data: &[u8]) -> Result<BikeData> {
+        let mut bike = BikeData::default();
+        for field in &self.fields {
+            let value = field.kind.read(data, field.offset).ok_or_else(|| {
+                KondisError::InvalidData(format!(
+                    "Frame too short for {:?}: {data:02x?}",
+                    field.metric
+                ))
+            })? * field.scale;
+            match field.metric {
+                Metric::Speed => bike.speed = Some(value as f32),
+                Metric::Cadence => bike.cadence = Some(value as f32),
+                Metric::Distance => bike.distance = Some(value as f32),
+                Metric::Resistance => bike.resistance = Some(value),
+                Metric::Power => bike.power = Some(value.round() as i16),
+                Metric::Calories => bike.calories = Some(value),
+                Metric::HeartRate => bike.heart_rate = Some(value),
+            }
+        }
+        Ok(bike)
+    }
+
+    /// The command setting the resistance level, failing when the bike doesn't take one, or when the
+    /// scaled level doesn't fit its `resistance_type`
+    pub fn resistance_command(&self, level: i16) -> Result<Vec<u8>> {
+        let commands = &self.commands;
+        let Some(mut command) = commands.resistance.clone() else {
+            return Err(KondisError::Unsupported(format!(
+                "{} does not support a target resistance level",
+                self.name
+            )));
+        };
+        let value = (f64::from(level) * commands.resistance_scale).round() as i64;
+        let bytes = commands.resistance_type.write(value).ok_or_else(|| {
+            KondisError::InvalidArgument(format!(
+                "Resistance level {level} is {value} once scaled, which doesn't fit a {:?}",
+                commands.resistance_type
+            ))
+        })?;
+        command.extend_from_slice(&bytes);
+        Ok(self.command(&command))
+    }
+
+    /// `sequence` as written to the bike, with its checksum if the bike wants one
+    pub fn command(&self, sequence: &[u8]) -> Vec<u8> {
+        let mut command = sequence.to_vec();
+        if self.commands.checksum {
+            command.push(sequence.iter().fold(0, |sum, byte| sum.wrapping_add(*byte)));
+        }
+        command
+    }
+
+    /// What the bike reports and takes, going by its fields and commands
+    pub fn capabilities(&self) -> Capabilities {
+        let has = |metric| self.fields.iter().any(|field| field.metric == metric);
+        Capabilities {
+            data: DataCapabilities {
+                cadence: has(Metric::Cadence),
+                distance: has(Metric::Distance),
+                resistance: has(Metric::Resistance),
+                expended_energy: has(Metric::Calories),
+                heart_rate: has(Metric::HeartRate),
+                power: has(Metric::Power),
+                ..Default::default()
+            },
+            targets: TargetCapabilities {
+                resistance: self.commands.resistance.is_some(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl FieldType {
+    fn len(self) -> usize {
+        match self {
+            FieldType::U8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U24 => 3,
+        }
+    }
+
+    fn read(self, data: &[u8], offset: usize) -> Option<f64> {
+        let bytes = data.get(offset..offset.checked_add(self.len())?)?;
+        Some(match self {
+            FieldType::U8 => f64::from(bytes[0]),
+            FieldType::U16 => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            FieldType::I16 => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+            FieldType::U24 => f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])),
+        })
+    }
+
+    /// `value` in little-endian, unless it's out of the range of the type
+    fn write(self, value: i64) -> Option<Vec<u8>> {
+        Some(match self {
+            FieldType::U8 => u8::try_from(value).ok()?.to_le_bytes().to_vec(),
+            FieldType::U16 => u16::try_from(value).ok()?.to_le_bytes().to_vec(),
+            FieldType::I16 => i16::try_from(value).ok()?.to_le_bytes().to_vec(),
+            FieldType::U24 => {
+                let value = u32::try_from(value).ok().filter(|value| *value < 1 << 24)?;
+                value.to_le_bytes()[..3].to_vec()
+            }
+        })
+    }
+}
+
+/// Parse a quirk file, a TOML file like the one below, with the `quirks` feature
+///
+/// Metrics not in the file aren't reported, and commands not in it aren't supported. Commands are
+/// arrays of bytes, and `init` an array of them.
+///
+/// # Examples
+///
+/// ```
+/// let quirks = kondis::parse_quirks(
+///     r#"
+///     name = "CloneBike"
+///     data = "0000fff1-0000-1000-8000-00805f9b34fb"
+///     control = "0000fff2-0000-1000-8000-00805f9b34fb"
+///     max_resistance = 24
+///
+///     [cadence]
+///     offset = 3
+///     type = "u8"
+///
+///     [power]
+///     offset = 4
+///     type = "u16"
+///     scale = 0.1
+///
+///     [commands]
+///     init = [[0xf0, 0xa1], [0xf0, 0xa3]]
+///     resistance = [0xf0, 0xb1, 0x01]
+///     checksum = true
+///     "#,
+/// )?;
+/// let data = quirks.decode(&[0xf0, 0xd1, 0x09, 85, 0xd0, 0x07])?;
+/// assert_eq!((data.cadence, data.power), (Some(85.), Some(200)));
+/// assert_eq!(quirks.resistance_command(12)?, vec![0xf0, 0xb1, 0x01, 0x0c, 0xae]);
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+#[cfg(feature = "quirks")]
+pub fn parse_quirks(text: &str) -> Result<DeviceQuirks> {
+    let file: file::QuirkFile = toml::from_str(text)
+        .map_err(|e| KondisError::InvalidData(format!("Invalid quirk file: {e}")))?;
+    Ok(file.into())
+}
+
+/// The layout of quirk files, see `parse_quirks`
+#[cfg(feature = "quirks")]
+mod file {
+    use serde::Deserialize;
+    use uuid::Uuid;
+
+    use super::{CommandQuirks, DeviceQuirks, FieldQuirk, FieldType, Metric};
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct QuirkFile {
+        name: String,
+        service: Option<Uuid>,
+        data: Uuid,
+        control: Option<Uuid>,
+        max_resistance: i16,
+        speed: Option<Field>,
+        cadence: Option<Field>,
+        distance: Option<Field>,
+        resistance: Option<Field>,
+        power: Option<Field>,
+        calories: Option<Field>,
+        heart_rate: Option<Field>,
+        #[serde(default)]
+        commands: Commands,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Field {
+        offset: usize,
+        #[serde(rename = "type", default)]
+        kind: Type,
+        #[serde(default = "one")]
+        scale: f64,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Commands {
+        #[serde(default)]
+        init: Vec<Vec<u8>>,
+        start: Option<Vec<u8>>,
+        stop: Option<Vec<u8>>,
+        resistance: Option<Vec<u8>>,
+        #[serde(default)]
+        resistance_type: Type,
+        #[serde(default = "one")]
+        resistance_scale: f64,
+        #[serde(default)]
+        checksum: bool,
+    }
+
+    impl Default for Commands {
+        fn default() -> Self {
+            Commands {
+                init: Vec::new(),
+                start: None,
+                stop: None,
+                resistance: None,
+                resistance_type: Type::U8,
+                resistance_scale: 1.,
+                checksum: false,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(rename_all = "lowercase")]
+    enum Type {
+        #[default]
+        U8,
+        U16,
+        I16,
+        U24,
+    }
+
+    fn one() -> f64 {
+        1.
+    }
+
+    impl From<Type> for FieldType {
+        fn from(kind: Type) -> Self {
+            match kind {
+                Type::U8 => FieldType::U8,
+                Type::U16 => FieldType::U16,
+                Type::I16 => FieldType::I16,
+                Type::U24 => FieldType::U24,
+            }
+        }
+    }
+
+    impl From<QuirkFile> for DeviceQuirks {
+        fn from(file: QuirkFile) -> Self {
+            let fields = [
+                (Metric::Speed, file.speed),
+                (Metric::Cadence, file.cadence),
+                (Metric::Distance, file.distance),
+                (Metric::Resistance, file.resistance),
+                (Metric::Power, file.power),
+                (Metric::Calories, file.calories),
+                (Metric::HeartRate, file.heart_rate),
+            ];
+            let commands = file.commands;
+            DeviceQuirks {
+                name: file.name,
+                service: file.service,
+                data: file.data,
+                control: file.control,
+                max_resistance: file.max_resistance,
+                fields: fields
+                    .into_iter()
+                    .filter_map(|(metric, field)| {
+                        field.map(|field| FieldQuirk {
+                            metric,
+                            offset: field.offset,
+                            kind: field.kind.into(),
+                            scale: field.scale,
+                        })
+                    })
+                    .collect(),
+                commands: CommandQuirks {
+                    init: commands.init,
+                    start: commands.start,
+                    stop: commands.stop,
+                    resistance: commands.resistance,
+                    resistance_type: commands.resistance_type.into(),
+                    resistance_scale: commands.resistance_scale,
+                    checksum: commands.checksum,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "quirks"))]
+mod tests {
+    use super::*;
+
+    const QUIRKS: &str = r#"
+# a clone of some bike
+name = "CloneBike"
+service = "0000fff0-0000-1000-8000-00805f9b34fb"
+data = "0000fff1-0000-1000-8000-00805f9b34fb"
+control = "0000fff2-0000-1000-8000-00805f9b34fb"
+max_resistance = 32
+
+[speed]
+offset = 2
+type = "u16"
+scale = 0.01
+
+[resistance]
+offset = 4
+
+[commands]
+start = [0x07]
+resistance = [0x04]
+resistance_type = "i16"
+resistance_scale = 10
+"#;
+
+    #[test]
+    fn test_parse_quirks() -> Result<()> {
+        let quirks = parse_quirks(QUIRKS)?;
+        assert_eq!(quirks.name, "CloneBike");
+        assert_eq!(quirks.max_resistance, 32);
+        assert_eq!(quirks.fields.len(), 2);
+        assert_eq!(quirks.commands.start, Some(vec![0x07]));
+
+        let data = quirks.decode(&[0, 0, 0xc4, 0x09, 12])?;
+        assert_eq!((data.speed, data.resistance), (Some(25.), Some(12.)));
+        assert_eq!(data.power, None);
+        assert!(quirks.decode(&[0, 0, 0xc4, 0x09]).is_err());
+
+        assert_eq!(quirks.resistance_command(12)?, vec![0x04, 120, 0]);
+        assert!(matches!(
+            quirks.resistance_command(i16::MAX),
+            Err(KondisError::InvalidArgument(_))
+        ));
+        assert!(quirks.capabilities().targets.resistance);
+        assert!(!quirks.capabilities().data.power);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_field() {
+        let data = [0x01, 0xc4, 0x09];
+        assert_eq!(FieldType::U16.read(&data, 1), Some(2500.));
+        assert_eq!(FieldType::U16.read(&data, 2), None);
+        assert_eq!(FieldType::U24.read(&data, usize::MAX), None);
+    }
+
+    #[test]
+    fn test_invalid_quirks() {
+        assert!(parse_quirks("name = \"CloneBike\"").is_err());
+        assert!(parse_quirks(&QUIRKS.replace("[speed]", "[torque]")).is_err());
+        assert!(parse_quirks(&QUIRKS.replace("offset = 4", "offset = -4")).is_err());
+        assert!(parse_quirks(&QUIRKS.replace("[0x07]", "[0x107]")).is_err());
+        assert!(parse_quirks(&QUIRKS.replace("[0x07]", "\"07\"")).is_err());
+        assert!(parse_quirks(&QUIRKS.replace("scale = 0.01", "scale = 0.01\nsize = 2")).is_err());
+
+        // quoted strings keep their #
+        let named = parse_quirks(&QUIRKS.replace("\"CloneBike\"", "\"Clone#2\""));
+        assert_eq!(
+            named.map(|quirks| quirks.name).ok().as_deref(),
+            Some("Clone#2")
+        );
+    }
+}
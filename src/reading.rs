@@ -0,0 +1,54 @@
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use crate::MachineData;
+
+/// The start of the clock every reading is timestamped on
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Machine data as it arrived, numbered and timestamped
+///
+/// Readings of different equipment share the same clock, so they can be lined up with each other.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reading {
+    /// When the reading arrived, on a monotonic clock starting with the first reading of the process
+    pub timestamp: Duration,
+    /// Counts up by one with every notification of the stream or event receiver it came from
+    ///
+    /// A skipped number means a notification got lost, like one that couldn't be decoded or an event
+    /// a slow receiver fell too far behind on.
+    pub sequence: u64,
+    pub data: MachineData,
+}
+
+impl Reading {
+    /// Timestamp `data` as arriving now
+    pub(crate) fn new(sequence: u64, data: MachineData) -> Self {
+        Reading {
+            timestamp: EPOCH.elapsed(),
+            sequence,
+            data,
+        }
+    }
+
+    /// How long after `earlier` this reading arrived, zero if it arrived first
+    pub fn since(&self, earlier: &Reading) -> Duration {
+        self.timestamp.saturating_sub(earlier.timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BikeData;
+
+    #[test]
+    fn test_reading() {
+        let first = Reading::new(0, BikeData::default().into());
+        std::thread::sleep(Duration::from_millis(10));
+        let second = Reading::new(1, BikeData::default().into());
+        assert!(second.since(&first) >= Duration::from_millis(10));
+        assert_eq!(first.since(&second), Duration::ZERO);
+    }
+}
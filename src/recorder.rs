@@ -0,0 +1,261 @@
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{FTMSData, Reading};
+
+/// The columns of CSV files, and the keys of JSON lines
+const FIELDS: [&str; 11] = [
+    "timestamp",
+    "unix_time",
+    "sequence",
+    "speed",
+    "cadence",
+    "distance",
+    "resistance",
+    "power",
+    "calories",
+    "heart_rate",
+    "time",
+];
+
+/// How `SampleRecorder` writes samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecordFormat {
+    /// Comma separated values, with a header line starting every file and missing values left empty
+    Csv,
+    /// One JSON object per line, missing values, and NaN and infinite ones, being `null`
+    JsonLines,
+}
+
+/// Appends every reading to a file as it arrives, as CSV or JSON lines
+///
+/// Samples hold the `timestamp` of the reading in seconds, on the clock all readings share, the
+/// `unix_time` it was recorded at, its `sequence` number and the fields of `FTMSData`. Writes are
+/// buffered, and flushed when the recorder gets dropped.
+///
+/// With `rotate`, a file growing past a size gets renamed to `<path>.1`, older files moving on to
+/// `<path>.2` and so on, and recording goes on in a new file at `path`.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::{
+///     devices::NonBluetoothDevice, CancellationToken, Equipment, RecordFormat, SampleRecorder,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///     let path = std::env::temp_dir().join("kondis-example.csv");
+///     let mut recorder = SampleRecorder::open(&path, RecordFormat::Csv)?.rotate(1 << 20, 3);
+///     let mut data = device.data_stream().await?.take(2);
+///     while let Some(reading) = data.next().await {
+///         recorder.record(&reading)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SampleRecorder {
+    path: PathBuf,
+    format: RecordFormat,
+    writer: BufWriter<File>,
+    /// bytes in the current file
+    size: u64,
+    /// bytes, and how many rotated files to keep
+    rotation: Option<(u64, usize)>,
+}
+
+impl SampleRecorder {
+    /// Record to the file at `path`, appending to it if it exists
+    pub fn open(path: impl AsRef<Path>, format: RecordFormat) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (writer, size) = Self::append(&path, format)?;
+        Ok(SampleRecorder {
+            path,
+            format,
+            writer,
+            size,
+            rotation: None,
+        })
+    }
+
+    /// Start a new file whenever the current one grows past `max_size` bytes, keeping the last `keep`
+    /// files next to it
+    pub fn rotate(mut self, max_size: u64, keep: usize) -> Self {
+        self.rotation = Some((max_size, keep));
+        self
+    }
+
+    /// The file being recorded to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, reading: &Reading) -> io::Result<()> {
+        if let Some((max_size, keep)) = self.rotation
+            && self.size >= max_size
+        {
+            self.rotate_files(keep)?;
+        }
+        let data = FTMSData::from(reading.data.clone());
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let values = [
+            Some(reading.timestamp.as_secs_f64().to_string()),
+            Some(unix_time.as_secs_f64().to_string()),
+            Some(reading.sequence.to_string()),
+            number(data.speed),
+            number(data.cadence),
+            number(data.distance),
+            number(data.resistance),
+            value(data.power),
+            number(data.calories),
+            number(data.heart_rate),
+            value(data.time),
+        ];
+        let line = match self.format {
+            RecordFormat::Csv => values.map(Option::unwrap_or_default).join(","),
+            RecordFormat::JsonLines => {
+                let pairs = FIELDS.iter().zip(values).map(|(field, value)| {
+                    format!("\"{field}\":{}", value.as_deref().unwrap_or("null"))
+                });
+                format!("{{{}}}", pairs.collect::<Vec<_>>().join(","))
+            }
+        };
+        writeln!(self.writer, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Write out everything recorded so far
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn rotate_files(&mut self, keep: usize) -> io::Result<()> {
+        self.writer.flush()?;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..keep).rev() {
+                let older = self.rotated(index);
+                if older.exists() {
+                    fs::rename(older, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        (self.writer, self.size) = Self::append(&self.path, self.format)?;
+        Ok(())
+    }
+
+    /// `<path>.<index>`
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    /// A writer appending to the file at `path`, and the size of the file, starting new CSV files with
+    /// their header
+    fn append(path: &Path, format: RecordFormat) -> io::Result<(BufWriter<File>, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut size = file.metadata()?.len();
+        let mut writer = BufWriter::new(file);
+        if format == RecordFormat::Csv && size == 0 {
+            let header = FIELDS.join(",");
+            writeln!(writer, "{header}")?;
+            size += header.len() as u64 + 1;
+        }
+        Ok((writer, size))
+    }
+}
+
+impl Drop for SampleRecorder {
+    fn drop(&mut self) {
+        // nowhere to report a failure to, the samples are lost either way
+        let _ = self.writer.flush();
+    }
+}
+
+fn value(value: Option<impl Display>) -> Option<String> {
+    value.map(|value| value.to_string())
+}
+
+/// Like `value`, leaving out NaN and infinities, which neither CSV readers nor JSON take as numbers
+fn number<F: Display + Copy + Into<f64>>(number: Option<F>) -> Option<String> {
+    value(number.filter(|number| (*number).into().is_finite()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{BikeData, MachineData};
+
+    fn reading(sequence: u64) -> Reading {
+        Reading {
+            timestamp: Duration::from_millis(1500),
+            sequence,
+            data: MachineData::Bike(BikeData {
+                power: Some(180),
+                cadence: Some(90.5),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_record_and_rotate() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("kondis-recorder-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("ride.jsonl");
+        {
+            let mut recorder = SampleRecorder::open(&path, RecordFormat::JsonLines)?.rotate(1, 1);
+            for sequence in 0..3 {
+                recorder.record(&reading(sequence))?;
+            }
+        }
+        let line = fs::read_to_string(&path)?;
+        assert!(line.starts_with(r#"{"timestamp":1.5,"unix_time":"#));
+        assert!(line.ends_with(
+            r#""sequence":2,"speed":null,"cadence":90.5,"distance":null,"resistance":null,"power":180,"calories":null,"heart_rate":null,"time":null}
+"#
+        ));
+        assert!(dir.join("ride.jsonl.1").exists());
+        assert!(!dir.join("ride.jsonl.2").exists());
+
+        let path = dir.join("ride.csv");
+        SampleRecorder::open(&path, RecordFormat::Csv)?.record(&reading(0))?;
+        let csv = fs::read_to_string(&path)?;
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], FIELDS.join(","));
+        assert!(lines[1].ends_with(",0,,90.5,,,180,,,"));
+
+        let path = dir.join("non-finite.jsonl");
+        let mut non_finite = reading(0);
+        non_finite.data = MachineData::Bike(BikeData {
+            speed: Some(f32::NAN),
+            cadence: Some(f32::INFINITY),
+            calories: Some(f64::NEG_INFINITY),
+            ..Default::default()
+        });
+        SampleRecorder::open(&path, RecordFormat::JsonLines)?.record(&non_finite)?;
+        let line = fs::read_to_string(&path)?;
+        assert!(line.ends_with(
+            r#""sequence":0,"speed":null,"cadence":null,"distance":null,"resistance":null,"power":null,"calories":null,"heart_rate":null,"time":null}
+"#
+        ));
+        fs::remove_dir_all(dir)
+    }
+}
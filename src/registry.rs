@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::{
+    CsafeRower, DebugBike, EchelonBike, GenericFtmsBike, GenericFtmsCrossTrainer, GenericFtmsRower,
+    GenericFtmsTreadmill, HeartRateMonitor, Iconsole0010Bike, Iconsole0028Bike, Iconsole0051Bike,
+    KeiserM3iBike, KettlerBike, KickrBike, NonBluetoothDevice, Pm5Rower, SimulatorBike,
+    SpeedCadenceSensor, SterzoSteering, TacxFecBike, ZwiftController,
+};
+use crate::{DynEquipment, Equipment, ScanConfig};
+use crate::{KondisError, Result};
+
+/// Creates a piece of equipment from the same arguments as `Equipment::with_config`
+pub type EquipmentFactory = Arc<
+    dyn Fn(
+            i16,
+            ScanConfig,
+            CancellationToken,
+        ) -> Pin<Box<dyn Future<Output = Result<Box<dyn DynEquipment>>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Equipment constructible by name, open to equipment implemented outside of this crate
+///
+/// `Registry::default()` knows every equipment type of this crate, by the names listed in
+/// `Registry::names`.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Registry, ScanConfig};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let mut registry = Registry::default();
+///     // equipment of your own crate registers the same way
+///     registry.register_type::<NonBluetoothDevice>("my-device");
+///
+///     let shutdown = CancellationToken::new();
+///     let mut equipment = registry
+///         .create("my-device", 32, ScanConfig::default(), &shutdown)
+///         .await?;
+///     equipment.connect().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Registry {
+    factories: HashMap<String, EquipmentFactory>,
+}
+
+impl Registry {
+    /// A registry without any equipment
+    pub fn empty() -> Self {
+        Registry {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Register a factory under `name`, replacing any factory already registered under it
+    pub fn register(&mut self, name: impl Into<String>, factory: EquipmentFactory) {
+        self.factories.insert(name.into(), factory);
+    }
+
+    /// Register an equipment type under `name`, created through its `Equipment::with_config`
+    pub fn register_type<T: Equipment + 'static>(&mut self, name: impl Into<String>) {
+        self.register(
+            name,
+            Arc::new(|max_level, config, shutdown| {
+                Box::pin(async move {
+                    let equipment = T::with_config(max_level, config, &shutdown).await?;
+                    Ok(Box::new(equipment) as Box<dyn DynEquipment>)
+                })
+            }),
+        );
+    }
+
+    /// Create the equipment registered under `name`
+    pub async fn create(
+        &self,
+        name: &str,
+        max_level: i16,
+        config: ScanConfig,
+        shutdown: &CancellationToken,
+    ) -> Result<Box<dyn DynEquipment>> {
+        let Some(factory) = self.factories.get(name) else {
+            return Err(KondisError::UnknownEquipment(name.to_string()));
+        };
+        factory(max_level, config, shutdown.clone()).await
+    }
+
+    /// The names of every registered equipment, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register_type::<Iconsole0028Bike>("iconsole-0028-bike");
+        registry.register_type::<Iconsole0010Bike>("iconsole-0010-bike");
+        registry.register_type::<Iconsole0051Bike>("iconsole-0051-bike");
+        registry.register_type::<DebugBike>("debug-bike");
+        registry.register_type::<GenericFtmsBike>("generic-ftms-bike");
+        registry.register_type::<GenericFtmsTreadmill>("generic-ftms-treadmill");
+        registry.register_type::<GenericFtmsRower>("generic-ftms-rower");
+        registry.register_type::<GenericFtmsCrossTrainer>("generic-ftms-cross-trainer");
+        registry.register_type::<NonBluetoothDevice>("non-bluetooth-device");
+        registry.register_type::<SimulatorBike>("simulator-bike");
+        registry.register_type::<HeartRateMonitor>("heart-rate-monitor");
+        registry.register_type::<SpeedCadenceSensor>("speed-cadence-sensor");
+        registry.register_type::<KeiserM3iBike>("keiser-m3i-bike");
+        registry.register_type::<EchelonBike>("echelon-bike");
+        registry.register_type::<KickrBike>("kickr-bike");
+        registry.register_type::<TacxFecBike>("tacx-fec-bike");
+        registry.register_type::<Pm5Rower>("concept2-pm5-rower");
+        registry.register_type::<CsafeRower>("csafe-rower");
+        registry.register_type::<KettlerBike>("kettler-bike");
+        registry.register_type::<SterzoSteering>("sterzo-steering");
+        registry.register_type::<ZwiftController>("zwift-controller");
+        #[cfg(feature = "ant")]
+        registry.register_type::<crate::devices::AntFecBike>("ant-fec-bike");
+        #[cfg(feature = "ant")]
+        registry.register_type::<crate::devices::AntHeartRateMonitor>("ant-heart-rate-monitor");
+        #[cfg(feature = "ant")]
+        registry.register_type::<crate::devices::AntPowerMeter>("ant-power-meter");
+        #[cfg(all(feature = "bluer", target_os = "linux"))]
+        registry.register_type::<crate::devices::BluezFtmsBike>("bluez-ftms-bike");
+        registry
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registry() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut registry = Registry::empty();
+        assert!(
+            registry
+                .create("device", 10, ScanConfig::default(), &shutdown)
+                .await
+                .is_err()
+        );
+
+        registry.register_type::<NonBluetoothDevice>("device");
+        assert_eq!(registry.names(), vec!["device"]);
+        let mut equipment = registry
+            .create("device", 10, ScanConfig::default(), &shutdown)
+            .await?;
+        assert!(equipment.connect().await?);
+
+        let ant = if cfg!(feature = "ant") { 3 } else { 0 };
+        let bluez = if cfg!(all(feature = "bluer", target_os = "linux")) {
+            1
+        } else {
+            0
+        };
+        assert_eq!(Registry::default().names().len(), 21 + ant + bluez);
+        Ok(())
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::ftms::{DataCapabilities, TargetCapabilities};
+use crate::{
+    BikeData, Capabilities, ControlPointError, CrossTrainerData, DeviceEvent, Equipment,
+    HeartRateData, KondisError, MachineData, Reading, Result, RowerData, SpeedCadenceData,
+    TrainingGoal, TreadmillData,
+};
+
+/// The port servers listen on, unless told otherwise
+pub(crate) const DEFAULT_PORT: u16 = 7878;
+/// The version of the protocol, greeting every client
+const VERSION: &str = "1";
+
+/// Serves a piece of equipment to `devices::RemoteEquipment` over TCP, for using equipment connected
+/// to another machine, like a Raspberry Pi next to the bike
+///
+/// Every client gets the data and the battery level of the equipment, and its target and session
+/// commands go to the equipment, answered with their result. Clients may come and go while the
+/// equipment stays connected, and there may be several at once.
+///
+/// The protocol is lines of text. The server greets every client with the capabilities of the
+/// equipment, and then sends every reading as it arrives:
+///
+/// ```text
+/// kondis 1 targets=power,resistance data=cadence,power
+/// data 41 bike cadence=88.5 power=210
+/// battery 80
+/// ```
+///
+/// Clients number their commands, and get answered with the outcome:
+///
+/// ```text
+/// 1 power 220
+/// 1 ok
+/// 2 simulation 4.5 0 0.004 0.51
+/// 2 err unsupported Simulation parameters are not supported
+/// ```
+///
+/// There is no authentication nor encryption, so only serve on networks you trust.
+///
+/// # Examples
+///
+/// ```no_run
+/// use kondis::{devices::GenericFtmsBike, CancellationToken, Equipment, RemoteServer};
+/// use tokio::net::TcpListener;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut bike = GenericFtmsBike::new(400, &shutdown).await?;
+///     bike.connect().await?;
+///     let listener = TcpListener::bind("0.0.0.0:7878").await?;
+///     RemoteServer::new(&shutdown).serve(&bike, listener).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RemoteServer {
+    shutdown: CancellationToken,
+}
+
+impl RemoteServer {
+    /// A server serving until `shutdown` is cancelled
+    pub fn new(shutdown: &CancellationToken) -> Self {
+        RemoteServer {
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Serve `equipment` to every client connecting to `listener`, until shut down
+    ///
+    /// Clients going away, or sending what can't be understood, only end their own connection.
+    pub async fn serve<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        listener: TcpListener,
+    ) -> Result<()> {
+        let mut clients = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(()),
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    clients.push(self.session(equipment, stream));
+                }
+                Some(_) = clients.next(), if !clients.is_empty() => {}
+            }
+        }
+    }
+
+    async fn session<E: Equipment + ?Sized>(&self, equipment: &E, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut events = equipment.events();
+        let greeting = hello(equipment.capabilities().as_ref());
+        writer.write_all(format!("{greeting}\n").as_bytes()).await?;
+        if let Some(percent) = equipment.battery_level() {
+            writer
+                .write_all(format!("battery {percent}\n").as_bytes())
+                .await?;
+        }
+        loop {
+            let message = tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(()),
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        return Ok(());
+                    };
+                    let (id, request) = parse_request(&line)?;
+                    reply(id, &run(equipment, request).await)
+                }
+                event = events.recv() => match event {
+                    Ok(DeviceEvent::Data(reading)) => data(&reading),
+                    Ok(DeviceEvent::BatteryLevel(percent)) => format!("battery {percent}"),
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            };
+            writer.write_all(format!("{message}\n").as_bytes()).await?;
+        }
+    }
+}
+
+/// A command of a client, see `RemoteServer`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Request {
+    Cadence(i16),
+    Power(i16),
+    Resistance(i16),
+    HeartRate(u8),
+    Goal(TrainingGoal),
+    Simulation {
+        grade: f32,
+        wind_speed: f32,
+        crr: f32,
+        cw: f32,
+    },
+    Start,
+    Stop,
+    Pause,
+    Resume,
+    Reset,
+}
+
+/// Run `request` on `equipment`, see `EquipmentHandle` as well
+pub(crate) async fn run<E: Equipment + ?Sized>(equipment: &E, request: Request) -> Result<()> {
+    match request {
+        Request::Cadence(rpm) => equipment.set_target_cadence(rpm).await,
+        Request::Power(watts) => equipment.set_target_power(watts).await,
+        Request::Resistance(level) => equipment.set_target_resistance_level(level).await,
+        Request::HeartRate(bpm) => equipment.set_target_heart_rate(bpm).await,
+        Request::Goal(goal) => equipment.set_goal(goal).await,
+        Request::Simulation {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        } => {
+            equipment
+                .set_simulation_parameters(grade, wind_speed, crr, cw)
+                .await
+        }
+        Request::Start => equipment.start().await,
+        Request::Stop => equipment.stop().await,
+        Request::Pause => equipment.pause().await,
+        Request::Resume => equipment.resume().await,
+        Request::Reset => equipment.reset().await,
+    }
+}
+
+/// The line sending `request`, numbered `id`
+pub(crate) fn request(id: u64, request: &Request) -> String {
+    let command = match request {
+        Request::Cadence(rpm) => format!("cadence {rpm}"),
+        Request::Power(watts) => format!("power {watts}"),
+        Request::Resistance(level) => format!("resistance {level}"),
+        Request::HeartRate(bpm) => format!("heart_rate {bpm}"),
+        Request::Goal(TrainingGoal::ExpendedEnergy(kcal)) => format!("goal energy {kcal}"),
+        Request::Goal(TrainingGoal::Distance(meters)) => format!("goal distance {meters}"),
+        Request::Goal(TrainingGoal::TrainingTime(seconds)) => format!("goal time {seconds}"),
+        Request::Simulation {
+            grade,
+            wind_speed,
+            crr,
+            cw,
+        } => format!("simulation {grade} {wind_speed} {crr} {cw}"),
+        Request::Start => "start".to_string(),
+        Request::Stop => "stop".to_string(),
+        Request::Pause => "pause".to_string(),
+        Request::Resume => "resume".to_string(),
+        Request::Reset => "reset".to_string(),
+    };
+    format!("{id} {command}")
+}
+
+fn parse_request(line: &str) -> Result<(u64, Request)> {
+    let invalid = || KondisError::InvalidData(format!("Invalid request {line:?}"));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let id = word(&words, 0).ok_or_else(invalid)?;
+    let number = |index| word(&words, index).ok_or_else(invalid);
+    let request = match words.get(1..) {
+        Some(["cadence", _]) => Request::Cadence(word(&words, 2).ok_or_else(invalid)?),
+        Some(["power", _]) => Request::Power(word(&words, 2).ok_or_else(invalid)?),
+        Some(["resistance", _]) => Request::Resistance(word(&words, 2).ok_or_else(invalid)?),
+        Some(["heart_rate", _]) => Request::HeartRate(word(&words, 2).ok_or_else(invalid)?),
+        Some(["goal", "energy", _]) => Request::Goal(TrainingGoal::ExpendedEnergy(
+            word(&words, 3).ok_or_else(invalid)?,
+        )),
+        Some(["goal", "distance", _]) => {
+            Request::Goal(TrainingGoal::Distance(word(&words, 3).ok_or_else(invalid)?))
+        }
+        Some(["goal", "time", _]) => Request::Goal(TrainingGoal::TrainingTime(
+            word(&words, 3).ok_or_else(invalid)?,
+        )),
+        Some(["simulation", _, _, _, _]) => Request::Simulation {
+            grade: number(2)?,
+            wind_speed: number(3)?,
+            crr: number(4)?,
+            cw: number(5)?,
+        },
+        Some(["start"]) => Request::Start,
+        Some(["stop"]) => Request::Stop,
+        Some(["pause"]) => Request::Pause,
+        Some(["resume"]) => Request::Resume,
+        Some(["reset"]) => Request::Reset,
+        _ => return Err(invalid()),
+    };
+    Ok((id, request))
+}
+
+/// The word at `index` of `words`, as a number
+fn word<T: FromStr>(words: &[&str], index: usize) -> Option<T> {
+    words.get(index).and_then(|word| word.parse().ok())
+}
+
+/// The line answering the request numbered `id` with `result`
+fn reply(id: u64, result: &Result<()>) -> String {
+    // messages have to stay on their line
+    let message = |e: &KondisError| e.to_string().replace('\n', " ");
+    match result {
+        Ok(()) => format!("{id} ok"),
+        Err(e @ KondisError::InvalidArgument(_)) => format!("{id} err invalid {}", message(e)),
+        Err(e @ KondisError::Unsupported(_)) => format!("{id} err unsupported {}", message(e)),
+        Err(KondisError::ControlRejected(rejected)) => format!(
+            "{id} err rejected {} {}",
+            rejected.op_code,
+            u8::from(rejected.result)
+        ),
+        Err(e) => format!("{id} err failed {}", message(e)),
+    }
+}
+
+/// The request number and the outcome of a reply, `None` for lines that aren't replies
+pub(crate) fn parse_reply(line: &str) -> Option<(u64, Result<()>)> {
+    let (id, rest) = line.split_once(' ')?;
+    let id = id.parse().ok()?;
+    if rest == "ok" {
+        return Some((id, Ok(())));
+    }
+    let rest = rest.strip_prefix("err ")?;
+    let (kind, message) = rest.split_once(' ').unwrap_or((rest, ""));
+    let message = message.to_string();
+    let error = match kind {
+        "invalid" => KondisError::InvalidArgument(message),
+        "unsupported" => KondisError::Unsupported(message),
+        "rejected" => {
+            let mut codes = message.split_whitespace().map(str::parse::<u8>);
+            match (codes.next(), codes.next()) {
+                (Some(Ok(op_code)), Some(Ok(result))) => {
+                    KondisError::ControlRejected(ControlPointError {
+                        op_code,
+                        result: result.into(),
+                    })
+                }
+                _ => KondisError::Remote(format!("Invalid rejection {message:?}")),
+            }
+        }
+        _ => KondisError::Remote(message),
+    };
+    Some((id, Err(error)))
+}
+
+/// The capability names of the targets and the data of `capabilities`
+pub(crate) fn capability_flags(
+    capabilities: &mut Capabilities,
+) -> [Vec<(&'static str, &mut bool)>; 2] {
+    let TargetCapabilities {
+        speed,
+        inclination,
+        resistance,
+        power,
+        heart_rate,
+        expended_energy,
+        distance,
+        training_time,
+        simulation,
+        spin_down,
+        cadence,
+    } = &mut capabilities.targets;
+    let targets = vec![
+        ("speed", speed),
+        ("inclination", inclination),
+        ("resistance", resistance),
+        ("power", power),
+        ("heart_rate", heart_rate),
+        ("expended_energy", expended_energy),
+        ("distance", distance),
+        ("training_time", training_time),
+        ("simulation", simulation),
+        ("spin_down", spin_down),
+        ("cadence", cadence),
+    ];
+    let DataCapabilities {
+        average_speed,
+        cadence,
+        distance,
+        inclination,
+        elevation_gain,
+        pace,
+        step_count,
+        resistance,
+        stride_count,
+        expended_energy,
+        heart_rate,
+        elapsed_time,
+        remaining_time,
+        power,
+    } = &mut capabilities.data;
+    let data = vec![
+        ("average_speed", average_speed),
+        ("cadence", cadence),
+        ("distance", distance),
+        ("inclination", inclination),
+        ("elevation_gain", elevation_gain),
+        ("pace", pace),
+        ("step_count", step_count),
+        ("resistance", resistance),
+        ("stride_count", stride_count),
+        ("expended_energy", expended_energy),
+        ("heart_rate", heart_rate),
+        ("elapsed_time", elapsed_time),
+        ("remaining_time", remaining_time),
+        ("power", power),
+    ];
+    [targets, data]
+}
+
+/// The line greeting every client, with the capabilities of the equipment when it knows them
+fn hello(capabilities: Option<&Capabilities>) -> String {
+    let Some(capabilities) = capabilities else {
+        return format!("kondis {VERSION}");
+    };
+    let mut capabilities = *capabilities;
+    let [targets, data] = capability_flags(&mut capabilities).map(|flags| {
+        flags
+            .into_iter()
+            .filter(|(_, supported)| **supported)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    format!("kondis {VERSION} targets={targets} data={data}")
+}
+
+/// The capabilities of the equipment a server greeted with
+pub(crate) fn parse_hello(line: &str) -> Result<Option<Capabilities>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("kondis") || words.next() != Some(VERSION) {
+        return Err(KondisError::InvalidData(format!(
+            "Not a kondis server of version {VERSION}: {line:?}"
+        )));
+    }
+    let fields = fields(words);
+    let (Some(targets), Some(data)) = (fields.get("targets"), fields.get("data")) else {
+        return Ok(None);
+    };
+    let mut capabilities = Capabilities::default();
+    let [target_flags, data_flags] = capability_flags(&mut capabilities);
+    for (names, flags) in [(targets, target_flags), (data, data_flags)] {
+        let names: Vec<&str> = names.split(',').collect();
+        for (name, supported) in flags {
+            *supported = names.contains(&name);
+        }
+    }
+    Ok(Some(capabilities))
+}
+
+/// The line sending `reading`
+fn data(reading: &Reading) -> String {
+    let mut fields = Fields::default();
+    let kind = match &reading.data {
+        MachineData::Bike(data) => {
+            fields.put("speed", data.speed);
+            fields.put("cadence", data.cadence);
+            fields.put("distance", data.distance);
+            fields.put("resistance", data.resistance);
+            fields.put("power", data.power);
+            fields.put("calories", data.calories);
+            fields.put("heart_rate", data.heart_rate);
+            fields.put("time", data.time);
+            "bike"
+        }
+        MachineData::Treadmill(data) => {
+            fields.put("speed", data.speed);
+            fields.put("distance", data.distance);
+            fields.put("inclination", data.inclination);
+            fields.put("ramp_angle", data.ramp_angle);
+            fields.put("elevation_gain", data.elevation_gain);
+            fields.put("pace", data.pace);
+            fields.put("power", data.power);
+            fields.put("calories", data.calories);
+            fields.put("heart_rate", data.heart_rate);
+            fields.put("time", data.time);
+            "treadmill"
+        }
+        MachineData::Rower(data) => {
+            fields.put("stroke_rate", data.stroke_rate);
+            fields.put("stroke_count", data.stroke_count);
+            fields.put("distance", data.distance);
+            fields.put("pace", data.pace);
+            fields.put("power", data.power);
+            fields.put("resistance", data.resistance);
+            fields.put("calories", data.calories);
+            fields.put("heart_rate", data.heart_rate);
+            fields.put("time", data.time);
+            "rower"
+        }
+        MachineData::CrossTrainer(data) => {
+            fields.put("speed", data.speed);
+            fields.put("distance", data.distance);
+            fields.put("stride_rate", data.stride_rate);
+            fields.put("stride_count", data.stride_count);
+            fields.put("elevation_gain", data.elevation_gain);
+            fields.put("inclination", data.inclination);
+            fields.put("resistance", data.resistance);
+            fields.put("power", data.power);
+            fields.put("calories", data.calories);
+            fields.put("heart_rate", data.heart_rate);
+            fields.put("time", data.time);
+            fields.put("backwards", data.backwards.then_some(true));
+            "cross_trainer"
+        }
+        MachineData::HeartRate(data) => {
+            fields.put("heart_rate", Some(data.heart_rate));
+            fields.put("sensor_contact", data.sensor_contact);
+            fields.put("energy_expended", data.energy_expended);
+            let rr_intervals: Vec<String> = data
+                .rr_intervals
+                .iter()
+                .map(|interval| interval.as_nanos().to_string())
+                .collect();
+            fields.put(
+                "rr_intervals",
+                (!rr_intervals.is_empty()).then(|| rr_intervals.join(",")),
+            );
+            "heart_rate"
+        }
+        MachineData::SpeedCadence(data) => {
+            fields.put("speed", data.speed);
+            fields.put("cadence", data.cadence);
+            fields.put("distance", data.distance);
+            fields.put("wheel_revolutions", data.wheel_revolutions);
+            fields.put("crank_revolutions", data.crank_revolutions);
+            "speed_cadence"
+        }
+    };
+    format!("data {} {kind}{}", reading.sequence, fields.0)
+}
+
+/// The sequence number and the data of a data line, without its leading `data`
+pub(crate) fn parse_data(line: &str) -> Result<(u64, MachineData)> {
+    let invalid = || KondisError::InvalidData(format!("Invalid data {line:?}"));
+    let mut words = line.split_whitespace();
+    let sequence = words.next().and_then(|sequence| sequence.parse().ok());
+    let (Some(sequence), Some(kind)) = (sequence, words.next()) else {
+        return Err(invalid());
+    };
+    let fields = fields(words);
+    let data = match kind {
+        "bike" => BikeData {
+            speed: field(&fields, line, "speed")?,
+            cadence: field(&fields, line, "cadence")?,
+            distance: field(&fields, line, "distance")?,
+            resistance: field(&fields, line, "resistance")?,
+            power: field(&fields, line, "power")?,
+            calories: field(&fields, line, "calories")?,
+            heart_rate: field(&fields, line, "heart_rate")?,
+            time: field(&fields, line, "time")?,
+        }
+        .into(),
+        "treadmill" => TreadmillData {
+            speed: field(&fields, line, "speed")?,
+            distance: field(&fields, line, "distance")?,
+            inclination: field(&fields, line, "inclination")?,
+            ramp_angle: field(&fields, line, "ramp_angle")?,
+            elevation_gain: field(&fields, line, "elevation_gain")?,
+            pace: field(&fields, line, "pace")?,
+            power: field(&fields, line, "power")?,
+            calories: field(&fields, line, "calories")?,
+            heart_rate: field(&fields, line, "heart_rate")?,
+            time: field(&fields, line, "time")?,
+        }
+        .into(),
+        "rower" => RowerData {
+            stroke_rate: field(&fields, line, "stroke_rate")?,
+            stroke_count: field(&fields, line, "stroke_count")?,
+            distance: field(&fields, line, "distance")?,
+            pace: field(&fields, line, "pace")?,
+            power: field(&fields, line, "power")?,
+            resistance: field(&fields, line, "resistance")?,
+            calories: field(&fields, line, "calories")?,
+            heart_rate: field(&fields, line, "heart_rate")?,
+            time: field(&fields, line, "time")?,
+        }
+        .into(),
+        "cross_trainer" => CrossTrainerData {
+            speed: field(&fields, line, "speed")?,
+            distance: field(&fields, line, "distance")?,
+            stride_rate: field(&fields, line, "stride_rate")?,
+            stride_count: field(&fields, line, "stride_count")?,
+            elevation_gain: field(&fields, line, "elevation_gain")?,
+            inclination: field(&fields, line, "inclination")?,
+            resistance: field(&fields, line, "resistance")?,
+            power: field(&fields, line, "power")?,
+            calories: field(&fields, line, "calories")?,
+            heart_rate: field(&fields, line, "heart_rate")?,
+            time: field(&fields, line, "time")?,
+            backwards: field(&fields, line, "backwards")?.unwrap_or(false),
+        }
+        .into(),
+        "heart_rate" => HeartRateData {
+            heart_rate: field(&fields, line, "heart_rate")?.ok_or_else(invalid)?,
+            sensor_contact: field(&fields, line, "sensor_contact")?,
+            energy_expended: field(&fields, line, "energy_expended")?,
+            rr_intervals: match fields.get("rr_intervals") {
+                Some(intervals) => intervals
+                    .split(',')
+                    .map(|nanos| nanos.parse().map(Duration::from_nanos))
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| invalid())?,
+                None => Vec::new(),
+            },
+        }
+        .into(),
+        "speed_cadence" => SpeedCadenceData {
+            speed: field(&fields, line, "speed")?,
+            cadence: field(&fields, line, "cadence")?,
+            distance: field(&fields, line, "distance")?,
+            wheel_revolutions: field(&fields, line, "wheel_revolutions")?,
+            crank_revolutions: field(&fields, line, "crank_revolutions")?,
+        }
+        .into(),
+        _ => return Err(invalid()),
+    };
+    Ok((sequence, data))
+}
+
+/// `key=value` words, as they get sent
+#[derive(Debug, Default)]
+struct Fields(String);
+
+impl Fields {
+    fn put(&mut self, key: &str, value: Option<impl Display>) {
+        if let Some(value) = value {
+            self.0.push_str(&format!(" {key}={value}"));
+        }
+    }
+}
+
+fn fields<'a>(words: impl Iterator<Item = &'a str>) -> HashMap<&'a str, &'a str> {
+    words.filter_map(|word| word.split_once('=')).collect()
+}
+
+/// The field `key` of the data `line`, as a number, or whatever else it holds
+fn field<T: FromStr>(fields: &HashMap<&str, &str>, line: &str, key: &str) -> Result<Option<T>> {
+    fields
+        .get(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| KondisError::InvalidData(format!("Invalid data {line:?}")))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResultCode;
+
+    #[test]
+    fn test_requests() -> Result<()> {
+        let requests = [
+            Request::Power(210),
+            Request::Goal(TrainingGoal::Distance(10_000)),
+            Request::Simulation {
+                grade: -2.5,
+                wind_speed: 0.,
+                crr: 0.004,
+                cw: 0.51,
+            },
+            Request::Resume,
+        ];
+        for (id, sent) in requests.into_iter().enumerate() {
+            let line = request(id as u64, &sent);
+            assert_eq!(parse_request(&line)?, (id as u64, sent));
+        }
+        assert!(parse_request("1 power").is_err());
+        assert!(parse_request("1 spin").is_err());
+
+        assert!(matches!(parse_reply(&reply(3, &Ok(()))), Some((3, Ok(())))));
+        let rejected = KondisError::ControlRejected(ControlPointError {
+            op_code: 0x05,
+            result: ResultCode::InvalidParameter,
+        });
+        assert!(matches!(
+            parse_reply(&reply(4, &Err(rejected))),
+            Some((
+                4,
+                Err(KondisError::ControlRejected(ControlPointError {
+                    op_code: 0x05,
+                    result: ResultCode::InvalidParameter,
+                }))
+            ))
+        ));
+        let unsupported = KondisError::Unsupported("No simulation".to_string());
+        assert!(matches!(
+            parse_reply(&reply(5, &Err(unsupported))),
+            Some((5, Err(KondisError::Unsupported(message)))) if message == "No simulation"
+        ));
+        assert!(parse_reply("data 1 bike").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_data() -> Result<()> {
+        let sent = [
+            MachineData::Bike(BikeData {
+                cadence: Some(88.5),
+                power: Some(210),
+                ..Default::default()
+            }),
+            MachineData::CrossTrainer(CrossTrainerData {
+                stride_rate: Some(64.),
+                backwards: true,
+                ..Default::default()
+            }),
+            MachineData::HeartRate(HeartRateData {
+                heart_rate: 142.,
+                sensor_contact: Some(true),
+                energy_expended: None,
+                rr_intervals: vec![Duration::from_millis(812), Duration::from_millis(799)],
+            }),
+        ];
+        for (sequence, data) in sent.into_iter().enumerate() {
+            let line = super::data(&Reading::new(sequence as u64, data.clone()));
+            let (sequence_received, received) = parse_data(line.strip_prefix("data ").unwrap())?;
+            assert_eq!(sequence_received, sequence as u64);
+            assert_eq!(received, data);
+        }
+        assert!(parse_data("1 bike power=fast").is_err());
+        assert!(parse_data("1 unicycle").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hello() -> Result<()> {
+        let mut capabilities = Capabilities::default();
+        capabilities.targets.power = true;
+        capabilities.data.cadence = true;
+        capabilities.data.power = true;
+        let line = hello(Some(&capabilities));
+        assert_eq!(line, "kondis 1 targets=power data=cadence,power");
+        assert_eq!(parse_hello(&line)?, Some(capabilities));
+        assert_eq!(parse_hello(&hello(None))?, None);
+        assert!(parse_hello("HTTP/1.1 400 Bad Request").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::route::{Route, RoutePoint};
+use crate::{KondisError, Result};
+
+/// m, the mean radius of the earth
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Load a route from the track points of a GPX file, or its route points if it has no track
+///
+/// Distances are measured along the surface of the earth between the points. Points without an
+/// elevation keep that of the point before them.
+///
+/// # Examples
+///
+/// ```
+/// let route = kondis::route::parse_gpx(
+///     r#"<gpx version="1.1">
+///         <trk>
+///             <name>Up the hill</name>
+///             <trkseg>
+///                 <trkpt lat="59.9" lon="10.7"><ele>10</ele></trkpt>
+///                 <trkpt lat="59.9045" lon="10.7"><ele>35</ele></trkpt>
+///             </trkseg>
+///         </trk>
+///     </gpx>"#,
+/// )?;
+/// assert_eq!(route.name, "Up the hill");
+/// assert_eq!(route.length().round(), 500.);
+/// assert_eq!(route.grade_at(0.).round(), 5.);
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_gpx(xml: &str) -> Result<Route> {
+    // the route and the position of its last point, for the track and for the planned route
+    let mut track = (Route::default(), None);
+    let mut planned = (Route::default(), None);
+    let mut in_route = false;
+    let mut text_of = None;
+    for event in EventReader::from_str(xml) {
+        let event =
+            event.map_err(|e| KondisError::InvalidData(format!("Invalid GPX file: {e}")))?;
+        let (route, last) = if in_route { &mut planned } else { &mut track };
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "rte" => in_route = true,
+                "trk" => in_route = false,
+                element @ ("trkpt" | "rtept") => {
+                    let coordinate = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|attribute| attribute.name.local_name == key)
+                            .and_then(|attribute| attribute.value.trim().parse::<f64>().ok())
+                            .ok_or_else(|| {
+                                KondisError::InvalidData(format!("{element} without a valid {key}"))
+                            })
+                    };
+                    let position = (coordinate("lat")?, coordinate("lon")?);
+                    let point = match (*last, route.points.last()) {
+                        (Some(last), Some(point)) => RoutePoint {
+                            distance: point.distance + distance(last, position),
+                            elevation: point.elevation,
+                        },
+                        _ => RoutePoint {
+                            distance: 0.,
+                            elevation: 0.,
+                        },
+                    };
+                    route.points.push(point);
+                    *last = Some(position);
+                }
+                element @ ("name" | "ele") => text_of = Some(element.to_string()),
+                _ => {}
+            },
+            XmlEvent::Characters(text) => match text_of.as_deref() {
+                Some("name") if route.name.is_empty() => route.name = text.trim().to_string(),
+                Some("ele") => {
+                    let elevation = text.trim().parse().map_err(|_| {
+                        KondisError::InvalidData(format!("Invalid elevation {text:?}"))
+                    })?;
+                    if let Some(point) = route.points.last_mut() {
+                        point.elevation = elevation;
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { .. } => text_of = None,
+            _ => {}
+        }
+    }
+    let ((mut route, _), (planned, _)) = (track, planned);
+    if route.points.is_empty() {
+        route.points = planned.points;
+        if route.name.is_empty() {
+            route.name = planned.name;
+        }
+    }
+    Ok(route)
+}
+
+/// The great circle distance in meters between two positions in degrees of latitude and longitude
+fn distance((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let half_lat = (lat2 - lat1) / 2.;
+    let half_lon = (lon2 - lon1).to_radians() / 2.;
+    let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_lon.sin().powi(2);
+    2. * EARTH_RADIUS * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gpx() {
+        let route = parse_gpx(
+            r#"<?xml version="1.0"?>
+            <gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+                <metadata><name>Loop</name></metadata>
+                <rte><rtept lat="0" lon="0"/></rte>
+                <trk>
+                    <trkseg>
+                        <trkpt lat="0" lon="0"><ele>100</ele></trkpt>
+                        <trkpt lat="0" lon="0.01"/>
+                    </trkseg>
+                    <trkseg>
+                        <trkpt lat="0" lon="0.02"><ele>120.5</ele></trkpt>
+                    </trkseg>
+                </trk>
+            </gpx>"#,
+        )
+        .unwrap();
+        assert_eq!(route.name, "Loop");
+        assert_eq!(route.points.len(), 3);
+        // a hundredth of a degree of longitude at the equator
+        assert!((route.points[1].distance - 1112.).abs() < 1.);
+        assert_eq!(route.points[1].elevation, 100.);
+        assert_eq!(route.elevation_gain(), 20.5);
+
+        assert!(parse_gpx(r#"<gpx><trk><trkpt lat="north"/></trk></gpx>"#).is_err());
+    }
+}
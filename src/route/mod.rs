@@ -0,0 +1,150 @@
+//! Virtual routes, and riding them on equipment
+//!
+//! A `Route` is an elevation profile, elevation against distance. A `RouteRider` rides one on any
+//! `Equipment`, moving along it at the speed the equipment reports and sending the grade of wherever
+//! the rider is. With the `gpx` feature, `parse_gpx` loads routes from GPX tracks.
+
+#[cfg(feature = "gpx")]
+mod gpx;
+mod rider;
+
+#[cfg(feature = "gpx")]
+pub use gpx::parse_gpx;
+pub use rider::{GradeControl, RouteEvent, RouteRider};
+
+/// A point of a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoutePoint {
+    /// m from the start of the route
+    pub distance: f64,
+    /// m above sea level
+    pub elevation: f64,
+}
+
+/// The elevation profile of a route, points ordered by distance and joined by straight slopes
+///
+/// # Examples
+///
+/// ```
+/// use kondis::route::Route;
+///
+/// let route = Route::new("Hill repeats")
+///     .point(0., 10.)
+///     .point(1000., 60.)
+///     .point(2000., 10.);
+/// assert_eq!(route.grade_at(500.), 5.);
+/// assert_eq!(route.elevation_gain(), 50.);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Route {
+    pub name: String,
+    pub points: Vec<RoutePoint>,
+}
+
+impl Route {
+    /// An empty route called `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Route {
+            name: name.into(),
+            points: Vec::new(),
+        }
+    }
+
+    /// Add a point `distance` meters from the start, at `elevation` meters, to the end of the route
+    pub fn point(mut self, distance: f64, elevation: f64) -> Self {
+        self.points.push(RoutePoint {
+            distance,
+            elevation,
+        });
+        self
+    }
+
+    /// m
+    pub fn length(&self) -> f64 {
+        self.points.last().map_or(0., |point| point.distance)
+    }
+
+    /// The meters climbed riding the whole route, descents not taken off
+    pub fn elevation_gain(&self) -> f64 {
+        self.elevation_gain_until(self.length())
+    }
+
+    /// The meters climbed riding the first `distance` meters of the route
+    pub fn elevation_gain_until(&self, distance: f64) -> f64 {
+        let mut gain = 0.;
+        for pair in self.points.windows(2) {
+            let [from, to] = [pair[0], pair[1]];
+            if from.distance >= distance {
+                break;
+            }
+            let to_elevation = if to.distance > distance {
+                self.elevation_at(distance)
+            } else {
+                to.elevation
+            };
+            gain += (to_elevation - from.elevation).max(0.);
+        }
+        gain
+    }
+
+    /// The elevation in meters `distance` meters into the route
+    pub fn elevation_at(&self, distance: f64) -> f64 {
+        match self.segment_at(distance) {
+            Some((from, to)) => {
+                let progress =
+                    ((distance - from.distance) / (to.distance - from.distance)).clamp(0., 1.);
+                from.elevation + (to.elevation - from.elevation) * progress
+            }
+            None => self.points.first().map_or(0., |point| point.elevation),
+        }
+    }
+
+    /// The grade in percent `distance` meters into the route, that of its last slope past its end
+    pub fn grade_at(&self, distance: f64) -> f64 {
+        self.segment_at(distance).map_or(0., |(from, to)| {
+            (to.elevation - from.elevation) / (to.distance - from.distance) * 100.
+        })
+    }
+
+    /// The slope `distance` meters into the route, skipping points at the same distance
+    fn segment_at(&self, distance: f64) -> Option<(RoutePoint, RoutePoint)> {
+        let segments = self
+            .points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .filter(|(from, to)| to.distance > from.distance);
+        let mut last = None;
+        for segment in segments {
+            last = Some(segment);
+            if distance < segment.1.distance {
+                break;
+            }
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let route = Route::new("test")
+            .point(0., 100.)
+            .point(200., 110.)
+            .point(200., 110.)
+            .point(400., 100.)
+            .point(500., 120.);
+        assert_eq!(route.length(), 500.);
+        assert_eq!(route.grade_at(100.), 5.);
+        assert_eq!(route.grade_at(300.), -5.);
+        assert_eq!(route.grade_at(900.), 20.);
+        assert_eq!(route.elevation_at(450.), 110.);
+        assert_eq!(route.elevation_gain(), 30.);
+        assert_eq!(route.elevation_gain_until(450.), 20.);
+        assert_eq!(Route::new("empty").grade_at(0.), 0.);
+    }
+}
@@ -0,0 +1,314 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::route::Route;
+use crate::{DeviceEvent, Equipment, FTMSData, GradeSimulator, Result, RideModel};
+
+/// Gaps between readings longer than this are taken as a pause rather than riding
+const MAX_GAP: Duration = Duration::from_secs(5);
+/// How many events a slow receiver may fall behind before it starts missing them
+const EVENT_CAPACITY: usize = 64;
+
+/// How the grade of the route gets to the equipment
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GradeControl {
+    /// As simulation parameters, for equipment simulating the slope on its own
+    Simulation {
+        /// m/s, headwind when positive
+        wind_speed: f32,
+        /// rolling resistance coefficient
+        crr: f32,
+        /// wind resistance coefficient in kg/m
+        cw: f32,
+    },
+    /// As the target power riding the grade takes, see `GradeSimulator`
+    Power(RideModel),
+}
+
+impl Default for GradeControl {
+    /// Simulation parameters for a road bike on asphalt, without wind
+    fn default() -> Self {
+        GradeControl::Simulation {
+            wind_speed: 0.,
+            crr: 0.004,
+            cw: 0.51,
+        }
+    }
+}
+
+/// How far along a route being ridden is, see `RouteRider::events`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RouteEvent {
+    /// The rider moved along the route
+    Progress {
+        /// m from the start
+        distance: f64,
+        /// m above sea level
+        elevation: f64,
+        /// percent
+        grade: f64,
+        /// m climbed so far
+        elevation_gain: f64,
+    },
+    /// The rider reached the end of the route
+    Finished,
+}
+
+/// Rides a route on equipment, sending it the grade of wherever the rider is
+///
+/// The rider moves along the route at the speed the equipment reports, so equipment reporting no speed
+/// never gets anywhere.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{
+///     devices::NonBluetoothDevice,
+///     route::{Route, RouteRider},
+///     CancellationToken, Equipment,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let route = Route::new("The wall").point(0., 0.).point(500., 60.);
+///     let rider = RouteRider::new(route);
+///     shutdown.cancel();
+///     rider.run(&device, &shutdown).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RouteRider {
+    route: Route,
+    control: GradeControl,
+    events_tx: broadcast::Sender<RouteEvent>,
+}
+
+impl RouteRider {
+    pub fn new(route: Route) -> Self {
+        RouteRider {
+            route,
+            control: GradeControl::default(),
+            events_tx: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// How to get the grade to the equipment, simulation parameters by default
+    pub fn control(mut self, control: GradeControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+
+    /// Every event of the ride from now on
+    pub fn events(&self) -> broadcast::Receiver<RouteEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Ride the route from the start on `equipment`, until its end, `shutdown` getting cancelled or the
+    /// equipment no longer sending events
+    ///
+    /// Fails as soon as the equipment refuses the grade or a target power.
+    pub async fn run<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        match self.control {
+            GradeControl::Simulation { .. } => self.ride(equipment, None, shutdown).await,
+            GradeControl::Power(model) => {
+                let simulator = GradeSimulator::new(model);
+                tokio::select! {
+                    result = self.ride(equipment, Some(&simulator), shutdown) => result,
+                    result = simulator.run(equipment, shutdown) => result,
+                }
+            }
+        }
+    }
+
+    async fn ride<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        simulator: Option<&GradeSimulator>,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut events = equipment.events();
+        let mut distance = 0.;
+        let mut last = None;
+        let mut sent = None;
+        loop {
+            let reading = tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => return Ok(()),
+                event = events.recv() => match event {
+                    Ok(DeviceEvent::Data(reading)) => reading,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            };
+            let Some(speed) = FTMSData::from(reading.data.clone()).speed else {
+                continue;
+            };
+            // every speed lasts until the next one arrives
+            if let Some((timestamp, speed)) = last {
+                let interval = reading.timestamp.saturating_sub(timestamp);
+                if interval <= MAX_GAP {
+                    distance += f64::from(speed) / 3.6 * interval.as_secs_f64();
+                }
+            }
+            last = Some((reading.timestamp, speed));
+            if distance >= self.route.length() {
+                let _ = self.events_tx.send(RouteEvent::Finished);
+                return Ok(());
+            }
+            // simulation parameters take the grade in hundredths of a percent
+            let grade = (self.route.grade_at(distance) * 100.).round() / 100.;
+            if sent != Some(grade) {
+                match (simulator, self.control) {
+                    (Some(simulator), _) => simulator.set_grade(grade),
+                    (
+                        None,
+                        GradeControl::Simulation {
+                            wind_speed,
+                            crr,
+                            cw,
+                        },
+                    ) => {
+                        equipment
+                            .set_simulation_parameters(grade as f32, wind_speed, crr, cw)
+                            .await?
+                    }
+                    (None, GradeControl::Power(_)) => {}
+                }
+                sent = Some(grade);
+            }
+            // nobody listening is fine, someone may subscribe later
+            let _ = self.events_tx.send(RouteEvent::Progress {
+                distance,
+                elevation: self.route.elevation_at(distance),
+                grade,
+                elevation_gain: self.route.elevation_gain_until(distance),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::RecordingEquipment;
+    use crate::ftms::{BikeData, MachineData};
+    use crate::remote::Request;
+    use crate::{Reading, route::Route};
+
+    /// A reading of riding at `speed` km/h, `seconds` into the ride
+    fn reading(seconds: u64, speed: f32) -> DeviceEvent {
+        DeviceEvent::Data(Reading {
+            timestamp: Duration::from_secs(seconds),
+            sequence: seconds,
+            data: MachineData::Bike(BikeData {
+                speed: Some(speed),
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn grades(equipment: &RecordingEquipment) -> Vec<f32> {
+        equipment
+            .requests()
+            .into_iter()
+            .filter_map(|(_, request)| match request {
+                Request::Simulation { grade, .. } => Some(grade),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Ride `route` on equipment sending `readings`, once the rider listens
+    async fn ride(
+        route: Route,
+        readings: impl IntoIterator<Item = DeviceEvent>,
+    ) -> (RecordingEquipment, Vec<RouteEvent>) {
+        tokio::time::pause();
+        let shutdown = CancellationToken::new();
+        let equipment = RecordingEquipment::new();
+        let rider = RouteRider::new(route);
+        let mut events = rider.events();
+        let feed = async {
+            tokio::task::yield_now().await;
+            for reading in readings {
+                equipment.send(reading);
+            }
+            // the paused clock only moves on once the rider went through every reading
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            shutdown.cancel();
+        };
+        let (result, _) = tokio::join!(rider.run(&equipment, &shutdown), feed);
+        result.unwrap();
+        let events = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        (equipment, events)
+    }
+
+    #[tokio::test]
+    async fn test_ride() {
+        // a 5 % climb and a flat, at 10 m/s
+        let route = Route::new("test")
+            .point(0., 0.)
+            .point(100., 5.)
+            .point(200., 5.);
+        let (equipment, events) = ride(route, (0..30).map(|second| reading(second, 36.))).await;
+
+        // the grade only gets sent when it changes
+        assert_eq!(grades(&equipment), [5., 0.]);
+        let progress: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                RouteEvent::Progress {
+                    distance,
+                    elevation_gain,
+                    ..
+                } => Some((*distance, *elevation_gain)),
+                _ => None,
+            })
+            .collect();
+        // the end of the route is reached at the 20th second, after which readings are ignored
+        assert_eq!(progress.len(), 20);
+        assert_eq!(progress[5], (50., 2.5));
+        assert_eq!(progress[19], (190., 5.));
+        assert_eq!(events.last(), Some(&RouteEvent::Finished));
+    }
+
+    #[tokio::test]
+    async fn test_gap() {
+        let route = Route::new("test").point(0., 0.).point(1000., 10.);
+        let readings = [
+            reading(0, 36.),
+            reading(1, 36.),
+            reading(60, 36.),
+            reading(61, 36.),
+        ];
+        let (_, events) = ride(route, readings).await;
+
+        // a minute without readings is a pause, not 600 m of riding
+        let distances: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                RouteEvent::Progress { distance, .. } => Some(*distance),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(distances, [0., 10., 10., 20.]);
+        assert!(!events.contains(&RouteEvent::Finished));
+    }
+}
@@ -0,0 +1,200 @@
+//! The executor kondis spawns its tasks on and keeps time with
+//!
+//! That's tokio with the default `runtime-tokio` feature. Without default features, the
+//! `runtime-async-std` or `runtime-smol` feature spawns on async-std or smol and times with their
+//! timers instead, so apps on those don't need to run a tokio runtime next to their own. btleplug,
+//! serial ports and sockets still need a tokio reactor: async-std gets one through its `tokio1`
+//! compatibility, and tasks spawned on smol run within `async_compat::Compat`. In the browser, tasks
+//! run on the event loop of the page whatever the features, through `wasm-bindgen-futures`. tokio's
+//! channels and `select!` work on any executor, so they're used as they are.
+
+use std::time::Duration;
+
+#[cfg(not(any(
+    target_arch = "wasm32",
+    feature = "runtime-tokio",
+    feature = "runtime-async-std",
+    feature = "runtime-smol"
+)))]
+compile_error!(
+    "kondis needs one of the `runtime-tokio`, `runtime-async-std` or `runtime-smol` features"
+);
+
+#[cfg(target_arch = "wasm32")]
+mod backend {
+    pub(crate) use web_time::Instant;
+
+    pub(crate) fn spawn(future: impl Future<Output = ()> + 'static) {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+
+    pub(crate) async fn sleep_until(deadline: Instant) {
+        let duration = deadline.saturating_duration_since(Instant::now());
+        // the page has a single thread, which the timer never leaves
+        send_wrapper::SendWrapper::new(gloo_timers::future::sleep(duration)).await;
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "runtime-tokio"))]
+mod backend {
+    pub(crate) use tokio::time::{Instant, sleep_until};
+
+    pub(crate) fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        tokio::spawn(future);
+    }
+}
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "runtime-tokio"),
+    feature = "runtime-async-std"
+))]
+mod backend {
+    pub(crate) use std::time::Instant;
+
+    pub(crate) fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        async_std::task::spawn(future);
+    }
+
+    pub(crate) async fn sleep_until(deadline: Instant) {
+        async_std::task::sleep(deadline.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "runtime-tokio"),
+    not(feature = "runtime-async-std"),
+    feature = "runtime-smol"
+))]
+mod backend {
+    pub(crate) use std::time::Instant;
+
+    pub(crate) fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        smol::spawn(async_compat::Compat::new(future)).detach();
+    }
+
+    pub(crate) async fn sleep_until(deadline: Instant) {
+        smol::Timer::at(deadline).await;
+    }
+}
+
+/// A point in time, on the clock of the executor
+pub(crate) use backend::Instant;
+/// Wait until `deadline`
+pub(crate) use backend::sleep_until;
+
+/// Run `future` in the background, to completion or until the executor shuts down
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    backend::spawn(future);
+}
+
+/// Wait for `duration`
+pub(crate) async fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration).await;
+}
+
+/// Ticks every `period`, the first one right away, like `tokio::time::Interval`
+///
+/// Ticks missed while not waiting for them come right after each other, until caught up.
+pub(crate) struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    /// Wait for the next tick, returning when it was due
+    ///
+    /// A tick only passes once this completes, so it's fine to cancel.
+    pub(crate) async fn tick(&mut self) -> Instant {
+        sleep_until(self.next).await;
+        let due = self.next;
+        self.next += self.period;
+        due
+    }
+}
+
+/// Ticks every `period`, the first one right away
+pub(crate) fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "An interval needs a period");
+    Interval {
+        next: Instant::now(),
+        period,
+    }
+}
+
+/// A future ran out of the time it was given, see `timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Elapsed;
+
+/// The output of `future`, unless it takes longer than `duration`
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    tokio::select! {
+        biased;
+        output = future => Ok(output),
+        () = sleep(duration) => Err(Elapsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::NonBluetoothDevice;
+    use crate::{CancellationToken, DeviceEvent, Equipment};
+
+    async fn ride() -> crate::Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut device = NonBluetoothDevice::new(10, &shutdown).await?;
+        let mut events = device.events();
+        device.connect().await?;
+        let data = timeout(Duration::from_secs(3), async {
+            loop {
+                if let Ok(DeviceEvent::Data(reading)) = events.recv().await {
+                    return reading;
+                }
+            }
+        });
+        assert!(data.await.is_ok());
+        assert_eq!(
+            timeout(Duration::from_millis(10), std::future::pending::<()>()).await,
+            Err(Elapsed)
+        );
+
+        let start = Instant::now();
+        let mut ticks = interval(Duration::from_millis(20));
+        for _ in 0..3 {
+            ticks.tick().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn test_tokio() -> crate::Result<()> {
+        ride().await
+    }
+
+    #[cfg(all(not(feature = "runtime-tokio"), feature = "runtime-async-std"))]
+    #[test]
+    fn test_async_std() -> crate::Result<()> {
+        async_std::task::block_on(ride())
+    }
+
+    #[cfg(all(
+        not(feature = "runtime-tokio"),
+        not(feature = "runtime-async-std"),
+        feature = "runtime-smol"
+    ))]
+    #[test]
+    fn test_smol() -> crate::Result<()> {
+        smol::block_on(async_compat::Compat::new(ride()))
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use crate::FTMSData;
+use crate::Result;
+use crate::ftms::reader::Reader;
+
+/// Heart rate data structure
+/// Used to represent the data received from heart rate monitors
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeartRateData {
+    /// bpm
+    pub heart_rate: f64,
+    /// Whether the monitor is touching the skin, `None` for monitors that can't tell
+    pub sensor_contact: Option<bool>,
+    /// kJ, since the monitor was last reset
+    pub energy_expended: Option<u16>,
+    /// The times between beats since the last notification, oldest first
+    pub rr_intervals: Vec<Duration>,
+}
+
+impl From<HeartRateData> for FTMSData {
+    fn from(data: HeartRateData) -> Self {
+        FTMSData {
+            heart_rate: Some(data.heart_rate),
+            calories: data
+                .energy_expended
+                .map(|kilojoules| f64::from(kilojoules) / 4.184),
+            ..Default::default()
+        }
+    }
+}
+
+/// Flag bits of the Heart Rate Measurement characteristic (0x2A37)
+pub(crate) mod flags {
+    pub const HEART_RATE_U16: u8 = 1 << 0;
+    pub const SENSOR_CONTACT_DETECTED: u8 = 1 << 1;
+    pub const SENSOR_CONTACT_SUPPORTED: u8 = 1 << 2;
+    pub const ENERGY_EXPENDED: u8 = 1 << 3;
+    pub const RR_INTERVALS: u8 = 1 << 4;
+}
+
+/// Parse a Heart Rate Measurement notification into `HeartRateData`
+///
+/// RR intervals fill the rest of the notification, in 1/1024 s.
+pub fn parse_heart_rate_measurement(data: &[u8]) -> Result<HeartRateData> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u8()?;
+    let heart_rate = if flags & flags::HEART_RATE_U16 != 0 {
+        reader.u16()?
+    } else {
+        reader.u8()?.into()
+    };
+    let mut parsed = HeartRateData {
+        heart_rate: heart_rate.into(),
+        sensor_contact: (flags & flags::SENSOR_CONTACT_SUPPORTED != 0)
+            .then_some(flags & flags::SENSOR_CONTACT_DETECTED != 0),
+        ..Default::default()
+    };
+
+    if flags & flags::ENERGY_EXPENDED != 0 {
+        parsed.energy_expended = Some(reader.u16()?);
+    }
+    if flags & flags::RR_INTERVALS != 0 {
+        while reader.remaining() >= 2 {
+            let interval = reader.u16()?;
+            parsed
+                .rr_intervals
+                .push(Duration::from_secs_f64(f64::from(interval) / 1024.));
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heart_rate_u8() -> Result<()> {
+        // flags: contact supported but lost
+        let parsed = parse_heart_rate_measurement(&[0x04, 0x48])?;
+        assert_eq!(parsed.heart_rate, 72.);
+        assert_eq!(parsed.sensor_contact, Some(false));
+        assert_eq!(parsed.energy_expended, None);
+        assert!(parsed.rr_intervals.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_fields() -> Result<()> {
+        let data = [
+            0x1F, // flags, every field present and contact detected
+            0x2C, 0x01, // 300 bpm, as u16
+            0x64, 0x00, // 100 kJ
+            0x00, 0x04, // 1024/1024 s
+            0x00, 0x02, // 512/1024 s
+        ];
+        let parsed = parse_heart_rate_measurement(&data)?;
+        assert_eq!(parsed.heart_rate, 300.);
+        assert_eq!(parsed.sensor_contact, Some(true));
+        assert_eq!(parsed.energy_expended, Some(100));
+        assert_eq!(
+            parsed.rr_intervals,
+            [Duration::from_secs(1), Duration::from_millis(500)]
+        );
+        assert!(parse_heart_rate_measurement(&[0x01, 0x2C]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+//! Standard Bluetooth sensor services, for sensors worn or mounted next to the machine
+
+mod heart_rate;
+mod speed_cadence;
+
+#[cfg(all(feature = "bridge", target_os = "linux"))]
+pub(crate) use heart_rate::flags as heart_rate_flags;
+pub use heart_rate::{HeartRateData, parse_heart_rate_measurement};
+pub use speed_cadence::SpeedCadenceData;
+pub(crate) use speed_cadence::{CscCalculator, DEFAULT_WHEEL_CIRCUMFERENCE, parse_csc_measurement};
+use uuid::Uuid;
+
+use crate::ftms::uuid_from_u16;
+
+/// Heart Rate Service
+pub const HEART_RATE_SERVICE_UUID: Uuid = uuid_from_u16(0x180D);
+/// Heart Rate Measurement characteristic, notified by heart rate monitors
+pub const HEART_RATE_MEASUREMENT_UUID: Uuid = uuid_from_u16(0x2A37);
+/// Cycling Speed and Cadence Service
+pub const CYCLING_SPEED_AND_CADENCE_SERVICE_UUID: Uuid = uuid_from_u16(0x1816);
+/// CSC Measurement characteristic, notified by speed and cadence sensors
+pub const CSC_MEASUREMENT_UUID: Uuid = uuid_from_u16(0x2A5B);
+/// Battery Level characteristic of the Battery Service, in percent
+pub const BATTERY_LEVEL_UUID: Uuid = uuid_from_u16(0x2A19);
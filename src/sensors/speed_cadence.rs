@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use crate::FTMSData;
+use crate::Result;
+use crate::ftms::reader::Reader;
+
+/// m, of a 700x25c road tyre
+pub(crate) const DEFAULT_WHEEL_CIRCUMFERENCE: f64 = 2.105;
+/// How long a sensor may go without a new revolution before the wheel or crank counts as stopped
+const STOPPED_AFTER: Duration = Duration::from_secs(3);
+
+/// Speed and cadence data structure
+/// Used to represent the data worked out from speed and cadence sensors
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpeedCadenceData {
+    /// km/h, for sensors counting wheel revolutions
+    pub speed: Option<f32>,
+    /// rpm, for sensors counting crank revolutions
+    pub cadence: Option<f32>,
+    /// km, since the first measurement
+    pub distance: Option<f32>,
+    /// As counted by the sensor, going back to 0 once past the largest value it holds
+    pub wheel_revolutions: Option<u32>,
+    /// As counted by the sensor, going back to 0 once past the largest value it holds
+    pub crank_revolutions: Option<u16>,
+}
+
+impl From<SpeedCadenceData> for FTMSData {
+    fn from(data: SpeedCadenceData) -> Self {
+        FTMSData {
+            speed: data.speed,
+            cadence: data.cadence,
+            distance: data.distance,
+            ..Default::default()
+        }
+    }
+}
+
+/// A CSC Measurement notification, with cumulative revolutions and the time of the last one in 1/1024 s
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct CscMeasurement {
+    pub wheel: Option<(u32, u16)>,
+    pub crank: Option<(u16, u16)>,
+}
+
+/// Flag bits of the CSC Measurement characteristic (0x2A5B)
+mod flags {
+    pub const WHEEL_REVOLUTIONS: u8 = 1 << 0;
+    pub const CRANK_REVOLUTIONS: u8 = 1 << 1;
+}
+
+pub(crate) fn parse_csc_measurement(data: &[u8]) -> Result<CscMeasurement> {
+    let mut reader = Reader::new(data);
+    let flags = reader.u8()?;
+    let mut parsed = CscMeasurement::default();
+    if flags & flags::WHEEL_REVOLUTIONS != 0 {
+        parsed.wheel = Some((reader.u32()?, reader.u16()?));
+    }
+    if flags & flags::CRANK_REVOLUTIONS != 0 {
+        parsed.crank = Some((reader.u16()?, reader.u16()?));
+    }
+    Ok(parsed)
+}
+
+/// Works out speed, cadence and distance from consecutive CSC measurements
+///
+/// Every counter rolls over, the event times every 64 seconds, so differences wrap around them.
+#[derive(Debug, Clone)]
+pub(crate) struct CscCalculator {
+    /// m
+    wheel_circumference: f64,
+    wheel: Counter,
+    crank: Counter,
+    /// wheel revolutions since the first measurement
+    wheel_total: u64,
+}
+
+/// The revolutions of the wheel or crank, and the rate they were last turning at
+#[derive(Debug, Clone, Copy, Default)]
+struct Counter {
+    /// the revolutions and event time of the last measurement
+    last: Option<(u32, u16)>,
+    /// when the last new revolution arrived, on the clock the measurements come in on
+    turned_at: Duration,
+    /// revolutions per second
+    rate: f64,
+}
+
+impl Counter {
+    /// Take in a measurement arriving `at`, returning how many revolutions it adds
+    fn update(&mut self, revolutions: u32, event_time: u16, bits: u32, at: Duration) -> u32 {
+        let Some((last_revolutions, last_event_time)) =
+            self.last.replace((revolutions, event_time))
+        else {
+            self.turned_at = at;
+            return 0;
+        };
+        let mask = if bits == 32 {
+            u32::MAX
+        } else {
+            (1 << bits) - 1
+        };
+        let turned = revolutions.wrapping_sub(last_revolutions) & mask;
+        let ticks = event_time.wrapping_sub(last_event_time);
+        if turned > 0 && ticks > 0 {
+            self.rate = f64::from(turned) * 1024. / f64::from(ticks);
+            self.turned_at = at;
+        } else if at.saturating_sub(self.turned_at) >= STOPPED_AFTER {
+            self.rate = 0.;
+        }
+        turned
+    }
+}
+
+impl CscCalculator {
+    pub fn new(wheel_circumference: f64) -> Self {
+        CscCalculator {
+            wheel_circumference,
+            wheel: Counter::default(),
+            crank: Counter::default(),
+            wheel_total: 0,
+        }
+    }
+
+    /// Take in a measurement arriving `at`, on any clock going forward
+    pub fn update(&mut self, measurement: &CscMeasurement, at: Duration) -> SpeedCadenceData {
+        let mut data = SpeedCadenceData::default();
+        if let Some((revolutions, event_time)) = measurement.wheel {
+            self.wheel_total += u64::from(self.wheel.update(revolutions, event_time, 32, at));
+            data.speed = Some((self.wheel.rate * self.wheel_circumference * 3.6) as f32);
+            data.distance =
+                Some((self.wheel_total as f64 * self.wheel_circumference / 1000.) as f32);
+            data.wheel_revolutions = Some(revolutions);
+        }
+        if let Some((revolutions, event_time)) = measurement.crank {
+            self.crank.update(revolutions.into(), event_time, 16, at);
+            data.cadence = Some((self.crank.rate * 60.) as f32);
+            data.crank_revolutions = Some(revolutions);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csc_measurement() -> Result<()> {
+        let data = [
+            0x03, // flags, wheel and crank revolutions
+            0x0A, 0x00, 0x00, 0x00, // 10 wheel revolutions
+            0x00, 0x04, // at 1024/1024 s
+            0x05, 0x00, // 5 crank revolutions
+            0x00, 0x08, // at 2048/1024 s
+        ];
+        let parsed = parse_csc_measurement(&data)?;
+        assert_eq!(parsed.wheel, Some((10, 1024)));
+        assert_eq!(parsed.crank, Some((5, 2048)));
+        assert!(parse_csc_measurement(&[0x02, 0x05]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollover() {
+        let mut calculator = CscCalculator::new(2.);
+        let measurement =
+            |wheel: u32, wheel_time: u16, crank: u16, crank_time: u16| CscMeasurement {
+                wheel: Some((wheel, wheel_time)),
+                crank: Some((crank, crank_time)),
+            };
+        let first = calculator.update(
+            &measurement(u32::MAX, 65024, u16::MAX, 64512),
+            Duration::ZERO,
+        );
+        assert_eq!(
+            (first.speed, first.cadence, first.distance),
+            (Some(0.), Some(0.), Some(0.))
+        );
+
+        // 5 wheel and 1.5 crank revolutions a second, every counter and event time rolling over
+        let turning = measurement(4, 512, 2, 1024);
+        let data = calculator.update(&turning, Duration::from_secs(2));
+        assert_eq!(data.speed, Some(36.));
+        assert_eq!(data.cadence, Some(90.));
+        assert_eq!(data.distance, Some(0.01));
+
+        // the same measurement over again keeps the rate for a while, until the wheel counts as stopped
+        let data = calculator.update(&turning, Duration::from_secs(3));
+        assert_eq!(data.cadence, Some(90.));
+        let data = calculator.update(&turning, Duration::from_secs(5));
+        assert_eq!((data.speed, data.cadence), (Some(0.), Some(0.)));
+        assert_eq!(data.distance, Some(0.01));
+    }
+}
@@ -0,0 +1,256 @@
+use futures::future::BoxFuture;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "influx")]
+use crate::InfluxSink;
+use crate::{DeviceEvent, Equipment, FitRecorder, Reading, Result, SampleRecorder, SessionStats};
+
+/// Somewhere the readings and events of a session go, see `Session`
+///
+/// Implemented by the recorders and exporters of this crate, and open to outputs of your own, like
+/// publishing to a message broker. Only `on_sample` has to be implemented. Every sink is a
+/// `DynDataSink` as well, which is what `Session` holds them as.
+pub trait DataSink: Send {
+    /// Take a reading of the equipment
+    fn on_sample(&mut self, reading: &Reading) -> impl Future<Output = Result<()>> + Send;
+
+    /// Take any other event of the equipment, like it disconnecting
+    fn on_event(&mut self, _event: &DeviceEvent) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// The session is over, so write out what's left and let go of what's held on to
+    fn on_session_end(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// `DataSink` as a trait object, boxing the futures of its methods
+///
+/// Every sink is one, so `&mut dyn DynDataSink` holds sinks of different types side by side, like the
+/// sinks of a `Session`. `dyn DynDataSink` is a `DataSink` in turn.
+pub trait DynDataSink: Send {
+    /// See `DataSink::on_sample`
+    fn on_sample<'a>(&'a mut self, reading: &'a Reading) -> BoxFuture<'a, Result<()>>;
+    /// See `DataSink::on_event`
+    fn on_event<'a>(&'a mut self, event: &'a DeviceEvent) -> BoxFuture<'a, Result<()>>;
+    /// See `DataSink::on_session_end`
+    fn on_session_end(&mut self) -> BoxFuture<'_, Result<()>>;
+}
+
+impl<S: DataSink> DynDataSink for S {
+    fn on_sample<'a>(&'a mut self, reading: &'a Reading) -> BoxFuture<'a, Result<()>> {
+        Box::pin(DataSink::on_sample(self, reading))
+    }
+    fn on_event<'a>(&'a mut self, event: &'a DeviceEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(DataSink::on_event(self, event))
+    }
+    fn on_session_end(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(DataSink::on_session_end(self))
+    }
+}
+
+impl DataSink for dyn DynDataSink + '_ {
+    async fn on_sample(&mut self, reading: &Reading) -> Result<()> {
+        DynDataSink::on_sample(self, reading).await
+    }
+    async fn on_event(&mut self, event: &DeviceEvent) -> Result<()> {
+        DynDataSink::on_event(self, event).await
+    }
+    async fn on_session_end(&mut self) -> Result<()> {
+        DynDataSink::on_session_end(self).await
+    }
+}
+
+impl DataSink for SampleRecorder {
+    async fn on_sample(&mut self, reading: &Reading) -> Result<()> {
+        Ok(self.record(reading)?)
+    }
+
+    async fn on_session_end(&mut self) -> Result<()> {
+        Ok(self.flush()?)
+    }
+}
+
+/// Keeps every reading, to be written out with `FitRecorder::write` once the session is over
+impl DataSink for FitRecorder {
+    async fn on_sample(&mut self, reading: &Reading) -> Result<()> {
+        self.record(reading);
+        Ok(())
+    }
+}
+
+impl DataSink for SessionStats {
+    async fn on_sample(&mut self, reading: &Reading) -> Result<()> {
+        self.record(reading);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "influx")]
+impl DataSink for InfluxSink {
+    async fn on_sample(&mut self, reading: &Reading) -> Result<()> {
+        self.record(reading).await
+    }
+
+    async fn on_session_end(&mut self) -> Result<()> {
+        self.flush().await
+    }
+}
+
+/// Hands every reading and event of a piece of equipment to any number of sinks
+///
+/// Sinks are borrowed for the session, so what they gathered, like the statistics of
+/// `SessionStats`, can be looked at once it's over. A sink failing doesn't keep the others from
+/// getting what the equipment reports, and the first failure is what `run` fails with once the session
+/// is over.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::{
+///     devices::NonBluetoothDevice, CancellationToken, Equipment, FitRecorder, Session,
+///     SessionStats,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let mut stats = SessionStats::new();
+///     let mut recorder = FitRecorder::new();
+///     let mut session = Session::new().sink(&mut stats).sink(&mut recorder);
+///     shutdown.cancel();
+///     session.run(&device, &shutdown).await?;
+///     drop(session);
+///     println!("{:.1} kJ", stats.work());
+///     Ok(())
+/// }
+/// ```
+#[derive(Default)]
+pub struct Session<'a> {
+    sinks: Vec<&'a mut dyn DynDataSink>,
+}
+
+impl<'a> Session<'a> {
+    /// A session without any sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand everything to `sink` as well
+    pub fn sink(mut self, sink: &'a mut dyn DynDataSink) -> Self {
+        self.add_sink(sink);
+        self
+    }
+
+    /// Hand everything to `sink` as well
+    pub fn add_sink(&mut self, sink: &'a mut dyn DynDataSink) {
+        self.sinks.push(sink);
+    }
+
+    /// Hand the readings and events of `equipment` to every sink, until `shutdown` gets cancelled or
+    /// the equipment disconnects, and end the session for every sink
+    pub async fn run<E: Equipment + ?Sized>(
+        &mut self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let mut events = equipment.events();
+        let mut failure = None;
+        loop {
+            let event = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                event = events.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            for sink in &mut self.sinks {
+                let handed = match &event {
+                    DeviceEvent::Data(reading) => sink.on_sample(reading).await,
+                    event => sink.on_event(event).await,
+                };
+                if let Err(e) = handed {
+                    failure.get_or_insert(e);
+                }
+            }
+            if matches!(event, DeviceEvent::Disconnected) {
+                break;
+            }
+        }
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.on_session_end().await {
+                failure.get_or_insert(e);
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+impl std::fmt::Debug for Session<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("sinks", &self.sinks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KondisError;
+    use crate::devices::NonBluetoothDevice;
+
+    /// Counts what it gets, failing every sample
+    #[derive(Default)]
+    struct Failing {
+        samples: usize,
+        events: usize,
+        ended: bool,
+    }
+
+    impl DataSink for Failing {
+        async fn on_sample(&mut self, _: &Reading) -> Result<()> {
+            self.samples += 1;
+            Err(KondisError::Export("Nowhere to go".to_string()))
+        }
+
+        async fn on_event(&mut self, _: &DeviceEvent) -> Result<()> {
+            self.events += 1;
+            Ok(())
+        }
+
+        async fn on_session_end(&mut self) -> Result<()> {
+            self.ended = true;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session() -> Result<()> {
+        let shutdown = CancellationToken::new();
+        let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+        device.connect().await?;
+        let mut failing = Failing::default();
+        let mut recorder = FitRecorder::new();
+        let mut session = Session::new().sink(&mut failing).sink(&mut recorder);
+        let run = session.run(&device, &shutdown);
+        let report = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            device.disconnect().await
+        };
+        let (ran, reported) = tokio::join!(run, report);
+        reported?;
+        assert!(matches!(ran, Err(KondisError::Export(_))));
+        drop(session);
+        assert!(failing.samples > 0);
+        assert!(failing.events > 0);
+        assert!(failing.ended);
+        Ok(())
+    }
+}
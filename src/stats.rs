@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::{FTMSData, HeartRateZones, PowerZones, Reading, UserProfile};
+
+/// Gaps between readings longer than this are taken as a pause rather than riding
+const MAX_GAP: Duration = Duration::from_secs(5);
+/// How many seconds of power normalized power averages over
+const ROLLING_WINDOW: usize = 30;
+/// The share of the energy burnt that ends up turning the pedals
+const GROSS_EFFICIENCY: f64 = 0.24;
+/// kJ per kcal
+const KJ_PER_KCAL: f64 = 4.184;
+
+/// Statistics of a whole session, accumulated one reading at a time
+///
+/// Every value a reading holds is taken to last until the next reading arrives, so readings don't have
+/// to arrive at a steady rate. Averages only cover the time the machine reported the value.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::{devices::NonBluetoothDevice, CancellationToken, Equipment, SessionStats};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///     let mut stats = SessionStats::new();
+///     let mut data = device.data_stream().await?.take(2);
+///     while let Some(reading) = data.next().await {
+///         stats.record(&reading);
+///     }
+///     println!("{:?} elapsed, {:.1} kJ", stats.elapsed_time(), stats.work());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    profile: UserProfile,
+    first: Option<Duration>,
+    last: Option<(Duration, FTMSData)>,
+    moving_time: Duration,
+    /// joules
+    work: f64,
+    power: Average,
+    cadence: Average,
+    speed: Average,
+    heart_rate: Average,
+    normalized: NormalizedPower,
+    power_zones: Option<(PowerZones, [Duration; 7])>,
+    heart_rate_zones: Option<(HeartRateZones, [Duration; 5])>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calculate with the numbers of the rider, tracking the time spent in their zones if known
+    pub fn profile(mut self, profile: UserProfile) -> Self {
+        self.profile = profile;
+        if let Some(zones) = profile.power_zones() {
+            self = self.power_zones(zones);
+        }
+        if let Some(zones) = profile.heart_rate_zones() {
+            self = self.heart_rate_zones(zones);
+        }
+        self
+    }
+
+    /// Track the time spent in each of `zones`, see `time_in_power_zones`
+    pub fn power_zones(mut self, zones: PowerZones) -> Self {
+        self.power_zones = Some((zones, [Duration::ZERO; 7]));
+        self
+    }
+
+    /// Track the time spent in each of `zones`, see `time_in_heart_rate_zones`
+    pub fn heart_rate_zones(mut self, zones: HeartRateZones) -> Self {
+        self.heart_rate_zones = Some((zones, [Duration::ZERO; 5]));
+        self
+    }
+
+    /// Add a reading, which has to arrive after every reading recorded so far
+    pub fn record(&mut self, reading: &Reading) {
+        let data = FTMSData::from(reading.data.clone());
+        self.first.get_or_insert(reading.timestamp);
+        if let Some((timestamp, last)) = &self.last {
+            let gap = reading.timestamp.saturating_sub(*timestamp);
+            if gap <= MAX_GAP {
+                self.add_interval(last.clone(), gap);
+            }
+        }
+        self.power.max(data.power.map(f64::from));
+        self.cadence.max(data.cadence.map(f64::from));
+        self.speed.max(data.speed.map(f64::from));
+        self.heart_rate.max(data.heart_rate);
+        self.last = Some((reading.timestamp, data));
+    }
+
+    fn add_interval(&mut self, data: FTMSData, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let power = data.power.map(f64::from);
+        let moving = [
+            power,
+            data.cadence.map(f64::from),
+            data.speed.map(f64::from),
+        ]
+        .into_iter()
+        .any(|value| value.is_some_and(|value| value > 0.));
+        if moving {
+            self.moving_time += duration;
+        }
+        if let Some(power) = power {
+            self.work += power.max(0.) * seconds;
+            self.normalized.add(power, seconds);
+            if let Some((zones, times)) = &mut self.power_zones {
+                times[zones.zone(power)] += duration;
+            }
+        }
+        self.power.add(power, seconds);
+        self.cadence.add(data.cadence.map(f64::from), seconds);
+        self.speed.add(data.speed.map(f64::from), seconds);
+        self.heart_rate.add(data.heart_rate, seconds);
+        if let (Some(heart_rate), Some((zones, times))) =
+            (data.heart_rate, &mut self.heart_rate_zones)
+        {
+            times[zones.zone(heart_rate)] += duration;
+        }
+    }
+
+    /// Time from the first reading to the last, pauses included
+    pub fn elapsed_time(&self) -> Duration {
+        match (self.first, &self.last) {
+            (Some(first), Some((last, _))) => last.saturating_sub(first),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Time spent pedalling, striding or rowing, pauses excluded
+    pub fn moving_time(&self) -> Duration {
+        self.moving_time
+    }
+
+    /// Total work in kJ
+    pub fn work(&self) -> f64 {
+        self.work / 1000.
+    }
+
+    /// Watts
+    pub fn average_power(&self) -> Option<f64> {
+        self.power.average()
+    }
+
+    /// Watts
+    pub fn max_power(&self) -> Option<f64> {
+        self.power.max
+    }
+
+    /// Average power per kg of the rider, see `profile`
+    pub fn watts_per_kg(&self) -> Option<f64> {
+        Some(self.average_power()? / self.profile.weight_kg)
+    }
+
+    /// The energy burnt producing the work, in kcal
+    pub fn calories(&self) -> f64 {
+        self.work() / GROSS_EFFICIENCY / KJ_PER_KCAL
+    }
+
+    pub fn average_cadence(&self) -> Option<f64> {
+        self.cadence.average()
+    }
+
+    pub fn max_cadence(&self) -> Option<f64> {
+        self.cadence.max
+    }
+
+    /// km/h
+    pub fn average_speed(&self) -> Option<f64> {
+        self.speed.average()
+    }
+
+    /// km/h
+    pub fn max_speed(&self) -> Option<f64> {
+        self.speed.max
+    }
+
+    /// bpm
+    pub fn average_heart_rate(&self) -> Option<f64> {
+        self.heart_rate.average()
+    }
+
+    /// bpm
+    pub fn max_heart_rate(&self) -> Option<f64> {
+        self.heart_rate.max
+    }
+
+    /// The power the session was as hard as if ridden steadily, in watts
+    ///
+    /// Needs at least 30 seconds of power readings.
+    pub fn normalized_power(&self) -> Option<f64> {
+        self.normalized.get()
+    }
+
+    /// The time spent in each power zone, if configured with `power_zones`
+    pub fn time_in_power_zones(&self) -> Option<[Duration; 7]> {
+        self.power_zones.map(|(_, times)| times)
+    }
+
+    /// The time spent in each heart rate zone, if configured with `heart_rate_zones`
+    pub fn time_in_heart_rate_zones(&self) -> Option<[Duration; 5]> {
+        self.heart_rate_zones.map(|(_, times)| times)
+    }
+
+    /// Normalized power relative to functional threshold power
+    ///
+    /// Needs the functional threshold power of the rider, from either `profile` or `power_zones`.
+    pub fn intensity_factor(&self) -> Option<f64> {
+        let ftp = self
+            .profile
+            .ftp
+            .or_else(|| self.power_zones.map(|(zones, _)| zones.ftp()))
+            .filter(|&ftp| ftp > 0.)?;
+        Some(self.normalized_power()? / ftp)
+    }
+
+    /// Training stress score, 100 being an hour at functional threshold power
+    ///
+    /// Needs the functional threshold power of the rider, see `intensity_factor`.
+    pub fn training_stress_score(&self) -> Option<f64> {
+        let intensity_factor = self.intensity_factor()?;
+        let hours = self.moving_time.as_secs_f64() / 3600.;
+        Some(hours * intensity_factor * intensity_factor * 100.)
+    }
+}
+
+/// A time weighted average and the maximum of one value
+#[derive(Debug, Clone, Default)]
+struct Average {
+    sum: f64,
+    seconds: f64,
+    max: Option<f64>,
+}
+
+impl Average {
+    fn add(&mut self, value: Option<f64>, seconds: f64) {
+        if let Some(value) = value {
+            self.sum += value * seconds;
+            self.seconds += seconds;
+        }
+    }
+
+    fn max(&mut self, value: Option<f64>) {
+        if let Some(value) = value {
+            self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        }
+    }
+
+    fn average(&self) -> Option<f64> {
+        (self.seconds > 0.).then(|| self.sum / self.seconds)
+    }
+}
+
+/// The fourth power mean of the 30 second rolling average of power, sampled every second
+#[derive(Debug, Clone, Default)]
+struct NormalizedPower {
+    /// joules and seconds of the second being filled
+    second: (f64, f64),
+    window: VecDeque<f64>,
+    sum: f64,
+    count: u64,
+}
+
+impl NormalizedPower {
+    fn add(&mut self, power: f64, mut seconds: f64) {
+        while seconds > 0. {
+            let taken = seconds.min(1. - self.second.1);
+            self.second.0 += power * taken;
+            self.second.1 += taken;
+            seconds -= taken;
+            if self.second.1 >= 1. - f64::EPSILON {
+                self.push(self.second.0);
+                self.second = (0., 0.);
+            }
+        }
+    }
+
+    fn push(&mut self, power: f64) {
+        self.window.push_back(power);
+        if self.window.len() > ROLLING_WINDOW {
+            self.window.pop_front();
+        }
+        if self.window.len() == ROLLING_WINDOW {
+            let average = self.window.iter().sum::<f64>() / ROLLING_WINDOW as f64;
+            self.sum += average.powi(4);
+            self.count += 1;
+        }
+    }
+
+    fn get(&self) -> Option<f64> {
+        (self.count > 0).then(|| (self.sum / self.count as f64).powf(0.25))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BikeData;
+
+    fn reading(second: u64, power: i16) -> Reading {
+        Reading {
+            timestamp: Duration::from_secs(second),
+            sequence: second,
+            data: BikeData {
+                power: Some(power),
+                cadence: Some(90.),
+                ..Default::default()
+            }
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let mut stats = SessionStats::new().profile(UserProfile {
+            weight_kg: 80.,
+            ftp: Some(200.),
+            ..Default::default()
+        });
+        assert_eq!(stats.average_power(), None);
+
+        // an hour at 200 W, sampled every second
+        for second in 0..=3600 {
+            stats.record(&reading(second, 200));
+        }
+        assert_eq!(stats.elapsed_time(), Duration::from_secs(3600));
+        assert_eq!(stats.moving_time(), Duration::from_secs(3600));
+        assert_eq!(stats.average_power(), Some(200.));
+        assert_eq!(stats.max_power(), Some(200.));
+        assert_eq!(stats.work(), 720.);
+        let normalized = stats.normalized_power().unwrap();
+        assert!((normalized - 200.).abs() < 1e-6);
+        let tss = stats.training_stress_score().unwrap();
+        assert!((tss - 100.).abs() < 1e-6);
+        assert_eq!(stats.watts_per_kg(), Some(2.5));
+
+        // a pause counts towards elapsed time only
+        stats.record(&reading(3700, 400));
+        assert_eq!(stats.elapsed_time(), Duration::from_secs(3700));
+        assert_eq!(stats.moving_time(), Duration::from_secs(3600));
+        assert_eq!(stats.max_power(), Some(400.));
+        assert_eq!(stats.average_power(), Some(200.));
+        assert_eq!(stats.time_in_heart_rate_zones(), None);
+    }
+
+    #[test]
+    fn test_time_in_power_zones() {
+        let mut stats = SessionStats::new().power_zones(PowerZones::new(200.));
+        for second in 0..10 {
+            stats.record(&reading(second, 100));
+        }
+        for second in 10..=15 {
+            stats.record(&reading(second, 400));
+        }
+        let times = stats.time_in_power_zones().unwrap();
+        assert_eq!(times[0], Duration::from_secs(10));
+        assert_eq!(times[6], Duration::from_secs(5));
+        assert_eq!(times.iter().sum::<Duration>(), stats.moving_time());
+    }
+}
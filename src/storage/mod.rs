@@ -0,0 +1,309 @@
+//! Session history, kept in SQLite
+//!
+//! Behind the `sqlite` feature, through `rusqlite`, linking against the SQLite library of the system,
+//! or with the `sqlite-bundled` feature, building SQLite along with the crate. A `SessionStore` keeps
+//! sessions with their samples and laps, for apps to list and load past sessions from.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, Row, params};
+
+use crate::{FTMSData, KondisError, Reading, Result};
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        start_time REAL NOT NULL,
+        end_time REAL
+    );
+    CREATE TABLE IF NOT EXISTS samples (
+        session INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        timestamp REAL NOT NULL,
+        sequence INTEGER NOT NULL,
+        speed REAL,
+        cadence REAL,
+        distance REAL,
+        resistance REAL,
+        power INTEGER,
+        calories REAL,
+        heart_rate REAL,
+        time INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS samples_by_session ON samples (session, timestamp);
+    CREATE TABLE IF NOT EXISTS laps (
+        session INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        start REAL NOT NULL,
+        end REAL NOT NULL
+    );
+";
+
+/// A session kept in a `SessionStore`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoredSession {
+    pub id: i64,
+    pub name: String,
+    pub start_time: SystemTime,
+    /// `None` for a session that never got ended
+    pub end_time: Option<SystemTime>,
+    pub samples: u64,
+}
+
+/// A reading kept in a `SessionStore`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample {
+    /// The timestamp of the reading, see `Reading::timestamp`
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub data: FTMSData,
+}
+
+/// A lap of a session, between reading timestamps, see `Reading::timestamp`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lap {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Sessions with their samples and laps, kept in an SQLite database
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use kondis::{devices::NonBluetoothDevice, storage::SessionStore, CancellationToken, Equipment};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(32, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let store = SessionStore::open_in_memory()?;
+///     let session = store.start_session("Morning ride")?;
+///     let mut data = device.data_stream().await?.take(2);
+///     while let Some(reading) = data.next().await {
+///         store.add_sample(session, &reading)?;
+///     }
+///     store.end_session(session)?;
+///
+///     for session in store.sessions()? {
+///         println!("{}: {} samples", session.name, session.samples);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SessionStore {
+    connection: Connection,
+}
+
+impl SessionStore {
+    /// Open the database at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// A database living in memory only, gone once dropped
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(SessionStore { connection })
+    }
+
+    /// Start a session called `name`, returning its id
+    pub fn start_session(&self, name: &str) -> Result<i64> {
+        self.connection.execute(
+            "INSERT INTO sessions (name, start_time) VALUES (?, ?)",
+            params![name, unix_time(SystemTime::now())],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    pub fn end_session(&self, session: i64) -> Result<()> {
+        self.connection.execute(
+            "UPDATE sessions SET end_time = ? WHERE id = ?",
+            params![unix_time(SystemTime::now()), session],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a session, with its samples and laps
+    pub fn delete_session(&self, session: i64) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM sessions WHERE id = ?", [session])?;
+        Ok(())
+    }
+
+    pub fn add_sample(&self, session: i64, reading: &Reading) -> Result<()> {
+        let data = FTMSData::from(reading.data.clone());
+        self.connection.execute(
+            "INSERT INTO samples (session, timestamp, sequence, speed, cadence, distance, \
+             resistance, power, calories, heart_rate, time) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                session,
+                reading.timestamp.as_secs_f64(),
+                reading.sequence as i64,
+                data.speed,
+                data.cadence,
+                data.distance,
+                data.resistance,
+                data.power,
+                data.calories,
+                data.heart_rate,
+                data.time,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_lap(&self, session: i64, lap: Lap) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO laps (session, start, end) VALUES (?, ?, ?)",
+            params![session, lap.start.as_secs_f64(), lap.end.as_secs_f64()],
+        )?;
+        Ok(())
+    }
+
+    /// Every session, the latest first
+    pub fn sessions(&self) -> Result<Vec<StoredSession>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name, start_time, end_time, \
+             (SELECT count(*) FROM samples WHERE session = sessions.id) \
+             FROM sessions ORDER BY start_time DESC, id DESC",
+        )?;
+        let sessions = statement.query_map([], |row| {
+            Ok(StoredSession {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                start_time: system_time(row.get(2)?),
+                end_time: row.get::<_, Option<f64>>(3)?.map(system_time),
+                samples: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        Ok(sessions.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The samples of `session`, in the order they were taken
+    pub fn samples(&self, session: i64) -> Result<Vec<Sample>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, sequence, speed, cadence, distance, resistance, power, calories, \
+             heart_rate, time FROM samples WHERE session = ? ORDER BY timestamp, rowid",
+        )?;
+        let samples = statement.query_map([session], sample)?;
+        Ok(samples.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The laps of `session`, in order
+    pub fn laps(&self, session: i64) -> Result<Vec<Lap>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT start, end FROM laps WHERE session = ? ORDER BY start")?;
+        let laps = statement.query_map([session], |row| {
+            Ok(Lap {
+                start: seconds(row.get(0)?),
+                end: seconds(row.get(1)?),
+            })
+        })?;
+        Ok(laps.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+impl From<rusqlite::Error> for KondisError {
+    fn from(e: rusqlite::Error) -> Self {
+        KondisError::Storage(e.to_string())
+    }
+}
+
+fn sample(row: &Row) -> rusqlite::Result<Sample> {
+    Ok(Sample {
+        timestamp: seconds(row.get(0)?),
+        sequence: row.get::<_, i64>(1)? as u64,
+        data: FTMSData {
+            speed: row.get(2)?,
+            cadence: row.get(3)?,
+            distance: row.get(4)?,
+            resistance: row.get(5)?,
+            power: row.get(6)?,
+            calories: row.get(7)?,
+            heart_rate: row.get(8)?,
+            time: row.get(9)?,
+        },
+    })
+}
+
+fn seconds(value: f64) -> Duration {
+    Duration::try_from_secs_f64(value).unwrap_or_default()
+}
+
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn system_time(unix_time: f64) -> SystemTime {
+    UNIX_EPOCH + Duration::try_from_secs_f64(unix_time).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BikeData, MachineData};
+
+    #[test]
+    fn test_session_store() -> Result<()> {
+        let store = SessionStore::open_in_memory()?;
+        let first = store.start_session("first")?;
+        let second = store.start_session("second")?;
+        let reading = Reading {
+            timestamp: Duration::from_millis(2500),
+            sequence: 7,
+            data: MachineData::Bike(BikeData {
+                power: Some(230),
+                cadence: Some(88.5),
+                ..Default::default()
+            }),
+        };
+        store.add_sample(first, &reading)?;
+        store.add_lap(
+            first,
+            Lap {
+                start: Duration::ZERO,
+                end: Duration::from_secs(60),
+            },
+        )?;
+        store.end_session(first)?;
+
+        let sessions = store.sessions()?;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!((sessions[0].id, sessions[0].samples), (second, 0));
+        assert_eq!((sessions[1].id, sessions[1].samples), (first, 1));
+        assert!(sessions[1].end_time.is_some());
+
+        assert_eq!(
+            store.samples(first)?,
+            vec![Sample {
+                timestamp: reading.timestamp,
+                sequence: 7,
+                data: FTMSData::from(reading.data),
+            }]
+        );
+        assert_eq!(store.laps(first)?[0].end, Duration::from_secs(60));
+
+        store.delete_session(first)?;
+        assert!(store.samples(first)?.is_empty());
+        assert_eq!(store.sessions()?.len(), 1);
+        Ok(())
+    }
+}
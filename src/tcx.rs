@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::{BikeData, KondisError, MachineData, Result};
+
+/// The track points of a TCX activity file, timed from the first one
+///
+/// Like `parse_fit`, every track point turns into bike data holding the power, cadence, heart rate,
+/// speed and distance it has, from the Garmin activity extension for speed and power, and the time
+/// since the first track point. Track points without a time are left out.
+///
+/// # Examples
+///
+/// ```
+/// let records = kondis::parse_tcx(
+///     r#"<TrainingCenterDatabase>
+///         <Activities><Activity Sport="Biking"><Lap><Track>
+///             <Trackpoint>
+///                 <Time>2024-05-01T10:00:00Z</Time>
+///                 <HeartRateBpm><Value>130</Value></HeartRateBpm>
+///             </Trackpoint>
+///             <Trackpoint>
+///                 <Time>2024-05-01T10:00:05Z</Time>
+///                 <Cadence>90</Cadence>
+///                 <Extensions><TPX><Watts>200</Watts></TPX></Extensions>
+///             </Trackpoint>
+///         </Track></Lap></Activity></Activities>
+///     </TrainingCenterDatabase>"#,
+/// )?;
+/// assert_eq!(records[1].0.as_secs(), 5);
+/// assert_eq!(kondis::FTMSData::from(records[1].1.clone()).power, Some(200));
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_tcx(xml: &str) -> Result<Vec<(Duration, MachineData)>> {
+    // the points with their time in seconds since the unix epoch, the one being read last
+    let mut points: Vec<(f64, BikeData)> = Vec::new();
+    let mut point = None;
+    let mut elements: Vec<String> = Vec::new();
+    for event in EventReader::from_str(xml) {
+        let event =
+            event.map_err(|e| KondisError::InvalidData(format!("Invalid TCX file: {e}")))?;
+        match event {
+            XmlEvent::StartElement { name, .. } => {
+                if name.local_name == "Trackpoint" {
+                    point = Some((None, BikeData::default()));
+                }
+                elements.push(name.local_name);
+            }
+            XmlEvent::Characters(text) => {
+                let Some((time, data)) = point.as_mut() else {
+                    continue;
+                };
+                let text = text.trim();
+                let invalid = || KondisError::InvalidData(format!("Invalid TCX value {text:?}"));
+                let number = || text.parse::<f64>().map_err(|_| invalid());
+                let parent = elements.iter().rev().nth(1).map(String::as_str);
+                match (parent, elements.last().map(String::as_str)) {
+                    (Some("Trackpoint"), Some("Time")) => {
+                        *time = Some(unix_time(text).ok_or_else(invalid)?)
+                    }
+                    (Some("Trackpoint"), Some("DistanceMeters")) => {
+                        data.distance = Some((number()? / 1000.) as f32)
+                    }
+                    (Some("HeartRateBpm"), Some("Value")) => data.heart_rate = Some(number()?),
+                    (_, Some("Cadence" | "RunCadence")) => data.cadence = Some(number()? as f32),
+                    (Some("TPX"), Some("Speed")) => data.speed = Some((number()? * 3.6) as f32),
+                    (Some("TPX"), Some("Watts")) => data.power = Some(number()?.round() as i16),
+                    _ => {}
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                elements.pop();
+                if name.local_name == "Trackpoint"
+                    && let Some((Some(time), data)) = point.take()
+                {
+                    points.push((time, data));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let first = points.first().map_or(0., |(time, _)| *time);
+    Ok(points
+        .into_iter()
+        .map(|(time, data)| {
+            let offset = Duration::try_from_secs_f64(time - first).unwrap_or_default();
+            let data = BikeData {
+                time: Some(offset.as_secs() as u32),
+                ..data
+            };
+            (offset, MachineData::Bike(data))
+        })
+        .collect())
+}
+
+/// The seconds since the unix epoch of an ISO 8601 time like `2024-05-01T10:00:00.250+02:00`
+fn unix_time(text: &str) -> Option<f64> {
+    let (date, time) = text.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    // the offset from UTC follows the time, if there is one
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0.)
+    } else if let Some(at) = time.rfind(['+', '-']) {
+        let (hours, minutes) = time[at + 1..].split_once(':')?;
+        let offset = hours.parse::<f64>().ok()? * 3600. + minutes.parse::<f64>().ok()? * 60.;
+        let sign = if time[at..].starts_with('-') { -1. } else { 1. };
+        (&time[..at], sign * offset)
+    } else {
+        (time, 0.)
+    };
+    let mut time = time.splitn(3, ':').map(str::parse::<f64>);
+    let (hours, minutes, seconds) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    // days since the epoch in the proleptic gregorian calendar, with years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days as f64 * 86_400. + hours * 3600. + minutes * 60. + seconds - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FTMSData;
+
+    #[test]
+    fn test_parse_tcx() -> Result<()> {
+        assert_eq!(unix_time("1970-01-01T00:00:00Z"), Some(0.));
+        assert_eq!(
+            unix_time("2000-03-01T01:30:00.5+01:00"),
+            Some(951_870_600.5)
+        );
+
+        let records = parse_tcx(
+            r#"<?xml version="1.0"?>
+            <TrainingCenterDatabase xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+                <Activities><Activity Sport="Biking"><Lap StartTime="2024-05-01T10:00:00Z">
+                    <Cadence>85</Cadence>
+                    <Track>
+                        <Trackpoint><DistanceMeters>0</DistanceMeters></Trackpoint>
+                        <Trackpoint>
+                            <Time>2024-05-01T12:00:00+02:00</Time>
+                            <DistanceMeters>1500</DistanceMeters>
+                        </Trackpoint>
+                        <Trackpoint>
+                            <Time>2024-05-01T10:00:02.5Z</Time>
+                            <Extensions><ns3:TPX><ns3:Speed>10</ns3:Speed></ns3:TPX></Extensions>
+                        </Trackpoint>
+                    </Track>
+                </Lap></Activity></Activities>
+            </TrainingCenterDatabase>"#,
+        )?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].0, Duration::from_millis(2500));
+        let first = FTMSData::from(records[0].1.clone());
+        assert_eq!(first.distance, Some(1.5));
+        assert_eq!(first.cadence, None);
+        assert_eq!(FTMSData::from(records[1].1.clone()).speed, Some(36.));
+
+        assert!(parse_tcx("<Trackpoint><Time>yesterday</Time></Trackpoint>").is_err());
+        Ok(())
+    }
+}
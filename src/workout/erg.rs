@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use crate::workout::{Block, Message, PowerTarget, Workout};
+use crate::{KondisError, Result};
+
+/// Load a workout from an .erg or .mrc file, as TrainerRoad and CompuTrainer write them
+///
+/// Both formats list power against time, in minutes. The column header tells them apart: .erg files
+/// hold `WATTS`, turned into `PowerTarget::Watts`, and .mrc files hold `PERCENT` of functional
+/// threshold power, turned into `PowerTarget::Ftp`. Every two points make a block, steady where the
+/// power holds and a ramp where it changes. The `[COURSE TEXT]` section, if any, turns into messages.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use kondis::workout::{Block, PowerTarget};
+///
+/// let workout = kondis::workout::parse_erg(
+///     "[COURSE HEADER]
+///     FILE NAME = Threshold
+///     MINUTES PERCENT
+///     [END COURSE HEADER]
+///     [COURSE DATA]
+///     0 50
+///     10 90
+///     10 100
+///     20 100
+///     [END COURSE DATA]",
+/// )?;
+/// assert_eq!(workout.name, "Threshold");
+/// assert_eq!(workout.duration(), Duration::from_secs(1200));
+/// assert!(matches!(workout.blocks[1], Block::Steady { power: PowerTarget::Ftp(1.), .. }));
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_erg(text: &str) -> Result<Workout> {
+    let mut workout = Workout::default();
+    let mut section = "";
+    let mut relative = None;
+    let mut points = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = if name.starts_with("END ") { "" } else { name };
+            continue;
+        }
+        match section {
+            "COURSE HEADER" => {
+                if let Some((key, value)) = line.split_once('=') {
+                    match key.trim() {
+                        "FILE NAME" => workout.name = value.trim().to_string(),
+                        "DESCRIPTION" => workout.description = value.trim().to_string(),
+                        _ => {}
+                    }
+                } else if let Some(unit) = line.strip_prefix("MINUTES") {
+                    relative = match unit.trim() {
+                        "WATTS" => Some(false),
+                        "PERCENT" => Some(true),
+                        unit => {
+                            return Err(KondisError::InvalidData(format!(
+                                "Unknown power unit {unit:?}"
+                            )));
+                        }
+                    };
+                }
+            }
+            "COURSE DATA" => {
+                let mut columns = line.split_whitespace().map(number);
+                match (columns.next(), columns.next()) {
+                    (Some(minutes), Some(power)) => points.push((minutes?, power?)),
+                    _ => return Err(KondisError::InvalidData(format!("Invalid point {line:?}"))),
+                }
+            }
+            "COURSE TEXT" => {
+                let mut columns = line.split('\t');
+                if let (Some(seconds), Some(text)) = (columns.next(), columns.next()) {
+                    workout.messages.push(Message {
+                        offset: Duration::from_secs_f64(number(seconds)?),
+                        text: text.trim().to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    let relative = relative
+        .ok_or_else(|| KondisError::InvalidData("Missing MINUTES WATTS or PERCENT".to_string()))?;
+    let target = |power: f64| {
+        if relative {
+            PowerTarget::Ftp(power / 100.)
+        } else {
+            PowerTarget::Watts(power)
+        }
+    };
+    for pair in points.windows(2) {
+        let [(start, from), (end, to)] = [pair[0], pair[1]];
+        if end < start {
+            return Err(KondisError::InvalidData(format!(
+                "Point at {end} minutes comes after one at {start}"
+            )));
+        }
+        // a point repeating the time of the last one is a jump in power, not a block
+        if end == start {
+            continue;
+        }
+        let duration = Duration::from_secs_f64((end - start) * 60.);
+        workout.blocks.push(if from == to {
+            Block::Steady {
+                duration,
+                power: target(from),
+                cadence: None,
+            }
+        } else {
+            Block::Ramp {
+                duration,
+                from: target(from),
+                to: target(to),
+                cadence: None,
+            }
+        });
+    }
+    Ok(workout)
+}
+
+fn number(text: &str) -> Result<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.)
+        .ok_or_else(|| KondisError::InvalidData(format!("Invalid number {text:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_erg() {
+        let workout = parse_erg(
+            "[COURSE HEADER]\n\
+             VERSION = 2\n\
+             UNITS = ENGLISH\n\
+             DESCRIPTION = Warm up and hold\n\
+             FILE NAME = Test\n\
+             MINUTES WATTS\n\
+             [END COURSE HEADER]\n\
+             [COURSE DATA]\n\
+             0.00\t100\n\
+             5.00\t200\n\
+             5.00\t250\n\
+             6.50\t250\n\
+             [END COURSE DATA]\n\
+             [COURSE TEXT]\n\
+             300\tHold it\t10\n\
+             [END COURSE TEXT]\n",
+        )
+        .unwrap();
+        assert_eq!(workout.name, "Test");
+        assert_eq!(workout.description, "Warm up and hold");
+        assert!(!workout.needs_ftp());
+        assert_eq!(
+            workout.blocks,
+            vec![
+                Block::Ramp {
+                    duration: Duration::from_secs(300),
+                    from: PowerTarget::Watts(100.),
+                    to: PowerTarget::Watts(200.),
+                    cadence: None,
+                },
+                Block::Steady {
+                    duration: Duration::from_secs(90),
+                    power: PowerTarget::Watts(250.),
+                    cadence: None,
+                },
+            ]
+        );
+        assert_eq!(
+            workout.messages,
+            vec![Message {
+                offset: Duration::from_secs(300),
+                text: "Hold it".to_string(),
+            }]
+        );
+
+        // points going back in time
+        let backwards = "[COURSE HEADER]\nMINUTES PERCENT\n[END COURSE HEADER]\n\
+                         [COURSE DATA]\n5 50\n0 50\n[END COURSE DATA]\n";
+        assert!(parse_erg(backwards).is_err());
+    }
+}
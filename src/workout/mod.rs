@@ -0,0 +1,310 @@
+//! Structured workouts, and playing them on equipment
+//!
+//! A `Workout` is a list of blocks, each holding or ramping a power target for a while. A
+//! `WorkoutPlayer` rides one on any `Equipment`, through its target power. `parse_erg` loads workouts
+//! from .erg and .mrc files, and with the `zwo` feature, `parse_zwo` loads them from Zwift workout
+//! files.
+
+mod erg;
+mod player;
+#[cfg(feature = "zwo")]
+mod zwo;
+
+use std::time::Duration;
+
+pub use erg::parse_erg;
+pub use player::{WorkoutEvent, WorkoutPlayer};
+#[cfg(feature = "zwo")]
+pub use zwo::parse_zwo;
+
+/// A power to ride at, either absolute or relative to the functional threshold power of the rider
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PowerTarget {
+    /// watts
+    Watts(f64),
+    /// a share of functional threshold power, 1.0 being at threshold
+    Ftp(f64),
+}
+
+impl PowerTarget {
+    /// The target in watts, for a rider with a functional threshold power of `ftp` watts
+    ///
+    /// `None` for a relative target without an `ftp` to go by.
+    pub fn watts(&self, ftp: Option<f64>) -> Option<f64> {
+        match self {
+            PowerTarget::Watts(watts) => Some(*watts),
+            PowerTarget::Ftp(share) => Some(share * ftp?),
+        }
+    }
+}
+
+/// A part of a workout
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Block {
+    /// Hold one power for the whole block
+    Steady {
+        duration: Duration,
+        power: PowerTarget,
+        /// rpm
+        cadence: Option<f64>,
+    },
+    /// Go from one power to another at a steady rate, like a warm up or cool down
+    Ramp {
+        duration: Duration,
+        from: PowerTarget,
+        to: PowerTarget,
+        /// rpm
+        cadence: Option<f64>,
+    },
+    /// Alternate between a hard and an easy power, `repeat` times
+    Intervals {
+        repeat: u32,
+        on_duration: Duration,
+        on_power: PowerTarget,
+        off_duration: Duration,
+        off_power: PowerTarget,
+        /// rpm
+        cadence: Option<f64>,
+    },
+    /// Ride as the rider pleases, without a power target
+    FreeRide { duration: Duration },
+}
+
+impl Block {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Block::Steady { duration, .. }
+            | Block::Ramp { duration, .. }
+            | Block::FreeRide { duration } => *duration,
+            Block::Intervals {
+                repeat,
+                on_duration,
+                off_duration,
+                ..
+            } => (*on_duration + *off_duration) * *repeat,
+        }
+    }
+
+    /// The power in watts and the cadence to ride at `offset` into the block
+    fn target_at(&self, offset: Duration, ftp: Option<f64>) -> (Option<f64>, Option<f64>) {
+        match self {
+            Block::Steady { power, cadence, .. } => (power.watts(ftp), *cadence),
+            Block::Ramp {
+                duration,
+                from,
+                to,
+                cadence,
+            } => {
+                let progress = if duration.is_zero() {
+                    1.
+                } else {
+                    offset.as_secs_f64() / duration.as_secs_f64()
+                };
+                let power = from
+                    .watts(ftp)
+                    .zip(to.watts(ftp))
+                    .map(|(from, to)| from + (to - from) * progress.min(1.));
+                (power, *cadence)
+            }
+            Block::Intervals {
+                on_duration,
+                on_power,
+                off_duration,
+                off_power,
+                cadence,
+                ..
+            } => {
+                let period = (*on_duration + *off_duration).as_secs_f64();
+                let into_period = if period > 0. {
+                    offset.as_secs_f64() % period
+                } else {
+                    0.
+                };
+                if into_period < on_duration.as_secs_f64() {
+                    (on_power.watts(ftp), *cadence)
+                } else {
+                    (off_power.watts(ftp), None)
+                }
+            }
+            Block::FreeRide { .. } => (None, None),
+        }
+    }
+
+    fn power_targets(&self) -> Vec<PowerTarget> {
+        match self {
+            Block::Steady { power, .. } => vec![*power],
+            Block::Ramp { from, to, .. } => vec![*from, *to],
+            Block::Intervals {
+                on_power,
+                off_power,
+                ..
+            } => vec![*on_power, *off_power],
+            Block::FreeRide { .. } => Vec::new(),
+        }
+    }
+}
+
+/// What to ride at one moment of a workout
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Target {
+    /// The index of the block being ridden
+    pub block: usize,
+    /// watts, `None` when riding freely or for a relative target without a functional threshold power
+    /// to go by
+    pub power: Option<f64>,
+    /// rpm
+    pub cadence: Option<f64>,
+}
+
+/// Something to tell the rider during a workout
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message {
+    /// When to show the message, from the start of the workout
+    pub offset: Duration,
+    pub text: String,
+}
+
+/// A structured workout, a list of blocks ridden one after the other
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use kondis::workout::{Block, PowerTarget, Workout};
+///
+/// let workout = Workout::new("Sweet spot")
+///     .block(Block::Ramp {
+///         duration: Duration::from_secs(600),
+///         from: PowerTarget::Ftp(0.5),
+///         to: PowerTarget::Ftp(0.75),
+///         cadence: None,
+///     })
+///     .block(Block::Intervals {
+///         repeat: 3,
+///         on_duration: Duration::from_secs(600),
+///         on_power: PowerTarget::Ftp(0.9),
+///         off_duration: Duration::from_secs(300),
+///         off_power: PowerTarget::Watts(120.),
+///         cadence: Some(90.),
+///     });
+/// assert_eq!(workout.duration(), Duration::from_secs(3300));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Workout {
+    pub name: String,
+    pub description: String,
+    pub blocks: Vec<Block>,
+    pub messages: Vec<Message>,
+}
+
+impl Workout {
+    /// An empty workout called `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Workout {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a block to the end of the workout
+    pub fn block(mut self, block: Block) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    /// Tell the rider `text`, `offset` into the workout
+    pub fn message(mut self, offset: Duration, text: impl Into<String>) -> Self {
+        self.messages.push(Message {
+            offset,
+            text: text.into(),
+        });
+        self
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.blocks.iter().map(Block::duration).sum()
+    }
+
+    /// Whether any target is relative to the functional threshold power of the rider
+    pub fn needs_ftp(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            block
+                .power_targets()
+                .iter()
+                .any(|target| matches!(target, PowerTarget::Ftp(_)))
+        })
+    }
+
+    /// What to ride `elapsed` into the workout, `None` once it's over
+    pub fn target_at(&self, elapsed: Duration, ftp: Option<f64>) -> Option<Target> {
+        let mut start = Duration::ZERO;
+        for (index, block) in self.blocks.iter().enumerate() {
+            let end = start + block.duration();
+            if elapsed < end {
+                let (power, cadence) = block.target_at(elapsed - start, ftp);
+                return Some(Target {
+                    block: index,
+                    power,
+                    cadence,
+                });
+            }
+            start = end;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_at() {
+        let workout = Workout::new("test")
+            .block(Block::Ramp {
+                duration: Duration::from_secs(100),
+                from: PowerTarget::Watts(100.),
+                to: PowerTarget::Watts(200.),
+                cadence: None,
+            })
+            .block(Block::Intervals {
+                repeat: 2,
+                on_duration: Duration::from_secs(30),
+                on_power: PowerTarget::Ftp(1.2),
+                off_duration: Duration::from_secs(30),
+                off_power: PowerTarget::Ftp(0.5),
+                cadence: Some(100.),
+            });
+        assert_eq!(workout.duration(), Duration::from_secs(220));
+        assert!(workout.needs_ftp());
+
+        let target = workout.target_at(Duration::from_secs(50), None).unwrap();
+        assert_eq!((target.block, target.power), (0, Some(150.)));
+
+        let target = workout
+            .target_at(Duration::from_secs(110), Some(250.))
+            .unwrap();
+        assert_eq!(target.block, 1);
+        assert_eq!(target.power, Some(300.));
+        assert_eq!(target.cadence, Some(100.));
+
+        let target = workout
+            .target_at(Duration::from_secs(145), Some(250.))
+            .unwrap();
+        assert_eq!(target.power, Some(125.));
+        assert_eq!(
+            workout
+                .target_at(Duration::from_secs(145), None)
+                .unwrap()
+                .power,
+            None
+        );
+        assert_eq!(workout.target_at(Duration::from_secs(220), None), None);
+    }
+}
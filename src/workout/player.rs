@@ -0,0 +1,335 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::runtime::Instant;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::workout::{Target, Workout};
+use crate::{Equipment, KondisError, Result};
+
+/// How often the player moves along the workout
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// How many events a slow receiver may fall behind before it starts missing them
+const EVENT_CAPACITY: usize = 64;
+
+/// How far along a workout being played is, see `WorkoutPlayer::events`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WorkoutEvent {
+    /// The block at `index` of the workout started
+    BlockStarted { index: usize },
+    /// A message of the workout for the rider
+    Message(String),
+    /// Another second of the workout went by
+    Progress {
+        elapsed: Duration,
+        remaining: Duration,
+        target: Target,
+    },
+    /// The workout got paused, see `WorkoutPlayer::pause`
+    Paused,
+    /// The workout went on after a pause
+    Resumed,
+    /// The last block ended
+    Finished,
+}
+
+/// How long a workout was paused for
+#[derive(Debug, Default)]
+struct Pauses {
+    /// When the ongoing pause started
+    since: Option<Instant>,
+    /// The time spent in earlier pauses
+    total: Duration,
+}
+
+impl Pauses {
+    /// How far into the workout started at `start` the rider is, leaving the pauses out
+    fn elapsed(&self, start: Instant) -> Duration {
+        let ongoing = self.since.map(|since| since.elapsed()).unwrap_or_default();
+        start.elapsed().saturating_sub(self.total + ongoing)
+    }
+}
+
+/// Rides a workout on equipment, setting its target power as the workout goes along
+///
+/// Cadence targets are only reported in the events, for the rider to follow, and the target power is left
+/// as it was during free ride blocks. While paused, the workout stands still and the equipment keeps its
+/// last target power.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use kondis::{
+///     devices::NonBluetoothDevice,
+///     workout::{Block, PowerTarget, Workout, WorkoutEvent, WorkoutPlayer},
+///     CancellationToken, Equipment,
+/// };
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let shutdown = CancellationToken::new();
+///     let mut device = NonBluetoothDevice::new(400, &shutdown).await?;
+///     device.connect().await?;
+///
+///     let workout = Workout::new("Openers").block(Block::Steady {
+///         duration: Duration::from_secs(2),
+///         power: PowerTarget::Ftp(1.1),
+///         cadence: Some(95.),
+///     });
+///     let player = WorkoutPlayer::new(workout).ftp(250.);
+///     let mut events = player.events();
+///     tokio::spawn(async move {
+///         while let Ok(event) = events.recv().await {
+///             if let WorkoutEvent::Progress { remaining, .. } = event {
+///                 println!("{remaining:?} to go");
+///             }
+///         }
+///     });
+///     player.run(&device, &shutdown).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct WorkoutPlayer {
+    workout: Workout,
+    /// watts
+    ftp: Option<f64>,
+    events_tx: broadcast::Sender<WorkoutEvent>,
+    pauses: Mutex<Pauses>,
+}
+
+impl WorkoutPlayer {
+    pub fn new(workout: Workout) -> Self {
+        WorkoutPlayer {
+            workout,
+            ftp: None,
+            events_tx: broadcast::channel(EVENT_CAPACITY).0,
+            pauses: Mutex::default(),
+        }
+    }
+
+    /// The functional threshold power in watts relative targets go by, needed when the workout has any
+    pub fn ftp(mut self, ftp: f64) -> Self {
+        self.ftp = Some(ftp);
+        self
+    }
+
+    pub fn workout(&self) -> &Workout {
+        &self.workout
+    }
+
+    /// Every event of the workout from now on
+    pub fn events(&self) -> broadcast::Receiver<WorkoutEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Hold the workout where it is, until resumed
+    pub fn pause(&self) {
+        let mut pauses = self.pauses.lock().unwrap();
+        if pauses.since.is_none() {
+            pauses.since = Some(Instant::now());
+            let _ = self.events_tx.send(WorkoutEvent::Paused);
+        }
+    }
+
+    /// Go on with a paused workout
+    pub fn resume(&self) {
+        let mut pauses = self.pauses.lock().unwrap();
+        if let Some(since) = pauses.since.take() {
+            pauses.total += since.elapsed();
+            let _ = self.events_tx.send(WorkoutEvent::Resumed);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pauses.lock().unwrap().since.is_some()
+    }
+
+    /// Ride the workout from the start on `equipment`, until it's over or `shutdown` gets cancelled
+    ///
+    /// Fails right away when the workout needs a functional threshold power that wasn't given, and as
+    /// soon as the equipment refuses a target power.
+    pub async fn run<E: Equipment + ?Sized>(
+        &self,
+        equipment: &E,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        if self.ftp.is_none() && self.workout.needs_ftp() {
+            return Err(KondisError::InvalidArgument(
+                "The workout has targets relative to FTP, but no FTP was given".to_string(),
+            ));
+        }
+        let start = Instant::now();
+        {
+            // pauses before the start only count from the start on
+            let mut pauses = self.pauses.lock().unwrap();
+            pauses.total = Duration::ZERO;
+            if let Some(since) = &mut pauses.since {
+                *since = start;
+            }
+        }
+        let mut interval = crate::runtime::interval(TICK_INTERVAL);
+        let mut block = None;
+        let mut watts = None;
+        let mut last_elapsed = None;
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = interval.tick() => {}
+            }
+            let elapsed = {
+                let pauses = self.pauses.lock().unwrap();
+                if pauses.since.is_some() {
+                    continue;
+                }
+                pauses.elapsed(start)
+            };
+            for message in &self.workout.messages {
+                if last_elapsed.is_none_or(|last| message.offset > last)
+                    && message.offset <= elapsed
+                {
+                    let _ = self
+                        .events_tx
+                        .send(WorkoutEvent::Message(message.text.clone()));
+                }
+            }
+            last_elapsed = Some(elapsed);
+            let Some(target) = self.workout.target_at(elapsed, self.ftp) else {
+                let _ = self.events_tx.send(WorkoutEvent::Finished);
+                return Ok(());
+            };
+            if block != Some(target.block) {
+                block = Some(target.block);
+                let _ = self.events_tx.send(WorkoutEvent::BlockStarted {
+                    index: target.block,
+                });
+            }
+            if let Some(power) = target.power {
+                let next = power.round().max(1.) as i16;
+                if watts != Some(next) {
+                    equipment.set_target_power(next).await?;
+                    watts = Some(next);
+                }
+            }
+            // nobody listening is fine, someone may subscribe later
+            let _ = self.events_tx.send(WorkoutEvent::Progress {
+                elapsed,
+                remaining: self.workout.duration().saturating_sub(elapsed),
+                target,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::RecordingEquipment;
+    use crate::remote::Request;
+    use crate::workout::{Block, PowerTarget};
+
+    fn workout() -> Workout {
+        Workout::new("test")
+            .block(Block::Steady {
+                duration: Duration::from_secs(3),
+                power: PowerTarget::Watts(100.),
+                cadence: None,
+            })
+            .block(Block::Intervals {
+                repeat: 2,
+                on_duration: Duration::from_secs(2),
+                on_power: PowerTarget::Ftp(1.2),
+                off_duration: Duration::from_secs(1),
+                off_power: PowerTarget::Watts(150.),
+                cadence: Some(100.),
+            })
+    }
+
+    fn powers(equipment: &RecordingEquipment) -> Vec<(u64, i16)> {
+        equipment
+            .requests()
+            .into_iter()
+            .filter_map(|(at, request)| match request {
+                Request::Power(watts) => Some((at.as_secs(), watts)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_player() -> Result<()> {
+        tokio::time::pause();
+        let shutdown = CancellationToken::new();
+        let equipment = RecordingEquipment::new();
+        let player = WorkoutPlayer::new(workout()).ftp(250.);
+        let mut events = player.events();
+        player.run(&equipment, &shutdown).await?;
+
+        // only changes of the target get sent, as each block and interval starts
+        assert_eq!(
+            powers(&equipment),
+            [(0, 100), (3, 300), (5, 150), (6, 300), (8, 150)]
+        );
+        let events: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        let started: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                WorkoutEvent::BlockStarted { index } => Some(*index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(started, [0, 1]);
+        let progress = events
+            .iter()
+            .filter(|event| matches!(event, WorkoutEvent::Progress { .. }))
+            .count();
+        assert_eq!(progress, 9);
+        assert_eq!(events.last(), Some(&WorkoutEvent::Finished));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pause() -> Result<()> {
+        tokio::time::pause();
+        let shutdown = CancellationToken::new();
+        let equipment = RecordingEquipment::new();
+        let player = WorkoutPlayer::new(workout()).ftp(250.);
+        let mut events = player.events();
+        let rider = async {
+            tokio::time::sleep(Duration::from_millis(2500)).await;
+            player.pause();
+            assert!(player.is_paused());
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            player.resume();
+        };
+        let (result, _) = tokio::join!(player.run(&equipment, &shutdown), rider);
+        result?;
+
+        // the rest of the workout comes 10 s later
+        assert_eq!(
+            powers(&equipment),
+            [(0, 100), (13, 300), (15, 150), (16, 300), (18, 150)]
+        );
+        let events: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert!(events.contains(&WorkoutEvent::Paused));
+        assert!(events.contains(&WorkoutEvent::Resumed));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_ftp() {
+        let shutdown = CancellationToken::new();
+        let player = WorkoutPlayer::new(workout());
+        assert!(
+            player
+                .run(&RecordingEquipment::new(), &shutdown)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::workout::{Block, Message, PowerTarget, Workout};
+use crate::{KondisError, Result};
+
+/// Load a workout from a Zwift workout file
+///
+/// Powers in ZWO files are shares of functional threshold power, so every target of the workout is a
+/// `PowerTarget::Ftp`. `FreeRide` and `MaxEffort` blocks become `Block::FreeRide`, text events turn
+/// into messages, and anything else the crate has no use for, like tags, is skipped.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let workout = kondis::workout::parse_zwo(
+///     r#"<workout_file>
+///         <name>Short and sharp</name>
+///         <workout>
+///             <Warmup Duration="300" PowerLow="0.4" PowerHigh="0.75"/>
+///             <IntervalsT Repeat="5" OnDuration="60" OffDuration="60" OnPower="1.2" OffPower="0.5">
+///                 <textevent timeoffset="0" message="Here we go"/>
+///             </IntervalsT>
+///             <Cooldown Duration="300" PowerLow="0.6" PowerHigh="0.3"/>
+///         </workout>
+///     </workout_file>"#,
+/// )?;
+/// assert_eq!(workout.name, "Short and sharp");
+/// assert_eq!(workout.duration(), Duration::from_secs(1200));
+/// assert_eq!(workout.messages[0].offset, Duration::from_secs(300));
+/// # Ok::<(), kondis::KondisError>(())
+/// ```
+pub fn parse_zwo(xml: &str) -> Result<Workout> {
+    let mut workout = Workout::default();
+    // the element whose text is being read, if it's one the workout keeps
+    let mut text_of = None;
+    // text events are timed from the start of the block they're in
+    let mut block_start = Duration::ZERO;
+    for event in EventReader::from_str(xml) {
+        let event =
+            event.map_err(|e| KondisError::InvalidData(format!("Invalid ZWO file: {e}")))?;
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                element @ ("name" | "description") => text_of = Some(element.to_string()),
+                "textevent" => {
+                    let offset = duration(&attributes, "timeoffset")?;
+                    let text = attribute(&attributes, "message").unwrap_or_default();
+                    workout.messages.push(Message {
+                        offset: block_start + offset,
+                        text: text.to_string(),
+                    });
+                }
+                element => {
+                    if let Some(block) = block(element, &attributes)? {
+                        block_start = workout.duration();
+                        workout.blocks.push(block);
+                    }
+                }
+            },
+            XmlEvent::Characters(text) => match text_of.as_deref() {
+                Some("name") => workout.name = text,
+                Some("description") => workout.description = text,
+                _ => {}
+            },
+            XmlEvent::EndElement { .. } => text_of = None,
+            _ => {}
+        }
+    }
+    Ok(workout)
+}
+
+/// The block an `element` of the workout stands for, `None` for elements that aren't blocks
+fn block(element: &str, attributes: &[OwnedAttribute]) -> Result<Option<Block>> {
+    let cadence = optional_number(attributes, "Cadence")?;
+    let block = match element {
+        "SteadyState" => Block::Steady {
+            duration: duration(attributes, "Duration")?,
+            power: PowerTarget::Ftp(number(attributes, "Power")?),
+            cadence,
+        },
+        "Warmup" | "Cooldown" | "Ramp" => Block::Ramp {
+            duration: duration(attributes, "Duration")?,
+            from: PowerTarget::Ftp(number(attributes, "PowerLow")?),
+            to: PowerTarget::Ftp(number(attributes, "PowerHigh")?),
+            cadence,
+        },
+        "IntervalsT" => Block::Intervals {
+            repeat: number(attributes, "Repeat")? as u32,
+            on_duration: duration(attributes, "OnDuration")?,
+            on_power: PowerTarget::Ftp(number(attributes, "OnPower")?),
+            off_duration: duration(attributes, "OffDuration")?,
+            off_power: PowerTarget::Ftp(number(attributes, "OffPower")?),
+            cadence,
+        },
+        "FreeRide" | "MaxEffort" => Block::FreeRide {
+            duration: duration(attributes, "Duration")?,
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(block))
+}
+
+/// The value of the attribute called `key`, ZWO files being loose about its case
+fn attribute<'a>(attributes: &'a [OwnedAttribute], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.name.local_name.eq_ignore_ascii_case(key))
+        .map(|attribute| attribute.value.as_str())
+}
+
+fn optional_number(attributes: &[OwnedAttribute], key: &str) -> Result<Option<f64>> {
+    attribute(attributes, key)
+        .map(|value| {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite() && *number >= 0.)
+                .ok_or_else(|| KondisError::InvalidData(format!("Invalid {key} {value:?}")))
+        })
+        .transpose()
+}
+
+fn number(attributes: &[OwnedAttribute], key: &str) -> Result<f64> {
+    optional_number(attributes, key)?
+        .ok_or_else(|| KondisError::InvalidData(format!("Missing {key}")))
+}
+
+/// A number of seconds
+fn duration(attributes: &[OwnedAttribute], key: &str) -> Result<Duration> {
+    number(attributes, key).map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zwo() {
+        let workout = parse_zwo(
+            r#"<?xml version="1.0"?>
+            <workout_file>
+                <author>someone</author>
+                <name>Test</name>
+                <description>A bit of everything</description>
+                <sportType>bike</sportType>
+                <tags><tag name="test"/></tags>
+                <workout>
+                    <SteadyState Duration="120" Power="0.65" Cadence="85"/>
+                    <FreeRide Duration="60">
+                        <textevent timeoffset="30" message="Halfway"/>
+                    </FreeRide>
+                </workout>
+            </workout_file>"#,
+        )
+        .unwrap();
+        assert_eq!(workout.name, "Test");
+        assert_eq!(workout.description, "A bit of everything");
+        assert_eq!(
+            workout.blocks,
+            vec![
+                Block::Steady {
+                    duration: Duration::from_secs(120),
+                    power: PowerTarget::Ftp(0.65),
+                    cadence: Some(85.),
+                },
+                Block::FreeRide {
+                    duration: Duration::from_secs(60),
+                },
+            ]
+        );
+        assert_eq!(
+            workout.messages,
+            vec![Message {
+                offset: Duration::from_secs(150),
+                text: "Halfway".to_string(),
+            }]
+        );
+
+        assert!(parse_zwo(r#"<workout><SteadyState Duration="60"/></workout>"#).is_err());
+        assert!(parse_zwo("<workout>").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+/// Upper bounds of the first 6 power zones in percent of functional threshold power, after Coggan
+const POWER_ZONE_LIMITS: [f64; 6] = [55., 75., 90., 105., 120., 150.];
+/// Upper bounds of the first 4 heart rate zones in percent of maximum heart rate
+const MAX_HEART_RATE_ZONE_LIMITS: [f64; 4] = [60., 70., 80., 90.];
+/// Upper bounds of the first 4 heart rate zones in percent of lactate threshold heart rate, after Friel
+const THRESHOLD_HEART_RATE_ZONE_LIMITS: [f64; 4] = [85., 90., 95., 100.];
+
+/// The 7 power zones of a rider, going by their functional threshold power
+///
+/// Zones are numbered from 0, active recovery, to 6, neuromuscular power, to index the durations of
+/// `SessionStats::time_in_power_zones` with.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::PowerZones;
+///
+/// let zones = PowerZones::new(250.);
+/// assert_eq!(zones.zone(200.), 2);
+/// assert_eq!(zones.range(2), Some((187.5, 225.)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerZones {
+    ftp: f64,
+}
+
+impl PowerZones {
+    /// Zones for a functional threshold power of `ftp` watts
+    pub fn new(ftp: f64) -> Self {
+        PowerZones { ftp }
+    }
+
+    /// The functional threshold power in watts
+    pub fn ftp(&self) -> f64 {
+        self.ftp
+    }
+
+    /// The zone `power` in watts falls in
+    pub fn zone(&self, power: f64) -> usize {
+        POWER_ZONE_LIMITS
+            .iter()
+            .take_while(|&&limit| power > limit * self.ftp / 100.)
+            .count()
+    }
+
+    /// The lowest and highest power in watts of `zone`, the highest being infinite for the last zone
+    pub fn range(&self, zone: usize) -> Option<(f64, f64)> {
+        if zone > POWER_ZONE_LIMITS.len() {
+            return None;
+        }
+        let low = zone
+            .checked_sub(1)
+            .map_or(0., |zone| POWER_ZONE_LIMITS[zone]);
+        let high = POWER_ZONE_LIMITS
+            .get(zone)
+            .copied()
+            .unwrap_or(f64::INFINITY);
+        Some((low * self.ftp / 100., high * self.ftp / 100.))
+    }
+}
+
+/// The 5 heart rate zones of a rider, going by either their maximum or lactate threshold heart rate
+///
+/// Zones are numbered from 0, recovery, to 4, maximum effort, to index the durations of
+/// `SessionStats::time_in_heart_rate_zones` with.
+///
+/// # Examples
+///
+/// ```
+/// use kondis::HeartRateZones;
+///
+/// let zones = HeartRateZones::from_max_heart_rate(190.);
+/// assert_eq!(zones.zone(150.), 2);
+///
+/// let zones = HeartRateZones::from_threshold_heart_rate(170.);
+/// assert_eq!(zones.zone(150.), 1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeartRateZones {
+    /// bpm
+    limits: [f64; 4],
+}
+
+impl HeartRateZones {
+    /// Zones in steps of 10 % of a maximum heart rate of `max` bpm, starting at 60 %
+    pub fn from_max_heart_rate(max: f64) -> Self {
+        HeartRateZones {
+            limits: MAX_HEART_RATE_ZONE_LIMITS.map(|limit| limit * max / 100.),
+        }
+    }
+
+    /// Zones around a lactate threshold heart rate of `threshold` bpm, zone 4 starting above it
+    pub fn from_threshold_heart_rate(threshold: f64) -> Self {
+        HeartRateZones {
+            limits: THRESHOLD_HEART_RATE_ZONE_LIMITS.map(|limit| limit * threshold / 100.),
+        }
+    }
+
+    /// The zone `heart_rate` in bpm falls in
+    pub fn zone(&self, heart_rate: f64) -> usize {
+        self.limits
+            .iter()
+            .take_while(|&&limit| heart_rate > limit)
+            .count()
+    }
+
+    /// The lowest and highest heart rate in bpm of `zone`, the highest being infinite for the last zone
+    pub fn range(&self, zone: usize) -> Option<(f64, f64)> {
+        if zone > self.limits.len() {
+            return None;
+        }
+        let low = zone.checked_sub(1).map_or(0., |zone| self.limits[zone]);
+        let high = self.limits.get(zone).copied().unwrap_or(f64::INFINITY);
+        Some((low, high))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_zones() {
+        let zones = PowerZones::new(200.);
+        assert_eq!(zones.zone(0.), 0);
+        assert_eq!(zones.zone(110.), 0);
+        assert_eq!(zones.zone(111.), 1);
+        assert_eq!(zones.zone(200.), 3);
+        assert_eq!(zones.zone(1000.), 6);
+        assert_eq!(zones.range(0), Some((0., 110.)));
+        assert_eq!(zones.range(6), Some((300., f64::INFINITY)));
+        assert_eq!(zones.range(7), None);
+    }
+
+    #[test]
+    fn test_heart_rate_zones() {
+        let zones = HeartRateZones::from_max_heart_rate(200.);
+        assert_eq!(zones.zone(100.), 0);
+        assert_eq!(zones.zone(121.), 1);
+        assert_eq!(zones.zone(200.), 4);
+        assert_eq!(zones.range(1), Some((120., 140.)));
+
+        let zones = HeartRateZones::from_threshold_heart_rate(160.);
+        assert_eq!(zones.zone(136.), 0);
+        assert_eq!(zones.zone(150.), 2);
+        assert_eq!(zones.zone(161.), 4);
+        assert_eq!(zones.range(4), Some((160., f64::INFINITY)));
+    }
+}